@@ -411,12 +411,14 @@ pub async fn handle_update_bucket(
 
 	if let Some(wa) = req.website_access {
 		if wa.enabled {
-			state.website_config.update(Some(WebsiteConfig {
-				index_document: wa.index_document.ok_or_bad_request(
-					"Please specify indexDocument when enabling website access.",
-				)?,
-				error_document: wa.error_document,
-			}));
+			// Only the index and error documents are set through this API,
+			// other settings of the website are kept as they are
+			let mut website = state.website_config.get().clone().unwrap_or_default();
+			website.index_document = wa
+				.index_document
+				.ok_or_bad_request("Please specify indexDocument when enabling website access.")?;
+			website.error_document = wa.error_document;
+			state.website_config.update(Some(website));
 		} else {
 			if wa.index_document.is_some() || wa.error_document.is_some() {
 				return Err(Error::bad_request(
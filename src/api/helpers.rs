@@ -44,7 +44,9 @@ pub fn host_to_bucket<'a>(host: &'a str, root: &str) -> Option<&'a str> {
 /// We do not use the collect pattern as there is no way in std rust to collect over a stack allocated value
 /// check here: <https://docs.rs/collect_slice/1.2.0/collect_slice/>
 pub fn authority_to_host(authority: &str) -> Result<String, Error> {
-	let mut iter = authority.chars().enumerate();
+	// Byte indices are needed to split the authority, as the host
+	// can contain non-ASCII characters (internationalized domain names)
+	let mut iter = authority.char_indices();
 	let (_, first_char) = iter
 		.next()
 		.ok_or_else(|| Error::bad_request("Authority is empty".to_string()))?;
@@ -212,6 +214,10 @@ mod tests {
 		assert_eq!(domain2, "garage.tld");
 		let domain3 = authority_to_host("127.0.0.1:80")?;
 		assert_eq!(domain3, "127.0.0.1");
+		let domain4 = authority_to_host("bücher.garage.tld:8080")?;
+		assert_eq!(domain4, "bücher.garage.tld");
+		let domain5 = authority_to_host("[2001:db8::1]:443")?;
+		assert_eq!(domain5, "[2001:db8::1]");
 		Ok(())
 	}
 
@@ -173,7 +173,17 @@ impl ApiHandler for S3ApiServer {
 			} => handle_head(garage, &req, bucket_id, &key, part_number).await,
 			Endpoint::GetObject {
 				key, part_number, ..
-			} => handle_get(garage, &req, bucket_id, &key, part_number).await,
+			} => {
+				handle_get(
+					garage,
+					&req,
+					bucket_id,
+					&key,
+					part_number,
+					GET_PREFETCH_BLOCKS,
+				)
+				.await
+			}
 			Endpoint::UploadPart {
 				key,
 				part_number,
@@ -3,10 +3,10 @@ use std::sync::Arc;
 use std::time::{Duration, UNIX_EPOCH};
 
 use futures::future;
-use futures::stream::{self, StreamExt};
+use futures::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
 use http::header::{
-	ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
-	IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+	ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE,
+	IF_NONE_MATCH, IF_UNMODIFIED_SINCE, LAST_MODIFIED, RANGE,
 };
 use hyper::{Body, Request, Response, StatusCode};
 use tokio::sync::mpsc;
@@ -14,7 +14,6 @@ use tokio::sync::mpsc;
 use garage_rpc::rpc_helper::{netapp::stream::ByteStream, OrderTag};
 use garage_table::EmptyKey;
 use garage_util::data::*;
-use garage_util::error::OkOrMessage;
 
 use garage_model::garage::Garage;
 use garage_model::s3::object_table::*;
@@ -24,18 +23,18 @@ use crate::s3::error::*;
 
 const X_AMZ_MP_PARTS_COUNT: &str = "x-amz-mp-parts-count";
 
+/// Number of blocks of an object that are fetched ahead of the one being sent
+pub const GET_PREFETCH_BLOCKS: usize = 2;
+
 fn object_headers(
 	version: &ObjectVersion,
 	version_meta: &ObjectVersionMeta,
 ) -> http::response::Builder {
 	debug!("Version meta: {:?}", version_meta);
 
-	let date = UNIX_EPOCH + Duration::from_millis(version.timestamp);
-	let date_str = httpdate::fmt_http_date(date);
-
 	let mut resp = Response::builder()
 		.header(CONTENT_TYPE, version_meta.headers.content_type.to_string())
-		.header(LAST_MODIFIED, date_str)
+		.header(LAST_MODIFIED, last_modified(version))
 		.header(ACCEPT_RANGES, "bytes".to_string());
 
 	if !version_meta.etag.is_empty() {
@@ -49,6 +48,10 @@ fn object_headers(
 	resp
 }
 
+fn last_modified(version: &ObjectVersion) -> String {
+	httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_millis(version.timestamp))
+}
+
 fn try_answer_cached(
 	version: &ObjectVersion,
 	version_meta: &ObjectVersionMeta,
@@ -62,32 +65,86 @@ fn try_answer_cached(
 	let cached = if let Some(none_match) = req.headers().get(IF_NONE_MATCH) {
 		let none_match = none_match.to_str().ok()?;
 		let expected = format!("\"{}\"", version_meta.etag);
-		let found = none_match
-			.split(',')
-			.map(str::trim)
-			.any(|etag| etag == expected || etag == "\"*\"");
+		// If-None-Match uses the weak comparison (rfc7232, section 3.2)
+		let found = none_match.split(',').map(str::trim).any(|etag| {
+			etag.strip_prefix("W/").unwrap_or(etag) == expected || etag == "*" || etag == "\"*\""
+		});
 		found
 	} else if let Some(modified_since) = req.headers().get(IF_MODIFIED_SINCE) {
 		let modified_since = modified_since.to_str().ok()?;
 		let client_date = httpdate::parse_http_date(modified_since).ok()?;
-		let server_date = UNIX_EPOCH + Duration::from_millis(version.timestamp);
+		// Last-Modified is sent with a precision of one second,
+		// so the comparison is done at that precision too
+		let server_date = UNIX_EPOCH + Duration::from_secs(version.timestamp / 1000);
 		client_date >= server_date
 	} else {
 		false
 	};
 
 	if cached {
-		Some(
-			Response::builder()
-				.status(StatusCode::NOT_MODIFIED)
-				.body(Body::empty())
-				.unwrap(),
-		)
+		// A 304 response has the validators that a 200 response
+		// would have had (rfc7232, section 4.1)
+		let mut resp = Response::builder()
+			.status(StatusCode::NOT_MODIFIED)
+			.header(LAST_MODIFIED, last_modified(version));
+		if !version_meta.etag.is_empty() {
+			resp = resp.header(ETAG, format!("\"{}\"", version_meta.etag));
+		}
+		for (k, v) in version_meta.headers.other.iter() {
+			if k.eq_ignore_ascii_case("cache-control") || k.eq_ignore_ascii_case("expires") {
+				resp = resp.header(k, v.to_string());
+			}
+		}
+		Some(resp.body(Body::empty()).unwrap())
 	} else {
 		None
 	}
 }
 
+/// Check the If-Match and If-Unmodified-Since preconditions, which make the
+/// request fail if the object is not the one the client expects. As with
+/// the cache headers above, If-Match takes precedence over If-Unmodified-Since
+/// (rfc7232, section 6), and these two are evaluated first.
+fn check_preconditions(
+	version: &ObjectVersion,
+	version_meta: &ObjectVersionMeta,
+	req: &Request<Body>,
+) -> Result<(), Error> {
+	let holds = if let Some(if_match) = req.headers().get(IF_MATCH) {
+		let expected = format!("\"{}\"", version_meta.etag);
+		match if_match.to_str() {
+			Ok(if_match) => if_match
+				.split(',')
+				.map(str::trim)
+				.any(|etag| etag == expected || etag == "*"),
+			Err(_) => false,
+		}
+	} else if let Some(unmodified_since) = req.headers().get(IF_UNMODIFIED_SINCE) {
+		// An invalid date means the header is ignored
+		match unmodified_since
+			.to_str()
+			.ok()
+			.and_then(|x| httpdate::parse_http_date(x).ok())
+		{
+			Some(client_date) => {
+				// Last-Modified is sent with a precision of one second,
+				// so the comparison is done at that precision too
+				let server_date = UNIX_EPOCH + Duration::from_secs(version.timestamp / 1000);
+				server_date <= client_date
+			}
+			None => true,
+		}
+	} else {
+		true
+	};
+
+	if holds {
+		Ok(())
+	} else {
+		Err(Error::PreconditionFailed)
+	}
+}
+
 /// Handle HEAD request
 pub async fn handle_head(
 	garage: Arc<Garage>,
@@ -120,6 +177,7 @@ pub async fn handle_head(
 		_ => unreachable!(),
 	};
 
+	check_preconditions(object_version, version_meta, req)?;
 	if let Some(cached) = try_answer_cached(object_version, version_meta, req) {
 		return Ok(cached);
 	}
@@ -183,6 +241,7 @@ pub async fn handle_get(
 	bucket_id: Uuid,
 	key: &str,
 	part_number: Option<u64>,
+	prefetch_blocks: usize,
 ) -> Result<Response<Body>, Error> {
 	let object = garage
 		.object_table
@@ -207,6 +266,7 @@ pub async fn handle_get(
 		ObjectVersionData::FirstBlock(meta, _) => meta,
 	};
 
+	check_preconditions(last_v, last_v_meta, req)?;
 	if let Some(cached) = try_answer_cached(last_v, last_v_meta, req) {
 		return Ok(cached);
 	}
@@ -245,63 +305,107 @@ pub async fn handle_get(
 			Ok(resp_builder.body(body)?)
 		}
 		ObjectVersionData::FirstBlock(_, first_block_hash) => {
-			let (tx, rx) = mpsc::channel(2);
-
 			let order_stream = OrderTag::stream();
 			let first_block_hash = *first_block_hash;
 			let version_uuid = last_v.uuid;
 
-			tokio::spawn(async move {
-				match async {
-					let garage2 = garage.clone();
-					let version_fut = tokio::spawn(async move {
-						garage2.version_table.get(&version_uuid, &EmptyKey).await
-					});
-
-					let stream_block_0 = garage
-						.block_manager
-						.rpc_get_block_streaming(&first_block_hash, Some(order_stream.order(0)))
-						.await?;
-					tx.send(stream_block_0)
-						.await
-						.ok_or_message("channel closed")?;
+			let garage2 = garage.clone();
+			let version_fut =
+				tokio::spawn(
+					async move { garage2.version_table.get(&version_uuid, &EmptyKey).await },
+				);
 
+			let garage2 = garage.clone();
+			let first_block = stream::once(async move {
+				garage2
+					.block_manager
+					.rpc_get_block_streaming(&first_block_hash, Some(order_stream.order(0)))
+					.await
+					.map_err(Error::from)
+			});
+			let other_blocks =
+				stream::once(async move {
 					let version = version_fut.await.unwrap()?.ok_or(Error::NoSuchKey)?;
-					for (i, (_, vb)) in version.blocks.items().iter().enumerate().skip(1) {
-						let stream_block_i = garage
-							.block_manager
-							.rpc_get_block_streaming(&vb.hash, Some(order_stream.order(i as u64)))
-							.await?;
-						tx.send(stream_block_i)
-							.await
-							.ok_or_message("channel closed")?;
-					}
-
-					Ok::<(), Error>(())
+					let hashes = version
+						.blocks
+						.items()
+						.iter()
+						.map(|(_, vb)| vb.hash)
+						.collect::<Vec<_>>();
+					Ok::<_, Error>(stream::iter(hashes).enumerate().skip(1).then(
+						move |(i, hash)| {
+							let garage = garage.clone();
+							async move {
+								garage
+									.block_manager
+									.rpc_get_block_streaming(
+										&hash,
+										Some(order_stream.order(i as u64)),
+									)
+									.await
+									.map_err(Error::from)
+							}
+						},
+					))
+				})
+				.try_flatten();
+
+			// The body ends with the first error
+			let block_streams = first_block.chain(other_blocks).scan(false, |failed, res| {
+				if *failed {
+					return future::ready(None);
 				}
-				.await
-				{
-					Ok(()) => (),
+				let block_stream: ByteStream = match res {
+					Ok(block_stream) => block_stream,
 					Err(e) => {
+						*failed = true;
 						let err = std::io::Error::new(
 							std::io::ErrorKind::Other,
 							format!("Error while getting object data: {}", e),
 						);
-						let _ = tx
-							.send(Box::pin(stream::once(future::ready(Err(err)))))
-							.await;
+						Box::pin(stream::once(future::ready(Err(err))))
 					}
-				}
+				};
+				future::ready(Some(block_stream))
 			});
 
-			let body_stream = tokio_stream::wrappers::ReceiverStream::new(rx).flatten();
-
+			let body_stream = chain_prefetched(block_streams, prefetch_blocks);
 			let body = hyper::body::Body::wrap_stream(body_stream);
 			Ok(resp_builder.body(body)?)
 		}
 	}
 }
 
+/// Chain the streams of `streams`, opening at most `prefetch` of them ahead
+/// of the one being read, so that the data held for a slow client is
+/// bounded. The streams are opened by a background task if `prefetch` is
+/// not zero, and only once the previous one is done otherwise.
+fn chain_prefetched<S>(
+	streams: S,
+	prefetch: usize,
+) -> BoxStream<'static, Result<hyper::body::Bytes, std::io::Error>>
+where
+	S: Stream<Item = ByteStream> + Send + 'static,
+{
+	if prefetch == 0 {
+		return Box::pin(streams.flatten());
+	}
+
+	let (tx, rx) = mpsc::channel(prefetch);
+	tokio::spawn(async move {
+		let mut streams = Box::pin(streams);
+		// Room is reserved in the channel before a stream is opened,
+		// so that at most `prefetch` opened streams wait in it
+		while let Ok(permit) = tx.reserve().await {
+			match streams.next().await {
+				Some(stream) => permit.send(stream),
+				None => break,
+			}
+		}
+	});
+	Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx).flatten())
+}
+
 async fn handle_get_range(
 	garage: Arc<Garage>,
 	version: &ObjectVersion,
@@ -521,3 +625,59 @@ fn error_stream(i: usize, e: garage_util::error::Error) -> ByteStream {
 		))
 	}))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	/// Counts the streams that are dropped
+	struct DropCounter(Arc<AtomicUsize>);
+
+	impl Drop for DropCounter {
+		fn drop(&mut self) {
+			self.0.fetch_add(1, Ordering::SeqCst);
+		}
+	}
+
+	#[tokio::test]
+	async fn chain_prefetched_test() {
+		const BLOCK_SIZE: usize = 1000;
+
+		for prefetch in [0, 1, 2] {
+			let opened = Arc::new(AtomicUsize::new(0));
+			let dropped = Arc::new(AtomicUsize::new(0));
+			let (opened2, dropped2) = (opened.clone(), dropped.clone());
+			let streams = stream::iter(0..10).map(move |_| {
+				opened2.fetch_add(1, Ordering::SeqCst);
+				let counter = DropCounter(dropped2.clone());
+				let chunks = vec![Ok(hyper::body::Bytes::from(vec![0u8; BLOCK_SIZE]))];
+				Box::pin(stream::iter(chunks).map(move |chunk| {
+					let _ = &counter;
+					chunk
+				})) as ByteStream
+			});
+			let mut body = chain_prefetched(streams, prefetch);
+
+			// The consumer reads a single chunk and stalls: only the block
+			// being sent and `prefetch` blocks are held in memory
+			let first = body.next().await.unwrap().unwrap();
+			assert_eq!(first.len(), BLOCK_SIZE);
+			tokio::time::sleep(Duration::from_millis(100)).await;
+			let held_bytes =
+				(opened.load(Ordering::SeqCst) - dropped.load(Ordering::SeqCst)) * BLOCK_SIZE;
+			assert_eq!(
+				held_bytes,
+				(prefetch + 1) * BLOCK_SIZE,
+				"prefetch {}",
+				prefetch
+			);
+
+			let mut total = first.len();
+			while let Some(chunk) = body.next().await {
+				total += chunk.unwrap().len();
+			}
+			assert_eq!(total, 10 * BLOCK_SIZE);
+		}
+	}
+}
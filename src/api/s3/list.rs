@@ -61,27 +61,30 @@ pub struct ListPartsQuery {
 	pub max_parts: u64,
 }
 
+/// Object of a directory listing
+#[derive(Debug)]
+pub struct DirectoryEntry {
+	pub key: String,
+	pub size: u64,
+	pub last_modified: u64,
+}
+
+/// Objects and common prefixes found directly under a prefix,
+/// using / as the delimiter
+#[derive(Debug)]
+pub struct DirectoryListing {
+	pub common_prefixes: Vec<String>,
+	pub objects: Vec<DirectoryEntry>,
+	/// Whether there were more than the requested number of entries
+	pub truncated: bool,
+}
+
 pub async fn handle_list(
 	garage: Arc<Garage>,
 	query: &ListObjectsQuery,
 ) -> Result<Response<Body>, Error> {
-	let io = |bucket, key, count| {
-		let t = &garage.object_table;
-		async move {
-			t.get_range(
-				&bucket,
-				key,
-				Some(ObjectFilter::IsData),
-				count,
-				EnumerationOrder::Forward,
-			)
-			.await
-		}
-	};
-
 	debug!("ListObjects {:?}", query);
-	let mut acc = query.build_accumulator();
-	let pagination = fetch_list_entries(&query.common, query.begin()?, &mut acc, &io).await?;
+	let (acc, pagination) = list_objects(&garage, query).await?;
 
 	let result = s3_xml::ListBucketResult {
 		xmlns: (),
@@ -166,6 +169,47 @@ pub async fn handle_list(
 		.body(Body::from(xml.into_bytes()))?)
 }
 
+/// List the objects and common prefixes directly under `prefix`,
+/// at most `max_entries` of them in total
+pub async fn list_directory(
+	garage: &Garage,
+	bucket_id: Uuid,
+	prefix: &str,
+	max_entries: usize,
+) -> Result<DirectoryListing, Error> {
+	let query = ListObjectsQuery {
+		is_v2: true,
+		marker: None,
+		continuation_token: None,
+		start_after: None,
+		common: ListQueryCommon {
+			bucket_name: String::new(),
+			bucket_id,
+			delimiter: Some("/".to_string()),
+			page_size: max_entries,
+			prefix: prefix.to_string(),
+			urlencode_resp: false,
+		},
+	};
+
+	debug!("ListDirectory {:?}", query);
+	let (acc, pagination) = list_objects(garage, &query).await?;
+
+	Ok(DirectoryListing {
+		common_prefixes: acc.common_prefixes.into_iter().collect(),
+		objects: acc
+			.keys
+			.into_iter()
+			.map(|(key, info)| DirectoryEntry {
+				key,
+				size: info.size,
+				last_modified: info.last_modified,
+			})
+			.collect(),
+		truncated: pagination.is_some(),
+	})
+}
+
 pub async fn handle_list_multipart_upload(
 	garage: Arc<Garage>,
 	query: &ListMultipartUploadsQuery,
@@ -382,6 +426,29 @@ type Pagination = Option<RangeBegin>;
  * Fetch list entries
  */
 
+async fn list_objects(
+	garage: &Garage,
+	query: &ListObjectsQuery,
+) -> Result<(ObjectAccumulator, Pagination), Error> {
+	let io = |bucket, key, count| {
+		let t = &garage.object_table;
+		async move {
+			t.get_range(
+				&bucket,
+				key,
+				Some(ObjectFilter::IsData),
+				count,
+				EnumerationOrder::Forward,
+			)
+			.await
+		}
+	};
+
+	let mut acc = query.build_accumulator();
+	let pagination = fetch_list_entries(&query.common, query.begin()?, &mut acc, &io).await?;
+	Ok((acc, pagination))
+}
+
 async fn fetch_list_entries<R, F>(
 	query: &ListQueryCommon,
 	begin: RangeBegin,
@@ -6,7 +6,7 @@ mod copy;
 pub mod cors;
 mod delete;
 pub mod get;
-mod list;
+pub mod list;
 mod post_object;
 mod put;
 mod website;
@@ -18,16 +18,38 @@ pub async fn handle_get_website(bucket: &Bucket) -> Result<Response<Body>, Error
 		.ok_or_internal_error("Bucket should not be deleted at this point")?;
 
 	if let Some(website) = param.website_config.get() {
-		let wc = WebsiteConfiguration {
-			xmlns: (),
-			error_document: website.error_document.as_ref().map(|v| Key {
-				key: Value(v.to_string()),
-			}),
-			index_document: Some(Suffix {
-				suffix: Value(website.index_document.to_string()),
-			}),
-			redirect_all_requests_to: None,
-			routing_rules: None,
+		let wc = match &website.redirect_all {
+			Some(redirect_all) => WebsiteConfiguration {
+				xmlns: (),
+				error_document: None,
+				index_document: None,
+				redirect_all_requests_to: Some(Target {
+					hostname: Value(redirect_all.hostname.clone()),
+					protocol: redirect_all.protocol.clone().map(Value),
+				}),
+				routing_rules: None,
+			},
+			None => WebsiteConfiguration {
+				xmlns: (),
+				error_document: website.error_document.as_ref().map(|v| Key {
+					key: Value(v.to_string()),
+				}),
+				index_document: Some(Suffix {
+					suffix: Value(website.index_document.to_string()),
+				}),
+				redirect_all_requests_to: None,
+				routing_rules: if website.routing_rules.is_empty() {
+					None
+				} else {
+					Some(
+						website
+							.routing_rules
+							.iter()
+							.map(RoutingRule::from_garage_routing_rule)
+							.collect(),
+					)
+				},
+			},
 		};
 		let xml = to_xml_with_header(&wc)?;
 		Ok(Response::builder()
@@ -82,9 +104,8 @@ pub async fn handle_put_website(
 	let conf: WebsiteConfiguration = from_reader(&body as &[u8])?;
 	conf.validate()?;
 
-	param
-		.website_config
-		.update(Some(conf.into_garage_website_config()?));
+	let website = conf.into_garage_website_config(param.website_config.get().as_ref())?;
+	param.website_config.update(Some(website));
 	garage.bucket_table.insert(&bucket).await?;
 
 	Ok(Response::builder()
@@ -191,23 +212,71 @@ impl WebsiteConfiguration {
 		Ok(())
 	}
 
-	pub fn into_garage_website_config(self) -> Result<WebsiteConfig, Error> {
-		if self.redirect_all_requests_to.is_some() {
-			Err(Error::NotImplemented(
-				"S3 website redirects are not currently implemented in Garage.".into(),
-			))
-		} else if self.routing_rules.map(|x| !x.is_empty()).unwrap_or(false) {
-			Err(Error::NotImplemented(
-				"S3 routing rules are not currently implemented in Garage.".into(),
-			))
-		} else {
-			Ok(WebsiteConfig {
-				index_document: self
-					.index_document
-					.map(|x| x.suffix.0)
-					.unwrap_or_else(|| "index.html".to_string()),
-				error_document: self.error_document.map(|x| x.key.0),
-			})
+	/// Build the website configuration of a bucket from this S3 configuration,
+	/// keeping the settings of the existing configuration that cannot be
+	/// expressed through the S3 API
+	pub fn into_garage_website_config(
+		self,
+		existing: Option<&WebsiteConfig>,
+	) -> Result<WebsiteConfig, Error> {
+		let mut config = existing.cloned().unwrap_or_default();
+		config.index_document = self
+			.index_document
+			.map(|x| x.suffix.0)
+			.unwrap_or_else(|| "index.html".to_string());
+		config.error_document = self.error_document.map(|x| x.key.0);
+		config.redirect_all = self.redirect_all_requests_to.map(|x| RedirectAll {
+			hostname: x.hostname.0,
+			protocol: x.protocol.map(|p| p.0),
+		});
+		config.routing_rules = self
+			.routing_rules
+			.unwrap_or_default()
+			.into_iter()
+			.map(|x| x.inner.into_garage_routing_rule())
+			.collect();
+		Ok(config)
+	}
+}
+
+impl RoutingRule {
+	fn from_garage_routing_rule(rule: &garage_model::bucket_table::RoutingRule) -> Self {
+		RoutingRule {
+			inner: RoutingRuleInner {
+				condition: rule.condition.as_ref().map(|c| Condition {
+					http_error_code: c.http_error_code.map(|x| IntValue(x as i64)),
+					prefix: c.key_prefix.clone().map(Value),
+				}),
+				redirect: Redirect {
+					hostname: rule.redirect.hostname.clone().map(Value),
+					protocol: rule.redirect.protocol.clone().map(Value),
+					http_redirect_code: rule
+						.redirect
+						.http_redirect_code
+						.map(|x| IntValue(x as i64)),
+					replace_prefix: rule.redirect.replace_key_prefix.clone().map(Value),
+					replace_full: rule.redirect.replace_key.clone().map(Value),
+				},
+			},
+		}
+	}
+}
+
+impl RoutingRuleInner {
+	/// Must be called on a validated rule
+	fn into_garage_routing_rule(self) -> garage_model::bucket_table::RoutingRule {
+		garage_model::bucket_table::RoutingRule {
+			condition: self.condition.map(|c| RoutingRuleCondition {
+				http_error_code: c.http_error_code.map(|x| x.0 as u16),
+				key_prefix: c.prefix.map(|x| x.0),
+			}),
+			redirect: RoutingRuleRedirect {
+				hostname: self.redirect.hostname.map(|x| x.0),
+				protocol: self.redirect.protocol.map(|x| x.0),
+				http_redirect_code: self.redirect.http_redirect_code.map(|x| x.0 as u16),
+				replace_key_prefix: self.redirect.replace_prefix.map(|x| x.0),
+				replace_key: self.redirect.replace_full.map(|x| x.0),
+			},
 		}
 	}
 }
@@ -249,6 +318,17 @@ impl Target {
 
 impl RoutingRuleInner {
 	pub fn validate(&self) -> Result<(), Error> {
+		if let Some(IntValue(code)) = self
+			.condition
+			.as_ref()
+			.and_then(|c| c.http_error_code.as_ref())
+		{
+			if !(400..600).contains(code) {
+				return Err(Error::bad_request(
+					"Bad XML: HttpErrorCodeReturnedEquals must be a 4xx or 5xx code",
+				));
+			}
+		}
 		let has_prefix = self
 			.condition
 			.as_ref()
@@ -277,6 +357,13 @@ impl Redirect {
 				return Err(Error::bad_request("Bad XML: invalid protocol"));
 			}
 		}
+		if let Some(IntValue(code)) = &self.http_redirect_code {
+			if !(300..400).contains(code) {
+				return Err(Error::bad_request(
+					"Bad XML: HttpRedirectCode must be a 3xx code",
+				));
+			}
+		}
 		// TODO there are probably more invalide cases, but which ones?
 		Ok(())
 	}
@@ -359,4 +446,47 @@ mod tests {
 
 		Ok(())
 	}
+
+	#[test]
+	fn test_routing_rules() -> Result<(), Error> {
+		let message = r#"<?xml version="1.0" encoding="UTF-8"?>
+<WebsiteConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+   <IndexDocument>
+      <Suffix>index.html</Suffix>
+   </IndexDocument>
+   <RoutingRules>
+      <RoutingRule>
+         <Condition>
+            <KeyPrefixEquals>docs/</KeyPrefixEquals>
+         </Condition>
+         <Redirect>
+            <ReplaceKeyPrefixWith>documents/</ReplaceKeyPrefixWith>
+         </Redirect>
+      </RoutingRule>
+   </RoutingRules>
+</WebsiteConfiguration>"#;
+		let conf: WebsiteConfiguration = from_str(message).unwrap();
+		conf.validate()?;
+		let wc = conf.into_garage_website_config(None)?;
+		assert_eq!(wc.index_document, "index.html");
+		assert_eq!(wc.redirect_all, None);
+		assert_eq!(wc.routing_rules.len(), 1);
+		let rule = &wc.routing_rules[0];
+		assert!(rule.matches("docs/a.html", None));
+		assert_eq!(rule.redirect_key("docs/a.html"), "documents/a.html");
+
+		let bad_code = RoutingRuleInner {
+			condition: None,
+			redirect: Redirect {
+				hostname: None,
+				protocol: None,
+				http_redirect_code: Some(IntValue(200)),
+				replace_prefix: None,
+				replace_full: None,
+			},
+		};
+		assert!(bad_code.validate().is_err());
+
+		Ok(())
+	}
 }
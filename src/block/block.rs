@@ -1,6 +1,9 @@
+use std::io::Write;
+use std::sync::Mutex;
+
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
-use zstd::stream::{decode_all as zstd_decode, Encoder};
+use zstd::stream::{decode_all as zstd_decode, write::Decoder, Encoder};
 
 use garage_util::data::*;
 use garage_util::error::*;
@@ -95,6 +98,60 @@ impl DataBlock {
 	}
 }
 
+/// Verifies the integrity of a block whose content is read in several parts,
+/// in the same way as [`DataBlock::verify`], so that a block can be verified
+/// while it is streamed instead of being loaded in memory
+pub enum DataBlockVerifier {
+	Plain(Blake2Hasher),
+	// The decoder is not Sync, but the streams that are sent to other
+	// nodes must be
+	Compressed(Mutex<Decoder<'static, std::io::Sink>>),
+	Corrupted,
+}
+
+impl DataBlockVerifier {
+	pub fn new(header: DataBlockHeader) -> Self {
+		match header {
+			DataBlockHeader::Plain => DataBlockVerifier::Plain(Blake2Hasher::new()),
+			DataBlockHeader::Compressed => match Decoder::new(std::io::sink()) {
+				Ok(decoder) => DataBlockVerifier::Compressed(Mutex::new(decoder)),
+				Err(_) => DataBlockVerifier::Corrupted,
+			},
+		}
+	}
+
+	/// Add the next part of the content of the block
+	pub fn update(&mut self, data: &[u8]) {
+		let valid = match self {
+			DataBlockVerifier::Plain(hasher) => {
+				hasher.update(data);
+				true
+			}
+			DataBlockVerifier::Compressed(decoder) => {
+				decoder.get_mut().unwrap().write_all(data).is_ok()
+			}
+			DataBlockVerifier::Corrupted => false,
+		};
+		if !valid {
+			*self = DataBlockVerifier::Corrupted;
+		}
+	}
+
+	/// Check the block once all its content has been given
+	pub fn finish(self, hash: Hash) -> Result<(), Error> {
+		let valid = match self {
+			DataBlockVerifier::Plain(hasher) => hasher.finalize() == hash,
+			DataBlockVerifier::Compressed(decoder) => decoder.into_inner().unwrap().flush().is_ok(),
+			DataBlockVerifier::Corrupted => false,
+		};
+		if valid {
+			Ok(())
+		} else {
+			Err(Error::CorruptData(hash))
+		}
+	}
+}
+
 fn zstd_encode<R: std::io::Read>(mut source: R, level: i32) -> std::io::Result<Vec<u8>> {
 	let mut result = Vec::<u8>::new();
 	let mut encoder = Encoder::new(&mut result, level)?;
@@ -103,3 +160,36 @@ fn zstd_encode<R: std::io::Read>(mut source: R, level: i32) -> std::io::Result<V
 	encoder.finish()?;
 	Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn verify_in_parts(header: DataBlockHeader, data: &[u8], hash: Hash) -> Result<(), Error> {
+		let mut verifier = DataBlockVerifier::new(header);
+		for part in data.chunks(100) {
+			verifier.update(part);
+		}
+		verifier.finish(hash)
+	}
+
+	#[test]
+	fn test_verifier() {
+		let data = (0..1000u32)
+			.flat_map(|i| i.to_le_bytes())
+			.collect::<Vec<u8>>();
+		let hash = blake2sum(&data);
+		assert!(verify_in_parts(DataBlockHeader::Plain, &data, hash).is_ok());
+		assert!(verify_in_parts(DataBlockHeader::Plain, &data[1..], hash).is_err());
+
+		let compressed = zstd_encode(&data[..], 3).unwrap();
+		assert!(DataBlock::Compressed(compressed.clone().into())
+			.verify(hash)
+			.is_ok());
+		assert!(verify_in_parts(DataBlockHeader::Compressed, &compressed, hash).is_ok());
+		let mut corrupted = compressed;
+		let mid = corrupted.len() / 2;
+		corrupted[mid] ^= 0xff;
+		assert!(verify_in_parts(DataBlockHeader::Compressed, &corrupted, hash).is_err());
+	}
+}
@@ -4,7 +4,10 @@ extern crate tracing;
 pub mod manager;
 pub mod repair;
 pub mod resync;
+pub mod source;
 
 mod block;
 mod metrics;
 mod rc;
+
+pub use block::{DataBlock, DataBlockHeader};
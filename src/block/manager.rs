@@ -1,5 +1,6 @@
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -9,11 +10,12 @@ use bytes::Bytes;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use futures::Stream;
+use futures::{stream, Stream};
 use futures_util::stream::StreamExt;
 use tokio::fs;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::{mpsc, Mutex, MutexGuard};
+use tokio::sync::{broadcast, mpsc, Mutex, MutexGuard};
+use tokio_util::io::ReaderStream;
 
 use opentelemetry::{
 	trace::{FutureExt as OtelFutureExt, TraceContextExt, Tracer},
@@ -25,11 +27,12 @@ use garage_rpc::rpc_helper::netapp::stream::{stream_asyncread, ByteStream};
 use garage_db as db;
 
 use garage_util::background::{vars, BackgroundRunner};
+use garage_util::config::ResyncScheduleWindow;
 use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::metrics::RecordDuration;
 use garage_util::persister::PersisterShared;
-use garage_util::time::msec_to_rfc3339;
+use garage_util::time::{msec_to_rfc3339, now_msec};
 
 use garage_rpc::rpc_helper::OrderTag;
 use garage_rpc::system::System;
@@ -42,6 +45,7 @@ use crate::metrics::*;
 use crate::rc::*;
 use crate::repair::*;
 use crate::resync::*;
+use crate::source::*;
 
 /// Size under which data will be stored inlined in database instead of as files
 pub const INLINE_THRESHOLD: usize = 3072;
@@ -51,6 +55,9 @@ pub const INLINE_THRESHOLD: usize = 3072;
 // to delete the block locally.
 pub(crate) const BLOCK_GC_DELAY: Duration = Duration::from_secs(600);
 
+// Number of resync events kept for subscribers that are lagging behind
+const RESYNC_EVENTS_CAPACITY: usize = 1024;
+
 /// RPC messages used to share blocks of data between nodes
 #[derive(Debug, Serialize, Deserialize)]
 pub enum BlockRpc {
@@ -94,6 +101,9 @@ pub struct BlockManager {
 
 	pub scrub_persister: PersisterShared<ScrubWorkerPersisted>,
 	tx_scrub_command: ArcSwapOption<mpsc::Sender<ScrubWorkerCommand>>,
+
+	resync_events: broadcast::Sender<ResyncEvent>,
+	recent_resync_events: RecentResyncEvents,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -103,6 +113,33 @@ pub struct BlockResyncErrorInfo {
 	pub error_count: u64,
 	pub last_try: u64,
 	pub next_try: u64,
+	/// Message of the last error, if it was recorded
+	pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockQuarantineInfo {
+	pub hash: Hash,
+	pub refcount: u64,
+	pub write_errors: u64,
+	pub quarantined_at: u64,
+}
+
+/// What resync would do with a block, see `BlockManager::resync_dry_run`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ResyncPlan {
+	pub hash: Hash,
+	pub exists: bool,
+	pub refcount: u64,
+	pub is_deletable: bool,
+	/// The block would be sent to other nodes before being deleted
+	pub would_offload: bool,
+	/// Nodes that answered that they need the block
+	pub offload_to: Vec<Uuid>,
+	pub would_delete: bool,
+	pub would_fetch: bool,
+	/// Why a deletable block would be kept, if it would
+	pub skipped_reason: Option<String>,
 }
 
 // This custom struct contains functions that must only be ran
@@ -115,6 +152,9 @@ impl BlockManager {
 		db: &db::Db,
 		data_dir: PathBuf,
 		compression_level: Option<i32>,
+		resync_schedule: Vec<ResyncScheduleWindow>,
+		resync_max_workers: usize,
+		resync_events_history: usize,
 		replication: TableShardedReplication,
 		system: Arc<System>,
 	) -> Arc<Self> {
@@ -123,7 +163,12 @@ impl BlockManager {
 			.expect("Unable to open block_local_rc tree");
 		let rc = BlockRc::new(rc);
 
-		let resync = BlockResyncManager::new(db, &system);
+		let resync = BlockResyncManager::new(
+			db,
+			&system.metadata_dir,
+			resync_schedule,
+			resync_max_workers,
+		);
 
 		let endpoint = system
 			.netapp
@@ -134,6 +179,7 @@ impl BlockManager {
 			rc.rc.clone(),
 			resync.queue.clone(),
 			resync.errors.clone(),
+			system.id,
 		);
 
 		let scrub_persister = PersisterShared::new(&system.metadata_dir, "scrub_info");
@@ -150,8 +196,13 @@ impl BlockManager {
 			metrics,
 			scrub_persister,
 			tx_scrub_command: ArcSwapOption::new(None),
+			resync_events: broadcast::channel(RESYNC_EVENTS_CAPACITY).0,
+			recent_resync_events: RecentResyncEvents::new(resync_events_history),
 		});
 		block_manager.endpoint.set_handler(block_manager.clone());
+		block_manager
+			.resync
+			.add_block_source(Arc::new(PeerBlockSource(Arc::downgrade(&block_manager))));
 		block_manager.scrub_persister.set_with(|_| ()).unwrap();
 
 		block_manager
@@ -159,10 +210,14 @@ impl BlockManager {
 
 	pub fn spawn_workers(self: &Arc<Self>, bg: &BackgroundRunner) {
 		// Spawn a bunch of resync workers
-		for index in 0..MAX_RESYNC_WORKERS {
+		for index in 0..self.resync.max_workers {
 			let worker = ResyncWorker::new(index, self.clone());
 			bg.spawn_worker(worker);
 		}
+		if !self.resync.schedule.is_empty() {
+			bg.spawn_worker(ResyncScheduleWorker::new(self.clone()));
+		}
+		bg.spawn_worker(ResyncAutoScaleWorker::new(self.clone()));
 
 		// Spawn scrub worker
 		let (scrub_tx, scrub_rx) = mpsc::channel(1);
@@ -250,7 +305,33 @@ impl BlockManager {
 	) -> Result<DataBlock, Error> {
 		let who = self.replication.read_nodes(hash);
 		let who = self.system.rpc.request_order(&who);
+		let (block, _) = self
+			.rpc_get_raw_block_internal(hash, &who, order_tag, false)
+			.await?;
+		Ok(block)
+	}
+
+	/// Ask nodes that might have a (possibly compressed) block for it,
+	/// until one of them returns a valid block. Return the block and
+	/// the node that returned it. This is used by resync, so the nodes
+	/// are asked in an order that spreads the fetches of different
+	/// blocks over all their replicas (see `resync_fetch_order`).
+	pub(crate) async fn rpc_get_raw_block_verified(
+		&self,
+		hash: &Hash,
+	) -> Result<(DataBlock, Uuid), Error> {
+		let who = resync_fetch_order(self.replication.read_nodes(hash), hash, self.system.id);
+		self.rpc_get_raw_block_internal(hash, &who, None, true)
+			.await
+	}
 
+	async fn rpc_get_raw_block_internal(
+		&self,
+		hash: &Hash,
+		who: &[Uuid],
+		order_tag: Option<OrderTag>,
+		verify: bool,
+	) -> Result<(DataBlock, Uuid), Error> {
 		for node in who.iter() {
 			let node_id = NodeID::from(*node);
 			let rpc = self.endpoint.call_streaming(
@@ -275,7 +356,16 @@ impl BlockManager {
 						}
 					};
 					match read_stream_to_end(stream).await {
-						Ok(bytes) => return Ok(DataBlock::from_parts(header, bytes)),
+						Ok(bytes) => {
+							let block = DataBlock::from_parts(header, bytes);
+							if verify {
+								if let Err(e) = block.verify(*hash) {
+									warn!("Node {:?} returned an invalid block {:?}: {}", node, hash, e);
+									continue;
+								}
+							}
+							return Ok((block, *node));
+						}
 						Err(e) => {
 							debug!("Error reading stream from node {:?}: {}", node, e);
 						}
@@ -305,6 +395,7 @@ impl BlockManager {
 		Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + Sync + 'static>>,
 		Error,
 	> {
+		self.resync.record_access(hash);
 		let (header, stream) = self.rpc_get_raw_block_streaming(hash, order_tag).await?;
 		match header {
 			DataBlockHeader::Plain => Ok(stream),
@@ -324,6 +415,7 @@ impl BlockManager {
 		hash: &Hash,
 		order_tag: Option<OrderTag>,
 	) -> Result<Bytes, Error> {
+		self.resync.record_access(hash);
 		self.rpc_get_raw_block(hash, order_tag)
 			.await?
 			.verify_get(*hash)
@@ -363,6 +455,35 @@ impl BlockManager {
 		Ok(self.rc.rc.fast_len()?)
 	}
 
+	/// Subscribe to the events of resync: blocks offloaded, deleted,
+	/// fetched, and resync errors. A subscriber that does not keep up
+	/// misses the oldest events.
+	pub fn subscribe_resync_events(&self) -> broadcast::Receiver<ResyncEvent> {
+		self.resync_events.subscribe()
+	}
+
+	/// The most recent resync events, from the oldest to the most recent,
+	/// with the time at which they happened. Unlike subscribing, this gives
+	/// the events that happened before the call.
+	pub fn recent_resync_events(&self) -> Vec<(u64, ResyncEvent)> {
+		self.recent_resync_events.list()
+	}
+
+	/// Send a resync event to the subscribers and keep it in the history
+	/// of recent events. The event is only built if it is used.
+	pub(crate) fn emit_resync_event(&self, event: impl FnOnce() -> ResyncEvent) {
+		let has_subscribers = self.resync_events.receiver_count() > 0;
+		if !has_subscribers && !self.recent_resync_events.is_enabled() {
+			return;
+		}
+		let event = event();
+		if has_subscribers {
+			// (an error means that all subscribers have just left)
+			let _ = self.resync_events.send(event.clone());
+		}
+		self.recent_resync_events.push(now_msec(), event);
+	}
+
 	/// Send command to start/stop/manager scrub worker
 	pub async fn send_scrub_command(&self, cmd: ScrubWorkerCommand) -> Result<(), Error> {
 		let tx = self.tx_scrub_command.load();
@@ -378,24 +499,100 @@ impl BlockManager {
 
 	/// List all resync errors
 	pub fn list_resync_errors(&self) -> Result<Vec<BlockResyncErrorInfo>, Error> {
-		let mut blocks = Vec::with_capacity(self.resync.errors.len());
-		for ent in self.resync.errors.iter()? {
-			let (hash, cnt) = ent?;
-			let cnt = ErrorCounter::decode(&cnt);
+		self.list_resync_errors_page(None, usize::MAX)
+	}
+
+	/// List at most `limit` resync errors, starting after the block `start`
+	/// (in the order of block hashes), so that the list can be paged through
+	pub fn list_resync_errors_page(
+		&self,
+		start: Option<Hash>,
+		limit: usize,
+	) -> Result<Vec<BlockResyncErrorInfo>, Error> {
+		let backoff = self.resync.retry_backoff();
+		let mut blocks = vec![];
+		for (hash, cnt) in self.resync.list_errors(start, limit)? {
 			blocks.push(BlockResyncErrorInfo {
-				hash: Hash::try_from(&hash).unwrap(),
-				refcount: 0,
+				hash,
+				refcount: self.get_block_rc(&hash)?,
 				error_count: cnt.errors,
 				last_try: cnt.last_try,
-				next_try: cnt.next_try(),
+				next_try: cnt.next_try(&hash, &backoff),
+				last_error: cnt.last_error,
 			});
 		}
-		for block in blocks.iter_mut() {
-			block.refcount = self.get_block_rc(&block.hash)?;
+		Ok(blocks)
+	}
+
+	/// List all blocks that were quarantined because they could not be written to disk
+	pub fn list_resync_quarantine(&self) -> Result<Vec<BlockQuarantineInfo>, Error> {
+		let mut blocks = vec![];
+		for (hash, cnt) in self.resync.list_quarantine()? {
+			blocks.push(BlockQuarantineInfo {
+				hash,
+				refcount: self.get_block_rc(&hash)?,
+				write_errors: cnt.errors,
+				quarantined_at: cnt.last_try,
+			});
 		}
 		Ok(blocks)
 	}
 
+	/// Find out what resync would do with a block now, without doing it:
+	/// the other nodes are asked whether they need the block, but nothing
+	/// is sent to them, and the block is neither written nor deleted.
+	pub async fn resync_dry_run(&self, hash: &Hash) -> Result<ResyncPlan, Error> {
+		let explanation = self.resync.explain(self, hash, false).await?;
+		let mut plan = ResyncPlan {
+			hash: *hash,
+			exists: explanation.exists,
+			refcount: explanation.refcount,
+			is_deletable: explanation.is_deletable,
+			would_offload: false,
+			offload_to: vec![],
+			would_delete: false,
+			would_fetch: explanation.action == ResyncAction::Fetch,
+			skipped_reason: None,
+		};
+
+		// The block is only deleted if resync would do it now: a deletable
+		// block is kept while it waits for its deletion delay or for the
+		// backoff of a previous error, or if it is quarantined
+		match explanation.action {
+			ResyncAction::OffloadAndDelete => (),
+			ResyncAction::WaitForDeletionDelay => {
+				plan.skipped_reason = Some("its minimum deletion delay has not passed".into());
+				return Ok(plan);
+			}
+			ResyncAction::WaitForBackoff if explanation.is_deletable => {
+				plan.skipped_reason = Some("a previous resync failed, waiting to retry".into());
+				return Ok(plan);
+			}
+			ResyncAction::Quarantined if explanation.is_deletable => {
+				plan.skipped_reason = Some("it is quarantined".into());
+				return Ok(plan);
+			}
+			_ => return Ok(plan),
+		}
+
+		let mut who = self.replication.write_nodes(hash);
+		if who.len() < self.replication.write_quorum() {
+			plan.skipped_reason = Some("we don't have a quorum of nodes to write to".into());
+			return Ok(plan);
+		}
+		who.retain(|id| *id != self.system.id);
+
+		for (node, needed) in query_need_block(self, &who, hash, PRIO_NORMAL).await? {
+			if needed.err_context("NeedBlockQuery RPC")? {
+				plan.offload_to.push(node);
+			}
+		}
+		plan.would_offload = !plan.offload_to.is_empty();
+		plan.would_delete = true;
+
+		Ok(plan)
+	}
+
 	//// ----- Managing the reference counter ----
 
 	/// Increment the number of time a block is used, putting it to resynchronization if it is
@@ -405,6 +602,7 @@ impl BlockManager {
 		tx: &mut db::Transaction,
 		hash: Hash,
 	) -> db::TxOpResult<()> {
+		self.resync.invalidate_block_status(&hash);
 		if self.rc.block_incref(tx, &hash)? {
 			// When the reference counter is incremented, there is
 			// normally a node that is responsible for sending us the
@@ -431,6 +629,7 @@ impl BlockManager {
 		tx: &mut db::Transaction,
 		hash: Hash,
 	) -> db::TxOpResult<()> {
+		self.resync.invalidate_block_status(&hash);
 		if self.rc.block_decref(tx, &hash)? {
 			// When the RC is decremented, it might drop to zero,
 			// indicating that we don't need the block.
@@ -441,7 +640,7 @@ impl BlockManager {
 			tokio::spawn(async move {
 				if let Err(e) = this
 					.resync
-					.put_to_resync(&hash, BLOCK_GC_DELAY + Duration::from_secs(10))
+					.put_to_resync_deletable(&hash, BLOCK_GC_DELAY + Duration::from_secs(10))
 				{
 					error!("Block {:?} could not be put in resync queue: {}.", hash, e);
 				}
@@ -485,6 +684,7 @@ impl BlockManager {
 	}
 
 	async fn handle_get_block(&self, hash: &Hash, order_tag: Option<OrderTag>) -> Resp<BlockRpc> {
+		self.resync.record_access(hash);
 		let block = match self.read_block(hash).await {
 			Ok(data) => data,
 			Err(e) => return Resp::new(Err(e)),
@@ -546,19 +746,81 @@ impl BlockManager {
 		};
 
 		if data.verify(*hash).is_err() {
-			self.metrics.corruption_counter.add(1);
-
-			self.lock_mutate(hash)
-				.await
-				.move_block_to_corrupted(hash, self)
-				.await?;
-			self.resync.put_to_resync(hash, Duration::from_millis(0))?;
+			self.handle_corrupted_block(hash).await?;
 			return Err(Error::CorruptData(*hash));
 		}
 
 		Ok(data)
 	}
 
+	/// Read a block from disk as a stream, so that it can be sent to another
+	/// node without being loaded in memory. Returns the header of the block,
+	/// its size on disk and its content. The content is verified while it is
+	/// read: if the block is corrupted, the stream ends with an error and
+	/// `corrupted` is set, the caller must then call `handle_corrupted_block`.
+	pub(crate) async fn read_block_stream(
+		&self,
+		hash: &Hash,
+		corrupted: &Arc<AtomicBool>,
+	) -> Result<(DataBlockHeader, u64, ByteStream), Error> {
+		let mut path = self.block_path(hash);
+		let header = if self.is_block_compressed(hash).await? {
+			path.set_extension("zst");
+			DataBlockHeader::Compressed
+		} else {
+			DataBlockHeader::Plain
+		};
+		let f = fs::File::open(&path).await?;
+		let size = f.metadata().await?.len();
+		self.metrics.bytes_read.add(size);
+
+		let hash = *hash;
+		let corrupted = corrupted.clone();
+		let state = Some((ReaderStream::new(f), DataBlockVerifier::new(header)));
+		let stream = stream::unfold(state, move |state| {
+			let corrupted = corrupted.clone();
+			async move {
+				let (mut reader, mut verifier) = state?;
+				match reader.next().await {
+					Some(Ok(bytes)) => {
+						verifier.update(&bytes);
+						Some((Ok(bytes), Some((reader, verifier))))
+					}
+					Some(Err(e)) => Some((Err(e), None)),
+					None => match verifier.finish(hash) {
+						Ok(()) => None,
+						Err(e) => {
+							corrupted.store(true, Ordering::Relaxed);
+							let e =
+								std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string());
+							Some((Err(e), None))
+						}
+					},
+				}
+			}
+		});
+
+		Ok((header, size, Box::pin(stream)))
+	}
+
+	/// Move a block whose content is corrupted out of the way, and queue it
+	/// to be fetched again from other nodes
+	pub(crate) async fn handle_corrupted_block(&self, hash: &Hash) -> Result<(), Error> {
+		self.metrics.corruption_counter.add(1);
+
+		self.lock_mutate(hash)
+			.await
+			.move_block_to_corrupted(hash, self)
+			.await?;
+		// The block is still needed, but now absent
+		self.resync.put_to_resync_with_priority(
+			hash,
+			Duration::from_millis(0),
+			ResyncPriority::High,
+		)?;
+		Ok(())
+	}
+
 	/// Check if this node has a block and whether it needs it
 	pub(crate) async fn check_block_status(&self, hash: &Hash) -> Result<BlockStatus, Error> {
 		self.lock_mutate(hash)
@@ -657,6 +919,7 @@ impl StreamingEndpointHandler<BlockRpc> for BlockManager {
 	}
 }
 
+#[derive(Clone, Copy)]
 pub(crate) struct BlockStatus {
 	pub(crate) exists: bool,
 	pub(crate) needed: RcEntry,
@@ -717,6 +980,7 @@ impl BlockManagerLocked {
 		drop(f);
 
 		fs::rename(path_tmp, path).await?;
+		mgr.resync.invalidate_block_status(hash);
 
 		delete_on_drop.cancel();
 
@@ -754,6 +1018,8 @@ impl BlockManagerLocked {
 			path2.set_extension("corrupted");
 		}
 		fs::rename(path, path2).await?;
+		mgr.resync.invalidate_block_status(hash);
+		mgr.resync.abort_in_flight(hash);
 		Ok(())
 	}
 
@@ -766,6 +1032,8 @@ impl BlockManagerLocked {
 				path.set_extension("zst");
 			}
 			fs::remove_file(path).await?;
+			mgr.resync.invalidate_block_status(hash);
+			mgr.resync.abort_in_flight(hash);
 			mgr.metrics.delete_counter.add(1);
 		}
 		Ok(())
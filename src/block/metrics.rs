@@ -1,20 +1,33 @@
-use opentelemetry::{global, metrics::*};
+use opentelemetry::{global, metrics::*, KeyValue};
 
 use garage_db as db;
 use garage_db::counted_tree_hack::CountedTree;
 
+use garage_util::data::Uuid;
+use garage_util::time::now_msec;
+
+use crate::resync::oldest_queued_at;
+
 /// TableMetrics reference all counter used for metrics
 pub struct BlockManagerMetrics {
 	pub(crate) _compression_level: ValueObserver<u64>,
 	pub(crate) _rc_size: ValueObserver<u64>,
 	pub(crate) _resync_queue_len: ValueObserver<u64>,
 	pub(crate) _resync_errored_blocks: ValueObserver<u64>,
+	pub(crate) _resync_queue_oldest_age: ValueObserver<u64>,
 
 	pub(crate) resync_counter: BoundCounter<u64>,
 	pub(crate) resync_error_counter: BoundCounter<u64>,
+	pub(crate) resync_recovered_counter: BoundCounter<u64>,
+	pub(crate) resync_underreplicated: BoundCounter<u64>,
+	pub(crate) resync_decision_counter: Counter<u64>,
 	pub(crate) resync_duration: BoundValueRecorder<f64>,
 	pub(crate) resync_send_counter: Counter<u64>,
-	pub(crate) resync_recv_counter: BoundCounter<u64>,
+	pub(crate) resync_recv_counter: Counter<u64>,
+	pub(crate) resync_clock_skew_counter: BoundCounter<u64>,
+	pub(crate) resync_corruption_detected: BoundCounter<u64>,
+	pub(crate) resync_reconcile_removed_counter: BoundCounter<u64>,
+	pub(crate) resync_reconcile_enqueued_counter: BoundCounter<u64>,
 
 	pub(crate) bytes_read: BoundCounter<u64>,
 	pub(crate) block_read_duration: BoundValueRecorder<f64>,
@@ -31,8 +44,15 @@ impl BlockManagerMetrics {
 		rc_tree: db::Tree,
 		resync_queue: CountedTree,
 		resync_errors: CountedTree,
+		node_id: Uuid,
 	) -> Self {
 		let meter = global::meter("garage_model/block");
+		// The resync gauges are tagged with the node id, so that
+		// the health of each node can be told apart on a dashboard
+		let queue_node_tag = KeyValue::new("node", format!("{:?}", node_id));
+		let errors_node_tag = queue_node_tag.clone();
+		let oldest_node_tag = queue_node_tag.clone();
+		let oldest_queue = resync_queue.clone();
 		Self {
 			_compression_level: meter
 				.u64_value_observer("block.compression_level", move |observer| {
@@ -53,7 +73,7 @@ impl BlockManagerMetrics {
 				.init(),
 			_resync_queue_len: meter
 				.u64_value_observer("block.resync_queue_length", move |observer| {
-					observer.observe(resync_queue.len() as u64, &[])
+					observer.observe(resync_queue.len() as u64, &[queue_node_tag.clone()])
 				})
 				.with_description(
 					"Number of block hashes queued for local check and possible resync",
@@ -61,10 +81,22 @@ impl BlockManagerMetrics {
 				.init(),
 			_resync_errored_blocks: meter
 				.u64_value_observer("block.resync_errored_blocks", move |observer| {
-					observer.observe(resync_errors.len() as u64, &[])
+					observer.observe(resync_errors.len() as u64, &[errors_node_tag.clone()])
 				})
 				.with_description("Number of block hashes whose last resync resulted in an error")
 				.init(),
+			_resync_queue_oldest_age: meter
+				.u64_value_observer("block.resync_queue_oldest_age_seconds", move |observer| {
+					let age = match oldest_queued_at(&oldest_queue) {
+						Ok(Some(time)) => now_msec().saturating_sub(time) / 1000,
+						_ => 0,
+					};
+					observer.observe(age, &[oldest_node_tag.clone()])
+				})
+				.with_description(
+					"Time in seconds since the first entry of the resync queue was due",
+				)
+				.init(),
 
 			resync_counter: meter
 				.u64_counter("block.resync_counter")
@@ -76,6 +108,20 @@ impl BlockManagerMetrics {
 				.with_description("Number of calls to resync_block that returned an error")
 				.init()
 				.bind(&[]),
+			resync_recovered_counter: meter
+				.u64_counter("block.resync_recovered_counter")
+				.with_description("Number of blocks resynced successfully after previous resyncs failed")
+				.init()
+				.bind(&[]),
+			resync_underreplicated: meter
+				.u64_counter("block.resync_underreplicated")
+				.with_description("Number of blocks found missing on nodes that need them, in verify-only mode")
+				.init()
+				.bind(&[]),
+			resync_decision_counter: meter
+				.u64_counter("block.resync_decision_counter")
+				.with_description("Number of successful calls to resync_block, by action taken")
+				.init(),
 			resync_duration: meter
 				.f64_value_recorder("block.resync_duration")
 				.with_description("Duration of resync_block operations")
@@ -88,6 +134,25 @@ impl BlockManagerMetrics {
 			resync_recv_counter: meter
 				.u64_counter("block.resync_recv_counter")
 				.with_description("Number of blocks received from other nodes in resync operations")
+				.init(),
+			resync_clock_skew_counter: meter
+				.u64_counter("block.resync_clock_skew_counter")
+				.with_description("Number of resync queue entries scheduled unreasonably far in the future, which were rescheduled immediately")
+				.init()
+				.bind(&[]),
+			resync_corruption_detected: meter
+				.u64_counter("block.resync_corruption_detected")
+				.with_description("Number of blocks found corrupted when reading them to offload them to other nodes")
+				.init()
+				.bind(&[]),
+			resync_reconcile_removed_counter: meter
+				.u64_counter("block.resync_reconcile_removed_counter")
+				.with_description("Number of resync queue entries removed by the reconciliation repair because their block needed no action")
+				.init()
+				.bind(&[]),
+			resync_reconcile_enqueued_counter: meter
+				.u64_counter("block.resync_reconcile_enqueued_counter")
+				.with_description("Number of missing blocks added to the resync queue by the reconciliation repair")
 				.init()
 				.bind(&[]),
 
@@ -5,6 +5,7 @@ use std::time::Duration;
 
 use async_trait::async_trait;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use tokio::fs;
 use tokio::select;
 use tokio::sync::mpsc;
@@ -18,6 +19,7 @@ use garage_util::time::*;
 use garage_util::tranquilizer::Tranquilizer;
 
 use crate::manager::*;
+use crate::resync::{needs_resync, ResyncPriority};
 
 // Full scrub every 25 days with a random element of 10 days mixed in below
 const SCRUB_INTERVAL: Duration = Duration::from_secs(3600 * 24 * 25);
@@ -153,6 +155,289 @@ impl Worker for RepairWorker {
 	}
 }
 
+// ---- ---- ----
+// RECONCILING THE RESYNC QUEUE WITH THE RC TABLE
+// Also a one-shot repair operation. Instead of queueing every block like
+// RepairWorker does, it only corrects the resync queue where it disagrees
+// with the RC table: it removes entries of blocks that need no action,
+// and queues blocks that are needed and missing but are not queued.
+// Its position is persisted, so that if it is interrupted, launching it
+// again resumes where it stopped.
+// ---- ---- ----
+
+const RECONCILE_BATCH_SIZE: usize = 1000;
+const RECONCILE_TRANQUILITY: u32 = 2;
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ReconcileResyncPersisted {
+	/// Last resync queue key that was checked (phase 1)
+	queue_pos: Option<Vec<u8>>,
+	/// Last RC table key that was checked (phase 2)
+	rc_pos: Option<Vec<u8>>,
+	in_phase2: bool,
+	removed: u64,
+	enqueued: u64,
+}
+
+impl garage_util::migrate::InitialFormat for ReconcileResyncPersisted {}
+
+pub struct ReconcileResyncWorker {
+	manager: Arc<BlockManager>,
+	persister: PersisterShared<ReconcileResyncPersisted>,
+	tranquilizer: Tranquilizer,
+}
+
+impl ReconcileResyncWorker {
+	pub fn new(manager: Arc<BlockManager>) -> Self {
+		let persister = PersisterShared::new(&manager.system.metadata_dir, "reconcile_resync_info");
+		Self {
+			manager,
+			persister,
+			tranquilizer: Tranquilizer::new(30),
+		}
+	}
+
+	async fn reconcile_queue_batch(&mut self, pos: Option<Vec<u8>>) -> Result<bool, Error> {
+		let batch = self
+			.manager
+			.resync
+			.queue_batch(pos.as_deref(), RECONCILE_BATCH_SIZE)?;
+		let last_key = match batch.last() {
+			Some((key, _)) => key.clone(),
+			None => return Ok(false),
+		};
+
+		let mut entries = Vec::with_capacity(batch.len());
+		for (key, hash) in batch {
+			let BlockStatus { exists, needed } = self.manager.check_block_status(&hash).await?;
+			entries.push((key, hash, needs_resync(exists, &needed)));
+		}
+		let removed = self.manager.resync.remove_unneeded_entries(&entries)?;
+
+		self.manager
+			.metrics
+			.resync_reconcile_removed_counter
+			.add(removed);
+		self.persister.set_with(|x| {
+			x.queue_pos = Some(last_key);
+			x.removed += removed;
+		})?;
+		Ok(true)
+	}
+
+	async fn reconcile_rc_batch(&mut self, pos: Option<Vec<u8>>) -> Result<bool, Error> {
+		// As in RepairWorker, we first read a batch of hashes and only
+		// then access the DB again for each of them, because of SQLite.
+		let mut batch_of_hashes = vec![];
+		let start_bound = match pos.as_ref() {
+			None => Bound::Unbounded,
+			Some(x) => Bound::Excluded(x.as_slice()),
+		};
+		for entry in self
+			.manager
+			.rc
+			.rc
+			.range::<&[u8], _>((start_bound, Bound::Unbounded))?
+		{
+			let (hash, _) = entry?;
+			batch_of_hashes.push(Hash::try_from(&hash[..]).unwrap());
+			if batch_of_hashes.len() >= RECONCILE_BATCH_SIZE {
+				break;
+			}
+		}
+		let last_hash = match batch_of_hashes.last() {
+			Some(hash) => *hash,
+			None => return Ok(false),
+		};
+
+		let mut enqueued = 0;
+		for hash in batch_of_hashes {
+			if self.manager.resync.is_queued(&hash)? {
+				continue;
+			}
+			let BlockStatus { exists, needed } = self.manager.check_block_status(&hash).await?;
+			if needed.is_nonzero() && !exists {
+				self.manager.resync.put_to_resync_with_priority(
+					&hash,
+					Duration::from_secs(0),
+					ResyncPriority::High,
+				)?;
+				enqueued += 1;
+			}
+		}
+
+		self.manager
+			.metrics
+			.resync_reconcile_enqueued_counter
+			.add(enqueued);
+		self.persister.set_with(|x| {
+			x.rc_pos = Some(last_hash.to_vec());
+			x.enqueued += enqueued;
+		})?;
+		Ok(true)
+	}
+}
+
+#[async_trait]
+impl Worker for ReconcileResyncWorker {
+	fn name(&self) -> String {
+		"Resync queue reconciliation worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let (in_phase2, removed, enqueued) = self
+			.persister
+			.get_with(|x| (x.in_phase2, x.removed, x.enqueued));
+		let phase = if in_phase2 {
+			"Currently in phase 2 (queueing missing blocks)"
+		} else {
+			"Currently in phase 1 (removing unneeded queue entries)"
+		};
+		WorkerStatus {
+			tranquility: Some(RECONCILE_TRANQUILITY),
+			freeform: vec![
+				phase.into(),
+				format!("Queue entries removed: {}", removed),
+				format!("Missing blocks queued: {}", enqueued),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		self.tranquilizer.reset();
+
+		let (in_phase2, queue_pos, rc_pos) = self
+			.persister
+			.get_with(|x| (x.in_phase2, x.queue_pos.clone(), x.rc_pos.clone()));
+
+		if !in_phase2 {
+			// Phase 1: remove queue entries of blocks that need no action
+			if !self.reconcile_queue_batch(queue_pos).await? {
+				self.persister.set_with(|x| x.in_phase2 = true)?;
+			}
+		} else {
+			// Phase 2: queue blocks that are needed, missing and not queued
+			if !self.reconcile_rc_batch(rc_pos).await? {
+				let (removed, enqueued) = self.persister.get_with(|x| (x.removed, x.enqueued));
+				info!(
+					"Resync queue reconciliation finished: {} entries removed, {} missing blocks queued",
+					removed, enqueued
+				);
+				self.persister
+					.set_with(|x| *x = ReconcileResyncPersisted::default())?;
+				return Ok(WorkerState::Done);
+			}
+		}
+
+		Ok(self.tranquilizer.tranquilize_worker(RECONCILE_TRANQUILITY))
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+// ---- ---- ----
+// RESYNCING STALE BLOCKS
+// Also a one-shot repair operation. Like phase 1 of RepairWorker, it queues
+// the blocks of the RC table, but only those that were not resynced
+// successfully for some time, so that periodic checks can skip the blocks
+// that were checked recently.
+// ---- ---- ----
+
+const RESYNC_STALE_BATCH_SIZE: usize = 1000;
+
+pub struct ResyncStaleWorker {
+	manager: Arc<BlockManager>,
+	older_than: Duration,
+	/// Blocks not resynced successfully since this time are queued
+	since: u64,
+	next_start: Option<Hash>,
+	checked: u64,
+	enqueued: u64,
+}
+
+impl ResyncStaleWorker {
+	pub fn new(manager: Arc<BlockManager>, older_than: Duration) -> Self {
+		Self {
+			manager,
+			older_than,
+			since: now_msec().saturating_sub(older_than.as_millis() as u64),
+			next_start: None,
+			checked: 0,
+			enqueued: 0,
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for ResyncStaleWorker {
+	fn name(&self) -> String {
+		"Stale block resync worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			progress: Some(self.checked.to_string()),
+			freeform: vec![
+				format!(
+					"Queueing blocks not resynced for {} days",
+					self.older_than.as_secs() / (24 * 3600)
+				),
+				format!("Stale blocks queued: {}", self.enqueued),
+			],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		// As in RepairWorker, we first read a batch of hashes and only
+		// then access the DB again for each of them, because of SQLite.
+		let mut batch_of_hashes = vec![];
+		let start_bound = match self.next_start.as_ref() {
+			None => Bound::Unbounded,
+			Some(x) => Bound::Excluded(x.as_slice()),
+		};
+		for entry in self
+			.manager
+			.rc
+			.rc
+			.range::<&[u8], _>((start_bound, Bound::Unbounded))?
+		{
+			let (hash, _) = entry?;
+			batch_of_hashes.push(Hash::try_from(&hash[..]).unwrap());
+			if batch_of_hashes.len() >= RESYNC_STALE_BATCH_SIZE {
+				break;
+			}
+		}
+		if batch_of_hashes.is_empty() {
+			info!(
+				"Stale block resync finished: {} blocks checked, {} queued",
+				self.checked, self.enqueued
+			);
+			return Ok(WorkerState::Done);
+		}
+
+		for hash in batch_of_hashes {
+			if self.manager.resync.is_stale(&hash, self.since)? {
+				self.manager
+					.resync
+					.put_to_resync(&hash, Duration::from_secs(0))?;
+				self.enqueued += 1;
+			}
+			self.checked += 1;
+			self.next_start = Some(hash);
+		}
+
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
 // ---- ---- ----
 // SECOND KIND OF REPAIR: SCRUBBING THE DATASTORE
 // This is significantly more complex than the process above,
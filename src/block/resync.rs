@@ -1,13 +1,19 @@
-use std::collections::HashSet;
+use core::ops::Bound;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryInto;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use arc_swap::ArcSwapOption;
 use async_trait::async_trait;
+use futures::future::join_all;
+use futures::Future;
 use serde::{Deserialize, Serialize};
 
 use tokio::select;
-use tokio::sync::{watch, Notify};
+use tokio::sync::{watch, Notify, OwnedSemaphorePermit, Semaphore};
 
 use opentelemetry::{
 	trace::{FutureExt as OtelFutureExt, TraceContextExt, Tracer},
@@ -18,6 +24,7 @@ use garage_db as db;
 use garage_db::counted_tree_hack::CountedTree;
 
 use garage_util::background::*;
+use garage_util::config::ResyncScheduleWindow;
 use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::metrics::RecordDuration;
@@ -25,41 +32,221 @@ use garage_util::persister::PersisterShared;
 use garage_util::time::*;
 use garage_util::tranquilizer::Tranquilizer;
 
-use garage_rpc::system::System;
 use garage_rpc::*;
 
 use garage_table::replication::TableReplication;
 
+use crate::block::*;
 use crate::manager::*;
+use crate::rc::RcEntry;
+use crate::source::*;
 
 // The delay between the time where a resync operation fails
 // and the time when it is retried, with exponential backoff
 // (multiplied by 2, 4, 8, 16, etc. for every consecutive failure).
+// Both values below are initial values that can be changed in the CLI
+// and are persisted over Garage restarts.
 pub(crate) const RESYNC_RETRY_DELAY: Duration = Duration::from_secs(60);
 // The minimum retry delay is 60 seconds = 1 minute
 // The maximum retry delay is 60 seconds * 2^6 = 60 seconds << 6 = 64 minutes (~1 hour)
 pub(crate) const RESYNC_RETRY_DELAY_MAX_BACKOFF_POWER: u64 = 6;
+// Bounds for the values that can be set at runtime
+const RESYNC_RETRY_DELAY_MAX: Duration = Duration::from_secs(24 * 3600);
+const RESYNC_MAX_BACKOFF_POWER_MAX: u64 = 16;
+// Retry delays are spread by up to +/- 25% (by default) so that blocks
+// that failed at the same time are not all retried at the same time.
+// The jitter of a block is derived from its hash, so it is stable.
+const INITIAL_RESYNC_RETRY_JITTER: f64 = 0.25;
+const RESYNC_RETRY_JITTER_MAX: f64 = 0.5;
 
-// No more than 4 resync workers can be running in the system
-pub(crate) const MAX_RESYNC_WORKERS: usize = 4;
+// The maximum number of resync workers is set in the config file
+// (4 by default), but can never be more than 32
+const MAX_RESYNC_WORKERS_CEILING: usize = 32;
 // Resync tranquility is initially set to 2, but can be changed in the CLI
 // and the updated version is persisted over Garage restarts
 const INITIAL_RESYNC_TRANQUILITY: u32 = 2;
+// When the queue is empty, resync workers check it again every 10 seconds
+// even if they are not notified of a new entry (see resync_iter). This can
+// be set at runtime between 100ms and 10 minutes.
+const INITIAL_RESYNC_IDLE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const RESYNC_IDLE_POLL_INTERVAL_MIN: Duration = Duration::from_millis(100);
+const RESYNC_IDLE_POLL_INTERVAL_MAX: Duration = Duration::from_secs(600);
+// A single resync_block operation is aborted if it takes more than 5 minutes
+// (e.g. a peer is half-alive and never answers), and is retried later
+// with the usual exponential backoff.
+const INITIAL_RESYNC_BLOCK_TIMEOUT_SECS: u64 = 300;
+
+// When auto-scaling is enabled, the number of resync workers is checked
+// every 30 seconds: a worker is added if the queue is longer than the upper
+// threshold, and one is removed if it is shorter than the lower threshold.
+// Changing by one worker at a time, with a gap between the thresholds,
+// avoids flapping.
+const RESYNC_AUTO_SCALE_INTERVAL: Duration = Duration::from_secs(30);
+const INITIAL_RESYNC_AUTO_SCALE_UP_QUEUE_LEN: u64 = 10_000;
+const INITIAL_RESYNC_AUTO_SCALE_DOWN_QUEUE_LEN: u64 = 1_000;
+
+// Error messages stored in the resync error table are truncated to 256 bytes
+const RESYNC_ERROR_MESSAGE_MAX_LEN: usize = 256;
+
+// Each node that needs a block being offloaded must receive it within
+// this delay, otherwise sending the block to that node is abandoned
+const RESYNC_OFFLOAD_SEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+// When the database is busy, resync workers wait this long before trying again
+const RESYNC_TRANSIENT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+// Number of blocks of the error table requeued at once by retry_all_errors,
+// and of queue entries rescheduled at once by expedite_all
+const RESYNC_RETRY_ALL_BATCH_SIZE: usize = 1000;
+
+// If writing a fetched block to disk fails this many times in a row,
+// the block is quarantined: the disk is probably failing, and retrying
+// forever would only hide the problem.
+pub(crate) const RESYNC_QUARANTINE_WRITE_FAILURES: u64 = 5;
+
+// When resync prioritization of recently accessed blocks is enabled,
+// the hashes of the last 4096 blocks read are remembered, and these blocks
+// are put in the resync queue with a delay divided by 4.
+const RESYNC_RECENT_BLOCKS_CAPACITY: usize = 4096;
+const RESYNC_RECENT_DELAY_DIVISOR: u32 = 4;
+
+// When the resync queue is longer than its high-water mark (if one is set),
+// blocks newly put in the queue with normal priority are scheduled with an
+// additional delay of 10ms per block in excess, up to 1 hour, so that work
+// is spread out instead of piling up at the head of the queue. Blocks that
+// might be under-replicated are never delayed.
+const RESYNC_BACKPRESSURE_DELAY_PER_BLOCK: Duration = Duration::from_millis(10);
+const RESYNC_BACKPRESSURE_MAX_DELAY: Duration = Duration::from_secs(3600);
+
+// No entry of the resync queue should ever be scheduled further in the
+// future than the longest delay allowed by the configuration (the maximum
+// retry backoff with its jitter, or the minimum deletion delay with the
+// maximum backpressure delay), plus 6 hours. If one is, the system clock
+// has jumped backwards, and the entry is rescheduled immediately instead of
+// stalling the queue until real time catches up.
+const RESYNC_SANE_DELAY_MARGIN: Duration = Duration::from_secs(6 * 3600);
+
+// Keys of the resync queue written before entries had a priority
+// are the scheduled time followed by the hash, with no priority byte
+const LEGACY_QUEUE_KEY_LEN: usize = 8 + 32;
+// Number of legacy queue entries converted at once at startup
+const LEGACY_QUEUE_MIGRATION_BATCH_SIZE: usize = 1000;
+
+// A block claimed by a resync worker is considered abandoned if it has not
+// been released this long after the block timeout. The work done on the
+// block after the timed resync operation is bounded by this delay as well.
+const RESYNC_CLAIM_LEASE_MARGIN: Duration = Duration::from_secs(60);
+
+// The resync queue is reported as drained once it has been empty,
+// with no block being processed, for 2 seconds
+const RESYNC_DRAINED_DEBOUNCE: Duration = Duration::from_secs(2);
+
+// When the block status cache is enabled, the status of a block checked by
+// resync is reused for 5 seconds, unless the block is written, deleted or
+// its reference counter changes. At most 10000 statuses are cached.
+const RESYNC_STATUS_CACHE_TTL: Duration = Duration::from_secs(5);
+const RESYNC_STATUS_CACHE_CAPACITY: usize = 10000;
 
 pub struct BlockResyncManager {
 	pub(crate) queue: CountedTree,
 	pub(crate) notify: Arc<Notify>,
 	pub(crate) errors: CountedTree,
+	pub(crate) write_errors: db::Tree,
+	pub(crate) quarantine: CountedTree,
+	/// Queue entries that were processed successfully but might not have
+	/// been removed from the queue yet, with the state of their block at
+	/// that time (see `status_fingerprint`)
+	resync_done: db::Tree,
+	/// For each block in the queue, the key of its queue entry,
+	/// used to keep a single entry per block
+	queue_index: db::Tree,
+	/// Queue entries being resynced by a worker, with the time until
+	/// which they are claimed
+	claims: CountedTree,
+	/// Number of queue entries whose resync was interrupted when Garage
+	/// last stopped, i.e. that still had a claim at startup
+	orphaned_claims: usize,
+	/// For each block stored locally, the time of its last successful resync
+	last_verified: db::Tree,
 
 	busy_set: BusySet,
+	recent_blocks: Mutex<RecentBlocks>,
+	/// Recently checked block statuses, with the time at which they were checked
+	status_cache: Mutex<HashMap<Hash, (u64, BlockStatus)>>,
+	/// Resyncs in progress by block hash, each with a flag that is set
+	/// when the block is deleted or moved away in the meantime
+	in_flight: Mutex<HashMap<Hash, Vec<Arc<AtomicBool>>>>,
+	upload_limiter: Mutex<TokenBucket>,
+	/// Limit on the number of blocks being sent to other nodes at the same time
+	send_limiter: Arc<Mutex<SendLimiter>>,
+	sources: Mutex<Vec<Arc<dyn BlockSource>>>,
+
+	drained: watch::Sender<bool>,
+	queue_len_watch: watch::Sender<usize>,
+	empty_since: Mutex<Option<u64>>,
 
 	persister: PersisterShared<ResyncPersistedConfig>,
+	pub(crate) schedule: Vec<ResyncScheduleWindow>,
+	/// Index in `schedule` of the window currently in effect
+	active_window: Mutex<Option<usize>>,
+	/// Number of resync workers spawned at startup, which is also the
+	/// maximum number of workers that can be enabled
+	pub(crate) max_workers: usize,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
+#[derive(Serialize, Deserialize, Clone)]
 struct ResyncPersistedConfig {
 	n_workers: usize,
 	tranquility: u32,
+	/// Tranquility of each worker, by index, when it differs from `tranquility`
+	#[serde(default)]
+	worker_tranquility: Vec<Option<u32>>,
+	#[serde(default = "default_block_timeout_secs")]
+	block_timeout_secs: u64,
+	#[serde(default)]
+	prioritize_recent: bool,
+	/// Reuse the status of blocks checked a few seconds ago
+	#[serde(default)]
+	status_cache: bool,
+	#[serde(default)]
+	queue_high_water: u64,
+	#[serde(default = "default_retry_delay_secs")]
+	retry_delay_secs: u64,
+	#[serde(default = "default_max_backoff_power")]
+	max_backoff_power: u64,
+	#[serde(default = "default_retry_jitter")]
+	retry_jitter: f64,
+	/// Maximum number of bytes per second sent when offloading blocks,
+	/// 0 for no limit
+	#[serde(default)]
+	max_bytes_per_sec: u64,
+	/// Maximum number of blocks sent to other nodes at the same time
+	/// when offloading blocks, 0 for no limit
+	#[serde(default)]
+	max_concurrent_sends: u64,
+	/// Delete the local copy of an offloaded block once a write quorum
+	/// of nodes has it, even if some of the nodes that need it could
+	/// not receive it
+	#[serde(default)]
+	offload_with_quorum: bool,
+	#[serde(default)]
+	auto_scale: bool,
+	#[serde(default)]
+	paused: bool,
+	/// Only check that the nodes that should store blocks have them,
+	/// without sending, fetching or deleting any block
+	#[serde(default)]
+	verify_only: bool,
+	#[serde(default = "default_idle_poll_interval_ms")]
+	idle_poll_interval_ms: u64,
+	#[serde(default = "default_auto_scale_up_queue_len")]
+	auto_scale_up_queue_len: u64,
+	#[serde(default = "default_auto_scale_down_queue_len")]
+	auto_scale_down_queue_len: u64,
+	/// Minimum delay before a block that has no references anymore is
+	/// checked for deletion, never shorter than the block GC delay
+	#[serde(default = "default_min_deletion_delay_secs")]
+	min_deletion_delay_secs: u64,
 }
 impl garage_util::migrate::InitialFormat for ResyncPersistedConfig {}
 impl Default for ResyncPersistedConfig {
@@ -67,6 +254,281 @@ impl Default for ResyncPersistedConfig {
 		ResyncPersistedConfig {
 			n_workers: 1,
 			tranquility: INITIAL_RESYNC_TRANQUILITY,
+			worker_tranquility: vec![],
+			block_timeout_secs: INITIAL_RESYNC_BLOCK_TIMEOUT_SECS,
+			prioritize_recent: false,
+			status_cache: false,
+			queue_high_water: 0,
+			retry_delay_secs: default_retry_delay_secs(),
+			max_backoff_power: default_max_backoff_power(),
+			retry_jitter: default_retry_jitter(),
+			max_bytes_per_sec: 0,
+			max_concurrent_sends: 0,
+			offload_with_quorum: false,
+			auto_scale: false,
+			paused: false,
+			verify_only: false,
+			idle_poll_interval_ms: default_idle_poll_interval_ms(),
+			auto_scale_up_queue_len: default_auto_scale_up_queue_len(),
+			auto_scale_down_queue_len: default_auto_scale_down_queue_len(),
+			min_deletion_delay_secs: default_min_deletion_delay_secs(),
+		}
+	}
+}
+
+fn default_block_timeout_secs() -> u64 {
+	INITIAL_RESYNC_BLOCK_TIMEOUT_SECS
+}
+
+fn default_retry_delay_secs() -> u64 {
+	RESYNC_RETRY_DELAY.as_secs()
+}
+
+fn default_max_backoff_power() -> u64 {
+	RESYNC_RETRY_DELAY_MAX_BACKOFF_POWER
+}
+
+fn default_retry_jitter() -> f64 {
+	INITIAL_RESYNC_RETRY_JITTER
+}
+
+fn default_idle_poll_interval_ms() -> u64 {
+	INITIAL_RESYNC_IDLE_POLL_INTERVAL.as_millis() as u64
+}
+
+fn default_auto_scale_up_queue_len() -> u64 {
+	INITIAL_RESYNC_AUTO_SCALE_UP_QUEUE_LEN
+}
+
+fn default_auto_scale_down_queue_len() -> u64 {
+	INITIAL_RESYNC_AUTO_SCALE_DOWN_QUEUE_LEN
+}
+
+fn default_min_deletion_delay_secs() -> u64 {
+	BLOCK_GC_DELAY.as_secs()
+}
+
+/// Everything resync knows about a block, see `BlockResyncManager::explain`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ResyncExplanation {
+	pub hash: Hash,
+	/// The block is stored on this node
+	pub exists: bool,
+	/// Reference count of the block on this node
+	pub refcount: u64,
+	pub is_nonzero: bool,
+	pub is_deletable: bool,
+	/// Times at which the block is scheduled in the resync queue
+	pub queued_at: Vec<u64>,
+	/// A resync worker is currently processing the block
+	pub busy: bool,
+	/// Number of consecutive resync errors, and time of the next try
+	pub errors: Option<(u64, u64)>,
+	pub quarantined: bool,
+	/// What resync would do if it processed the block now
+	pub action: ResyncAction,
+	/// Nodes that answered that they need the block, if they were asked
+	pub peers_needing: Option<Vec<Uuid>>,
+}
+
+/// A snapshot of the state of resync on this node, see `BlockResyncManager::status`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResyncStatus {
+	/// Number of resync workers currently in effect
+	pub n_workers: usize,
+	/// Tranquility currently in effect
+	pub tranquility: u32,
+	pub queue_len: usize,
+	pub errors_len: usize,
+	/// How long ago the first entry of the resync queue was due,
+	/// or None if the queue is empty or nothing is due yet
+	pub oldest_queued_age_ms: Option<u64>,
+	pub paused: bool,
+}
+
+/// Scheduling state of a single block, see `BlockResyncManager::block_state`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockResyncState {
+	/// Time at which the block is scheduled in the resync queue, if it is queued
+	pub queued_at: Option<u64>,
+	/// A resync worker is currently processing the block
+	pub busy: bool,
+	/// Number of consecutive resync errors, and time of the next try
+	pub errors: Option<(u64, u64)>,
+	pub quarantined: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResyncAction {
+	/// The block is where it should be, nothing to do
+	Nothing,
+	/// The block is needed but absent, it would be fetched from other nodes
+	Fetch,
+	/// The block is not needed anymore, it would be offloaded
+	/// to nodes that need it and deleted
+	OffloadAndDelete,
+	/// A previous resync failed, the block will not be retried before the next try
+	WaitForBackoff,
+	/// The block was quarantined and will not be processed until it is released
+	Quarantined,
+	/// The block is not needed anymore, but it is kept until its minimum
+	/// deletion delay has passed
+	WaitForDeletionDelay,
+}
+
+/// Something done by resync to a block, see `BlockManager::subscribe_resync_events`
+#[derive(Clone, Debug)]
+pub enum ResyncEvent {
+	/// The block was sent to other nodes that need it
+	Offloaded { hash: Hash, to_nodes: Vec<Uuid> },
+	/// The block was deleted locally as it is not needed anymore
+	Deleted { hash: Hash },
+	/// The block was absent but needed, and was fetched from a block source
+	Fetched { hash: Hash, from: String },
+	/// The resync of the block failed
+	Errored { hash: Hash, error: String },
+}
+
+/// The most recent resync events, with the time at which they happened,
+/// see `BlockManager::recent_resync_events`. The oldest events are dropped
+/// when there are more than `capacity` of them.
+pub(crate) struct RecentResyncEvents {
+	events: Mutex<VecDeque<(u64, ResyncEvent)>>,
+	capacity: usize,
+}
+
+impl RecentResyncEvents {
+	pub(crate) fn new(capacity: usize) -> Self {
+		Self {
+			events: Mutex::new(VecDeque::with_capacity(capacity)),
+			capacity,
+		}
+	}
+
+	pub(crate) fn is_enabled(&self) -> bool {
+		self.capacity > 0
+	}
+
+	pub(crate) fn push(&self, time: u64, event: ResyncEvent) {
+		if self.capacity == 0 {
+			return;
+		}
+		let mut events = self.events.lock().unwrap();
+		if events.len() >= self.capacity {
+			events.pop_front();
+		}
+		events.push_back((time, event));
+	}
+
+	/// Copy of the events, from the oldest to the most recent
+	pub(crate) fn list(&self) -> Vec<(u64, ResyncEvent)> {
+		self.events.lock().unwrap().iter().cloned().collect()
+	}
+}
+
+/// Priority of an entry of the resync queue. Entries that are due are
+/// processed by order of priority, and then of scheduled time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum ResyncPriority {
+	/// The block is needed but absent, it might be under-replicated
+	High = 0,
+	Normal = 1,
+}
+
+impl ResyncPriority {
+	const ALL: [ResyncPriority; 2] = [ResyncPriority::High, ResyncPriority::Normal];
+
+	fn from_byte(b: u8) -> Self {
+		match b {
+			0 => ResyncPriority::High,
+			_ => ResyncPriority::Normal,
+		}
+	}
+}
+
+/// Key of an entry of the resync queue: the priority byte, then the
+/// scheduled time, then the hash, so that the queue is ordered
+/// by priority and then by time
+fn queue_key(priority: ResyncPriority, when: u64, hash: &[u8]) -> Vec<u8> {
+	let mut key = vec![priority as u8];
+	key.extend(u64::to_be_bytes(when));
+	key.extend(hash);
+	key
+}
+
+fn queue_key_priority(key: &[u8]) -> ResyncPriority {
+	ResyncPriority::from_byte(key[0])
+}
+
+pub(crate) fn queue_key_time(key: &[u8]) -> u64 {
+	u64::from_be_bytes(key[1..9].try_into().unwrap())
+}
+
+fn queue_key_hash(key: &[u8]) -> &[u8] {
+	&key[9..]
+}
+
+/// Range of the queue keys that have a given priority
+fn priority_range(priority: ResyncPriority) -> std::ops::Range<Vec<u8>> {
+	vec![priority as u8]..vec![priority as u8 + 1]
+}
+
+/// Time at which the first entry of the resync queue is scheduled,
+/// among all priorities
+pub(crate) fn oldest_queued_at(queue: &CountedTree) -> Result<Option<u64>, db::Error> {
+	let mut oldest = None;
+	for priority in ResyncPriority::ALL {
+		if let Some(ent) = queue.range(priority_range(priority))?.next() {
+			let time = queue_key_time(&ent?.0);
+			oldest = Some(oldest.map_or(time, |t: u64| t.min(time)));
+		}
+	}
+	Ok(oldest)
+}
+
+/// Convert the entries of the resync queue that were written without
+/// a priority byte to entries of normal priority. Returns the number
+/// of entries that were converted.
+fn migrate_legacy_queue(
+	queue: &CountedTree,
+	queue_index: &db::Tree,
+	resync_done: &db::Tree,
+) -> Result<usize, db::Error> {
+	let mut count = 0;
+	let mut start: Option<Vec<u8>> = None;
+	loop {
+		// Legacy entries are collected in batches before being rewritten,
+		// so that we never modify the queue while iterating on it
+		let bound = match &start {
+			Some(key) => Bound::Excluded(key.clone()),
+			None => Bound::Unbounded,
+		};
+		let mut batch = vec![];
+		let mut done = true;
+		for ent in queue.range::<Vec<u8>, _>((bound, Bound::Unbounded))? {
+			let (key, hash) = ent?;
+			start = Some(key.clone());
+			if key.len() == LEGACY_QUEUE_KEY_LEN {
+				batch.push((key, hash));
+				if batch.len() >= LEGACY_QUEUE_MIGRATION_BATCH_SIZE {
+					done = false;
+					break;
+				}
+			}
+		}
+
+		for (old_key, hash) in batch.iter() {
+			let when = u64::from_be_bytes(old_key[0..8].try_into().unwrap());
+			let key = queue_key(ResyncPriority::Normal, when, hash);
+			queue.insert(&key, hash)?;
+			queue_index.insert(hash, &key)?;
+			queue.remove(old_key)?;
+			resync_done.remove(old_key)?;
+			count += 1;
+		}
+
+		if done {
+			return Ok(count);
 		}
 	}
 }
@@ -79,32 +541,267 @@ enum ResyncIterResult {
 
 type BusySet = Arc<Mutex<HashSet<Vec<u8>>>>;
 
+/// A resync of a block in progress, registered until it is dropped
+struct InFlightResync<'a> {
+	resync: &'a BlockResyncManager,
+	hash: Hash,
+	aborted: Arc<AtomicBool>,
+}
+
+impl<'a> InFlightResync<'a> {
+	/// Whether the block was deleted or moved away during the resync
+	fn is_aborted(&self) -> bool {
+		self.aborted.load(Ordering::Relaxed)
+	}
+}
+
 struct BusyBlock {
 	time_bytes: Vec<u8>,
 	hash_bytes: Vec<u8>,
 	busy_set: BusySet,
+	claims: CountedTree,
+	/// The end of the lease of the claim taken by this worker, if any
+	claimed_until: Option<[u8; 8]>,
+}
+
+/// Token bucket limiting the rate at which resync workers send blocks.
+/// The bucket holds at most one second worth of bytes. A send is allowed
+/// as soon as the bucket is not empty, and may leave it in debt, so that
+/// blocks larger than the rate can still go through.
+struct TokenBucket {
+	tokens: f64,
+	last_refill: Instant,
+}
+
+impl TokenBucket {
+	fn new() -> Self {
+		Self {
+			tokens: 0.0,
+			last_refill: Instant::now(),
+		}
+	}
+
+	/// Take `bytes` tokens at the given rate, or return how long to wait
+	/// before trying again if the bucket is in debt
+	fn take(&mut self, bytes: u64, rate: u64, now: Instant) -> Option<Duration> {
+		let rate = rate as f64;
+		let elapsed = now.saturating_duration_since(self.last_refill);
+		self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(rate);
+		self.last_refill = now;
+		if self.tokens < 0.0 {
+			return Some(Duration::from_secs_f64(-self.tokens / rate));
+		}
+		self.tokens -= bytes as f64;
+		None
+	}
+}
+
+/// Semaphore limiting the number of blocks sent to other nodes at the same
+/// time. When the limit is lowered, the permits in excess are forgotten as
+/// they are released, so that the sends in progress are counted in the new
+/// limit.
+struct SendLimiter {
+	max: u64,
+	semaphore: Arc<Semaphore>,
+	/// Number of permits to forget when they are released
+	excess: u64,
+}
+
+impl SendLimiter {
+	fn set_max(&mut self, max: u64) {
+		if max > self.max {
+			let added = max - self.max;
+			let cancelled = std::cmp::min(added, self.excess);
+			self.excess -= cancelled;
+			self.semaphore.add_permits((added - cancelled) as usize);
+		} else {
+			for _ in max..self.max {
+				match self.semaphore.clone().try_acquire_owned() {
+					Ok(permit) => permit.forget(),
+					Err(_) => self.excess += 1,
+				}
+			}
+		}
+		self.max = max;
+	}
+}
+
+/// Permit to send a block to another node, released when it is dropped
+struct SendPermit {
+	permit: Option<OwnedSemaphorePermit>,
+	limiter: Arc<Mutex<SendLimiter>>,
+}
+
+impl Drop for SendPermit {
+	fn drop(&mut self) {
+		let mut limiter = self.limiter.lock().unwrap();
+		if limiter.excess > 0 {
+			limiter.excess -= 1;
+			if let Some(permit) = self.permit.take() {
+				permit.forget();
+			}
+		}
+	}
+}
+
+/// Bounded set of the most recently accessed blocks, oldest first
+#[derive(Default)]
+struct RecentBlocks {
+	order: VecDeque<Hash>,
+	set: HashSet<Hash>,
+}
+
+impl RecentBlocks {
+	fn insert(&mut self, hash: Hash) {
+		if self.set.contains(&hash) {
+			if let Some(pos) = self.order.iter().position(|h| *h == hash) {
+				self.order.remove(pos);
+			}
+		} else {
+			if self.order.len() >= RESYNC_RECENT_BLOCKS_CAPACITY {
+				if let Some(oldest) = self.order.pop_front() {
+					self.set.remove(&oldest);
+				}
+			}
+			self.set.insert(hash);
+		}
+		self.order.push_back(hash);
+	}
+
+	fn contains(&self, hash: &Hash) -> bool {
+		self.set.contains(hash)
+	}
 }
 
 impl BlockResyncManager {
-	pub(crate) fn new(db: &db::Db, system: &System) -> Self {
+	pub(crate) fn new(
+		db: &db::Db,
+		metadata_dir: &Path,
+		schedule: Vec<ResyncScheduleWindow>,
+		max_workers: usize,
+	) -> Self {
 		let queue = db
 			.open_tree("block_local_resync_queue")
 			.expect("Unable to open block_local_resync_queue tree");
 		let queue = CountedTree::new(queue).expect("Could not count block_local_resync_queue");
+		let queue_len = queue.len();
 
 		let errors = db
 			.open_tree("block_local_resync_errors")
 			.expect("Unable to open block_local_resync_errors tree");
 		let errors = CountedTree::new(errors).expect("Could not count block_local_resync_errors");
 
-		let persister = PersisterShared::new(&system.metadata_dir, "resync_cfg");
+		let write_errors = db
+			.open_tree("block_local_resync_write_errors")
+			.expect("Unable to open block_local_resync_write_errors tree");
+
+		let quarantine = db
+			.open_tree("block_resync_quarantine")
+			.expect("Unable to open block_resync_quarantine tree");
+		let quarantine =
+			CountedTree::new(quarantine).expect("Could not count block_resync_quarantine");
+
+		let resync_done = db
+			.open_tree("block_local_resync_done")
+			.expect("Unable to open block_local_resync_done tree");
+
+		let queue_index = db
+			.open_tree("block_local_resync_queue_index")
+			.expect("Unable to open block_local_resync_queue_index tree");
+
+		let migrated = migrate_legacy_queue(&queue, &queue_index, &resync_done)
+			.expect("Unable to migrate block_local_resync_queue");
+		if migrated > 0 {
+			info!(
+				"Converted {} resync queue entries to the format with priorities",
+				migrated
+			);
+		}
+
+		let claims = db
+			.open_tree("block_local_resync_claims")
+			.expect("Unable to open block_local_resync_claims tree");
+		// The entries that are still in the queue are processed again,
+		// releasing their claims is enough for that
+		let mut orphaned_claims = 0;
+		for it in claims
+			.iter()
+			.expect("Unable to read block_local_resync_claims")
+		{
+			let (key, _) = it.expect("Unable to read block_local_resync_claims");
+			if queue
+				.get(&key)
+				.expect("Unable to read block_local_resync_queue")
+				.is_some()
+			{
+				orphaned_claims += 1;
+			}
+		}
+		if orphaned_claims > 0 {
+			warn!(
+				"Resync of {} blocks was interrupted when Garage last stopped, it will be retried",
+				orphaned_claims
+			);
+		}
+		claims
+			.clear()
+			.expect("Unable to clear block_local_resync_claims tree");
+		let claims = CountedTree::new(claims).expect("Could not count block_local_resync_claims");
+
+		let last_verified = db
+			.open_tree("block_local_resync_last_verified")
+			.expect("Unable to open block_local_resync_last_verified tree");
+
+		let persister = PersisterShared::new(metadata_dir, "resync_cfg");
+
+		if !(1..=MAX_RESYNC_WORKERS_CEILING).contains(&max_workers) {
+			warn!(
+				"Invalid maximum number of resync workers {}, it will be clamped between 1 and {}",
+				max_workers, MAX_RESYNC_WORKERS_CEILING
+			);
+		}
+		let max_workers = max_workers.clamp(1, MAX_RESYNC_WORKERS_CEILING);
+
+		for window in schedule.iter() {
+			if let Some(n) = window.n_workers {
+				if !(1..=max_workers).contains(&n) {
+					warn!(
+						"Resync schedule window {}: invalid number of workers {}, it will be clamped between 1 and {}",
+						window.name, n, max_workers
+					);
+				}
+			}
+		}
 
 		Self {
 			queue,
 			notify: Arc::new(Notify::new()),
 			errors,
+			write_errors,
+			quarantine,
+			resync_done,
+			queue_index,
+			claims,
+			orphaned_claims,
+			last_verified,
 			busy_set: Arc::new(Mutex::new(HashSet::new())),
+			recent_blocks: Mutex::new(RecentBlocks::default()),
+			status_cache: Mutex::new(HashMap::new()),
+			in_flight: Mutex::new(HashMap::new()),
+			upload_limiter: Mutex::new(TokenBucket::new()),
+			send_limiter: Arc::new(Mutex::new(SendLimiter {
+				max: 0,
+				semaphore: Arc::new(Semaphore::new(0)),
+				excess: 0,
+			})),
+			sources: Mutex::new(vec![]),
+			drained: watch::channel(false).0,
+			queue_len_watch: watch::channel(queue_len).0,
+			empty_since: Mutex::new(None),
 			persister,
+			schedule,
+			active_window: Mutex::new(None),
+			max_workers,
 		}
 	}
 
@@ -122,15 +819,114 @@ impl BlockResyncManager {
 		Ok(self.errors.len())
 	}
 
-	/// Clear the error counter for a block and put it in queue immediately
+	/// Get the time at which the first entry of the resync queue is
+	/// scheduled, or None if the queue is empty
+	pub fn oldest_queued_at(&self) -> Result<Option<u64>, Error> {
+		Ok(oldest_queued_at(&self.queue)?)
+	}
+
+	/// Get a snapshot of the state of resync on this node
+	pub fn status(&self) -> Result<ResyncStatus, Error> {
+		let (n_workers, tranquility) = self.effective_tuning();
+		let now = now_msec();
+		let oldest_queued_age_ms = self
+			.oldest_queued_at()?
+			.filter(|t| *t < now)
+			.map(|t| now - t);
+		Ok(ResyncStatus {
+			n_workers,
+			tranquility,
+			queue_len: self.queue_len()?,
+			errors_len: self.errors_len()?,
+			oldest_queued_age_ms,
+			paused: self.is_paused(),
+		})
+	}
+
+	/// Get the scheduling state of a block: whether it is in the resync
+	/// queue, and whether its resync failed. Unlike `explain`, this doesn't
+	/// scan the queue, the entry of the block is found with the queue index.
+	pub fn block_state(&self, hash: &Hash) -> Result<BlockResyncState, Error> {
+		let mut state = BlockResyncState::default();
+
+		if let Some(key) = self.queue_index.get(hash.as_slice())? {
+			if self.queue.get(&key[..])?.is_some() {
+				state.queued_at = Some(queue_key_time(&key));
+				state.busy = self.busy_set.lock().unwrap().contains(&key[..]);
+			}
+		}
+
+		state.errors = self
+			.errors
+			.get(hash.as_slice())?
+			.map(|ec| ErrorCounter::decode(&ec))
+			.filter(|ec| ec.errors > 0)
+			.map(|ec| (ec.errors, ec.next_try(hash, &self.retry_backoff())));
+		state.quarantined = self.quarantine.get(hash.as_slice())?.is_some();
+
+		Ok(state)
+	}
+
+	/// Get number of blocks that are quarantined
+	pub fn quarantine_len(&self) -> Result<usize, Error> {
+		// (see queue_len comment)
+		Ok(self.quarantine.len())
+	}
+
+	/// Get a channel that tells whether the resync queue is drained,
+	/// i.e. it has been empty with no block being resynced for a short while.
+	/// It switches back to false as soon as a block is added to the queue.
+	pub fn subscribe_drained(&self) -> watch::Receiver<bool> {
+		self.drained.subscribe()
+	}
+
+	/// Get a channel that follows the length of the resync queue, updated
+	/// each time an entry is added to or removed from it. Requeueing a block
+	/// inserts its new entry before removing the old one, so the count can
+	/// briefly go up by one before settling again.
+	pub fn subscribe_queue_len(&self) -> watch::Receiver<usize> {
+		self.queue_len_watch.subscribe()
+	}
+
+	fn update_queue_len_watch(&self) {
+		let len = self.queue.len();
+		self.queue_len_watch
+			.send_if_modified(|l| std::mem::replace(l, len) != len);
+	}
+
+	/// Called by workers when they find nothing to do: mark the queue as
+	/// drained if it has been idle for long enough, otherwise return
+	/// how long to wait before checking again
+	fn update_drained(&self, now: u64) -> Option<Duration> {
+		let idle = self.queue.is_empty() && self.busy_set.lock().unwrap().is_empty();
+		let mut empty_since = self.empty_since.lock().unwrap();
+		if !idle {
+			*empty_since = None;
+			return None;
+		}
+		let elapsed = now.saturating_sub(*empty_since.get_or_insert(now));
+		let debounce = RESYNC_DRAINED_DEBOUNCE.as_millis() as u64;
+		if elapsed >= debounce {
+			self.drained
+				.send_if_modified(|drained| !std::mem::replace(drained, true));
+			None
+		} else {
+			Some(Duration::from_millis(debounce - elapsed))
+		}
+	}
+
+	/// Clear the error counter for a block and put it in queue immediately.
+	/// If the block was quarantined, it is released from quarantine.
 	pub fn clear_backoff(&self, hash: &Hash) -> Result<(), Error> {
 		let now = now_msec();
 		if let Some(ec) = self.errors.get(hash)? {
 			let mut ec = ErrorCounter::decode(&ec);
 			if ec.errors > 0 {
-				ec.last_try = now - ec.delay_msec();
+				ec.last_try = now.saturating_sub(ec.delay_msec(hash, &self.retry_backoff()));
 				self.errors.insert(hash, ec.encode())?;
-				self.put_to_resync_at(hash, now)?;
+				self.write_errors.remove(hash)?;
+				self.quarantine.remove(hash)?;
+				self.put_to_resync_at(hash, now, ResyncPriority::Normal)?;
 				return Ok(());
 			}
 		}
@@ -140,23 +936,98 @@ impl BlockResyncManager {
 		)))
 	}
 
+	/// Forget the errors of a block and put it in queue immediately.
+	/// Unlike `clear_backoff`, the error count is reset, so that a new failure
+	/// starts over from the smallest retry delay. Returns whether the block
+	/// had errors: if not, nothing is done.
+	pub fn retry_now(&self, hash: &Hash) -> Result<bool, Error> {
+		if self.errors.remove(hash.as_slice())?.is_none() {
+			return Ok(false);
+		}
+		self.write_errors.remove(hash.as_slice())?;
+		self.quarantine.remove(hash.as_slice())?;
+		self.put_to_resync_at(hash, now_msec(), ResyncPriority::Normal)?;
+		Ok(true)
+	}
+
+	/// Forget the errors of all blocks and put them in queue immediately,
+	/// e.g. after a node that was down comes back. Returns the number of
+	/// blocks that were requeued.
+	pub fn retry_all_errors(&self) -> Result<usize, Error> {
+		// The error table is read in batches, which are then requeued
+		// using the same operations as the workers, so that we never
+		// modify the table while iterating on it (see RepairWorker)
+		// and never hold the busy set for more than a single operation.
+		let mut count = 0;
+		let mut start = None;
+		loop {
+			let batch = self.list_errors(start, RESYNC_RETRY_ALL_BATCH_SIZE)?;
+			let last = match batch.last() {
+				Some((hash, _)) => *hash,
+				None => break,
+			};
+			for (hash, _) in batch.iter() {
+				if self.retry_now(hash)? {
+					count += 1;
+				}
+			}
+			start = Some(last);
+		}
+		Ok(count)
+	}
+
+	/// Reschedule all the entries of the resync queue to now, so that every
+	/// queued block is processed as soon as possible, e.g. after a change of
+	/// the replication settings. Priorities are kept, and entries being
+	/// processed are left alone. Returns the number of entries rescheduled.
+	pub fn expedite_all(&self) -> Result<usize, Error> {
+		let now = now_msec();
+		let mut count = 0;
+		let mut start: Option<Vec<u8>> = None;
+		loop {
+			// A rescheduled entry has a smaller key than the one it replaces,
+			// so it is never read again by a later batch
+			let batch = self.queue_batch(start.as_deref(), RESYNC_RETRY_ALL_BATCH_SIZE)?;
+			let last = match batch.last() {
+				Some((key, _)) => key.clone(),
+				None => break,
+			};
+			for (key, hash) in batch.iter() {
+				if queue_key_time(key) <= now || self.busy_set.lock().unwrap().contains(key) {
+					continue;
+				}
+				// Several entries of a same block, if any, end up with the same key,
+				// which removes the duplicates
+				let new_key = queue_key(queue_key_priority(key), now, hash.as_slice());
+				if self.queue.insert(&new_key, hash.as_slice())?.is_none() {
+					count += 1;
+				}
+				self.queue_index.insert(hash.as_slice(), &new_key)?;
+				self.queue.remove(key)?;
+				self.update_queue_len_watch();
+			}
+			start = Some(last);
+		}
+		self.notify.notify_waiters();
+		Ok(count)
+	}
+
 	pub fn register_bg_vars(&self, vars: &mut vars::BgVars) {
+		let max_workers = self.max_workers;
 		let notify = self.notify.clone();
 		vars.register_rw(
 			&self.persister,
 			"resync-worker-count",
 			|p| p.get_with(|x| x.n_workers),
-			move |p, n_workers| {
-				if !(1..=MAX_RESYNC_WORKERS).contains(&n_workers) {
-					return Err(Error::Message(format!(
-						"Invalid number of resync workers, must be between 1 and {}",
-						MAX_RESYNC_WORKERS
-					)));
-				}
-				p.set_with(|x| x.n_workers = n_workers)?;
-				notify.notify_waiters();
-				Ok(())
-			},
+			move |p, n_workers| set_resync_tuning(p, &notify, max_workers, Some(n_workers), None),
+		);
+
+		let notify = self.notify.clone();
+		vars.register_rw(
+			&self.persister,
+			"resync-paused",
+			|p| p.get_with(|x| x.paused),
+			move |p, paused| set_paused(p, &notify, paused),
 		);
 
 		let notify = self.notify.clone();
@@ -165,72 +1036,629 @@ impl BlockResyncManager {
 			"resync-tranquility",
 			|p| p.get_with(|x| x.tranquility),
 			move |p, tranquility| {
-				p.set_with(|x| x.tranquility = tranquility)?;
-				notify.notify_waiters();
-				Ok(())
+				set_resync_tuning(p, &notify, max_workers, None, Some(tranquility))
 			},
 		);
-	}
 
-	// ---- Resync loop ----
+		for index in 0..max_workers {
+			let notify = self.notify.clone();
+			vars.register_rw(
+				&self.persister,
+				format!("resync-tranquility-{}", index + 1),
+				move |p| {
+					p.get_with(|x| x.worker_tranquility.get(index).copied().flatten())
+						.map(|t| t.to_string())
+						.unwrap_or_else(|| "default".into())
+				},
+				move |p, value: String| {
+					let tranquility = match value.as_str() {
+						"default" => None,
+						v => Some(v.parse().ok_or_message("Invalid tranquility")?),
+					};
+					set_worker_tranquility(p, &notify, max_workers, index, tranquility)
+				},
+			);
+		}
 
-	// This part manages a queue of blocks that need to be
-	// "resynchronized", i.e. that need to have a check that
-	// they are at present if we need them, or that they are
-	// deleted once the garbage collection delay has passed.
-	//
-	// Here are some explanations on how the resync queue works.
-	// There are two Sled trees that are used to have information
-	// about the status of blocks that need to be resynchronized:
-	//
-	// - resync.queue: a tree that is ordered first by a timestamp
-	//   (in milliseconds since Unix epoch) that is the time at which
-	//   the resync must be done, and second by block hash.
-	//   The key in this tree is just:
-	//       concat(timestamp (8 bytes), hash (32 bytes))
-	//   The value is the same 32-byte hash.
-	//
-	// - resync.errors: a tree that indicates for each block
-	//   if the last resync resulted in an error, and if so,
-	//   the following two informations (see the ErrorCounter struct):
-	//   - how many consecutive resync errors for this block?
-	//   - when was the last try?
-	//   These two informations are used to implement an
-	//   exponential backoff retry strategy.
-	//   The key in this tree is the 32-byte hash of the block,
-	//   and the value is the encoded ErrorCounter value.
-	//
-	// We need to have these two trees, because the resync queue
-	// is not just a queue of items to process, but a set of items
-	// that are waiting a specific delay until we can process them
-	// (the delay being necessary both internally for the exponential
-	// backoff strategy, and exposed as a parameter when adding items
-	// to the queue, e.g. to wait until the GC delay has passed).
-	// This is why we need one tree ordered by time, and one
-	// ordered by identifier of item to be processed (block hash).
-	//
-	// When the worker wants to process an item it takes from
-	// resync.queue, it checks in resync.errors that if there is an
-	// exponential back-off delay to await, it has passed before we
-	// process the item. If not, the item in the queue is skipped
-	// (but added back for later processing after the time of the
-	// delay).
-	//
-	// An alternative that would have seemed natural is to
-	// only add items to resync.queue with a processing time that is
-	// after the delay, but there are several issues with this:
-	// - This requires to synchronize updates to resync.queue and
-	//   resync.errors (with the current model, there is only one thread,
-	//   the worker thread, that accesses resync.errors,
-	//   so no need to synchronize) by putting them both in a lock.
-	//   This would mean that block_incref might need to take a lock
-	//   before doing its thing, meaning it has much more chances of
-	//   not completing successfully if something bad happens to Garage.
-	//   Currently Garage is not able to recover from block_incref that
-	//   doesn't complete successfully, because it is necessary to ensure
-	//   the consistency between the state of the block manager and
-	//   information in the BlockRef table.
-	// - If a resync fails, we put that block in the resync.errors table,
+		vars.register_rw(
+			&self.persister,
+			"resync-block-timeout",
+			|p| p.get_with(|x| x.block_timeout_secs),
+			|p, block_timeout_secs: u64| {
+				if block_timeout_secs == 0 {
+					return Err(Error::Message(
+						"Resync block timeout must be at least 1 second".into(),
+					));
+				}
+				p.set_with(|x| x.block_timeout_secs = block_timeout_secs)
+			},
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-idle-poll-interval",
+			|p| p.get_with(|x| x.idle_poll_interval_ms),
+			|p, msec: u64| set_idle_poll_interval(p, Duration::from_millis(msec)),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-retry-delay",
+			|p| p.get_with(|x| x.retry_delay_secs),
+			|p, secs: u64| set_retry_delay(p, Duration::from_secs(secs)),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-min-deletion-delay",
+			|p| p.get_with(|x| x.min_deletion_delay_secs),
+			|p, secs: u64| set_min_deletion_delay(p, Duration::from_secs(secs)),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-retry-jitter",
+			|p| p.get_with(|x| x.retry_jitter),
+			|p, jitter: f64| set_retry_jitter(p, jitter),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-max-backoff-power",
+			|p| p.get_with(|x| x.max_backoff_power),
+			|p, power: u64| set_max_backoff_power(p, power),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-auto-scale",
+			|p| p.get_with(|x| x.auto_scale),
+			|p, auto_scale| p.set_with(|x| x.auto_scale = auto_scale),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-auto-scale-up",
+			|p| p.get_with(|x| x.auto_scale_up_queue_len),
+			|p, up: u64| {
+				let down = p.get_with(|x| x.auto_scale_down_queue_len);
+				set_auto_scale_thresholds(p, down, up)
+			},
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-auto-scale-down",
+			|p| p.get_with(|x| x.auto_scale_down_queue_len),
+			|p, down: u64| {
+				let up = p.get_with(|x| x.auto_scale_up_queue_len);
+				set_auto_scale_thresholds(p, down, up)
+			},
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-max-bytes-per-sec",
+			|p| p.get_with(|x| x.max_bytes_per_sec),
+			|p, max_bytes_per_sec| p.set_with(|x| x.max_bytes_per_sec = max_bytes_per_sec),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-max-concurrent-sends",
+			|p| p.get_with(|x| x.max_concurrent_sends),
+			|p, max_concurrent_sends| p.set_with(|x| x.max_concurrent_sends = max_concurrent_sends),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-offload-with-quorum",
+			|p| p.get_with(|x| x.offload_with_quorum),
+			|p, offload_with_quorum| p.set_with(|x| x.offload_with_quorum = offload_with_quorum),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-verify-only",
+			|p| p.get_with(|x| x.verify_only),
+			|p, verify_only| p.set_with(|x| x.verify_only = verify_only),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-status-cache",
+			|p| p.get_with(|x| x.status_cache),
+			|p, status_cache| p.set_with(|x| x.status_cache = status_cache),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-prioritize-recent",
+			|p| p.get_with(|x| x.prioritize_recent),
+			|p, prioritize_recent| p.set_with(|x| x.prioritize_recent = prioritize_recent),
+		);
+
+		vars.register_rw(
+			&self.persister,
+			"resync-queue-high-water",
+			|p| p.get_with(|x| x.queue_high_water),
+			|p, queue_high_water| p.set_with(|x| x.queue_high_water = queue_high_water),
+		);
+	}
+
+	/// Add a source from which resync can fetch blocks that are needed
+	/// but absent locally. Sources are tried in the order in which they are
+	/// added, the first one being the other nodes of the cluster.
+	pub fn add_block_source(&self, source: Arc<dyn BlockSource>) {
+		self.sources.lock().unwrap().push(source);
+	}
+
+	async fn fetch_block(&self, hash: &Hash) -> Result<(DataBlock, BlockOrigin), Error> {
+		let sources = self.sources.lock().unwrap().clone();
+		fetch_from_sources(&sources, hash).await
+	}
+
+	/// Explain the resync status of a block: whether it is present and needed,
+	/// its state in the resync queue, and what resync would do with it now.
+	/// If `query_peers` is set, the other nodes are asked whether they need
+	/// the block. This has no side effect on the block or the resync queue.
+	pub async fn explain(
+		&self,
+		manager: &BlockManager,
+		hash: &Hash,
+		query_peers: bool,
+	) -> Result<ResyncExplanation, Error> {
+		let BlockStatus { exists, needed } = manager.check_block_status(hash).await?;
+		let mut explanation = self.explain_status(hash, exists, &needed, now_msec())?;
+
+		if query_peers {
+			let mut who = manager.replication.write_nodes(hash);
+			who.retain(|id| *id != manager.system.id);
+			let mut peers_needing = vec![];
+			for (node, needed) in query_need_block(manager, &who, hash, PRIO_NORMAL).await? {
+				match needed {
+					Ok(true) => peers_needing.push(node),
+					Ok(false) => (),
+					Err(e) => warn!("NeedBlockQuery to {:?} failed: {}", node, e),
+				}
+			}
+			explanation.peers_needing = Some(peers_needing);
+		}
+
+		Ok(explanation)
+	}
+
+	fn explain_status(
+		&self,
+		hash: &Hash,
+		exists: bool,
+		needed: &RcEntry,
+		now: u64,
+	) -> Result<ResyncExplanation, Error> {
+		// The queue is ordered by priority and time, finding the entries
+		// of a block requires a full scan
+		let mut queued_at = vec![];
+		let mut busy = false;
+		{
+			let busy_set = self.busy_set.lock().unwrap();
+			for ent in self.queue.iter()? {
+				let (key, value) = ent?;
+				if value == hash.as_slice() {
+					queued_at.push(queue_key_time(&key));
+					busy |= busy_set.contains(&key);
+				}
+			}
+		}
+
+		let errors = self
+			.errors
+			.get(hash.as_slice())?
+			.map(|ec| ErrorCounter::decode(&ec))
+			.filter(|ec| ec.errors > 0)
+			.map(|ec| (ec.errors, ec.next_try(hash, &self.retry_backoff())));
+		let quarantined = self.quarantine.get(hash.as_slice())?.is_some();
+
+		let action = if quarantined {
+			ResyncAction::Quarantined
+		} else if matches!(errors, Some((_, next_try)) if now < next_try) {
+			ResyncAction::WaitForBackoff
+		} else if exists && needed.is_deletable() && !self.deletion_grace_elapsed(needed, now) {
+			ResyncAction::WaitForDeletionDelay
+		} else if exists && needed.is_deletable() {
+			ResyncAction::OffloadAndDelete
+		} else if needed.is_nonzero() && !exists {
+			ResyncAction::Fetch
+		} else {
+			ResyncAction::Nothing
+		};
+
+		Ok(ResyncExplanation {
+			hash: *hash,
+			exists,
+			refcount: needed.as_u64(),
+			is_nonzero: needed.is_nonzero(),
+			is_deletable: needed.is_deletable(),
+			queued_at,
+			busy,
+			errors,
+			quarantined,
+			action,
+			peers_needing: None,
+		})
+	}
+
+	/// Change the number of resync workers and/or the resync tranquility.
+	/// Both values are validated before any of them is applied, and they
+	/// are persisted together.
+	pub fn set_resync_tuning(
+		&self,
+		n_workers: Option<usize>,
+		tranquility: Option<u32>,
+	) -> Result<(), Error> {
+		set_resync_tuning(
+			&self.persister,
+			&self.notify,
+			self.max_workers,
+			n_workers,
+			tranquility,
+		)
+	}
+
+	/// Change the tranquility of a single resync worker, or make it use
+	/// the tranquility of all workers again if `tranquility` is None
+	pub fn set_worker_tranquility(
+		&self,
+		index: usize,
+		tranquility: Option<u32>,
+	) -> Result<(), Error> {
+		set_worker_tranquility(
+			&self.persister,
+			&self.notify,
+			self.max_workers,
+			index,
+			tranquility,
+		)
+	}
+
+	/// Change the minimum delay before a block that has no references
+	/// anymore is checked for deletion. It can't be shorter than the
+	/// block GC delay.
+	pub fn set_min_deletion_delay(&self, delay: Duration) -> Result<(), Error> {
+		set_min_deletion_delay(&self.persister, delay)
+	}
+
+	/// Minimum delay before a block that has no references anymore
+	/// is checked for deletion
+	pub(crate) fn min_deletion_delay(&self) -> Duration {
+		Duration::from_secs(self.persister.get_with(|x| x.min_deletion_delay_secs))
+	}
+
+	/// Whether a block with no references has been kept for long enough to
+	/// be deleted. Its reference counter already says that the GC delay has
+	/// passed, this also applies the minimum deletion delay when it is longer.
+	fn deletion_grace_elapsed(&self, needed: &RcEntry, now: u64) -> bool {
+		match needed {
+			RcEntry::Deletable { at_time } => now > self.deletion_grace_end(*at_time),
+			_ => true,
+		}
+	}
+
+	/// Time at which a block that became deletable at `at_time` (according
+	/// to its reference counter) can actually be deleted
+	fn deletion_grace_end(&self, at_time: u64) -> u64 {
+		let extra = self.min_deletion_delay().saturating_sub(BLOCK_GC_DELAY);
+		at_time + extra.as_millis() as u64
+	}
+
+	/// Change the base delay before a block whose resync failed is retried
+	pub fn set_retry_delay(&self, delay: Duration) -> Result<(), Error> {
+		set_retry_delay(&self.persister, delay)
+	}
+
+	/// Change the fraction by which retry delays are spread, 0 disables it
+	pub fn set_retry_jitter(&self, jitter: f64) -> Result<(), Error> {
+		set_retry_jitter(&self.persister, jitter)
+	}
+
+	/// Change the maximum number of times the retry delay is doubled
+	/// after consecutive failures
+	pub fn set_max_backoff_power(&self, max_backoff_power: u64) -> Result<(), Error> {
+		set_max_backoff_power(&self.persister, max_backoff_power)
+	}
+
+	/// Change the interval at which idle resync workers check the queue
+	/// without having been notified of a new entry
+	pub fn set_idle_poll_interval(&self, interval: Duration) -> Result<(), Error> {
+		set_idle_poll_interval(&self.persister, interval)
+	}
+
+	/// Pause or resume resync. While resync is paused, all resync workers
+	/// are idle, but the configured number of workers is kept.
+	pub fn set_paused(&self, paused: bool) -> Result<(), Error> {
+		set_paused(&self.persister, &self.notify, paused)
+	}
+
+	pub(crate) fn is_paused(&self) -> bool {
+		self.persister.get_with(|x| x.paused)
+	}
+
+	/// Enable or disable the verify-only mode, in which resync only checks
+	/// that the nodes that should store blocks have them, as a consistency
+	/// check that doesn't change anything
+	pub fn set_verify_only(&self, verify_only: bool) -> Result<(), Error> {
+		self.persister.set_with(|x| x.verify_only = verify_only)
+	}
+
+	pub(crate) fn is_verify_only(&self) -> bool {
+		self.persister.get_with(|x| x.verify_only)
+	}
+
+	/// Enable or disable the automatic scaling of the number of resync
+	/// workers with the length of the queue
+	pub fn set_auto_scale(&self, auto_scale: bool) -> Result<(), Error> {
+		self.persister.set_with(|x| x.auto_scale = auto_scale)
+	}
+
+	/// Change the queue lengths below which a resync worker is removed and
+	/// above which one is added, when auto-scaling is enabled
+	pub fn set_auto_scale_thresholds(&self, down: u64, up: u64) -> Result<(), Error> {
+		set_auto_scale_thresholds(&self.persister, down, up)
+	}
+
+	/// If auto-scaling is enabled, add or remove a resync worker depending
+	/// on the length of the queue. Returns the new number of workers,
+	/// if it was changed.
+	pub(crate) fn auto_scale_step(&self, queue_len: u64) -> Result<Option<usize>, Error> {
+		let (auto_scale, n_workers, down, up) = self.persister.get_with(|x| {
+			(
+				x.auto_scale,
+				x.n_workers,
+				x.auto_scale_down_queue_len,
+				x.auto_scale_up_queue_len,
+			)
+		});
+		if !auto_scale || self.is_paused() {
+			return Ok(None);
+		}
+		let target = auto_scale_target(n_workers, self.max_workers, queue_len, down, up);
+		if target == n_workers {
+			return Ok(None);
+		}
+		info!(
+			"Resync auto-scaling: {} blocks in queue, going from {} to {} workers",
+			queue_len, n_workers, target
+		);
+		set_resync_tuning(
+			&self.persister,
+			&self.notify,
+			self.max_workers,
+			Some(target),
+			None,
+		)?;
+		Ok(Some(target))
+	}
+
+	/// Change the maximum number of bytes per second sent by the resync
+	/// workers when offloading blocks, 0 for no limit
+	pub fn set_resync_rate_limit(&self, max_bytes_per_sec: u64) -> Result<(), Error> {
+		self.persister
+			.set_with(|x| x.max_bytes_per_sec = max_bytes_per_sec)
+	}
+
+	/// Wait until `bytes` can be sent without exceeding the upload rate limit.
+	/// The limit is shared by all resync workers.
+	async fn throttle_upload(&self, bytes: u64) {
+		loop {
+			let rate = self.persister.get_with(|x| x.max_bytes_per_sec);
+			if rate == 0 {
+				return;
+			}
+			let wait = self
+				.upload_limiter
+				.lock()
+				.unwrap()
+				.take(bytes, rate, Instant::now());
+			match wait {
+				None => return,
+				Some(delay) => tokio::time::sleep(delay).await,
+			}
+		}
+	}
+
+	/// Change the maximum number of blocks sent to other nodes at the same
+	/// time by all resync workers when offloading blocks, 0 for no limit
+	pub fn set_max_concurrent_sends(&self, max_concurrent_sends: u64) -> Result<(), Error> {
+		self.persister
+			.set_with(|x| x.max_concurrent_sends = max_concurrent_sends)
+	}
+
+	/// Wait until a block can be sent to another node without exceeding the
+	/// maximum number of concurrent sends. The permit must be kept until the
+	/// block is sent. When the limit is changed, the sends that are in progress
+	/// are counted in the new limit.
+	async fn acquire_send_permit(&self) -> Option<SendPermit> {
+		let max = self.persister.get_with(|x| x.max_concurrent_sends);
+		if max == 0 {
+			return None;
+		}
+		let semaphore = {
+			let mut send_limiter = self.send_limiter.lock().unwrap();
+			if send_limiter.max != max {
+				send_limiter.set_max(max);
+			}
+			send_limiter.semaphore.clone()
+		};
+		let permit = semaphore.acquire_owned().await.ok()?;
+		Some(SendPermit {
+			permit: Some(permit),
+			limiter: self.send_limiter.clone(),
+		})
+	}
+
+	pub(crate) fn retry_backoff(&self) -> RetryBackoff {
+		self.persister.get_with(|x| RetryBackoff {
+			delay_msec: x.retry_delay_secs * 1000,
+			max_power: x.max_backoff_power,
+			jitter: x.retry_jitter,
+		})
+	}
+
+	/// Apply the resync schedule for the given minute of the day (UTC):
+	/// the window that contains it, if any, overrides the persisted number
+	/// of workers and tranquility. Returns whether the window in effect changed.
+	pub(crate) fn update_schedule(&self, minute_of_day: u32) -> bool {
+		let window = self.schedule.iter().position(|w| w.contains(minute_of_day));
+		{
+			let mut active = self.active_window.lock().unwrap();
+			if *active == window {
+				return false;
+			}
+			match window {
+				Some(i) => info!("Resync schedule: entering {} window", self.schedule[i].name),
+				None => info!("Resync schedule: back to the static configuration"),
+			}
+			*active = window;
+		}
+		// Wake up workers that have to be enabled or disabled
+		self.notify.notify_waiters();
+		true
+	}
+
+	/// Name of the scheduled window currently in effect, if any
+	pub(crate) fn scheduled_window(&self) -> Option<&str> {
+		self.active_window
+			.lock()
+			.unwrap()
+			.map(|i| self.schedule[i].name.as_str())
+	}
+
+	/// Number of workers and tranquility currently in effect
+	pub(crate) fn effective_tuning(&self) -> (usize, u32) {
+		let (n_workers, tranquility) = self.persister.get_with(|x| (x.n_workers, x.tranquility));
+		match *self.active_window.lock().unwrap() {
+			Some(i) => {
+				let window = &self.schedule[i];
+				(
+					window
+						.n_workers
+						.map(|n| n.clamp(1, self.max_workers))
+						.unwrap_or(n_workers.min(self.max_workers)),
+					window.tranquility.unwrap_or(tranquility),
+				)
+			}
+			None => (n_workers.min(self.max_workers), tranquility),
+		}
+	}
+
+	/// Tranquility currently in effect for the resync worker of the given
+	/// index: the one of the scheduled window if it sets one, otherwise the
+	/// one of the worker if it has its own, otherwise the one of all workers
+	pub(crate) fn worker_tranquility(&self, index: usize) -> u32 {
+		if let Some(i) = *self.active_window.lock().unwrap() {
+			if let Some(tranquility) = self.schedule[i].tranquility {
+				return tranquility;
+			}
+		}
+		self.persister.get_with(|x| {
+			x.worker_tranquility
+				.get(index)
+				.copied()
+				.flatten()
+				.unwrap_or(x.tranquility)
+		})
+	}
+
+	/// Remember that a block was just read, so that it is resynced
+	/// in priority if it needs to be. Does nothing unless
+	/// resync-prioritize-recent is enabled.
+	pub(crate) fn record_access(&self, hash: &Hash) {
+		if self.persister.get_with(|x| x.prioritize_recent) {
+			self.recent_blocks.lock().unwrap().insert(*hash);
+		}
+	}
+
+	// ---- Resync loop ----
+
+	// This part manages a queue of blocks that need to be
+	// "resynchronized", i.e. that need to have a check that
+	// they are at present if we need them, or that they are
+	// deleted once the garbage collection delay has passed.
+	//
+	// Here are some explanations on how the resync queue works.
+	// There are two main Sled trees that are used to have information
+	// about the status of blocks that need to be resynchronized:
+	//
+	// - resync.queue: a tree that is ordered first by priority
+	//   (see ResyncPriority, 0 being the highest), then by a timestamp
+	//   (in milliseconds since Unix epoch) that is the time at which
+	//   the resync must be done, and then by block hash.
+	//   The key in this tree is:
+	//       concat(priority (1 byte), timestamp (8 bytes), hash (32 bytes))
+	//   The value is the same 32-byte hash.
+	//
+	// - resync.errors: a tree that indicates for each block
+	//   if the last resync resulted in an error, and if so,
+	//   the following two informations (see the ErrorCounter struct):
+	//   - how many consecutive resync errors for this block?
+	//   - when was the last try?
+	//   These two informations are used to implement an
+	//   exponential backoff retry strategy.
+	//   The key in this tree is the 32-byte hash of the block,
+	//   and the value is the encoded ErrorCounter value.
+	//   Besides the workers, which update it after each try, it is
+	//   written by the admin operations that reset backoff delays
+	//   (retry_now, retry_all_errors and clear_backoff).
+	//
+	// Other trees complement these two:
+	//
+	// - resync.queue_index: for each block in resync.queue, the key of
+	//   its entry, so that a block is queued at most once.
+	// - resync.claims: the entries of resync.queue that are being
+	//   processed by a worker, with the time until which they are
+	//   claimed, so that no other worker processes them meanwhile.
+	//   Claims left at shutdown are counted and cleared at startup.
+	// - resync.resync_done: entries that were processed successfully
+	//   but might not have been removed from resync.queue yet,
+	//   with the state of their block at that time.
+	// - resync.write_errors: the consecutive failures to write a
+	//   fetched block to disk, counted apart from resync.errors.
+	// - resync.quarantine: blocks that failed to be written too many
+	//   times, which are not processed until they are released.
+	// - resync.last_verified: for each block stored locally, the time
+	//   of its last successful resync.
+	//
+	// We need to have the two main trees, because the resync queue
+	// is not just a queue of items to process, but a set of items
+	// that are waiting a specific delay until we can process them
+	// (the delay being necessary both internally for the exponential
+	// backoff strategy, and exposed as a parameter when adding items
+	// to the queue, e.g. to wait until the GC delay has passed).
+	// This is why we need one tree ordered by time, and one
+	// ordered by identifier of item to be processed (block hash).
+	//
+	// When the worker wants to process an item it takes from
+	// resync.queue, it checks in resync.errors that if there is an
+	// exponential back-off delay to await, it has passed before we
+	// process the item. If not, the item in the queue is skipped
+	// (but added back for later processing after the time of the
+	// delay).
+	//
+	// An alternative that would have seemed natural is to
+	// only add items to resync.queue with a processing time that is
+	// after the delay, but there are several issues with this:
+	// - This requires to synchronize updates to resync.queue and
+	//   resync.errors (with the current model, resync.errors is only
+	//   accessed by the workers and by the admin operations, which do
+	//   not need to synchronize with block_incref) by putting them
+	//   both in a lock.
+	//   This would mean that block_incref might need to take a lock
+	//   before doing its thing, meaning it has much more chances of
+	//   not completing successfully if something bad happens to Garage.
+	//   Currently Garage is not able to recover from block_incref that
+	//   doesn't complete successfully, because it is necessary to ensure
+	//   the consistency between the state of the block manager and
+	//   information in the BlockRef table.
+	// - If a resync fails, we put that block in the resync.errors table,
 	//   and also add it back to resync.queue to be processed after
 	//   the exponential back-off delay,
 	//   but maybe the block is already scheduled to be resynced again
@@ -246,368 +1674,3301 @@ impl BlockResyncManager {
 	// is a natural condition that is handled properly).
 
 	pub(crate) fn put_to_resync(&self, hash: &Hash, delay: Duration) -> db::Result<()> {
+		self.put_to_resync_with_priority(hash, delay, ResyncPriority::Normal)
+	}
+
+	pub(crate) fn put_to_resync_with_priority(
+		&self,
+		hash: &Hash,
+		delay: Duration,
+		priority: ResyncPriority,
+	) -> db::Result<()> {
+		let prioritize_recent = self.persister.get_with(|x| x.prioritize_recent);
+		let delay = if prioritize_recent && self.recent_blocks.lock().unwrap().contains(hash) {
+			delay / RESYNC_RECENT_DELAY_DIVISOR
+		} else {
+			delay
+		};
+		let delay = match priority {
+			ResyncPriority::High => delay,
+			ResyncPriority::Normal => delay + self.backpressure(),
+		};
 		let when = now_msec() + delay.as_millis() as u64;
-		self.put_to_resync_at(hash, when)
+		self.put_to_resync_at(hash, when, priority)
+	}
+
+	/// Additional delay of blocks put in the queue with normal priority,
+	/// when the queue is longer than its high-water mark. This is only a
+	/// delay adjustment: blocks are put in the queue in the incref and
+	/// decref path, which must never block.
+	fn backpressure(&self) -> Duration {
+		let queue_high_water = self.persister.get_with(|x| x.queue_high_water);
+		backpressure_delay(self.queue.len() as u64, queue_high_water)
+	}
+
+	/// Put in the queue a block that might have no references anymore, to be
+	/// checked for deletion after `delay`. Whatever the given delay, the block
+	/// is not checked before the minimum deletion delay, so that it is
+	/// never evaluated for deletion during the GC grace period.
+	pub(crate) fn put_to_resync_deletable(&self, hash: &Hash, delay: Duration) -> db::Result<()> {
+		let delay = delay.max(self.min_deletion_delay()) + self.backpressure();
+		self.put_to_resync_at(
+			hash,
+			now_msec() + delay.as_millis() as u64,
+			ResyncPriority::Normal,
+		)
+	}
+
+	pub(crate) fn put_to_resync_at(
+		&self,
+		hash: &Hash,
+		when: u64,
+		priority: ResyncPriority,
+	) -> db::Result<()> {
+		trace!("Put resync_queue: {} {:?} {:?}", when, priority, hash);
+
+		// Only a single entry of each block is kept in the queue, scheduled
+		// at the soonest time and with the highest priority of the requests.
+		// An entry that is being processed is left alone, as its worker
+		// removes it once it is done.
+		let previous = match self.queue_index.get(hash.as_slice())? {
+			Some(old)
+				if !self.busy_set.lock().unwrap().contains(&old[..])
+					&& self.queue.get(&old[..])?.is_some() =>
+			{
+				Some(old)
+			}
+			_ => None,
+		};
+		let (priority, when) = match &previous {
+			Some(old) => (
+				priority.min(queue_key_priority(old)),
+				when.min(queue_key_time(old)),
+			),
+			None => (priority, when),
+		};
+		let key = queue_key(priority, when, hash.as_slice());
+		if previous.as_deref() == Some(&key[..]) {
+			trace!("Block {:?} is already queued sooner", hash);
+			return Ok(());
+		}
+
+		// The new entry is inserted before the old one is removed,
+		// so that the block stays in the queue if we crash in between
+		self.queue.insert(&key, hash.as_ref())?;
+		self.update_queue_len_watch();
+		self.queue_index.insert(hash.as_slice(), &key)?;
+		if let Some(old) = previous {
+			self.queue.remove(old)?;
+			self.update_queue_len_watch();
+		}
+		*self.empty_since.lock().unwrap() = None;
+		self.drained
+			.send_if_modified(|drained| std::mem::replace(drained, false));
+		self.notify.notify_waiters();
+		Ok(())
+	}
+
+	/// Process the next block of the queue if it is due. `current` is set to
+	/// the hash of the block while it is being resynced. Errors caused by
+	/// contention on the database are not returned: the block is simply
+	/// processed again by a later iteration.
+	async fn resync_iter(
+		&self,
+		manager: &BlockManager,
+		current: &ArcSwapOption<Hash>,
+	) -> Result<ResyncIterResult, db::Error> {
+		match self.resync_iter_inner(manager, current).await {
+			Err(e) if e.is_transient() => {
+				debug!("Resync: database busy, retrying later ({})", e);
+				tokio::time::sleep(RESYNC_TRANSIENT_ERROR_DELAY).await;
+				Ok(ResyncIterResult::BusyDidNothing)
+			}
+			res => res,
+		}
 	}
 
-	pub(crate) fn put_to_resync_at(&self, hash: &Hash, when: u64) -> db::Result<()> {
-		trace!("Put resync_queue: {} {:?}", when, hash);
-		let mut key = u64::to_be_bytes(when).to_vec();
-		key.extend(hash.as_ref());
-		self.queue.insert(key, hash.as_ref())?;
-		self.notify.notify_waiters();
-		Ok(())
+	async fn resync_iter_inner(
+		&self,
+		manager: &BlockManager,
+		current: &ArcSwapOption<Hash>,
+	) -> Result<ResyncIterResult, db::Error> {
+		if let Some(mut block) = self.get_block_to_resync()? {
+			let time_msec = queue_key_time(&block.time_bytes);
+			let priority = queue_key_priority(&block.time_bytes);
+			let now = now_msec();
+
+			if now >= time_msec {
+				let hash = Hash::try_from(&block.hash_bytes[..]).unwrap();
+
+				if self.quarantine.get(hash.as_slice())?.is_some() {
+					// Quarantined blocks are not processed anymore until
+					// an operator releases them (see clear_backoff), which
+					// puts them back in the queue
+					self.finish_entry(&block.time_bytes)?;
+					return Ok(ResyncIterResult::BusyDidNothing);
+				}
+
+				if let Some(ec) = self.errors.get(hash.as_slice())? {
+					let next_try = ErrorCounter::decode(&ec).next_try(&hash, &self.retry_backoff());
+					// (if the next try is unreasonably far away, the clock has
+					// jumped backwards and the backoff delay is ignored)
+					if now < next_try && !self.is_beyond_sane_delay(next_try, now) {
+						// if next retry after an error is not yet,
+						// don't do resync and return early, but still
+						// make sure the item is still in queue at expected time
+						self.put_to_resync_at(&hash, next_try, priority)?;
+						// next_try > now >= time_msec, so this remove
+						// is not removing the one we added just above
+						// (we want to do the remove after the insert to ensure
+						// that the item is not lost if we crash in-between)
+						self.finish_entry(&block.time_bytes)?;
+						return Ok(ResyncIterResult::BusyDidNothing);
+					}
+				}
+
+				// If this exact queue entry was already processed successfully,
+				// but we crashed before removing it from the queue, there is
+				// nothing to do again unless the block has changed since
+				if self.resync_done.get(&block.time_bytes)?.is_some() {
+					if let Ok(BlockStatus { exists, needed }) =
+						manager.check_block_status(&hash).await
+					{
+						let fingerprint = status_fingerprint(exists, &needed);
+						if self.is_replay_noop(&block.time_bytes, &fingerprint)? {
+							debug!(
+								"Resync block {:?}: already done before restart, skipping",
+								hash
+							);
+							self.finish_entry(&block.time_bytes)?;
+							return Ok(ResyncIterResult::BusyDidNothing);
+						}
+					}
+				}
+
+				let tracer = opentelemetry::global::tracer("garage");
+				let trace_id = gen_uuid();
+				let span = tracer
+					.span_builder("Resync block")
+					.with_trace_id(
+						opentelemetry::trace::TraceId::from_hex(&hex::encode(
+							&trace_id.as_slice()[..16],
+						))
+						.unwrap(),
+					)
+					.with_attributes(vec![KeyValue::new("block", format!("{:?}", hash))])
+					.start(&tracer);
+
+				let block_timeout =
+					Duration::from_secs(self.persister.get_with(|x| x.block_timeout_secs));
+				block.claim(block_timeout + RESYNC_CLAIM_LEASE_MARGIN)?;
+				current.store(Some(Arc::new(hash)));
+				let res = with_block_timeout(
+					&hash,
+					block_timeout,
+					self.resync_block(manager, &hash)
+						.with_context(Context::current_with_span(span))
+						.bound_record_duration(&manager.metrics.resync_duration),
+				)
+				.await;
+				current.store(None);
+
+				manager.metrics.resync_counter.add(1);
+
+				if let Err(e) = &res {
+					manager.metrics.resync_error_counter.add(1);
+					error!("Error when resyncing {:?}: {}", hash, e);
+					manager.emit_resync_event(|| ResyncEvent::Errored {
+						hash,
+						error: e.to_string(),
+					});
+
+					self.reschedule_after_error(&block, &hash, now, e)?;
+				} else {
+					if self.errors.remove(hash.as_slice())?.is_some() {
+						manager.metrics.resync_recovered_counter.add(1);
+					}
+					// Remember that this entry is done before removing it,
+					// so that if we crash in-between it is not processed again
+					// (the check must end before the claim of the entry does)
+					if let Ok(Ok(BlockStatus { exists, needed })) = tokio::time::timeout(
+						RESYNC_CLAIM_LEASE_MARGIN,
+						manager.check_block_status(&hash),
+					)
+					.await
+					{
+						self.mark_done(&block.time_bytes, &status_fingerprint(exists, &needed))?;
+					}
+					self.finish_entry(&block.time_bytes)?;
+				}
+
+				Ok(ResyncIterResult::BusyDidSomething)
+			} else if self.clamp_far_future_entry(&block, time_msec, now)? {
+				manager.metrics.resync_clock_skew_counter.add(1);
+				Ok(ResyncIterResult::BusyDidSomething)
+			} else {
+				Ok(ResyncIterResult::IdleFor(Duration::from_millis(
+					time_msec - now,
+				)))
+			}
+		} else {
+			// Here we wait either for a notification that an item has been
+			// added to the queue, or for the idle poll interval (10 secs by
+			// default) to expire.
+			// The delay avoids a race condition where the notification happens
+			// between the time we checked the queue and the first poll
+			// to resync_notify.notified(): if that happens, we'll just loop
+			// back after the interval, which is fine.
+			// If the queue was just emptied, we come back sooner to
+			// report it as drained.
+			let poll_interval =
+				Duration::from_millis(self.persister.get_with(|x| x.idle_poll_interval_ms));
+			match self.update_drained(now_msec()) {
+				Some(wait) => Ok(ResyncIterResult::IdleFor(wait.min(poll_interval))),
+				None => Ok(ResyncIterResult::IdleFor(poll_interval)),
+			}
+		}
+	}
+
+	/// Count an error for the block of a queue entry whose resync failed,
+	/// and move the entry to the time of its next try
+	fn reschedule_after_error(
+		&self,
+		block: &BusyBlock,
+		hash: &Hash,
+		now: u64,
+		e: &Error,
+	) -> Result<(), db::Error> {
+		let err_counter = match self.errors.get(hash.as_slice())? {
+			Some(ec) => ErrorCounter::decode(&ec).add1(now + 1),
+			None => ErrorCounter::new(now + 1),
+		}
+		.with_last_error(&e.to_string());
+
+		self.errors.insert(hash.as_slice(), err_counter.encode())?;
+
+		self.put_to_resync_at(
+			hash,
+			err_counter.next_try(hash, &self.retry_backoff()),
+			queue_key_priority(&block.time_bytes),
+		)?;
+		// the next try is >= now + 1 > now,
+		// the entry we remove from the queue is not
+		// the entry we inserted with put_to_resync_at
+		self.finish_entry(&block.time_bytes)
+	}
+
+	/// Whether `when` is further in the future than any delay the resync
+	/// queue can currently be scheduled with
+	fn is_beyond_sane_delay(&self, when: u64, now: u64) -> bool {
+		let backoff = self.retry_backoff();
+		let longest = std::cmp::max(
+			backoff.max_delay().mul_f64(1.0 + backoff.jitter),
+			self.min_deletion_delay() + RESYNC_BACKPRESSURE_MAX_DELAY,
+		);
+		when.saturating_sub(now) > (longest + RESYNC_SANE_DELAY_MARGIN).as_millis() as u64
+	}
+
+	/// If a queue entry is scheduled further in the future than any delay
+	/// we use, reschedule it now. Returns whether the entry was rescheduled.
+	fn clamp_far_future_entry(
+		&self,
+		block: &BusyBlock,
+		time_msec: u64,
+		now: u64,
+	) -> Result<bool, db::Error> {
+		if !self.is_beyond_sane_delay(time_msec, now) {
+			return Ok(false);
+		}
+		let hash = Hash::try_from(&block.hash_bytes[..]).unwrap();
+		warn!(
+			"Resync of block {:?} is scheduled {}s in the future, the system clock has probably jumped backwards. Rescheduling it now.",
+			hash,
+			(time_msec - now) / 1000
+		);
+		self.put_to_resync_at(&hash, now, queue_key_priority(&block.time_bytes))?;
+		// now < time_msec, so this is not removing the entry we just added
+		self.finish_entry(&block.time_bytes)?;
+		Ok(true)
+	}
+
+	/// Record that the queue entry `key` was processed successfully,
+	/// leaving its block in the state described by `fingerprint`
+	fn mark_done(&self, key: &[u8], fingerprint: &[u8]) -> Result<(), db::Error> {
+		self.resync_done.insert(key, fingerprint)?;
+		Ok(())
+	}
+
+	/// Whether the queue entry `key` was already processed successfully,
+	/// and its block is still in the state in which it was left then
+	fn is_replay_noop(&self, key: &[u8], fingerprint: &[u8]) -> Result<bool, db::Error> {
+		Ok(matches!(self.resync_done.get(key)?, Some(fp) if fp == fingerprint))
+	}
+
+	/// Remove an entry from the queue, and then its done marker if any.
+	/// Done markers thus only exist for the short time between these two
+	/// removals, or for entries that were left in the queue by a crash.
+	fn finish_entry(&self, key: &[u8]) -> Result<(), db::Error> {
+		self.queue.remove(key)?;
+		self.update_queue_len_watch();
+		self.resync_done.remove(key)?;
+		let hash = queue_key_hash(key);
+		if matches!(self.queue_index.get(hash)?, Some(k) if k == key) {
+			self.queue_index.remove(hash)?;
+		}
+		Ok(())
+	}
+
+	fn get_block_to_resync(&self) -> Result<Option<BusyBlock>, db::Error> {
+		let now = now_msec();
+		let mut busy = self.busy_set.lock().unwrap();
+		// The first available entry of each priority is considered: the
+		// entries that are due are taken by order of priority, and if none
+		// is due, the one that will be due first is returned.
+		let mut found: Option<(Vec<u8>, Vec<u8>)> = None;
+		for priority in ResyncPriority::ALL {
+			let mut first = None;
+			for it in self.queue.range(priority_range(priority))? {
+				let (time_bytes, hash_bytes) = it?;
+				if !busy.contains(&time_bytes) && !self.is_claimed(&time_bytes, now)? {
+					first = Some((time_bytes, hash_bytes));
+					break;
+				}
+			}
+			if let Some((time_bytes, hash_bytes)) = first {
+				let time = queue_key_time(&time_bytes);
+				if found
+					.as_ref()
+					.map_or(true, |(k, _)| time < queue_key_time(k))
+				{
+					found = Some((time_bytes, hash_bytes));
+				}
+				if time <= now {
+					break;
+				}
+			}
+		}
+		let (time_bytes, hash_bytes) = match found {
+			Some(entry) => entry,
+			None => return Ok(None),
+		};
+
+		busy.insert(time_bytes.clone());
+		Ok(Some(BusyBlock {
+			time_bytes,
+			hash_bytes,
+			busy_set: self.busy_set.clone(),
+			claims: self.claims.clone(),
+			claimed_until: None,
+		}))
+	}
+
+	/// Whether the queue entry `key` is claimed by a worker whose lease
+	/// has not expired yet
+	fn is_claimed(&self, key: &[u8], now: u64) -> Result<bool, db::Error> {
+		Ok(match self.claims.get(key)? {
+			Some(until) => u64::from_be_bytes(until[0..8].try_into().unwrap()) > now,
+			None => false,
+		})
+	}
+
+	/// Get the number of blocks whose resync was interrupted
+	/// when Garage last stopped
+	pub fn orphaned_claims(&self) -> usize {
+		self.orphaned_claims
+	}
+
+	/// Status of a block, taken from the status cache if it is enabled
+	/// and the block was checked recently
+	async fn block_status(
+		&self,
+		manager: &BlockManager,
+		hash: &Hash,
+	) -> Result<BlockStatus, Error> {
+		if !self.persister.get_with(|x| x.status_cache) {
+			return manager.check_block_status(hash).await;
+		}
+		let now = now_msec();
+		if let Some(status) = self.cached_block_status(hash, now) {
+			return Ok(status);
+		}
+		let status = manager.check_block_status(hash).await?;
+		self.cache_block_status(hash, now, status);
+		Ok(status)
+	}
+
+	fn cached_block_status(&self, hash: &Hash, now: u64) -> Option<BlockStatus> {
+		let ttl = RESYNC_STATUS_CACHE_TTL.as_millis() as u64;
+		match self.status_cache.lock().unwrap().get(hash) {
+			Some((checked_at, status)) if now < checked_at + ttl => Some(*status),
+			_ => None,
+		}
+	}
+
+	fn cache_block_status(&self, hash: &Hash, now: u64, status: BlockStatus) {
+		let ttl = RESYNC_STATUS_CACHE_TTL.as_millis() as u64;
+		let mut cache = self.status_cache.lock().unwrap();
+		if cache.len() >= RESYNC_STATUS_CACHE_CAPACITY {
+			cache.retain(|_, (checked_at, _)| now < *checked_at + ttl);
+			if cache.len() >= RESYNC_STATUS_CACHE_CAPACITY {
+				cache.clear();
+			}
+		}
+		cache.insert(*hash, (now, status));
+	}
+
+	fn track_in_flight(&self, hash: &Hash) -> InFlightResync<'_> {
+		let aborted = Arc::new(AtomicBool::new(false));
+		self.in_flight
+			.lock()
+			.unwrap()
+			.entry(*hash)
+			.or_default()
+			.push(aborted.clone());
+		InFlightResync {
+			resync: self,
+			hash: *hash,
+			aborted,
+		}
+	}
+
+	/// Signal the resyncs of a block in progress that it was deleted or
+	/// moved away, so that they stop cleanly if they can't read it anymore
+	pub(crate) fn abort_in_flight(&self, hash: &Hash) {
+		if let Some(flags) = self.in_flight.lock().unwrap().get(hash) {
+			for aborted in flags.iter() {
+				aborted.store(true, Ordering::Relaxed);
+			}
+		}
+	}
+
+	/// Forget the cached status of a block, which must be done
+	/// whenever the block is written or deleted, or its reference
+	/// counter changes
+	pub(crate) fn invalidate_block_status(&self, hash: &Hash) {
+		self.status_cache.lock().unwrap().remove(hash);
+	}
+
+	async fn resync_block(&self, manager: &BlockManager, hash: &Hash) -> Result<(), Error> {
+		let in_flight = self.track_in_flight(hash);
+		let BlockStatus { exists, needed } = self.block_status(manager, hash).await?;
+
+		if exists != needed.is_needed() || exists != needed.is_nonzero() {
+			debug!(
+				"Resync block {:?}: exists {}, nonzero rc {}, deletable {}",
+				hash,
+				exists,
+				needed.is_nonzero(),
+				needed.is_deletable(),
+			);
+		}
+
+		let mut decision = "no_action";
+
+		// Defense in depth: even if the block was queued too soon,
+		// it is not deleted before its grace period has passed
+		let deletable = needed.is_deletable() && self.deletion_grace_elapsed(&needed, now_msec());
+
+		// Record in the span created in resync_iter why the block is
+		// handled the way it is
+		let cx = Context::current();
+		let span = cx.span();
+		span.set_attribute(KeyValue::new("exists", exists));
+		span.set_attribute(KeyValue::new("needed", needed.is_nonzero()));
+		span.set_attribute(KeyValue::new("deletable", deletable));
+
+		if self.is_verify_only() {
+			let decision = self.verify_replicas(manager, hash, exists, &needed).await?;
+			self.record_decision(manager, decision);
+			return Ok(());
+		}
+
+		if exists && deletable {
+			info!("Resync block {:?}: offloading and deleting", hash);
+
+			let mut who = manager.replication.write_nodes(hash);
+			if who.len() < manager.replication.write_quorum() {
+				return Err(Error::Message("Not trying to offload block because we don't have a quorum of nodes to write to".to_string()));
+			}
+			who.retain(|id| *id != manager.system.id);
+
+			let mut need_nodes = vec![];
+			for (node, needed) in query_need_block(manager, &who, hash, PRIO_BACKGROUND).await? {
+				if needed.err_context("NeedBlockQuery RPC")? {
+					need_nodes.push(node);
+				}
+			}
+
+			if !need_nodes.is_empty() {
+				trace!(
+					"Block {:?} needed by {} nodes, sending",
+					hash,
+					need_nodes.len()
+				);
+
+				for node in need_nodes.iter() {
+					manager
+						.metrics
+						.resync_send_counter
+						.add(1, &[KeyValue::new("to", format!("{:?}", node))]);
+				}
+
+				// The block is streamed from disk to each node, without
+				// being loaded in memory. Its content is verified while it
+				// is read: if our copy is corrupted, the nodes receive an
+				// error instead of the block, the block is moved aside,
+				// and it is not deleted before it has been offloaded.
+				let corrupted = Arc::new(AtomicBool::new(false));
+				let mut put_block_messages = Vec::with_capacity(need_nodes.len());
+				let mut block_size = 0;
+				for _ in need_nodes.iter() {
+					let (header, size, stream) =
+						match manager.read_block_stream(hash, &corrupted).await {
+							Ok(block) => block,
+							Err(e) if in_flight.is_aborted() => {
+								// The block was deleted by another resync of the same
+								// block, or moved aside: it is checked again later
+								info!(
+									"Resync block {:?}: aborted, the block was removed meanwhile ({})",
+									hash, e
+								);
+								self.put_to_resync(hash, Duration::ZERO)?;
+								self.record_decision(manager, "aborted");
+								return Ok(());
+							}
+							Err(e) => return Err(e),
+						};
+					block_size = size;
+					put_block_messages.push(
+						Req::new(BlockRpc::PutBlock {
+							hash: *hash,
+							header,
+						})?
+						.with_stream(stream),
+					);
+				}
+				self.throttle_upload(block_size * need_nodes.len() as u64)
+					.await;
+				// Each send counts in the limit of concurrent sends of all workers,
+				// waiting for it does not count in the timeout of the send
+				let resps = send_to_nodes(
+					need_nodes.iter().copied().zip(put_block_messages).collect(),
+					RESYNC_OFFLOAD_SEND_TIMEOUT,
+					|node, put_block_message| async move {
+						let permit = self.acquire_send_permit().await;
+						async move {
+							let _permit = permit;
+							manager
+								.system
+								.rpc
+								.call(
+									&manager.endpoint,
+									node,
+									put_block_message,
+									RequestStrategy::with_priority(PRIO_BACKGROUND)
+										.without_timeout(),
+								)
+								.await
+						}
+					},
+				)
+				.await;
+				if corrupted.load(Ordering::Relaxed) {
+					manager.metrics.resync_corruption_detected.add(1);
+					error!(
+						"Block {:?} is corrupted, not offloading it to other nodes",
+						hash
+					);
+					manager.handle_corrupted_block(hash).await?;
+					return Err(Error::CorruptData(*hash));
+				}
+				let sent_to = check_offload_result(
+					hash,
+					who.len(),
+					resps,
+					manager.replication.write_quorum(),
+					self.persister.get_with(|x| x.offload_with_quorum),
+				)?;
+				manager.emit_resync_event(|| ResyncEvent::Offloaded {
+					hash: *hash,
+					to_nodes: sent_to,
+				});
+			}
+			info!(
+				"Deleting unneeded block {:?}, offload finished ({} / {})",
+				hash,
+				need_nodes.len(),
+				who.len()
+			);
+
+			manager.delete_if_unneeded(hash).await?;
+			manager.emit_resync_event(|| ResyncEvent::Deleted { hash: *hash });
+
+			manager.rc.clear_deleted_block_rc(hash)?;
+			self.invalidate_block_status(hash);
+			decision = "offloaded_deleted";
+		}
+
+		if let RcEntry::Deletable { at_time } = needed {
+			if exists && !deletable {
+				// The check scheduled when the block became deletable
+				// may have been merged into this sooner one, so schedule
+				// it again for when the GC delay has expired
+				self.put_to_resync_at(
+					hash,
+					self.deletion_grace_end(at_time) + 10_000,
+					ResyncPriority::Normal,
+				)?;
+			}
+		}
+
+		if needed.is_nonzero() && !exists {
+			info!(
+				"Resync block {:?}: fetching absent but needed block (refcount > 0)",
+				hash
+			);
+
+			let (block_data, origin) = self.fetch_block(hash).await?;
+			let source = origin.to_string();
+
+			// Which of the nodes that should store the block served it,
+			// by position in the list of these nodes
+			let replica = match origin {
+				BlockOrigin::Node(from) => manager
+					.replication
+					.read_nodes(hash)
+					.iter()
+					.position(|node| *node == from)
+					.map(|i| i.to_string()),
+				BlockOrigin::Source(_) => None,
+			}
+			.unwrap_or_else(|| "none".into());
+			manager.metrics.resync_recv_counter.add(
+				1,
+				&[
+					KeyValue::new("from", source.clone()),
+					KeyValue::new("replica", replica),
+				],
+			);
+
+			if let Err(e) = manager.write_block(hash, &block_data).await {
+				self.record_write_failure(hash)?;
+				return Err(e);
+			}
+			self.write_errors.remove(hash.as_slice())?;
+			manager.emit_resync_event(|| ResyncEvent::Fetched {
+				hash: *hash,
+				from: source,
+			});
+			decision = "fetched";
+		}
+
+		// Remember when the block was last resynced, so that periodic
+		// resyncs can skip the blocks that were checked recently
+		let stored = match decision {
+			"offloaded_deleted" => false,
+			"fetched" => true,
+			_ => exists,
+		};
+		self.record_verified(hash, stored, now_msec())?;
+
+		self.record_decision(manager, decision);
+		Ok(())
+	}
+
+	/// In verify-only mode, ask the other nodes of the write set of a block
+	/// whether they are missing it, without sending, fetching or deleting
+	/// anything. Blocks that are missing on nodes that need them, including
+	/// this one, are counted as under-replicated.
+	async fn verify_replicas(
+		&self,
+		manager: &BlockManager,
+		hash: &Hash,
+		exists: bool,
+		needed: &RcEntry,
+	) -> Result<&'static str, Error> {
+		let mut who = manager.replication.write_nodes(hash);
+		let n_nodes = who.len();
+		who.retain(|id| *id != manager.system.id);
+
+		let who_needs_resps = query_need_block(manager, &who, hash, PRIO_BACKGROUND).await?;
+
+		let mut missing = 0;
+		if who.len() < n_nodes && needed.is_nonzero() && !exists {
+			missing += 1;
+		}
+		for (_node, needed) in who_needs_resps {
+			if needed.err_context("NeedBlockQuery RPC")? {
+				missing += 1;
+			}
+		}
+
+		if missing == 0 {
+			return Ok("verified");
+		}
+		manager.metrics.resync_underreplicated.add(1);
+		if n_nodes - missing < manager.replication.write_quorum() {
+			warn!(
+				"Resync block {:?}: missing on {} of {} nodes, below write quorum",
+				hash, missing, n_nodes
+			);
+		} else {
+			info!(
+				"Resync block {:?}: missing on {} of {} nodes",
+				hash, missing, n_nodes
+			);
+		}
+		Ok("underreplicated")
+	}
+
+	fn record_decision(&self, manager: &BlockManager, decision: &'static str) {
+		Context::current()
+			.span()
+			.set_attribute(KeyValue::new("decision", decision));
+		manager
+			.metrics
+			.resync_decision_counter
+			.add(1, &[KeyValue::new("decision", decision)]);
+	}
+
+	/// Record that a fetched block could not be written to disk.
+	/// Write failures are counted separately from other resync errors
+	/// (which are mostly network errors), and once a block has failed
+	/// to be written RESYNC_QUARANTINE_WRITE_FAILURES times in a row,
+	/// it is quarantined. Returns whether the block was quarantined.
+	fn record_write_failure(&self, hash: &Hash) -> Result<bool, Error> {
+		let now = now_msec();
+		let write_errors = match self.write_errors.get(hash.as_slice())? {
+			Some(ec) => ErrorCounter::decode(&ec).add1(now),
+			None => ErrorCounter::new(now),
+		};
+		self.write_errors
+			.insert(hash.as_slice(), write_errors.encode())?;
+
+		if write_errors.errors >= RESYNC_QUARANTINE_WRITE_FAILURES {
+			error!(
+				"Block {:?} could not be written to disk after {} attempts, quarantining it. \
+				This probably indicates a hardware failure, please check your data disk!",
+				hash, write_errors.errors
+			);
+			self.quarantine
+				.insert(hash.as_slice(), write_errors.encode())?;
+			Ok(true)
+		} else {
+			Ok(false)
+		}
+	}
+
+	/// List at most `limit` blocks of the error table, starting after
+	/// the block `start`, along with their error counter. The list is
+	/// read in a single pass, before anything else is done with the DB.
+	pub(crate) fn list_errors(
+		&self,
+		start: Option<Hash>,
+		limit: usize,
+	) -> Result<Vec<(Hash, ErrorCounter)>, Error> {
+		let start_bound = match start.as_ref() {
+			None => Bound::Unbounded,
+			Some(x) => Bound::Excluded(x.as_slice()),
+		};
+		let mut ret = vec![];
+		for ent in self
+			.errors
+			.range::<&[u8], _>((start_bound, Bound::Unbounded))?
+		{
+			if ret.len() >= limit {
+				break;
+			}
+			let (hash, ec) = ent?;
+			ret.push((Hash::try_from(&hash).unwrap(), ErrorCounter::decode(&ec)));
+		}
+		Ok(ret)
+	}
+
+	/// List blocks that are quarantined, along with the number of
+	/// write failures and the time of the last one
+	pub(crate) fn list_quarantine(&self) -> Result<Vec<(Hash, ErrorCounter)>, Error> {
+		let mut ret = Vec::with_capacity(self.quarantine.len());
+		for ent in self.quarantine.iter()? {
+			let (hash, ec) = ent?;
+			ret.push((Hash::try_from(&hash).unwrap(), ErrorCounter::decode(&ec)));
+		}
+		Ok(ret)
+	}
+
+	// ---- Time of the last successful resync of each block ----
+	// (see ResyncStaleWorker in repair.rs)
+
+	/// Record that a block was resynced successfully at time `now`. Only
+	/// blocks that are stored locally are remembered.
+	fn record_verified(&self, hash: &Hash, stored: bool, now: u64) -> Result<(), db::Error> {
+		if stored {
+			self.last_verified
+				.insert(hash.as_slice(), u64::to_be_bytes(now))?;
+		} else {
+			self.last_verified.remove(hash.as_slice())?;
+		}
+		Ok(())
+	}
+
+	/// Time of the last successful resync of a block, if it is stored
+	/// locally and was resynced since it was first recorded
+	pub(crate) fn last_verified(&self, hash: &Hash) -> Result<Option<u64>, db::Error> {
+		Ok(self
+			.last_verified
+			.get(hash.as_slice())?
+			.map(|t| u64::from_be_bytes(t[0..8].try_into().unwrap())))
+	}
+
+	/// Whether a block was not resynced successfully since time `since`
+	pub(crate) fn is_stale(&self, hash: &Hash, since: u64) -> Result<bool, db::Error> {
+		Ok(match self.last_verified(hash)? {
+			Some(t) => t < since,
+			None => true,
+		})
+	}
+
+	// ---- Reconciliation of the queue with the RC table ----
+	// (see ReconcileResyncWorker in repair.rs)
+
+	/// Read up to `limit` entries of the resync queue that come strictly
+	/// after the queue key `after`
+	pub(crate) fn queue_batch(
+		&self,
+		after: Option<&[u8]>,
+		limit: usize,
+	) -> Result<Vec<(Vec<u8>, Hash)>, db::Error> {
+		let start_bound = match after {
+			None => Bound::Unbounded,
+			Some(x) => Bound::Excluded(x),
+		};
+		let mut ret = vec![];
+		for ent in self
+			.queue
+			.range::<&[u8], _>((start_bound, Bound::Unbounded))?
+		{
+			let (key, hash) = ent?;
+			ret.push((key, Hash::try_from(&hash[..]).unwrap()));
+			if ret.len() >= limit {
+				break;
+			}
+		}
+		Ok(ret)
+	}
+
+	/// Remove the queue entries of blocks that were found not to need
+	/// any action, as indicated by the third member of each tuple.
+	/// Entries that are currently being processed, and blocks that have
+	/// errors or are quarantined, are left for the resync workers to handle.
+	/// Returns the number of entries removed.
+	pub(crate) fn remove_unneeded_entries(
+		&self,
+		entries: &[(Vec<u8>, Hash, bool)],
+	) -> Result<u64, db::Error> {
+		let mut removed = 0;
+		for (key, hash, needs_resync) in entries.iter() {
+			if *needs_resync
+				|| self.busy_set.lock().unwrap().contains(key)
+				|| self.errors.get(hash.as_slice())?.is_some()
+				|| self.quarantine.get(hash.as_slice())?.is_some()
+			{
+				continue;
+			}
+			if self.queue.get(key)?.is_some() {
+				self.finish_entry(key)?;
+				removed += 1;
+			}
+		}
+		Ok(removed)
+	}
+
+	/// Whether a block currently has an entry in the queue, according
+	/// to the queue index
+	pub(crate) fn is_queued(&self, hash: &Hash) -> Result<bool, db::Error> {
+		Ok(self.queue_index.get(hash.as_slice())?.is_some())
+	}
+}
+
+/// Order in which resync asks the nodes that should store a block for it.
+/// The first node asked is at an offset given by the hash of the block, so
+/// that when many blocks are fetched, e.g. to heal a node, the load is spread
+/// over all their replicas. The local node is asked last, as the block was
+/// found to be missing on it.
+pub(crate) fn resync_fetch_order(mut nodes: Vec<Uuid>, hash: &Hash, local: Uuid) -> Vec<Uuid> {
+	if !nodes.is_empty() {
+		// (the first bytes of the hash give its partition, and so its
+		// nodes, they are not used for the offset)
+		let offset = u64::from_be_bytes(hash.as_slice()[8..16].try_into().unwrap());
+		let offset = (offset % nodes.len() as u64) as usize;
+		nodes.rotate_left(offset);
+	}
+	if let Some(i) = nodes.iter().position(|node| *node == local) {
+		let local = nodes.remove(i);
+		nodes.push(local);
+	}
+	nodes
+}
+
+/// Ask the nodes `who` whether they need a block. Returns the answer of
+/// each node, or the error that prevented it from answering.
+pub(crate) async fn query_need_block(
+	manager: &BlockManager,
+	who: &[Uuid],
+	hash: &Hash,
+	prio: RequestPriority,
+) -> Result<Vec<(Uuid, Result<bool, Error>)>, Error> {
+	let resps = manager
+		.system
+		.rpc
+		.call_many(
+			&manager.endpoint,
+			who,
+			BlockRpc::NeedBlockQuery(*hash),
+			RequestStrategy::with_priority(prio),
+		)
+		.await?;
+	Ok(resps
+		.into_iter()
+		.map(|(node, resp)| {
+			let needed = match resp {
+				Ok(BlockRpc::NeedBlockReply(needed)) => Ok(needed),
+				Ok(m) => Err(Error::unexpected_rpc_message(m)),
+				Err(e) => Err(e),
+			};
+			(node, needed)
+		})
+		.collect())
+}
+
+/// Send a block to several nodes in parallel, each with its own timeout,
+/// so that a single slow node does not prevent the others from receiving
+/// it. `send` waits until the block can be sent to a node, which does not
+/// count in the timeout, and returns the future sending it. Returns the
+/// result of sending the block to each node.
+async fn send_to_nodes<M, F, Fut, S>(
+	msgs: Vec<(Uuid, M)>,
+	timeout: Duration,
+	send: F,
+) -> Vec<(Uuid, Result<BlockRpc, Error>)>
+where
+	F: Fn(Uuid, M) -> Fut,
+	Fut: Future<Output = S>,
+	S: Future<Output = Result<BlockRpc, Error>>,
+{
+	join_all(msgs.into_iter().map(|(node, msg)| {
+		let ready = send(node, msg);
+		async move {
+			let send = ready.await;
+			let resp = match tokio::time::timeout(timeout, send).await {
+				Ok(resp) => resp,
+				Err(_) => Err(Error::Timeout),
+			};
+			(node, resp)
+		}
+	}))
+	.await
+}
+
+/// Check the results of sending a block to the nodes that need it, before
+/// the local copy is deleted. `n_others` is the number of the other nodes
+/// of the write set, and `resps` are the results of sending the block to
+/// those of them that needed it. By default, the local copy can only be
+/// deleted if all these nodes received the block. With `with_quorum`, it
+/// can be deleted if the nodes that already had the block and those that
+/// received it make a write quorum; the others will fetch it from them.
+/// Returns the nodes that received the block.
+fn check_offload_result(
+	hash: &Hash,
+	n_others: usize,
+	resps: Vec<(Uuid, Result<BlockRpc, Error>)>,
+	write_quorum: usize,
+	with_quorum: bool,
+) -> Result<Vec<Uuid>, Error> {
+	let n_had_block = n_others - resps.len();
+	let mut sent_to = vec![];
+	let mut errors = vec![];
+	for (node, resp) in resps {
+		match resp {
+			Ok(_) => sent_to.push(node),
+			Err(e) => {
+				warn!("Could not offload block {:?} to {:?}: {}", hash, node, e);
+				errors.push(format!("{:?}: {}", node, e));
+			}
+		}
+	}
+	if !with_quorum && !errors.is_empty() {
+		return Err(Error::Message(format!(
+			"PutBlock RPC: block not stored on {} of the {} nodes that need it ({})",
+			errors.len(),
+			errors.len() + sent_to.len(),
+			errors.join("; ")
+		)));
+	}
+	if n_had_block + sent_to.len() < write_quorum {
+		return Err(Error::Message(format!(
+			"PutBlock RPC: block only stored on {} nodes, which is less than the write quorum of {} ({})",
+			n_had_block + sent_to.len(),
+			write_quorum,
+			errors.join("; ")
+		)));
+	}
+	Ok(sent_to)
+}
+
+/// Summary of the local state of a block, used to detect whether it has
+/// changed since a queue entry was processed. Whether the block is
+/// deletable is included, as it changes when its deletion delay expires.
+fn status_fingerprint(exists: bool, needed: &RcEntry) -> Vec<u8> {
+	let mut fingerprint = vec![exists as u8, needed.is_deletable() as u8];
+	match needed {
+		RcEntry::Present { count } => {
+			fingerprint.push(1);
+			fingerprint.extend(count.to_be_bytes());
+		}
+		RcEntry::Deletable { at_time } => {
+			fingerprint.push(2);
+			fingerprint.extend(at_time.to_be_bytes());
+		}
+		RcEntry::Absent => fingerprint.push(0),
+	}
+	fingerprint
+}
+
+/// Whether resyncing a block would do anything: fetching it if it is
+/// needed but absent, or offloading/deleting it (possibly later, once its
+/// deletion delay has passed) if it is present but not referenced anymore
+pub(crate) fn needs_resync(exists: bool, needed: &RcEntry) -> bool {
+	exists != needed.is_nonzero()
+}
+
+fn set_min_deletion_delay(
+	persister: &PersisterShared<ResyncPersistedConfig>,
+	delay: Duration,
+) -> Result<(), Error> {
+	if delay < BLOCK_GC_DELAY {
+		return Err(Error::Message(format!(
+			"Invalid minimum deletion delay, must be at least the block GC delay ({} seconds)",
+			BLOCK_GC_DELAY.as_secs()
+		)));
+	}
+	persister.set_with(|x| x.min_deletion_delay_secs = delay.as_secs())
+}
+
+fn set_retry_delay(
+	persister: &PersisterShared<ResyncPersistedConfig>,
+	delay: Duration,
+) -> Result<(), Error> {
+	if delay < Duration::from_secs(1) || delay > RESYNC_RETRY_DELAY_MAX {
+		return Err(Error::Message(format!(
+			"Invalid resync retry delay, must be between 1 and {} seconds",
+			RESYNC_RETRY_DELAY_MAX.as_secs()
+		)));
+	}
+	persister.set_with(|x| x.retry_delay_secs = delay.as_secs())
+}
+
+fn set_retry_jitter(
+	persister: &PersisterShared<ResyncPersistedConfig>,
+	jitter: f64,
+) -> Result<(), Error> {
+	if !(0.0..=RESYNC_RETRY_JITTER_MAX).contains(&jitter) {
+		return Err(Error::Message(format!(
+			"Invalid resync retry jitter, must be between 0 and {}",
+			RESYNC_RETRY_JITTER_MAX
+		)));
+	}
+	persister.set_with(|x| x.retry_jitter = jitter)
+}
+
+fn set_max_backoff_power(
+	persister: &PersisterShared<ResyncPersistedConfig>,
+	max_backoff_power: u64,
+) -> Result<(), Error> {
+	if max_backoff_power > RESYNC_MAX_BACKOFF_POWER_MAX {
+		return Err(Error::Message(format!(
+			"Invalid resync max backoff power, must be at most {}",
+			RESYNC_MAX_BACKOFF_POWER_MAX
+		)));
+	}
+	persister.set_with(|x| x.max_backoff_power = max_backoff_power)
+}
+
+fn set_idle_poll_interval(
+	persister: &PersisterShared<ResyncPersistedConfig>,
+	interval: Duration,
+) -> Result<(), Error> {
+	if interval < RESYNC_IDLE_POLL_INTERVAL_MIN || interval > RESYNC_IDLE_POLL_INTERVAL_MAX {
+		return Err(Error::Message(format!(
+			"Invalid resync idle poll interval, must be between {} and {} milliseconds",
+			RESYNC_IDLE_POLL_INTERVAL_MIN.as_millis(),
+			RESYNC_IDLE_POLL_INTERVAL_MAX.as_millis()
+		)));
+	}
+	persister.set_with(|x| x.idle_poll_interval_ms = interval.as_millis() as u64)
+}
+
+fn set_paused(
+	persister: &PersisterShared<ResyncPersistedConfig>,
+	notify: &Notify,
+	paused: bool,
+) -> Result<(), Error> {
+	persister.set_with(|x| x.paused = paused)?;
+	// Wake up workers that have to be resumed
+	notify.notify_waiters();
+	Ok(())
+}
+
+fn set_auto_scale_thresholds(
+	persister: &PersisterShared<ResyncPersistedConfig>,
+	down: u64,
+	up: u64,
+) -> Result<(), Error> {
+	if down >= up {
+		return Err(Error::Message(format!(
+			"Invalid resync auto-scaling thresholds, the lower one ({}) must be less than the upper one ({})",
+			down, up
+		)));
+	}
+	persister.set_with(|x| {
+		x.auto_scale_down_queue_len = down;
+		x.auto_scale_up_queue_len = up;
+	})
+}
+
+/// Number of resync workers that auto-scaling goes to from `n_workers`,
+/// given the length of the queue
+fn auto_scale_target(
+	n_workers: usize,
+	max_workers: usize,
+	queue_len: u64,
+	down: u64,
+	up: u64,
+) -> usize {
+	if queue_len > up && n_workers < max_workers {
+		n_workers + 1
+	} else if queue_len < down && n_workers > 1 {
+		n_workers - 1
+	} else {
+		n_workers
+	}
+}
+
+/// Validate and persist new values for the resync tuning parameters
+fn set_resync_tuning(
+	persister: &PersisterShared<ResyncPersistedConfig>,
+	notify: &Notify,
+	max_workers: usize,
+	n_workers: Option<usize>,
+	tranquility: Option<u32>,
+) -> Result<(), Error> {
+	if let Some(n_workers) = n_workers {
+		if !(1..=max_workers).contains(&n_workers) {
+			return Err(Error::Message(format!(
+				"Invalid number of resync workers, must be between 1 and {}",
+				max_workers
+			)));
+		}
+	}
+	if n_workers.is_none() && tranquility.is_none() {
+		return Ok(());
+	}
+	persister.set_with(|x| {
+		if let Some(n_workers) = n_workers {
+			x.n_workers = n_workers;
+		}
+		if let Some(tranquility) = tranquility {
+			x.tranquility = tranquility;
+		}
+	})?;
+	notify.notify_waiters();
+	Ok(())
+}
+
+/// Validate and persist the tranquility of a single resync worker
+fn set_worker_tranquility(
+	persister: &PersisterShared<ResyncPersistedConfig>,
+	notify: &Notify,
+	max_workers: usize,
+	index: usize,
+	tranquility: Option<u32>,
+) -> Result<(), Error> {
+	if index >= max_workers {
+		return Err(Error::Message(format!(
+			"Invalid resync worker, there are at most {} workers",
+			max_workers
+		)));
+	}
+	persister.set_with(|x| {
+		if x.worker_tranquility.len() <= index {
+			x.worker_tranquility.resize(index + 1, None);
+		}
+		x.worker_tranquility[index] = tranquility;
+		while x.worker_tranquility.last() == Some(&None) {
+			x.worker_tranquility.pop();
+		}
+	})?;
+	notify.notify_waiters();
+	Ok(())
+}
+
+/// Additional delay to apply to blocks put in a resync queue of length
+/// `queue_len`, given the configured high-water mark (0 means disabled)
+fn backpressure_delay(queue_len: u64, high_water: u64) -> Duration {
+	if high_water == 0 || queue_len <= high_water {
+		return Duration::ZERO;
+	}
+	let excess = (queue_len - high_water).min(u32::MAX as u64) as u32;
+	std::cmp::min(
+		RESYNC_BACKPRESSURE_DELAY_PER_BLOCK.saturating_mul(excess),
+		RESYNC_BACKPRESSURE_MAX_DELAY,
+	)
+}
+
+/// Run a resync operation for a block, turning it into an error if it
+/// does not complete within the given delay, so that it is retried later
+/// instead of blocking a resync worker forever.
+async fn with_block_timeout<F>(hash: &Hash, timeout: Duration, f: F) -> Result<(), Error>
+where
+	F: Future<Output = Result<(), Error>>,
+{
+	match tokio::time::timeout(timeout, f).await {
+		Ok(res) => res,
+		Err(_) => Err(Error::Message(format!(
+			"Resync of block {:?} timed out after {}s",
+			hash,
+			timeout.as_secs()
+		))),
+	}
+}
+
+impl<'a> Drop for InFlightResync<'a> {
+	fn drop(&mut self) {
+		let mut in_flight = self.resync.in_flight.lock().unwrap();
+		if let Some(flags) = in_flight.get_mut(&self.hash) {
+			flags.retain(|aborted| !Arc::ptr_eq(aborted, &self.aborted));
+			if flags.is_empty() {
+				in_flight.remove(&self.hash);
+			}
+		}
+	}
+}
+
+impl BusyBlock {
+	/// Claim the queue entry for the given delay. The claim is persisted,
+	/// so that a resync interrupted by a crash can be reported at startup.
+	fn claim(&mut self, lease: Duration) -> Result<(), db::Error> {
+		let until = u64::to_be_bytes(now_msec() + lease.as_millis() as u64);
+		self.claims.insert(&self.time_bytes, until)?;
+		self.claimed_until = Some(until);
+		Ok(())
+	}
+}
+
+impl Drop for BusyBlock {
+	fn drop(&mut self) {
+		let mut busy = self.busy_set.lock().unwrap();
+		busy.remove(&self.time_bytes);
+		// The claim is only released if it is still ours
+		if let Some(until) = self.claimed_until {
+			if let Err(e) =
+				self.claims
+					.compare_and_swap::<_, _, &[u8]>(&self.time_bytes, Some(until), None)
+			{
+				warn!("Unable to release resync claim: {}", e);
+			}
+		}
+	}
+}
+
+pub(crate) struct ResyncWorker {
+	index: usize,
+	manager: Arc<BlockManager>,
+	tranquilizer: Tranquilizer,
+	next_delay: Duration,
+	persister: PersisterShared<ResyncPersistedConfig>,
+	/// Block that the worker is resyncing, if any
+	current: ArcSwapOption<Hash>,
+}
+
+impl ResyncWorker {
+	pub(crate) fn new(index: usize, manager: Arc<BlockManager>) -> Self {
+		let persister = manager.resync.persister.clone();
+		Self {
+			index,
+			manager,
+			tranquilizer: Tranquilizer::new(30),
+			next_delay: Duration::from_secs(10),
+			persister,
+			current: ArcSwapOption::new(None),
+		}
+	}
+}
+
+#[async_trait]
+impl Worker for ResyncWorker {
+	fn name(&self) -> String {
+		format!("Block resync worker #{}", self.index + 1)
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let status = self.manager.resync.status().unwrap_or_default();
+		let (block_timeout_secs, prioritize_recent, max_bytes_per_sec) =
+			self.persister.get_with(|x| {
+				(
+					x.block_timeout_secs,
+					x.prioritize_recent,
+					x.max_bytes_per_sec,
+				)
+			});
+
+		if self.index >= status.n_workers {
+			return WorkerStatus {
+				freeform: vec!["This worker is currently disabled".into()],
+				..Default::default()
+			};
+		}
+
+		if status.paused {
+			return WorkerStatus {
+				queue_length: Some(status.queue_len as u64),
+				persistent_errors: Some(status.errors_len as u64),
+				freeform: vec!["(paused)".into()],
+				..Default::default()
+			};
+		}
+
+		let mut freeform = vec![format!("Block timeout: {}s", block_timeout_secs)];
+		if self.manager.resync.is_verify_only() {
+			freeform.push("Verify-only mode: blocks are checked but not transferred".into());
+		}
+		if let Some(hash) = self.current.load().as_deref() {
+			freeform.push(format!("Processing {:?}", hash));
+		}
+		if let Some(window) = self.manager.resync.scheduled_window() {
+			freeform.push(format!("Scheduled: {} profile", window));
+		}
+		if prioritize_recent {
+			freeform.push("Recently accessed blocks are resynced first".into());
+		}
+		if max_bytes_per_sec > 0 {
+			freeform.push(format!("Upload rate limit: {} bytes/s", max_bytes_per_sec));
+		}
+		let backoff = self.manager.resync.retry_backoff();
+		freeform.push(format!(
+			"Retry delay: {}s, doubled after each failure up to {}s",
+			backoff.delay_msec / 1000,
+			backoff.max_delay().as_secs()
+		));
+		let orphaned = self.manager.resync.orphaned_claims();
+		if orphaned > 0 {
+			freeform.push(format!("Blocks interrupted at last shutdown: {}", orphaned));
+		}
+		if let Some(age) = status.oldest_queued_age_ms {
+			if age >= 1000 {
+				freeform.push(format!("Oldest queued block: due {}s ago", age / 1000));
+			}
+		}
+
+		WorkerStatus {
+			queue_length: Some(status.queue_len as u64),
+			tranquility: Some(self.manager.resync.worker_tranquility(self.index)),
+			persistent_errors: Some(status.errors_len as u64),
+			freeform,
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (n_workers, _) = self.manager.resync.effective_tuning();
+
+		if self.index >= n_workers || self.manager.resync.is_paused() {
+			self.current.store(None);
+			return Ok(WorkerState::Idle);
+		}
+
+		self.tranquilizer.reset();
+		match self
+			.manager
+			.resync
+			.resync_iter(&self.manager, &self.current)
+			.await
+		{
+			Ok(ResyncIterResult::BusyDidSomething) => {
+				let tranquility = self.manager.resync.worker_tranquility(self.index);
+				Ok(self.tranquilizer.tranquilize_worker(tranquility))
+			}
+			Ok(ResyncIterResult::BusyDidNothing) => Ok(WorkerState::Busy),
+			Ok(ResyncIterResult::IdleFor(delay)) => {
+				self.next_delay = delay;
+				Ok(WorkerState::Idle)
+			}
+			Err(e) => {
+				// The errors that we have here are only db errors that can't
+				// be retried (resync_iter handles those caused by contention)
+				// We don't really know how to handle them so just ¯\_(ツ)_/¯
+				// (there is kind of an assumption that Sled won't error on us,
+				// if it does there is not much we can do -- TODO should we just panic?)
+				// Here we just give the error to the worker manager,
+				// it will print it to the logs and increment a counter
+				Err(e.into())
+			}
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		while self.index >= self.manager.resync.effective_tuning().0
+			|| self.manager.resync.is_paused()
+		{
+			self.manager.resync.notify.notified().await
+		}
+
+		select! {
+			_ = tokio::time::sleep(self.next_delay) => (),
+			_ = self.manager.resync.notify.notified() => (),
+		};
+
+		WorkerState::Busy
+	}
+}
+
+/// Applies the resync schedule, checking every minute whether
+/// a window of the schedule has started or ended
+pub(crate) struct ResyncScheduleWorker {
+	manager: Arc<BlockManager>,
+}
+
+impl ResyncScheduleWorker {
+	pub(crate) fn new(manager: Arc<BlockManager>) -> Self {
+		Self { manager }
+	}
+}
+
+#[async_trait]
+impl Worker for ResyncScheduleWorker {
+	fn name(&self) -> String {
+		"Block resync scheduler".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let freeform = match self.manager.resync.scheduled_window() {
+			Some(window) => format!("Scheduled: {} profile", window),
+			None => "No scheduled window in effect".into(),
+		};
+		WorkerStatus {
+			freeform: vec![freeform],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		self.manager
+			.resync
+			.update_schedule(minute_of_day(now_msec()));
+		Ok(WorkerState::Idle)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		let until_next_minute = 60_000 - now_msec() % 60_000;
+		tokio::time::sleep(Duration::from_millis(until_next_minute)).await;
+		WorkerState::Busy
+	}
+}
+
+/// Adjusts the number of resync workers to the length of the queue,
+/// when auto-scaling is enabled
+pub(crate) struct ResyncAutoScaleWorker {
+	manager: Arc<BlockManager>,
+	persister: PersisterShared<ResyncPersistedConfig>,
+}
+
+impl ResyncAutoScaleWorker {
+	pub(crate) fn new(manager: Arc<BlockManager>) -> Self {
+		let persister = manager.resync.persister.clone();
+		Self { manager, persister }
+	}
+}
+
+#[async_trait]
+impl Worker for ResyncAutoScaleWorker {
+	fn name(&self) -> String {
+		"Block resync auto-scaler".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let (auto_scale, down, up) = self.persister.get_with(|x| {
+			(
+				x.auto_scale,
+				x.auto_scale_down_queue_len,
+				x.auto_scale_up_queue_len,
+			)
+		});
+		let freeform = if auto_scale {
+			format!("Scaling between {} and {} queued blocks", down, up)
+		} else {
+			"Auto-scaling is disabled".into()
+		};
+		WorkerStatus {
+			queue_length: Some(self.manager.resync.queue_len().unwrap_or(0) as u64),
+			freeform: vec![freeform],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let queue_len = self.manager.resync.queue_len()? as u64;
+		self.manager.resync.auto_scale_step(queue_len)?;
+		Ok(WorkerState::Idle)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		tokio::time::sleep(RESYNC_AUTO_SCALE_INTERVAL).await;
+		WorkerState::Busy
+	}
+}
+
+/// Minute of the day (UTC) of a timestamp in milliseconds
+fn minute_of_day(time_msec: u64) -> u32 {
+	((time_msec / 60_000) % (24 * 60)) as u32
+}
+
+/// Counts the number of errors when resyncing a block,
+/// and the time of the last try.
+/// Used to implement exponential backoff.
+/// The message of the last error is kept too, if there is one: it follows
+/// the two counters in the encoded value (older versions only stored the
+/// counters, on 16 bytes).
+#[derive(Clone, Debug)]
+pub(crate) struct ErrorCounter {
+	pub(crate) errors: u64,
+	pub(crate) last_try: u64,
+	pub(crate) last_error: Option<String>,
+}
+
+impl ErrorCounter {
+	fn new(now: u64) -> Self {
+		Self {
+			errors: 1,
+			last_try: now,
+			last_error: None,
+		}
+	}
+
+	pub(crate) fn decode(data: &[u8]) -> Self {
+		let last_error = match &data[16..] {
+			[] => None,
+			msg => Some(String::from_utf8_lossy(msg).into_owned()),
+		};
+		Self {
+			errors: u64::from_be_bytes(data[0..8].try_into().unwrap()),
+			last_try: u64::from_be_bytes(data[8..16].try_into().unwrap()),
+			last_error,
+		}
+	}
+
+	fn encode(&self) -> Vec<u8> {
+		let mut data = [
+			u64::to_be_bytes(self.errors),
+			u64::to_be_bytes(self.last_try),
+		]
+		.concat();
+		if let Some(msg) = &self.last_error {
+			data.extend(msg.as_bytes());
+		}
+		data
+	}
+
+	fn add1(self, now: u64) -> Self {
+		Self {
+			errors: self.errors + 1,
+			last_try: now,
+			last_error: self.last_error,
+		}
+	}
+
+	/// Set the message of the last error, truncated to at most
+	/// RESYNC_ERROR_MESSAGE_MAX_LEN bytes
+	fn with_last_error(mut self, msg: &str) -> Self {
+		let mut len = std::cmp::min(msg.len(), RESYNC_ERROR_MESSAGE_MAX_LEN);
+		while !msg.is_char_boundary(len) {
+			len -= 1;
+		}
+		self.last_error = Some(msg[..len].to_string());
+		self
+	}
+
+	fn delay_msec(&self, hash: &Hash, backoff: &RetryBackoff) -> u64 {
+		let delay = backoff.delay_msec << std::cmp::min(self.errors - 1, backoff.max_power);
+		if backoff.jitter == 0.0 {
+			return delay;
+		}
+		// A number in [-1, 1) that only depends on the block
+		let seed = u64::from_be_bytes(hash.as_slice()[..8].try_into().unwrap());
+		let unit = (seed as f64 / u64::MAX as f64) * 2.0 - 1.0;
+		(delay as f64 * (1.0 + backoff.jitter * unit)) as u64
+	}
+
+	pub(crate) fn next_try(&self, hash: &Hash, backoff: &RetryBackoff) -> u64 {
+		self.last_try + self.delay_msec(hash, backoff)
+	}
+}
+
+/// Parameters of the exponential backoff between retries of a block
+/// whose resync failed
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RetryBackoff {
+	delay_msec: u64,
+	max_power: u64,
+	/// Retry delays are multiplied by a factor between 1 - jitter and 1 + jitter
+	jitter: f64,
+}
+
+impl RetryBackoff {
+	/// Delay after which a block is retried once it has failed many times,
+	/// before jitter is applied
+	fn max_delay(&self) -> Duration {
+		Duration::from_millis(self.delay_msec << self.max_power)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn test_resync_manager() -> (BlockResyncManager, mktemp::Temp) {
+		let path = mktemp::Temp::new_dir().unwrap();
+		let db = db::sled_adapter::SledDb::init(
+			db::sled_adapter::sled::open(path.as_path().join("db")).unwrap(),
+		);
+		let resync = BlockResyncManager::new(&db, path.as_path(), vec![], 4);
+		(resync, path)
+	}
+
+	/// A block manager of a single node cluster, whose node stores all blocks
+	async fn test_block_manager() -> (Arc<BlockManager>, mktemp::Temp) {
+		use garage_rpc::layout::{ClusterLayout, NodeRole, NodeRoleV};
+		use garage_rpc::replication_mode::ReplicationMode;
+		use garage_rpc::system::System;
+		use garage_table::replication::TableShardedReplication;
+		use garage_util::crdt::Crdt;
+
+		let path = mktemp::Temp::new_dir().unwrap();
+		let config_file = path.as_path().join("garage.toml");
+		std::fs::write(
+			&config_file,
+			format!(
+				r#"
+				metadata_dir = "{}"
+				data_dir = "{}"
+				replication_mode = "1"
+				rpc_bind_addr = "127.0.0.1:3901"
+				rpc_public_addr = "127.0.0.1:3901"
+				rpc_secret = "{}"
+
+				[s3_api]
+				s3_region = "garage"
+				api_bind_addr = "127.0.0.1:3900"
+				"#,
+				path.as_path().join("meta").display(),
+				path.as_path().join("data").display(),
+				hex::encode([0u8; 32]),
+			),
+		)
+		.unwrap();
+		let config = garage_util::config::read_config(config_file).unwrap();
+
+		let network_key = netapp::NetworkKey::from_slice(&[0u8; 32]).unwrap();
+		let system = System::new(network_key, ReplicationMode::None, &config).unwrap();
+
+		let mut layout = ClusterLayout::new(1);
+		let role = NodeRoleV(Some(NodeRole {
+			zone: "dc1".into(),
+			capacity: Some(1000),
+			tags: vec![],
+		}));
+		layout
+			.staging
+			.merge(&layout.roles.update_mutator(system.id, role));
+		let layout = layout.apply_staged_changes(Some(1)).unwrap();
+		system.update_cluster_layout(&layout).await.unwrap();
+
+		let db = db::sled_adapter::SledDb::init(
+			db::sled_adapter::sled::open(path.as_path().join("db")).unwrap(),
+		);
+		let replication = TableShardedReplication {
+			system: system.clone(),
+			replication_factor: 1,
+			write_quorum: 1,
+			read_quorum: 1,
+		};
+		let manager = BlockManager::new(
+			&db,
+			config.data_dir.clone(),
+			None,
+			vec![],
+			1,
+			16,
+			replication,
+			system,
+		);
+		(manager, path)
+	}
+
+	#[test]
+	fn test_write_failure_quarantine() {
+		let (resync, _path) = test_resync_manager();
+		let hash = blake2sum(b"block on a bad sector");
+		let other = blake2sum(b"another block");
+
+		for _ in 1..RESYNC_QUARANTINE_WRITE_FAILURES {
+			assert!(!resync.record_write_failure(&hash).unwrap());
+		}
+		assert!(!resync.record_write_failure(&other).unwrap());
+		assert_eq!(resync.quarantine_len().unwrap(), 0);
+
+		assert!(resync.record_write_failure(&hash).unwrap());
+		assert_eq!(resync.quarantine_len().unwrap(), 1);
+
+		let quarantine = resync.list_quarantine().unwrap();
+		assert_eq!(quarantine.len(), 1);
+		assert_eq!(quarantine[0].0, hash);
+		assert_eq!(quarantine[0].1.errors, RESYNC_QUARANTINE_WRITE_FAILURES);
+	}
+
+	#[test]
+	fn test_prioritize_recent() {
+		let (resync, _path) = test_resync_manager();
+		let accessed = blake2sum(b"popular block");
+		let other = blake2sum(b"forgotten block");
+		let delay = Duration::from_secs(3600);
+
+		// Accesses are not recorded while the option is disabled
+		resync.record_access(&accessed);
+		resync.put_to_resync(&other, delay).unwrap();
+		resync.put_to_resync(&accessed, delay).unwrap();
+		let (_, first) = resync.queue.first().unwrap().unwrap();
+		assert_eq!(first, other.as_slice());
+
+		resync
+			.persister
+			.set_with(|x| x.prioritize_recent = true)
+			.unwrap();
+		resync.record_access(&accessed);
+		resync.put_to_resync(&other, delay).unwrap();
+		resync.put_to_resync(&accessed, delay).unwrap();
+		let (_, first) = resync.queue.first().unwrap().unwrap();
+		assert_eq!(first, accessed.as_slice());
+	}
+
+	#[test]
+	fn test_set_idle_poll_interval() {
+		let (resync, _path) = test_resync_manager();
+		assert_eq!(
+			resync.persister.get_with(|x| x.idle_poll_interval_ms),
+			10_000
+		);
+		resync
+			.set_idle_poll_interval(Duration::from_millis(500))
+			.unwrap();
+		assert_eq!(resync.persister.get_with(|x| x.idle_poll_interval_ms), 500);
+		assert!(resync
+			.set_idle_poll_interval(Duration::from_millis(10))
+			.is_err());
+		assert!(resync
+			.set_idle_poll_interval(Duration::from_secs(3600))
+			.is_err());
+		assert_eq!(resync.persister.get_with(|x| x.idle_poll_interval_ms), 500);
+	}
+
+	#[test]
+	fn test_check_offload_result() {
+		let hash = blake2sum(b"offloaded block");
+		let ok_node = gen_uuid();
+		let slow_node = gen_uuid();
+		let resps = || {
+			vec![
+				(ok_node, Ok(BlockRpc::Ok)),
+				(slow_node, Err(Error::Timeout)),
+			]
+		};
+
+		// By default, the local copy is kept until all the nodes
+		// that need the block have received it
+		assert!(check_offload_result(&hash, 3, resps(), 2, false).is_err());
+
+		// Two other nodes need the block and one times out: with the
+		// node that already had it, the block is still on 2 nodes
+		let sent_to = check_offload_result(&hash, 3, resps(), 2, true).unwrap();
+		assert_eq!(sent_to, vec![ok_node]);
+
+		// If no other node had the block, the quorum is not reached
+		// and the local copy must be kept
+		assert!(check_offload_result(&hash, 2, resps(), 2, true).is_err());
+	}
+
+	#[tokio::test]
+	async fn test_send_to_nodes_timeout() {
+		let hash = blake2sum(b"offloaded block");
+		let ok_node = gen_uuid();
+		let slow_node = gen_uuid();
+		let waiting_node = gen_uuid();
+
+		// The slow node never answers: sending to it times out without
+		// delaying the result of the other nodes. Waiting before sending,
+		// e.g. for a send permit, does not count in the timeout.
+		let start = Instant::now();
+		let resps = send_to_nodes(
+			vec![(ok_node, ()), (slow_node, ()), (waiting_node, ())],
+			Duration::from_millis(100),
+			|node, ()| async move {
+				if node == waiting_node {
+					tokio::time::sleep(Duration::from_millis(300)).await;
+				}
+				async move {
+					if node == slow_node {
+						futures::future::pending::<()>().await;
+					}
+					Ok(BlockRpc::Ok)
+				}
+			},
+		)
+		.await;
+		assert!(start.elapsed() < Duration::from_secs(5));
+		assert_eq!(resps.len(), 3);
+		assert!(matches!(resps[0], (n, Ok(BlockRpc::Ok)) if n == ok_node));
+		assert!(matches!(resps[1], (n, Err(Error::Timeout)) if n == slow_node));
+		assert!(matches!(resps[2], (n, Ok(BlockRpc::Ok)) if n == waiting_node));
+
+		// A fourth node already had the block
+		let sent_to = check_offload_result(&hash, 4, resps, 2, true).unwrap();
+		assert_eq!(sent_to, vec![ok_node, waiting_node]);
+	}
+
+	#[test]
+	fn test_paused() {
+		let (resync, _path) = test_resync_manager();
+		resync.set_resync_tuning(Some(3), None).unwrap();
+		resync.set_auto_scale(true).unwrap();
+
+		resync.set_paused(true).unwrap();
+		assert!(resync.is_paused());
+		// Auto-scaling does nothing while resync is paused
+		assert_eq!(resync.auto_scale_step(50_000).unwrap(), None);
+
+		resync.set_paused(false).unwrap();
+		assert!(!resync.is_paused());
+		assert_eq!(resync.persister.get_with(|x| x.n_workers), 3);
+	}
+
+	#[test]
+	fn test_status() {
+		let (resync, _path) = test_resync_manager();
+		resync.set_resync_tuning(Some(2), Some(5)).unwrap();
+
+		let status = resync.status().unwrap();
+		assert_eq!(status.n_workers, 2);
+		assert_eq!(status.tranquility, 5);
+		assert_eq!(status.queue_len, 0);
+		assert_eq!(status.oldest_queued_age_ms, None);
+		assert!(!status.paused);
+
+		let now = now_msec();
+		resync
+			.put_to_resync_at(&blake2sum(b"a"), now - 5000, ResyncPriority::Normal)
+			.unwrap();
+		resync
+			.put_to_resync_at(&blake2sum(b"b"), now + 5000, ResyncPriority::Normal)
+			.unwrap();
+		resync.set_paused(true).unwrap();
+
+		let status = resync.status().unwrap();
+		assert_eq!(status.queue_len, 2);
+		assert!(status.oldest_queued_age_ms.unwrap() >= 5000);
+		assert!(status.paused);
+	}
+
+	#[test]
+	fn test_block_state() {
+		let (resync, _path) = test_resync_manager();
+		let hash = blake2sum(b"block");
+		assert_eq!(
+			resync.block_state(&hash).unwrap(),
+			BlockResyncState::default()
+		);
+
+		let now = now_msec();
+		resync
+			.put_to_resync_at(&hash, now + 5000, ResyncPriority::Normal)
+			.unwrap();
+		resync
+			.put_to_resync_at(&hash, now + 1000, ResyncPriority::Normal)
+			.unwrap();
+		let state = resync.block_state(&hash).unwrap();
+		assert_eq!(state.queued_at, Some(now + 1000));
+		assert!(!state.busy);
+		assert_eq!(state.errors, None);
+
+		resync
+			.errors
+			.insert(hash.as_slice(), ErrorCounter::new(now).encode())
+			.unwrap();
+		let state = resync.block_state(&hash).unwrap();
+		assert!(matches!(state.errors, Some((1, next_try)) if next_try > now));
+		assert!(!state.quarantined);
+
+		for _ in 0..RESYNC_QUARANTINE_WRITE_FAILURES {
+			resync.record_write_failure(&hash).unwrap();
+		}
+		assert!(resync.block_state(&hash).unwrap().quarantined);
+	}
+
+	#[test]
+	fn test_auto_scale() {
+		assert_eq!(auto_scale_target(1, 4, 50_000, 1000, 10_000), 2);
+		assert_eq!(auto_scale_target(4, 4, 50_000, 1000, 10_000), 4);
+		assert_eq!(auto_scale_target(4, 8, 50_000, 1000, 10_000), 5);
+		// Between the thresholds, the number of workers does not change
+		assert_eq!(auto_scale_target(3, 4, 5000, 1000, 10_000), 3);
+		assert_eq!(auto_scale_target(3, 4, 10, 1000, 10_000), 2);
+		assert_eq!(auto_scale_target(1, 4, 10, 1000, 10_000), 1);
+
+		let (resync, _path) = test_resync_manager();
+		// Manual mode is the default
+		assert_eq!(resync.auto_scale_step(50_000).unwrap(), None);
+		resync.set_auto_scale(true).unwrap();
+		assert_eq!(resync.auto_scale_step(50_000).unwrap(), Some(2));
+		assert_eq!(resync.auto_scale_step(50_000).unwrap(), Some(3));
+		assert_eq!(resync.persister.get_with(|x| x.n_workers), 3);
+		assert_eq!(resync.auto_scale_step(5000).unwrap(), None);
+		assert_eq!(resync.auto_scale_step(0).unwrap(), Some(2));
+
+		assert!(resync.set_auto_scale_thresholds(100, 100).is_err());
+		resync.set_auto_scale_thresholds(10, 100).unwrap();
+		assert_eq!(resync.auto_scale_step(500).unwrap(), Some(3));
+	}
+
+	#[test]
+	fn test_token_bucket() {
+		let start = Instant::now();
+		let mut bucket = TokenBucket {
+			tokens: 0.0,
+			last_refill: start,
+		};
+
+		// A send larger than the rate goes through, leaving the bucket in debt
+		assert_eq!(bucket.take(3000, 1000, start), None);
+		let wait = bucket.take(1000, 1000, start).unwrap();
+		assert_eq!(wait, Duration::from_secs(3));
+
+		// The debt is paid back over time
+		let later = start + Duration::from_secs(2);
+		let wait = bucket.take(1000, 1000, later).unwrap();
+		assert_eq!(wait, Duration::from_secs(1));
+		let later = start + Duration::from_secs(3);
+		assert_eq!(bucket.take(1000, 1000, later), None);
+
+		// The bucket never holds more than one second worth of bytes
+		let later = start + Duration::from_secs(100);
+		assert_eq!(bucket.take(0, 1000, later), None);
+		assert_eq!(bucket.tokens, 1000.0);
+	}
+
+	#[test]
+	fn test_recent_blocks_bounded() {
+		let mut recent = RecentBlocks::default();
+		let first = blake2sum(b"0");
+		recent.insert(first);
+		for i in 1..RESYNC_RECENT_BLOCKS_CAPACITY {
+			recent.insert(blake2sum(i.to_string().as_bytes()));
+		}
+		// Accessing the first block again makes it the most recent one
+		recent.insert(first);
+		recent.insert(blake2sum(b"one more"));
+		assert!(recent.contains(&first));
+		assert!(!recent.contains(&blake2sum(b"1")));
+		assert_eq!(recent.order.len(), RESYNC_RECENT_BLOCKS_CAPACITY);
+	}
+
+	#[tokio::test]
+	async fn test_send_limit() {
+		let (resync, _path) = test_resync_manager();
+		assert!(resync.acquire_send_permit().await.is_none());
+
+		resync.set_max_concurrent_sends(2).unwrap();
+		let first = resync.acquire_send_permit().await;
+		let second = resync.acquire_send_permit().await;
+		assert!(first.is_some());
+		let third = tokio::time::timeout(Duration::from_millis(50), resync.acquire_send_permit());
+		assert!(third.await.is_err());
+
+		// A send can start as soon as another one is done
+		drop(first);
+		let third = tokio::time::timeout(Duration::from_millis(50), resync.acquire_send_permit());
+		let third = third.await.unwrap();
+		assert!(third.is_some());
+
+		// The sends in progress count in a lowered limit
+		resync.set_max_concurrent_sends(1).unwrap();
+		let fourth = tokio::time::timeout(Duration::from_millis(50), resync.acquire_send_permit());
+		assert!(fourth.await.is_err());
+		drop(third);
+		let fourth = tokio::time::timeout(Duration::from_millis(50), resync.acquire_send_permit());
+		assert!(fourth.await.is_err());
+		drop(second);
+		let fourth = tokio::time::timeout(Duration::from_millis(50), resync.acquire_send_permit());
+		let fourth = fourth.await.unwrap();
+		assert!(fourth.is_some());
+
+		// Raising the limit allows more sends right away
+		resync.set_max_concurrent_sends(2).unwrap();
+		let fifth = tokio::time::timeout(Duration::from_millis(50), resync.acquire_send_permit());
+		assert!(fifth.await.unwrap().is_some());
+	}
+
+	#[test]
+	fn test_worker_tranquility() {
+		let (resync, _path) = test_resync_manager();
+		resync.set_resync_tuning(None, Some(5)).unwrap();
+		resync.set_worker_tranquility(1, Some(0)).unwrap();
+		assert_eq!(resync.worker_tranquility(0), 5);
+		assert_eq!(resync.worker_tranquility(1), 0);
+		assert_eq!(resync.worker_tranquility(2), 5);
+
+		// The tranquility of all workers doesn't change the one of worker #2
+		resync.set_resync_tuning(None, Some(7)).unwrap();
+		assert_eq!(resync.worker_tranquility(0), 7);
+		assert_eq!(resync.worker_tranquility(1), 0);
+
+		resync.set_worker_tranquility(1, None).unwrap();
+		assert_eq!(resync.worker_tranquility(1), 7);
+		assert!(resync
+			.persister
+			.get_with(|x| x.worker_tranquility.is_empty()));
+
+		assert!(resync
+			.set_worker_tranquility(resync.max_workers, Some(1))
+			.is_err());
+	}
+
+	#[test]
+	fn test_max_workers() {
+		let path = mktemp::Temp::new_dir().unwrap();
+		let db = db::sled_adapter::SledDb::init(
+			db::sled_adapter::sled::open(path.as_path().join("db")).unwrap(),
+		);
+		let resync = BlockResyncManager::new(&db, path.as_path(), vec![], 8);
+		resync.set_resync_tuning(Some(8), None).unwrap();
+		assert_eq!(resync.effective_tuning().0, 8);
+		resync.set_worker_tranquility(7, Some(0)).unwrap();
+		assert!(resync.set_resync_tuning(Some(9), None).is_err());
+
+		// A persisted number of workers above the configured maximum
+		// is capped, e.g. after the maximum was lowered
+		let resync = BlockResyncManager::new(&db, path.as_path(), vec![], 4);
+		assert_eq!(resync.effective_tuning().0, 4);
+
+		let resync = BlockResyncManager::new(&db, path.as_path(), vec![], 1000);
+		assert_eq!(resync.max_workers, MAX_RESYNC_WORKERS_CEILING);
+	}
+
+	#[test]
+	fn test_set_resync_tuning() {
+		let (resync, path) = test_resync_manager();
+		let saved = || {
+			garage_util::persister::Persister::<ResyncPersistedConfig>::new(
+				path.as_path(),
+				"resync_cfg",
+			)
+			.load()
+			.map(|x| (x.n_workers, x.tranquility))
+			.ok()
+		};
+
+		resync.set_resync_tuning(Some(3), Some(7)).unwrap();
+		assert_eq!(
+			resync.persister.get_with(|x| (x.n_workers, x.tranquility)),
+			(3, 7)
+		);
+		assert_eq!(saved(), Some((3, 7)));
+
+		// An invalid value is rejected and nothing is applied
+		assert!(resync
+			.set_resync_tuning(Some(resync.max_workers + 1), Some(0))
+			.is_err());
+		assert_eq!(saved(), Some((3, 7)));
+
+		resync.set_resync_tuning(None, Some(0)).unwrap();
+		assert_eq!(saved(), Some((3, 0)));
+	}
+
+	#[test]
+	fn test_error_counter_encoding() {
+		// Values written by older versions only have the two counters
+		let legacy = [u64::to_be_bytes(2), u64::to_be_bytes(1000)].concat();
+		let ec = ErrorCounter::decode(&legacy);
+		assert_eq!((ec.errors, ec.last_try), (2, 1000));
+		assert_eq!(ec.last_error, None);
+		assert_eq!(ec.encode(), legacy);
+
+		let ec = ec.add1(2000).with_last_error("Timeout");
+		let decoded = ErrorCounter::decode(&ec.encode());
+		assert_eq!((decoded.errors, decoded.last_try), (3, 2000));
+		assert_eq!(decoded.last_error.as_deref(), Some("Timeout"));
+
+		// Long messages are truncated on a character boundary
+		let long = "é".repeat(RESYNC_ERROR_MESSAGE_MAX_LEN);
+		let ec = ErrorCounter::new(0).with_last_error(&long);
+		let msg = ec.last_error.unwrap();
+		assert_eq!(msg.len(), RESYNC_ERROR_MESSAGE_MAX_LEN);
+		assert!(long.starts_with(&msg));
+	}
+
+	#[test]
+	fn test_retry_backoff() {
+		let (resync, path) = test_resync_manager();
+		let hash = blake2sum(b"a block that keeps failing");
+		resync.set_retry_jitter(0.0).unwrap();
+		let ec = ErrorCounter {
+			errors: 3,
+			last_try: 1000,
+			last_error: None,
+		};
+
+		// Initial values: 60s, doubled at most 6 times
+		assert_eq!(
+			ec.next_try(&hash, &resync.retry_backoff()),
+			1000 + 4 * 60_000
+		);
+		let many = ErrorCounter {
+			errors: 100,
+			last_try: 0,
+			last_error: None,
+		};
+		assert_eq!(many.next_try(&hash, &resync.retry_backoff()), 64 * 60_000);
+		assert_eq!(
+			resync.retry_backoff().max_delay(),
+			Duration::from_millis(many.next_try(&hash, &resync.retry_backoff()))
+		);
+
+		resync.set_retry_delay(Duration::from_secs(10)).unwrap();
+		resync.set_max_backoff_power(1).unwrap();
+		assert_eq!(
+			ec.next_try(&hash, &resync.retry_backoff()),
+			1000 + 2 * 10_000
+		);
+		assert_eq!(many.next_try(&hash, &resync.retry_backoff()), 2 * 10_000);
+
+		// Insane values are rejected
+		assert!(resync.set_retry_delay(Duration::from_millis(10)).is_err());
+		assert!(resync
+			.set_retry_delay(RESYNC_RETRY_DELAY_MAX + Duration::from_secs(1))
+			.is_err());
+		assert!(resync
+			.set_max_backoff_power(RESYNC_MAX_BACKOFF_POWER_MAX + 1)
+			.is_err());
+
+		let saved = garage_util::persister::Persister::<ResyncPersistedConfig>::new(
+			path.as_path(),
+			"resync_cfg",
+		)
+		.load()
+		.unwrap();
+		assert_eq!((saved.retry_delay_secs, saved.max_backoff_power), (10, 1));
+	}
+
+	#[test]
+	fn test_retry_now() {
+		let (resync, _path) = test_resync_manager();
+		let hash = blake2sum(b"a block in backoff");
+		let now = now_msec();
+		let ec = ErrorCounter {
+			errors: 6,
+			last_try: now,
+			last_error: None,
+		};
+		resync.errors.insert(hash.as_slice(), ec.encode()).unwrap();
+
+		assert!(resync.retry_now(&hash).unwrap());
+		assert_eq!(resync.errors_len().unwrap(), 0);
+		let (key, value) = resync.queue.first().unwrap().unwrap();
+		assert_eq!(value, hash.as_slice());
+		assert!(queue_key_time(&key) <= now_msec());
+
+		// A block without errors is left alone
+		let other = blake2sum(b"a healthy block");
+		assert!(!resync.retry_now(&other).unwrap());
+		assert!(!resync.retry_now(&hash).unwrap());
+		assert_eq!(resync.queue.len(), 1);
+	}
+
+	#[test]
+	fn test_retry_all_errors() {
+		let (resync, _path) = test_resync_manager();
+		let now = now_msec();
+		let n = RESYNC_RETRY_ALL_BATCH_SIZE + 10;
+		for i in 0..n as u32 {
+			let ec = ErrorCounter {
+				errors: 3,
+				last_try: now,
+				last_error: None,
+			};
+			resync
+				.errors
+				.insert(blake2sum(&i.to_be_bytes()).as_slice(), ec.encode())
+				.unwrap();
+		}
+		// A worker holds an entry of the queue while we requeue everything
+		resync
+			.put_to_resync_at(&blake2sum(b"busy"), now, ResyncPriority::Normal)
+			.unwrap();
+		let busy = resync.get_block_to_resync().unwrap().unwrap();
+
+		assert_eq!(resync.retry_all_errors().unwrap(), n);
+		assert_eq!(resync.errors_len().unwrap(), 0);
+		assert_eq!(resync.queue.len(), n + 1);
+		drop(busy);
+
+		assert_eq!(resync.retry_all_errors().unwrap(), 0);
+	}
+
+	#[test]
+	fn test_expedite_all() {
+		let (resync, _path) = test_resync_manager();
+		let now = now_msec();
+		let far = now + 3600 * 1000;
+		let n = RESYNC_RETRY_ALL_BATCH_SIZE + 10;
+		for i in 0..n as u32 {
+			resync
+				.put_to_resync_at(&blake2sum(&i.to_be_bytes()), far, ResyncPriority::Normal)
+				.unwrap();
+		}
+		let high = blake2sum(b"high priority");
+		resync
+			.put_to_resync_at(&high, far, ResyncPriority::High)
+			.unwrap();
+		// An old duplicate entry for a block, not referenced by the index
+		let dup = blake2sum(&0u32.to_be_bytes());
+		resync
+			.queue
+			.insert(
+				queue_key(ResyncPriority::Normal, far + 1, dup.as_slice()),
+				dup.as_slice(),
+			)
+			.unwrap();
+
+		assert_eq!(resync.expedite_all().unwrap(), n + 1);
+		assert_eq!(resync.queue.len(), n + 1);
+		for ent in resync.queue.iter().unwrap() {
+			let (key, hash) = ent.unwrap();
+			assert!(queue_key_time(&key) <= now_msec());
+			assert_eq!(resync.queue_index.get(&hash).unwrap().unwrap(), key);
+		}
+		let first = resync.queue.first().unwrap().unwrap();
+		assert_eq!(queue_key_priority(&first.0), ResyncPriority::High);
+		assert_eq!(&first.1[..], high.as_slice());
+
+		assert_eq!(resync.expedite_all().unwrap(), 0);
+	}
+
+	#[test]
+	fn test_list_errors() {
+		let (resync, _path) = test_resync_manager();
+		let mut hashes = (0..5u32)
+			.map(|i| blake2sum(&i.to_be_bytes()))
+			.collect::<Vec<_>>();
+		for (i, hash) in hashes.iter().enumerate() {
+			let ec = ErrorCounter {
+				errors: i as u64 + 1,
+				last_try: 1000,
+				last_error: None,
+			};
+			resync.errors.insert(hash.as_slice(), ec.encode()).unwrap();
+		}
+		hashes.sort();
+
+		// Page through the errors two at a time
+		let mut listed = vec![];
+		let mut start = None;
+		loop {
+			let page = resync.list_errors(start, 2).unwrap();
+			assert!(page.len() <= 2);
+			match page.last() {
+				Some((hash, _)) => start = Some(*hash),
+				None => break,
+			}
+			listed.extend(page);
+		}
+		assert_eq!(listed.iter().map(|(h, _)| *h).collect::<Vec<_>>(), hashes);
+		for (hash, ec) in listed.iter() {
+			let i = (0..5u32)
+				.position(|i| blake2sum(&i.to_be_bytes()) == *hash)
+				.unwrap();
+			assert_eq!(ec.errors, i as u64 + 1);
+			assert_eq!(ec.last_try, 1000);
+		}
+
+		assert!(resync.list_errors(None, 0).unwrap().is_empty());
+		assert_eq!(resync.list_errors(Some(hashes[3]), 10).unwrap().len(), 1);
+	}
+
+	#[test]
+	fn test_retry_jitter() {
+		let (resync, _path) = test_resync_manager();
+		let ec = ErrorCounter {
+			errors: 1,
+			last_try: 0,
+			last_error: None,
+		};
+		let base = RESYNC_RETRY_DELAY.as_millis() as u64;
+
+		// With the initial jitter, blocks that failed at the same time
+		// are retried at different times within +/- 25% of the delay
+		let backoff = resync.retry_backoff();
+		let tries = (0..100u32)
+			.map(|i| ec.next_try(&blake2sum(&i.to_be_bytes()), &backoff))
+			.collect::<HashSet<_>>();
+		assert!(tries.len() > 90);
+		assert!(tries
+			.iter()
+			.all(|t| *t >= base * 3 / 4 && *t <= base * 5 / 4));
+
+		// The jitter of a block is always the same
+		let hash = blake2sum(b"a block that keeps failing");
+		assert_eq!(ec.next_try(&hash, &backoff), ec.next_try(&hash, &backoff));
+		assert_eq!(
+			ec.next_try(&hash, &resync.retry_backoff()),
+			ec.next_try(&hash, &backoff)
+		);
+
+		// Zero disables it
+		resync.set_retry_jitter(0.0).unwrap();
+		assert_eq!(ec.next_try(&hash, &resync.retry_backoff()), base);
+		assert!(resync.set_retry_jitter(0.75).is_err());
+		assert!(resync.set_retry_jitter(-0.1).is_err());
+		assert!(resync.set_retry_jitter(f64::NAN).is_err());
+	}
+
+	#[test]
+	fn test_resync_cfg_without_retry_fields() {
+		#[derive(Serialize, Deserialize)]
+		struct OldResyncPersistedConfig {
+			n_workers: usize,
+			tranquility: u32,
+		}
+		impl garage_util::migrate::InitialFormat for OldResyncPersistedConfig {}
+
+		let path = mktemp::Temp::new_dir().unwrap();
+		garage_util::persister::Persister::<OldResyncPersistedConfig>::new(
+			path.as_path(),
+			"resync_cfg",
+		)
+		.save(&OldResyncPersistedConfig {
+			n_workers: 3,
+			tranquility: 5,
+		})
+		.unwrap();
+
+		let loaded = garage_util::persister::Persister::<ResyncPersistedConfig>::new(
+			path.as_path(),
+			"resync_cfg",
+		)
+		.load()
+		.unwrap();
+		assert_eq!((loaded.n_workers, loaded.tranquility), (3, 5));
+		assert_eq!(loaded.retry_delay_secs, RESYNC_RETRY_DELAY.as_secs());
+		assert_eq!(
+			loaded.max_backoff_power,
+			RESYNC_RETRY_DELAY_MAX_BACKOFF_POWER
+		);
+	}
+
+	struct MockSource(Option<bytes::Bytes>);
+
+	#[async_trait]
+	impl BlockSource for MockSource {
+		fn name(&self) -> String {
+			"mock".into()
+		}
+
+		async fn get_block(&self, hash: &Hash) -> Result<DataBlock, Error> {
+			match &self.0 {
+				Some(data) => Ok(DataBlock::from_parts(DataBlockHeader::Plain, data.clone())),
+				None => Err(Error::Message(format!("no block {:?}", hash))),
+			}
+		}
+	}
+
+	#[tokio::test]
+	async fn test_block_sources() {
+		let (resync, _path) = test_resync_manager();
+		let data = bytes::Bytes::from_static(b"a block only the cold tier has");
+		let hash = blake2sum(&data);
+
+		// No source has the block
+		resync.add_block_source(Arc::new(MockSource(None)));
+		assert!(resync.fetch_block(&hash).await.is_err());
+
+		// A source returning a corrupted block is skipped
+		resync.add_block_source(Arc::new(MockSource(Some("garbage".into()))));
+		assert!(resync.fetch_block(&hash).await.is_err());
+
+		// The secondary source is used when the first ones don't have the block
+		resync.add_block_source(Arc::new(MockSource(Some(data.clone()))));
+		let (fetched, origin) = resync.fetch_block(&hash).await.unwrap();
+		assert_eq!(fetched.verify_get(hash).unwrap(), data);
+		assert_eq!(origin, BlockOrigin::Source("mock".into()));
+	}
+
+	#[test]
+	fn test_clamp_far_future_entry() {
+		let (resync, _path) = test_resync_manager();
+		let hash = blake2sum(b"scheduled before a clock jump");
+		let now = now_msec();
+
+		// A normally delayed entry is left alone
+		resync
+			.put_to_resync_at(&hash, now + 60_000, ResyncPriority::Normal)
+			.unwrap();
+		let block = resync.get_block_to_resync().unwrap().unwrap();
+		assert!(!resync
+			.clamp_far_future_entry(&block, now + 60_000, now)
+			.unwrap());
+		let key = block.time_bytes.clone();
+		drop(block);
+		resync.queue.remove(&key).unwrap();
+		assert!(resync.queue.is_empty());
+
+		// An entry scheduled 100 days in the future is rescheduled now
+		let far = now + 100 * 24 * 3600 * 1000;
+		resync
+			.put_to_resync_at(&hash, far, ResyncPriority::Normal)
+			.unwrap();
+		let block = resync.get_block_to_resync().unwrap().unwrap();
+		assert!(resync.clamp_far_future_entry(&block, far, now).unwrap());
+		drop(block);
+
+		assert_eq!(resync.queue.len(), 1);
+		let (time_bytes, hash_bytes) = resync.queue.first().unwrap().unwrap();
+		assert_eq!(queue_key_time(&time_bytes), now);
+		assert_eq!(hash_bytes, hash.as_slice());
+		resync.queue.remove(&time_bytes).unwrap();
+
+		// A long backoff allowed by the configuration is respected
+		resync.set_retry_delay(Duration::from_secs(3600)).unwrap();
+		resync.set_max_backoff_power(10).unwrap();
+		let ec = ErrorCounter {
+			errors: 20,
+			last_try: now,
+			last_error: None,
+		};
+		resync.errors.insert(hash.as_slice(), ec.encode()).unwrap();
+		let next_try = ec.next_try(&hash, &resync.retry_backoff());
+		assert!(next_try - now > 6 * 3600 * 1000);
+		assert!(!resync.is_beyond_sane_delay(next_try, now));
+		resync
+			.put_to_resync_at(&hash, next_try, ResyncPriority::Normal)
+			.unwrap();
+		let block = resync.get_block_to_resync().unwrap().unwrap();
+		assert!(!resync
+			.clamp_far_future_entry(&block, next_try, now)
+			.unwrap());
+		drop(block);
+		let (time_bytes, _) = resync.queue.first().unwrap().unwrap();
+		assert_eq!(queue_key_time(&time_bytes), next_try);
+
+		// So is a long minimum deletion delay
+		let deletion_delay = Duration::from_secs(30 * 24 * 3600);
+		resync.set_min_deletion_delay(deletion_delay).unwrap();
+		assert!(!resync.is_beyond_sane_delay(now + deletion_delay.as_millis() as u64, now));
+		assert!(resync.is_beyond_sane_delay(far, now));
+	}
+
+	#[test]
+	fn test_orphaned_claims() {
+		let path = mktemp::Temp::new_dir().unwrap();
+		let db = db::sled_adapter::SledDb::init(
+			db::sled_adapter::sled::open(path.as_path().join("db")).unwrap(),
+		);
+		let resync = BlockResyncManager::new(&db, path.as_path(), vec![], 4);
+		let hash = blake2sum(b"interrupted");
+		resync
+			.put_to_resync_at(&hash, now_msec(), ResyncPriority::Normal)
+			.unwrap();
+
+		// A block is claimed while it is being resynced, and released
+		// when its worker is done
+		let mut block = resync.get_block_to_resync().unwrap().unwrap();
+		assert!(resync.get_block_to_resync().unwrap().is_none());
+		assert_eq!(resync.claims.len(), 0);
+		block.claim(Duration::from_secs(60)).unwrap();
+		assert_eq!(resync.claims.len(), 1);
+		drop(block);
+		assert_eq!(resync.claims.len(), 0);
+
+		// A worker only releases its own claim
+		let mut block = resync.get_block_to_resync().unwrap().unwrap();
+		block.claim(Duration::from_secs(60)).unwrap();
+		let other = u64::to_be_bytes(now_msec() + 120_000);
+		resync.claims.insert(&block.time_bytes, other).unwrap();
+		let key = block.time_bytes.clone();
+		drop(block);
+		assert_eq!(resync.claims.get(&key).unwrap().unwrap(), other);
+
+		// An entry is not given out while another worker holds its claim,
+		// unless that claim has expired
+		assert!(resync.get_block_to_resync().unwrap().is_none());
+		resync
+			.claims
+			.insert(&key, u64::to_be_bytes(now_msec() - 1))
+			.unwrap();
+		assert!(resync.get_block_to_resync().unwrap().is_some());
+
+		// Claims left by a crash are released at startup, and reported
+		// if their entry is still in the queue
+		let finished = blake2sum(b"finished");
+		resync
+			.claims
+			.insert(
+				queue_key(ResyncPriority::Normal, 0, finished.as_slice()),
+				[0; 8],
+			)
+			.unwrap();
+		assert_eq!(resync.orphaned_claims(), 0);
+		let resync = BlockResyncManager::new(&db, path.as_path(), vec![], 4);
+		assert_eq!(resync.orphaned_claims(), 1);
+		assert_eq!(resync.claims.len(), 0);
+		let block = resync.get_block_to_resync().unwrap().unwrap();
+		assert_eq!(block.hash_bytes, hash.as_slice());
+	}
+
+	#[test]
+	fn test_oldest_queued_at() {
+		let (resync, _path) = test_resync_manager();
+		assert_eq!(resync.oldest_queued_at().unwrap(), None);
+
+		let now = now_msec();
+		resync
+			.put_to_resync_at(&blake2sum(b"later"), now + 1000, ResyncPriority::Normal)
+			.unwrap();
+		resync
+			.put_to_resync_at(&blake2sum(b"sooner"), now, ResyncPriority::Normal)
+			.unwrap();
+		assert_eq!(resync.oldest_queued_at().unwrap(), Some(now));
+	}
+
+	#[test]
+	fn test_queue_priority() {
+		let (resync, _path) = test_resync_manager();
+		let now = now_msec();
+		let routine = blake2sum(b"routine");
+		let missing = blake2sum(b"missing");
+		let later = blake2sum(b"missing later");
+
+		// Due entries of high priority are taken first
+		resync
+			.put_to_resync_at(&routine, now - 1000, ResyncPriority::Normal)
+			.unwrap();
+		resync
+			.put_to_resync_at(&missing, now, ResyncPriority::High)
+			.unwrap();
+		resync
+			.put_to_resync_at(&later, now + 60_000, ResyncPriority::High)
+			.unwrap();
+		let first = resync.get_block_to_resync().unwrap().unwrap();
+		assert_eq!(first.hash_bytes, missing.as_slice());
+
+		// A high priority entry that is not due yet does not hold back
+		// entries that are due
+		let second = resync.get_block_to_resync().unwrap().unwrap();
+		assert_eq!(second.hash_bytes, routine.as_slice());
+		let third = resync.get_block_to_resync().unwrap().unwrap();
+		assert_eq!(third.hash_bytes, later.as_slice());
+		drop((first, second, third));
+
+		// Requeuing a block keeps the highest priority and the soonest time
+		resync
+			.put_to_resync_at(&routine, now - 5000, ResyncPriority::High)
+			.unwrap();
+		resync
+			.put_to_resync_at(&routine, now, ResyncPriority::Normal)
+			.unwrap();
+		let key = resync.queue_index.get(routine.as_slice()).unwrap().unwrap();
+		assert_eq!(queue_key_priority(&key), ResyncPriority::High);
+		assert_eq!(queue_key_time(&key), now - 5000);
+		assert_eq!(resync.queue_len().unwrap(), 3);
+	}
+
+	#[test]
+	fn test_migrate_legacy_queue() {
+		let path = mktemp::Temp::new_dir().unwrap();
+		let db = db::sled_adapter::SledDb::init(
+			db::sled_adapter::sled::open(path.as_path().join("db")).unwrap(),
+		);
+		let hash = blake2sum(b"queued by an older version");
+		let now = now_msec();
+		{
+			let queue = db.open_tree("block_local_resync_queue").unwrap();
+			let mut key = u64::to_be_bytes(now).to_vec();
+			key.extend(hash.as_slice());
+			queue.insert(&key, hash.as_slice()).unwrap();
+		}
+
+		let resync = BlockResyncManager::new(&db, path.as_path(), vec![], 4);
+		assert_eq!(resync.queue_len().unwrap(), 1);
+		let (key, value) = resync.queue.first().unwrap().unwrap();
+		assert_eq!(key, queue_key(ResyncPriority::Normal, now, hash.as_slice()));
+		assert_eq!(value, hash.as_slice());
+		assert_eq!(resync.queue_index.get(hash.as_slice()).unwrap(), Some(key));
 	}
 
-	async fn resync_iter(&self, manager: &BlockManager) -> Result<ResyncIterResult, db::Error> {
-		if let Some(block) = self.get_block_to_resync()? {
-			let time_msec = u64::from_be_bytes(block.time_bytes[0..8].try_into().unwrap());
-			let now = now_msec();
+	#[test]
+	fn test_queue_dedup() {
+		let (resync, _path) = test_resync_manager();
+		let hash = blake2sum(b"scheduled many times");
+		let now = now_msec();
 
-			if now >= time_msec {
-				let hash = Hash::try_from(&block.hash_bytes[..]).unwrap();
+		resync
+			.put_to_resync_at(&hash, now + 3000, ResyncPriority::Normal)
+			.unwrap();
+		resync
+			.put_to_resync_at(&hash, now + 1000, ResyncPriority::Normal)
+			.unwrap();
+		resync
+			.put_to_resync_at(&hash, now + 2000, ResyncPriority::Normal)
+			.unwrap();
+		assert_eq!(resync.queue_len().unwrap(), 1);
+		let (time_bytes, _) = resync.queue.first().unwrap().unwrap();
+		assert_eq!(queue_key_time(&time_bytes), now + 1000);
 
-				if let Some(ec) = self.errors.get(hash.as_slice())? {
-					let ec = ErrorCounter::decode(&ec);
-					if now < ec.next_try() {
-						// if next retry after an error is not yet,
-						// don't do resync and return early, but still
-						// make sure the item is still in queue at expected time
-						self.put_to_resync_at(&hash, ec.next_try())?;
-						// ec.next_try() > now >= time_msec, so this remove
-						// is not removing the one we added just above
-						// (we want to do the remove after the insert to ensure
-						// that the item is not lost if we crash in-between)
-						self.queue.remove(&block.time_bytes)?;
-						return Ok(ResyncIterResult::BusyDidNothing);
-					}
-				}
+		// An entry being processed is kept until its worker is done with it
+		let block = resync.get_block_to_resync().unwrap().unwrap();
+		resync
+			.put_to_resync_at(&hash, now + 5000, ResyncPriority::Normal)
+			.unwrap();
+		assert_eq!(resync.queue_len().unwrap(), 2);
+		resync.finish_entry(&block.time_bytes).unwrap();
+		drop(block);
+		assert_eq!(resync.queue_len().unwrap(), 1);
+		assert!(resync.queue_index.get(hash.as_slice()).unwrap().is_some());
+	}
 
-				let tracer = opentelemetry::global::tracer("garage");
-				let trace_id = gen_uuid();
-				let span = tracer
-					.span_builder("Resync block")
-					.with_trace_id(
-						opentelemetry::trace::TraceId::from_hex(&hex::encode(
-							&trace_id.as_slice()[..16],
-						))
-						.unwrap(),
-					)
-					.with_attributes(vec![KeyValue::new("block", format!("{:?}", hash))])
-					.start(&tracer);
+	#[test]
+	fn test_explain() {
+		let (resync, _path) = test_resync_manager();
+		let hash = blake2sum(b"a block to explain");
+		let now = now_msec();
+		let needed = RcEntry::Present { count: 2 };
 
-				let res = self
-					.resync_block(manager, &hash)
-					.with_context(Context::current_with_span(span))
-					.bound_record_duration(&manager.metrics.resync_duration)
-					.await;
+		let present = resync.explain_status(&hash, true, &needed, now).unwrap();
+		assert!(present.exists);
+		assert_eq!(present.refcount, 2);
+		assert!(present.is_nonzero);
+		assert!(!present.is_deletable);
+		assert!(present.queued_at.is_empty());
+		assert!(!present.busy);
+		assert_eq!(present.errors, None);
+		assert!(!present.quarantined);
+		assert_eq!(present.action, ResyncAction::Nothing);
 
-				manager.metrics.resync_counter.add(1);
+		resync
+			.put_to_resync_at(&hash, now + 1000, ResyncPriority::Normal)
+			.unwrap();
+		let absent = resync.explain_status(&hash, false, &needed, now).unwrap();
+		assert!(!absent.exists);
+		assert_eq!(absent.refcount, 2);
+		assert_eq!(absent.queued_at, vec![now + 1000]);
+		assert_eq!(absent.action, ResyncAction::Fetch);
 
-				if let Err(e) = &res {
-					manager.metrics.resync_error_counter.add(1);
-					error!("Error when resyncing {:?}: {}", hash, e);
+		// Explaining a block has no side effect
+		assert_eq!(resync.queue.len(), 1);
+		assert_eq!(resync.errors_len().unwrap(), 0);
 
-					let err_counter = match self.errors.get(hash.as_slice())? {
-						Some(ec) => ErrorCounter::decode(&ec).add1(now + 1),
-						None => ErrorCounter::new(now + 1),
-					};
+		let deletable = RcEntry::Deletable { at_time: now - 1 };
+		let unneeded = resync.explain_status(&hash, true, &deletable, now).unwrap();
+		assert_eq!(unneeded.action, ResyncAction::OffloadAndDelete);
 
-					self.errors.insert(hash.as_slice(), err_counter.encode())?;
+		// A longer minimum deletion delay keeps the block for now
+		resync
+			.set_min_deletion_delay(BLOCK_GC_DELAY + Duration::from_secs(3600))
+			.unwrap();
+		let unneeded = resync.explain_status(&hash, true, &deletable, now).unwrap();
+		assert_eq!(unneeded.action, ResyncAction::WaitForDeletionDelay);
+	}
 
-					self.put_to_resync_at(&hash, err_counter.next_try())?;
-					// err_counter.next_try() >= now + 1 > now,
-					// the entry we remove from the queue is not
-					// the entry we inserted with put_to_resync_at
-					self.queue.remove(&block.time_bytes)?;
-				} else {
-					self.errors.remove(hash.as_slice())?;
-					self.queue.remove(&block.time_bytes)?;
-				}
+	#[test]
+	fn test_reconcile_queue() {
+		let (resync, _path) = test_resync_manager();
+		let now = now_msec();
+		let present = RcEntry::Present { count: 1 };
 
-				Ok(ResyncIterResult::BusyDidSomething)
-			} else {
-				Ok(ResyncIterResult::IdleFor(Duration::from_millis(
-					time_msec - now,
-				)))
-			}
-		} else {
-			// Here we wait either for a notification that an item has been
-			// added to the queue, or for a constant delay of 10 secs to expire.
-			// The delay avoids a race condition where the notification happens
-			// between the time we checked the queue and the first poll
-			// to resync_notify.notified(): if that happens, we'll just loop
-			// back 10 seconds later, which is fine.
-			Ok(ResyncIterResult::IdleFor(Duration::from_secs(10)))
+		// Seed a queue that disagrees with the (simulated) RC table
+		let busy_unneeded = blake2sum(b"stored, busy");
+		let missing_and_needed = blake2sum(b"missing and needed");
+		let stored_with_errors = blake2sum(b"stored with errors");
+		let unneeded = blake2sum(b"stored");
+		let missing_not_queued = blake2sum(b"missing not queued");
+		for (i, hash) in [
+			busy_unneeded,
+			missing_and_needed,
+			stored_with_errors,
+			unneeded,
+		]
+		.iter()
+		.enumerate()
+		{
+			resync
+				.put_to_resync_at(hash, now + i as u64, ResyncPriority::Normal)
+				.unwrap();
 		}
-	}
+		resync
+			.errors
+			.insert(
+				stored_with_errors.as_slice(),
+				ErrorCounter::new(now).encode(),
+			)
+			.unwrap();
+		let busy = resync.get_block_to_resync().unwrap().unwrap();
+		assert_eq!(busy.hash_bytes, busy_unneeded.as_slice());
 
-	fn get_block_to_resync(&self) -> Result<Option<BusyBlock>, db::Error> {
-		let mut busy = self.busy_set.lock().unwrap();
-		for it in self.queue.iter()? {
-			let (time_bytes, hash_bytes) = it?;
-			if !busy.contains(&time_bytes) {
-				busy.insert(time_bytes.clone());
-				return Ok(Some(BusyBlock {
-					time_bytes,
-					hash_bytes,
-					busy_set: self.busy_set.clone(),
-				}));
+		let exists = |h: &Hash| *h != missing_and_needed && *h != missing_not_queued;
+
+		// Read the queue in two batches, as the repair worker would
+		let first = resync.queue_batch(None, 2).unwrap();
+		let rest = resync
+			.queue_batch(Some(&first.last().unwrap().0), 1000)
+			.unwrap();
+		assert_eq!(first.len(), 2);
+		assert_eq!(rest.len(), 2);
+		let entries = first
+			.into_iter()
+			.chain(rest)
+			.map(|(k, h)| {
+				let needs = needs_resync(exists(&h), &present);
+				(k, h, needs)
+			})
+			.collect::<Vec<_>>();
+
+		// Only the entry of `unneeded` can be removed: the busy entry,
+		// the needed entry and the entry with errors are kept
+		assert_eq!(resync.remove_unneeded_entries(&entries).unwrap(), 1);
+		drop(busy);
+		assert_eq!(resync.queue.len(), 3);
+		assert!(resync.is_queued(&busy_unneeded).unwrap());
+		assert!(resync.is_queued(&missing_and_needed).unwrap());
+		assert!(resync.is_queued(&stored_with_errors).unwrap());
+		assert!(!resync.is_queued(&unneeded).unwrap());
+
+		// A block that is needed but missing and not queued must be queued
+		assert!(!resync.is_queued(&missing_not_queued).unwrap());
+		assert!(needs_resync(exists(&missing_not_queued), &present));
+		// Present but unreferenced blocks need to be deleted eventually
+		assert!(needs_resync(
+			true,
+			&RcEntry::Deletable {
+				at_time: now + 1000
 			}
-		}
-		Ok(None)
+		));
+		assert!(!needs_resync(false, &RcEntry::Absent));
 	}
 
-	async fn resync_block(&self, manager: &BlockManager, hash: &Hash) -> Result<(), Error> {
-		let BlockStatus { exists, needed } = manager.check_block_status(hash).await?;
+	#[test]
+	fn test_replay_after_crash() {
+		let path = mktemp::Temp::new_dir().unwrap();
+		let db = db::sled_adapter::SledDb::init(
+			db::sled_adapter::sled::open(path.as_path().join("db")).unwrap(),
+		);
+		let hash = blake2sum(b"block resynced just before a crash");
+		let fingerprint = status_fingerprint(true, &RcEntry::Present { count: 1 });
 
-		if exists != needed.is_needed() || exists != needed.is_nonzero() {
-			debug!(
-				"Resync block {:?}: exists {}, nonzero rc {}, deletable {}",
-				hash,
-				exists,
-				needed.is_nonzero(),
-				needed.is_deletable(),
-			);
-		}
+		let key = {
+			let resync = BlockResyncManager::new(&db, path.as_path(), vec![], 4);
+			resync
+				.put_to_resync_at(&hash, now_msec(), ResyncPriority::Normal)
+				.unwrap();
+			let block = resync.get_block_to_resync().unwrap().unwrap();
+			// The resync succeeded, but we crash before the entry
+			// is removed from the queue
+			resync.mark_done(&block.time_bytes, &fingerprint).unwrap();
+			block.time_bytes.clone()
+		};
 
-		if exists && needed.is_deletable() {
-			info!("Resync block {:?}: offloading and deleting", hash);
+		// After restart, the same entry is dequeued again
+		let resync = BlockResyncManager::new(&db, path.as_path(), vec![], 4);
+		let block = resync.get_block_to_resync().unwrap().unwrap();
+		assert_eq!(block.time_bytes, key);
 
-			let mut who = manager.replication.write_nodes(hash);
-			if who.len() < manager.replication.write_quorum() {
-				return Err(Error::Message("Not trying to offload block because we don't have a quorum of nodes to write to".to_string()));
-			}
-			who.retain(|id| *id != manager.system.id);
+		// If the block has changed since, it must be processed again
+		let changed = status_fingerprint(true, &RcEntry::Present { count: 2 });
+		assert!(!resync.is_replay_noop(&key, &changed).unwrap());
+		let deleted = status_fingerprint(false, &RcEntry::Present { count: 1 });
+		assert!(!resync.is_replay_noop(&key, &deleted).unwrap());
 
-			let who_needs_resps = manager
-				.system
-				.rpc
-				.call_many(
-					&manager.endpoint,
-					&who,
-					BlockRpc::NeedBlockQuery(*hash),
-					RequestStrategy::with_priority(PRIO_BACKGROUND),
-				)
-				.await?;
+		// Otherwise the replay is a no-op, which leaves nothing behind
+		assert!(resync.is_replay_noop(&key, &fingerprint).unwrap());
+		resync.finish_entry(&key).unwrap();
+		assert!(resync.queue.is_empty());
+		assert_eq!(resync.resync_done.len().unwrap(), 0);
 
-			let mut need_nodes = vec![];
-			for (node, needed) in who_needs_resps {
-				match needed.err_context("NeedBlockQuery RPC")? {
-					BlockRpc::NeedBlockReply(needed) => {
-						if needed {
-							need_nodes.push(node);
-						}
-					}
-					m => {
-						return Err(Error::unexpected_rpc_message(m));
-					}
-				}
-			}
+		// An entry that was never processed is not a replay
+		resync
+			.put_to_resync_at(&hash, now_msec(), ResyncPriority::Normal)
+			.unwrap();
+		let block = resync.get_block_to_resync().unwrap().unwrap();
+		assert!(!resync
+			.is_replay_noop(&block.time_bytes, &fingerprint)
+			.unwrap());
+	}
 
-			if !need_nodes.is_empty() {
-				trace!(
-					"Block {:?} needed by {} nodes, sending",
-					hash,
-					need_nodes.len()
-				);
+	#[test]
+	fn test_resync_schedule() {
+		let path = mktemp::Temp::new_dir().unwrap();
+		let db = db::sled_adapter::SledDb::init(
+			db::sled_adapter::sled::open(path.as_path().join("db")).unwrap(),
+		);
+		let nighttime = ResyncScheduleWindow {
+			name: "nighttime".into(),
+			start: 22 * 60,
+			end: 6 * 60,
+			n_workers: Some(4),
+			tranquility: Some(0),
+		};
+		let lunch = ResyncScheduleWindow {
+			name: "lunch".into(),
+			start: 12 * 60,
+			end: 13 * 60,
+			n_workers: None,
+			tranquility: Some(10),
+		};
+		let resync = BlockResyncManager::new(&db, path.as_path(), vec![nighttime, lunch], 4);
+		let static_tuning = (1, INITIAL_RESYNC_TRANQUILITY);
 
-				for node in need_nodes.iter() {
-					manager
-						.metrics
-						.resync_send_counter
-						.add(1, &[KeyValue::new("to", format!("{:?}", node))]);
-				}
+		// Daytime: the persisted configuration applies
+		assert!(!resync.update_schedule(9 * 60));
+		assert_eq!(resync.effective_tuning(), static_tuning);
+		assert_eq!(resync.scheduled_window(), None);
 
-				let block = manager.read_block(hash).await?;
-				let (header, bytes) = block.into_parts();
-				let put_block_message = Req::new(BlockRpc::PutBlock {
-					hash: *hash,
-					header,
-				})?
-				.with_stream_from_buffer(bytes);
-				manager
-					.system
-					.rpc
-					.try_call_many(
-						&manager.endpoint,
-						&need_nodes[..],
-						put_block_message,
-						RequestStrategy::with_priority(PRIO_BACKGROUND)
-							.with_quorum(need_nodes.len()),
-					)
-					.await
-					.err_context("PutBlock RPC")?;
-			}
-			info!(
-				"Deleting unneeded block {:?}, offload finished ({} / {})",
-				hash,
-				need_nodes.len(),
-				who.len()
-			);
+		// Entering the nighttime window, which spans midnight
+		assert!(resync.update_schedule(22 * 60));
+		assert_eq!(resync.effective_tuning(), (4, 0));
+		assert_eq!(resync.scheduled_window(), Some("nighttime"));
+		assert!(!resync.update_schedule(2 * 60));
+		assert_eq!(resync.effective_tuning(), (4, 0));
 
-			manager.delete_if_unneeded(hash).await?;
+		// Leaving it reverts to the persisted configuration,
+		// including changes made in the meantime
+		resync.set_resync_tuning(Some(2), None).unwrap();
+		assert_eq!(resync.effective_tuning(), (4, 0));
+		assert!(resync.update_schedule(6 * 60));
+		assert_eq!(resync.effective_tuning(), (2, INITIAL_RESYNC_TRANQUILITY));
+		assert_eq!(resync.scheduled_window(), None);
 
-			manager.rc.clear_deleted_block_rc(hash)?;
-		}
+		// A window can override only some of the settings
+		assert!(resync.update_schedule(12 * 60 + 30));
+		assert_eq!(resync.effective_tuning(), (2, 10));
+		assert_eq!(resync.scheduled_window(), Some("lunch"));
+	}
 
-		if needed.is_nonzero() && !exists {
-			info!(
-				"Resync block {:?}: fetching absent but needed block (refcount > 0)",
-				hash
-			);
+	#[test]
+	fn test_minute_of_day() {
+		assert_eq!(minute_of_day(0), 0);
+		assert_eq!(minute_of_day(61_000), 1);
+		assert_eq!(minute_of_day(24 * 3600 * 1000 + 90 * 60_000), 90);
+	}
 
-			let block_data = manager.rpc_get_raw_block(hash, None).await?;
+	#[test]
+	fn test_drained() {
+		let (resync, _path) = test_resync_manager();
+		let drained = resync.subscribe_drained();
+		let hash = blake2sum(b"last block of the queue");
+		let debounce = RESYNC_DRAINED_DEBOUNCE.as_millis() as u64;
+		let now = now_msec();
 
-			manager.metrics.resync_recv_counter.add(1);
+		resync
+			.put_to_resync_at(&hash, now, ResyncPriority::Normal)
+			.unwrap();
+		assert_eq!(resync.update_drained(now), None);
+		assert!(!*drained.borrow());
 
-			manager.write_block(hash, &block_data).await?;
-		}
+		// The queue is emptied: it is reported as drained after the debounce delay
+		let key = resync.queue.first().unwrap().unwrap().0;
+		resync.queue.remove(&key).unwrap();
+		assert_eq!(
+			resync.update_drained(now + 1),
+			Some(RESYNC_DRAINED_DEBOUNCE)
+		);
+		assert!(!*drained.borrow());
+		assert!(resync.update_drained(now + 1 + debounce / 2).is_some());
+		assert!(!*drained.borrow());
+		assert_eq!(resync.update_drained(now + 1 + debounce), None);
+		assert!(*drained.borrow());
 
-		Ok(())
+		// A new block in the queue resets it immediately
+		resync.put_to_resync(&hash, Duration::ZERO).unwrap();
+		assert!(!*drained.borrow());
 	}
-}
 
-impl Drop for BusyBlock {
-	fn drop(&mut self) {
-		let mut busy = self.busy_set.lock().unwrap();
-		busy.remove(&self.time_bytes);
-	}
-}
+	#[test]
+	fn test_min_deletion_delay() {
+		let (resync, _path) = test_resync_manager();
+		let hash = blake2sum(b"block without references");
+		let gc_delay = BLOCK_GC_DELAY.as_millis() as u64;
 
-pub(crate) struct ResyncWorker {
-	index: usize,
-	manager: Arc<BlockManager>,
-	tranquilizer: Tranquilizer,
-	next_delay: Duration,
-	persister: PersisterShared<ResyncPersistedConfig>,
-}
+		let before = now_msec();
+		resync
+			.put_to_resync_deletable(&hash, Duration::ZERO)
+			.unwrap();
+		let key = resync.queue.first().unwrap().unwrap().0;
+		assert!(queue_key_time(&key) >= before + gc_delay);
 
-impl ResyncWorker {
-	pub(crate) fn new(index: usize, manager: Arc<BlockManager>) -> Self {
-		let persister = manager.resync.persister.clone();
-		Self {
-			index,
-			manager,
-			tranquilizer: Tranquilizer::new(30),
-			next_delay: Duration::from_secs(10),
-			persister,
-		}
+		assert!(resync.set_min_deletion_delay(BLOCK_GC_DELAY / 2).is_err());
+		resync.set_min_deletion_delay(BLOCK_GC_DELAY * 2).unwrap();
+		let now = now_msec();
+		let at_time = now - 1;
+		let needed = RcEntry::Deletable { at_time };
+		assert!(needed.is_deletable());
+		assert!(!resync.deletion_grace_elapsed(&needed, now));
+		assert!(resync.deletion_grace_elapsed(&needed, at_time + gc_delay + 1));
+		assert!(resync.deletion_grace_elapsed(&RcEntry::Absent, now));
 	}
-}
 
-#[async_trait]
-impl Worker for ResyncWorker {
-	fn name(&self) -> String {
-		format!("Block resync worker #{}", self.index + 1)
+	#[test]
+	fn test_status_cache() {
+		let (resync, _path) = test_resync_manager();
+		let hash = blake2sum(b"recently checked block");
+		let ttl = RESYNC_STATUS_CACHE_TTL.as_millis() as u64;
+		let now = now_msec();
+		let status = BlockStatus {
+			exists: true,
+			needed: RcEntry::Present { count: 1 },
+		};
+
+		assert!(resync.cached_block_status(&hash, now).is_none());
+		resync.cache_block_status(&hash, now, status);
+		let cached = resync.cached_block_status(&hash, now + ttl - 1).unwrap();
+		assert!(cached.exists);
+		assert!(resync.cached_block_status(&hash, now + ttl).is_none());
+
+		resync.invalidate_block_status(&hash);
+		assert!(resync.cached_block_status(&hash, now).is_none());
 	}
 
-	fn status(&self) -> WorkerStatus {
-		let (n_workers, tranquility) = self.persister.get_with(|x| (x.n_workers, x.tranquility));
+	#[test]
+	fn test_resync_fetch_order() {
+		let nodes = (0..3u8).map(|i| Uuid::from([i; 32])).collect::<Vec<_>>();
+		let local = Uuid::from([9u8; 32]);
 
-		if self.index >= n_workers {
-			return WorkerStatus {
-				freeform: vec!["This worker is currently disabled".into()],
-				..Default::default()
-			};
+		// Each block is fetched from all its nodes, starting from different ones
+		let mut first = HashSet::new();
+		for i in 0..100u64 {
+			let hash = blake2sum(&i.to_be_bytes());
+			let order = resync_fetch_order(nodes.clone(), &hash, local);
+			assert_eq!(order.iter().collect::<HashSet<_>>().len(), 3);
+			assert!(nodes.iter().all(|node| order.contains(node)));
+			first.insert(order[0]);
 		}
+		assert_eq!(first.len(), 3);
 
-		WorkerStatus {
-			queue_length: Some(self.manager.resync.queue_len().unwrap_or(0) as u64),
-			tranquility: Some(tranquility),
-			persistent_errors: Some(self.manager.resync.errors_len().unwrap_or(0) as u64),
-			..Default::default()
+		// The local node is asked last
+		let with_local = vec![local, nodes[0], nodes[1]];
+		for i in 0..10u64 {
+			let hash = blake2sum(&i.to_be_bytes());
+			let order = resync_fetch_order(with_local.clone(), &hash, local);
+			assert_eq!(order.len(), 3);
+			assert_eq!(order[2], local);
 		}
+
+		assert!(resync_fetch_order(vec![], &blake2sum(b"block"), local).is_empty());
 	}
 
-	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
-		let (n_workers, tranquility) = self.persister.get_with(|x| (x.n_workers, x.tranquility));
+	#[test]
+	fn test_verify_only() {
+		let (resync, _path) = test_resync_manager();
+		assert!(!resync.is_verify_only());
+		resync.set_verify_only(true).unwrap();
+		assert!(resync.is_verify_only());
+		resync.set_verify_only(false).unwrap();
+		assert!(!resync.is_verify_only());
+	}
 
-		if self.index >= n_workers {
-			return Ok(WorkerState::Idle);
-		}
+	#[tokio::test]
+	async fn test_verify_only_resync_block() {
+		let (manager, _path) = test_block_manager().await;
+		let resync = &manager.resync;
+		resync.set_verify_only(true).unwrap();
 
-		self.tranquilizer.reset();
-		match self.manager.resync.resync_iter(&self.manager).await {
-			Ok(ResyncIterResult::BusyDidSomething) => {
-				Ok(self.tranquilizer.tranquilize_worker(tranquility))
-			}
-			Ok(ResyncIterResult::BusyDidNothing) => Ok(WorkerState::Busy),
-			Ok(ResyncIterResult::IdleFor(delay)) => {
-				self.next_delay = delay;
-				Ok(WorkerState::Idle)
-			}
-			Err(e) => {
-				// The errors that we have here are only Sled errors
-				// We don't really know how to handle them so just ¯\_(ツ)_/¯
-				// (there is kind of an assumption that Sled won't error on us,
-				// if it does there is not much we can do -- TODO should we just panic?)
-				// Here we just give the error to the worker manager,
-				// it will print it to the logs and increment a counter
-				Err(e.into())
-			}
-		}
+		// A block that is needed but absent is counted as under-replicated,
+		// and is not fetched
+		let missing = blake2sum(b"missing block");
+		manager
+			.rc
+			.rc
+			.insert(missing.as_slice(), u64::to_be_bytes(1))
+			.unwrap();
+		let status = manager.check_block_status(&missing).await.unwrap();
+		let decision = resync
+			.verify_replicas(&manager, &missing, status.exists, &status.needed)
+			.await
+			.unwrap();
+		assert_eq!(decision, "underreplicated");
+		resync.resync_block(&manager, &missing).await.unwrap();
+		assert!(!manager.check_block_status(&missing).await.unwrap().exists);
+
+		// A block that is stored but deletable is neither offloaded
+		// nor deleted
+		let data = b"deletable block";
+		let deletable = blake2sum(data);
+		manager
+			.write_block(
+				&deletable,
+				&DataBlock::Plain(bytes::Bytes::from_static(data)),
+			)
+			.await
+			.unwrap();
+		let deletable_since = now_msec() - 1000;
+		manager
+			.rc
+			.rc
+			.insert(
+				deletable.as_slice(),
+				[u64::to_be_bytes(0), u64::to_be_bytes(deletable_since)].concat(),
+			)
+			.unwrap();
+		let status = manager.check_block_status(&deletable).await.unwrap();
+		assert!(status.exists && status.needed.is_deletable());
+		let decision = resync
+			.verify_replicas(&manager, &deletable, status.exists, &status.needed)
+			.await
+			.unwrap();
+		assert_eq!(decision, "verified");
+		resync.resync_block(&manager, &deletable).await.unwrap();
+		assert!(manager.check_block_status(&deletable).await.unwrap().exists);
+		assert!(manager.rc.rc.get(deletable.as_slice()).unwrap().is_some());
+		assert!(manager.recent_resync_events().is_empty());
+
+		// Out of verify-only mode, the same block is deleted
+		resync.set_verify_only(false).unwrap();
+		resync.resync_block(&manager, &deletable).await.unwrap();
+		assert!(!manager.check_block_status(&deletable).await.unwrap().exists);
+		let events = manager.recent_resync_events();
+		assert_eq!(events.len(), 1);
+		assert!(matches!(&events[0].1, ResyncEvent::Deleted { hash } if *hash == deletable));
 	}
 
-	async fn wait_for_work(&mut self) -> WorkerState {
-		while self.index >= self.persister.get_with(|x| x.n_workers) {
-			self.manager.resync.notify.notified().await
+	#[test]
+	fn test_recent_resync_events() {
+		let recent = RecentResyncEvents::new(3);
+		for i in 0..5u64 {
+			let hash = blake2sum(&i.to_be_bytes());
+			recent.push(1000 + i, ResyncEvent::Deleted { hash });
 		}
+		let times = recent.list().iter().map(|(t, _)| *t).collect::<Vec<_>>();
+		assert_eq!(times, vec![1002, 1003, 1004]);
 
-		select! {
-			_ = tokio::time::sleep(self.next_delay) => (),
-			_ = self.manager.resync.notify.notified() => (),
-		};
+		let disabled = RecentResyncEvents::new(0);
+		assert!(!disabled.is_enabled());
+		disabled.push(
+			1000,
+			ResyncEvent::Deleted {
+				hash: blake2sum(b"deleted block"),
+			},
+		);
+		assert!(disabled.list().is_empty());
+	}
 
-		WorkerState::Busy
+	#[test]
+	fn test_last_verified() {
+		let (resync, _path) = test_resync_manager();
+		let hash = blake2sum(b"block checked last week");
+		let day = 24 * 3600 * 1000;
+		let now = now_msec();
+
+		assert_eq!(resync.last_verified(&hash).unwrap(), None);
+		assert!(resync.is_stale(&hash, now).unwrap());
+
+		resync.record_verified(&hash, true, now - 7 * day).unwrap();
+		assert_eq!(resync.last_verified(&hash).unwrap(), Some(now - 7 * day));
+		assert!(resync.is_stale(&hash, now - 3 * day).unwrap());
+		assert!(!resync.is_stale(&hash, now - 10 * day).unwrap());
+
+		resync.record_verified(&hash, true, now).unwrap();
+		assert!(!resync.is_stale(&hash, now - 3 * day).unwrap());
+
+		// Deleted blocks are forgotten
+		resync.record_verified(&hash, false, now).unwrap();
+		assert_eq!(resync.last_verified(&hash).unwrap(), None);
 	}
-}
 
-/// Counts the number of errors when resyncing a block,
-/// and the time of the last try.
-/// Used to implement exponential backoff.
-#[derive(Clone, Copy, Debug)]
-pub(crate) struct ErrorCounter {
-	pub(crate) errors: u64,
-	pub(crate) last_try: u64,
-}
+	#[test]
+	fn test_abort_in_flight() {
+		let (resync, _path) = test_resync_manager();
+		let hash = blake2sum(b"block being resynced");
+		let other = blake2sum(b"other block");
 
-impl ErrorCounter {
-	fn new(now: u64) -> Self {
-		Self {
-			errors: 1,
-			last_try: now,
-		}
+		let first = resync.track_in_flight(&hash);
+		let second = resync.track_in_flight(&hash);
+		let unrelated = resync.track_in_flight(&other);
+		resync.abort_in_flight(&hash);
+		assert!(first.is_aborted());
+		assert!(second.is_aborted());
+		assert!(!unrelated.is_aborted());
+
+		// A resync that starts after the block was removed is not aborted
+		drop(first);
+		let third = resync.track_in_flight(&hash);
+		assert!(!third.is_aborted());
+
+		drop(second);
+		drop(third);
+		drop(unrelated);
+		assert!(resync.in_flight.lock().unwrap().is_empty());
 	}
 
-	pub(crate) fn decode(data: &[u8]) -> Self {
-		Self {
-			errors: u64::from_be_bytes(data[0..8].try_into().unwrap()),
-			last_try: u64::from_be_bytes(data[8..16].try_into().unwrap()),
+	#[test]
+	fn test_queue_len_watch() {
+		let (resync, _path) = test_resync_manager();
+		let mut queue_len = resync.subscribe_queue_len();
+		let h1 = blake2sum(b"first queued block");
+		let h2 = blake2sum(b"second queued block");
+		let now = now_msec();
+		assert_eq!(*queue_len.borrow(), 0);
+
+		resync
+			.put_to_resync_at(&h1, now + 1000, ResyncPriority::Normal)
+			.unwrap();
+		assert!(queue_len.has_changed().unwrap());
+		assert_eq!(*queue_len.borrow_and_update(), 1);
+
+		// Requeueing the same block sooner does not change the final count
+		resync
+			.put_to_resync_at(&h1, now, ResyncPriority::Normal)
+			.unwrap();
+		assert_eq!(*queue_len.borrow_and_update(), 1);
+
+		resync
+			.put_to_resync_at(&h2, now, ResyncPriority::Normal)
+			.unwrap();
+		assert_eq!(*queue_len.borrow_and_update(), 2);
+
+		for _ in 0..2 {
+			let key = resync.queue.first().unwrap().unwrap().0;
+			resync.finish_entry(&key).unwrap();
 		}
+		assert!(queue_len.has_changed().unwrap());
+		assert_eq!(*queue_len.borrow(), 0);
 	}
 
-	fn encode(&self) -> Vec<u8> {
-		[
-			u64::to_be_bytes(self.errors),
-			u64::to_be_bytes(self.last_try),
-		]
-		.concat()
+	#[test]
+	fn test_backpressure_delay() {
+		assert_eq!(backpressure_delay(1_000_000, 0), Duration::ZERO);
+		assert_eq!(backpressure_delay(100, 100), Duration::ZERO);
+
+		let d1 = backpressure_delay(101, 100);
+		let d2 = backpressure_delay(200, 100);
+		assert!(d1 > Duration::ZERO);
+		assert!(d2 > d1);
+		assert_eq!(
+			backpressure_delay(u64::MAX, 100),
+			RESYNC_BACKPRESSURE_MAX_DELAY
+		);
 	}
 
-	fn add1(self, now: u64) -> Self {
-		Self {
-			errors: self.errors + 1,
-			last_try: now,
+	#[test]
+	fn test_backpressure_enqueue() {
+		let (resync, _path) = test_resync_manager();
+		resync
+			.persister
+			.set_with(|x| x.queue_high_water = 2)
+			.unwrap();
+
+		let mut when = vec![];
+		for i in 0..5u32 {
+			let before = now_msec();
+			resync
+				.put_to_resync(&blake2sum(&i.to_be_bytes()), Duration::ZERO)
+				.unwrap();
+			let (time_bytes, _) = resync
+				.queue
+				.iter()
+				.unwrap()
+				.map(|x| x.unwrap())
+				.last()
+				.unwrap();
+			when.push(queue_key_time(&time_bytes) - before);
 		}
-	}
 
-	fn delay_msec(&self) -> u64 {
-		(RESYNC_RETRY_DELAY.as_millis() as u64)
-			<< std::cmp::min(self.errors - 1, RESYNC_RETRY_DELAY_MAX_BACKOFF_POWER)
+		// Below the high-water mark, blocks are scheduled immediately
+		assert!(when[0] < 10 && when[1] < 10 && when[2] < 10);
+		// Past it, the delay grows with the length of the queue
+		assert!(when[3] >= 10);
+		assert!(when[4] >= 20);
+
+		// Blocks that might be under-replicated are not delayed
+		let before = now_msec();
+		let missing = blake2sum(b"missing block");
+		resync
+			.put_to_resync_with_priority(&missing, Duration::ZERO, ResyncPriority::High)
+			.unwrap();
+		let (key, _) = resync.queue.first().unwrap().unwrap();
+		assert_eq!(queue_key_priority(&key), ResyncPriority::High);
+		assert!(queue_key_time(&key) - before < 10);
+
+		// Blocks checked for deletion are delayed past the deletion delay
+		let before = now_msec();
+		let deletable = blake2sum(b"deletable block");
+		resync
+			.put_to_resync_deletable(&deletable, Duration::ZERO)
+			.unwrap();
+		let when = resync
+			.queue
+			.iter()
+			.unwrap()
+			.map(|x| x.unwrap().0)
+			.find(|key| key.ends_with(deletable.as_slice()))
+			.unwrap();
+		let min_delay = BLOCK_GC_DELAY.as_millis() as u64;
+		assert!(queue_key_time(&when) - before >= min_delay + 40);
 	}
 
-	pub(crate) fn next_try(&self) -> u64 {
-		self.last_try + self.delay_msec()
+	#[tokio::test]
+	async fn test_block_timeout() {
+		let (resync, _path) = test_resync_manager();
+		let hash = blake2sum(b"some block");
+
+		let res = with_block_timeout(&hash, Duration::from_millis(10), async { Ok(()) }).await;
+		assert!(res.is_ok());
+
+		// An operation that never completes is aborted and turned into
+		// an error, which reschedules its block with backoff
+		let now = now_msec();
+		resync
+			.put_to_resync_at(&hash, now, ResyncPriority::Normal)
+			.unwrap();
+		let block = resync.get_block_to_resync().unwrap().unwrap();
+		let res = with_block_timeout(
+			&hash,
+			Duration::from_millis(10),
+			futures::future::pending::<Result<(), Error>>(),
+		)
+		.await;
+		let e = res.unwrap_err();
+		assert!(matches!(&e, Error::Message(m) if m.contains("timed out")));
+		resync
+			.reschedule_after_error(&block, &hash, now, &e)
+			.unwrap();
+		drop(block);
+
+		let ec = ErrorCounter::decode(&resync.errors.get(hash.as_slice()).unwrap().unwrap());
+		assert_eq!(ec.errors, 1);
+		assert_eq!(ec.last_try, now + 1);
+		assert!(ec.last_error.as_deref().unwrap().contains("timed out"));
+
+		assert_eq!(resync.queue.len(), 1);
+		let (key, value) = resync.queue.first().unwrap().unwrap();
+		assert_eq!(value, hash.as_slice());
+		assert_eq!(queue_key_priority(&key), ResyncPriority::Normal);
+		assert_eq!(
+			queue_key_time(&key),
+			ec.next_try(&hash, &resync.retry_backoff())
+		);
+		assert!(queue_key_time(&key) > now);
 	}
 }
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::sync::{Arc, Weak};
+
+use async_trait::async_trait;
+
+use garage_util::data::*;
+use garage_util::error::*;
+
+use crate::block::*;
+use crate::manager::BlockManager;
+
+/// Where exactly a fetched block was found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockOrigin {
+	/// A node of the cluster
+	Node(Uuid),
+	/// A source that is not a node of the cluster, by its name
+	Source(String),
+}
+
+impl fmt::Display for BlockOrigin {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			BlockOrigin::Node(node) => write!(f, "{:?}", node),
+			BlockOrigin::Source(name) => write!(f, "{}", name),
+		}
+	}
+}
+
+/// A place where resync can fetch a block that is needed locally but absent.
+///
+/// Sources are tried in the order in which they were added to the resync
+/// manager, starting with the other nodes of the cluster (`PeerBlockSource`),
+/// until one of them returns the block.
+#[async_trait]
+pub trait BlockSource: Send + Sync {
+	/// Name of the source, used in logs
+	fn name(&self) -> String;
+
+	/// Get a (possibly compressed) block, as the source has it
+	async fn get_block(&self, hash: &Hash) -> Result<DataBlock, Error>;
+
+	/// Get a block whose content was checked against its hash, and where
+	/// exactly it was found (by default, the name of the source), for logs
+	/// and metrics. This is the only place where fetched blocks are verified:
+	/// sources that override it must verify the blocks they return.
+	async fn get_block_from(&self, hash: &Hash) -> Result<(DataBlock, BlockOrigin), Error> {
+		let block = self.get_block(hash).await?;
+		block.verify(*hash)?;
+		Ok((block, BlockOrigin::Source(self.name())))
+	}
+}
+
+/// The default block source: ask the nodes that should have the block
+pub(crate) struct PeerBlockSource(pub(crate) Weak<BlockManager>);
+
+#[async_trait]
+impl BlockSource for PeerBlockSource {
+	fn name(&self) -> String {
+		"peer nodes".into()
+	}
+
+	async fn get_block(&self, hash: &Hash) -> Result<DataBlock, Error> {
+		Ok(self.get_block_from(hash).await?.0)
+	}
+
+	/// Nodes are asked in turn until one of them returns a valid block,
+	/// which is reported as coming from that node. Blocks are verified as
+	/// they are received, so that an invalid block from one node doesn't
+	/// prevent the block from being fetched from the next one.
+	async fn get_block_from(&self, hash: &Hash) -> Result<(DataBlock, BlockOrigin), Error> {
+		let manager = self
+			.0
+			.upgrade()
+			.ok_or_message("Block manager is shutting down")?;
+		let (block, node) = manager.rpc_get_raw_block_verified(hash).await?;
+		Ok((block, BlockOrigin::Node(node)))
+	}
+}
+
+/// Try all sources in turn, returning the first valid block that is found
+/// and where it was found
+pub(crate) async fn fetch_from_sources(
+	sources: &[Arc<dyn BlockSource>],
+	hash: &Hash,
+) -> Result<(DataBlock, BlockOrigin), Error> {
+	let mut errors = vec![];
+	for source in sources.iter() {
+		match source.get_block_from(hash).await {
+			Ok((block, from)) => return Ok((block, from)),
+			Err(e) => {
+				debug!(
+					"Block {:?} not available from {}: {}",
+					hash,
+					source.name(),
+					e
+				);
+				errors.push(format!("{}: {}", source.name(), e));
+			}
+		}
+	}
+	Err(Error::Message(format!(
+		"Unable to fetch block {:?} from any source: {}",
+		hash,
+		errors.join("; ")
+	)))
+}
@@ -52,7 +52,7 @@ fn open_db(path: PathBuf, engine: String) -> Result<Db> {
 		}
 		"lmdb" | "heed" => {
 			std::fs::create_dir_all(&path).map_err(|e| {
-				Error(format!("Unable to create LMDB data directory: {}", e).into())
+				Error::Message(format!("Unable to create LMDB data directory: {}", e).into())
 			})?;
 
 			let map_size = lmdb_adapter::recommended_map_size();
@@ -64,6 +64,6 @@ fn open_db(path: PathBuf, engine: String) -> Result<Db> {
 				.unwrap();
 			Ok(lmdb_adapter::LmdbDb::init(db))
 		}
-		e => Err(Error(format!("Invalid DB engine: {}", e).into())),
+		e => Err(Error::Message(format!("Invalid DB engine: {}", e).into())),
 	}
 }
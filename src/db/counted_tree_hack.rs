@@ -5,6 +5,7 @@
 //! hopefully not harmfull!). Note that a CountedTree cannot be part of a
 //! transaction.
 
+use core::ops::RangeBounds;
 use std::sync::{
 	atomic::{AtomicUsize, Ordering},
 	Arc,
@@ -49,6 +50,14 @@ impl CountedTree {
 		self.0.tree.iter()
 	}
 
+	pub fn range<K, R>(&self, range: R) -> Result<ValueIter<'_>>
+	where
+		K: AsRef<[u8]>,
+		R: RangeBounds<K>,
+	{
+		self.0.tree.range(range)
+	}
+
 	// ---- writing functions ----
 
 	pub fn insert<K, V>(&self, key: K, value: V) -> Result<Option<Value>>
@@ -40,11 +40,36 @@ pub type TxValueIter<'a> = Box<dyn std::iter::Iterator<Item = TxOpResult<(Value,
 // ----
 
 #[derive(Debug, Error)]
-#[error(display = "{}", _0)]
-pub struct Error(pub Cow<'static, str>);
+pub enum Error {
+	#[error(display = "{}", _0)]
+	Message(Cow<'static, str>),
+	/// The operation failed only because the database was busy,
+	/// and can be retried a bit later
+	#[error(display = "{}", _0)]
+	Transient(Cow<'static, str>),
+}
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+	/// Whether the operation can be retried a bit later
+	pub fn is_transient(&self) -> bool {
+		matches!(self, Error::Transient(_))
+	}
+}
+
+/// Whether an I/O error of a database engine is caused by contention,
+/// so that the operation can be retried
+#[cfg(any(feature = "lmdb", feature = "sled"))]
+pub(crate) fn is_transient_io_error(e: &std::io::Error) -> bool {
+	matches!(
+		e.kind(),
+		std::io::ErrorKind::WouldBlock
+			| std::io::ErrorKind::Interrupted
+			| std::io::ErrorKind::TimedOut
+	)
+}
+
 #[derive(Debug, Error)]
 #[error(display = "{}", _0)]
 pub struct TxOpError(pub(crate) Error);
@@ -124,7 +149,7 @@ impl Db {
 	pub fn import(&self, other: &Db) -> Result<()> {
 		let existing_trees = self.list_trees()?;
 		if !existing_trees.is_empty() {
-			return Err(Error(
+			return Err(Error::Message(
 				format!(
 					"destination database already contains data: {:?}",
 					existing_trees
@@ -137,7 +162,9 @@ impl Db {
 		for name in tree_names {
 			let tree = self.open_tree(&name)?;
 			if tree.len()? > 0 {
-				return Err(Error(format!("tree {} already contains data", name).into()));
+				return Err(Error::Message(
+					format!("tree {} already contains data", name).into(),
+				));
 			}
 
 			let ex_tree = other.open_tree(&name)?;
@@ -19,7 +19,16 @@ pub use heed;
 
 impl From<heed::Error> for Error {
 	fn from(e: heed::Error) -> Error {
-		Error(format!("LMDB: {}", e).into())
+		match &e {
+			// All the reader slots are taken by concurrent read transactions
+			heed::Error::Mdb(heed::MdbError::ReadersFull) => {
+				Error::Transient(format!("LMDB: {}", e).into())
+			}
+			heed::Error::Io(io) if crate::is_transient_io_error(io) => {
+				Error::Transient(format!("LMDB: {}", e).into())
+			}
+			_ => Error::Message(format!("LMDB: {}", e).into()),
+		}
 	}
 }
 
@@ -52,7 +61,7 @@ impl LmdbDb {
 			.0
 			.get(i)
 			.cloned()
-			.ok_or_else(|| Error("invalid tree id".into()))
+			.ok_or_else(|| Error::Message("invalid tree id".into()))
 	}
 }
 
@@ -209,7 +218,7 @@ impl IDb for LmdbDb {
 			}
 			TxFnResult::DbErr => {
 				tx.tx.abort().map_err(Error::from).map_err(TxError::Db)?;
-				Err(TxError::Db(Error(
+				Err(TxError::Db(Error::Message(
 					"(this message will be discarded)".into(),
 				)))
 			}
@@ -227,7 +236,7 @@ struct LmdbTx<'a> {
 impl<'a> LmdbTx<'a> {
 	fn get_tree(&self, i: usize) -> TxOpResult<&Database> {
 		self.trees.get(i).ok_or_else(|| {
-			TxOpError(Error(
+			TxOpError(Error::Message(
 				"invalid tree id (it might have been openned after the transaction started)".into(),
 			))
 		})
@@ -20,7 +20,12 @@ pub use sled;
 
 impl From<sled::Error> for Error {
 	fn from(e: sled::Error) -> Error {
-		Error(format!("Sled: {}", e).into())
+		match &e {
+			sled::Error::Io(io) if crate::is_transient_io_error(io) => {
+				Error::Transient(format!("Sled: {}", e).into())
+			}
+			_ => Error::Message(format!("Sled: {}", e).into()),
+		}
 	}
 }
 
@@ -53,7 +58,7 @@ impl SledDb {
 			.0
 			.get(i)
 			.cloned()
-			.ok_or_else(|| Error("invalid tree id".into()))
+			.ok_or_else(|| Error::Message("invalid tree id".into()))
 	}
 }
 
@@ -79,7 +84,7 @@ impl IDb for SledDb {
 		let mut trees = vec![];
 		for name in self.db.tree_names() {
 			let name = std::str::from_utf8(&name)
-				.map_err(|e| Error(format!("{}", e).into()))?
+				.map_err(|e| Error::Message(format!("{}", e).into()))?
 				.to_string();
 			if name != "__sled__default" {
 				trees.push(name);
@@ -198,7 +203,7 @@ struct SledTx<'a> {
 impl<'a> SledTx<'a> {
 	fn get_tree(&self, i: usize) -> TxOpResult<&TransactionalTree> {
 		self.trees.get(i).ok_or_else(|| {
-			TxOpError(Error(
+			TxOpError(Error::Message(
 				"invalid tree id (it might have been openned after the transaction started)".into(),
 			))
 		})
@@ -213,7 +218,7 @@ impl<'a> SledTx<'a> {
 			Err(e) => {
 				let txt = format!("{}", e);
 				self.err.set(Some(e));
-				Err(TxOpError(Error(txt.into())))
+				Err(TxOpError(Error::Message(txt.into())))
 			}
 		}
 	}
@@ -19,7 +19,12 @@ pub use rusqlite;
 
 impl From<rusqlite::Error> for Error {
 	fn from(e: rusqlite::Error) -> Error {
-		Error(format!("Sqlite: {}", e).into())
+		match e.sqlite_error_code() {
+			Some(rusqlite::ErrorCode::DatabaseBusy) | Some(rusqlite::ErrorCode::DatabaseLocked) => {
+				Error::Transient(format!("Sqlite: {}", e).into())
+			}
+			_ => Error::Message(format!("Sqlite: {}", e).into()),
+		}
 	}
 }
 
@@ -53,7 +58,7 @@ impl SqliteDbInner {
 		self.trees
 			.get(i)
 			.map(String::as_str)
-			.ok_or_else(|| Error("invalid tree id".into()))
+			.ok_or_else(|| Error::Message("invalid tree id".into()))
 	}
 
 	fn internal_get(&self, tree: &str, key: &[u8]) -> Result<Option<Value>> {
@@ -287,7 +292,7 @@ impl IDb for SqliteDb {
 			}
 			TxFnResult::DbErr => {
 				tx.tx.rollback().map_err(Error::from).map_err(TxError::Db)?;
-				Err(TxError::Db(Error(
+				Err(TxError::Db(Error::Message(
 					"(this message will be discarded)".into(),
 				)))
 			}
@@ -308,7 +313,7 @@ struct SqliteTx<'a> {
 impl<'a> SqliteTx<'a> {
 	fn get_tree(&self, i: usize) -> TxOpResult<&'_ str> {
 		self.trees.get(i).map(String::as_ref).ok_or_else(|| {
-			TxOpError(Error(
+			TxOpError(Error::Message(
 				"invalid tree id (it might have been openned after the transaction started)".into(),
 			))
 		})
@@ -109,3 +109,40 @@ fn test_sqlite_db() {
 	let db = SqliteDb::init(rusqlite::Connection::open_in_memory().unwrap());
 	test_suite(db);
 }
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_sqlite_transient_errors() {
+	let busy =
+		rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY), None);
+	assert!(Error::from(busy).is_transient());
+
+	let corrupt = rusqlite::Error::SqliteFailure(
+		rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CORRUPT),
+		None,
+	);
+	assert!(!Error::from(corrupt).is_transient());
+	assert!(!Error::Message("invalid tree id".into()).is_transient());
+}
+
+#[test]
+#[cfg(feature = "sled")]
+fn test_sled_transient_errors() {
+	let busy = sled::Error::Io(std::io::ErrorKind::WouldBlock.into());
+	assert!(Error::from(busy).is_transient());
+
+	let io = sled::Error::Io(std::io::ErrorKind::PermissionDenied.into());
+	assert!(!Error::from(io).is_transient());
+}
+
+#[test]
+#[cfg(feature = "lmdb")]
+fn test_lmdb_transient_errors() {
+	use crate::lmdb_adapter::heed;
+
+	let readers_full = heed::Error::Mdb(heed::MdbError::ReadersFull);
+	assert!(Error::from(readers_full).is_transient());
+
+	let map_full = heed::Error::Mdb(heed::MdbError::MapFull);
+	assert!(!Error::from(map_full).is_transient());
+}
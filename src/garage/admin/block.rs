@@ -13,10 +13,21 @@ use super::*;
 impl AdminRpcHandler {
 	pub(super) async fn handle_block_cmd(&self, cmd: &BlockOperation) -> Result<AdminRpc, Error> {
 		match cmd {
+			BlockOperation::Status { json } => Ok(AdminRpc::BlockResyncStatus(
+				self.garage.block_manager.resync.status()?,
+				*json,
+			)),
 			BlockOperation::ListErrors => Ok(AdminRpc::BlockErrorList(
 				self.garage.block_manager.list_resync_errors()?,
 			)),
+			BlockOperation::ListQuarantined => Ok(AdminRpc::BlockQuarantineList(
+				self.garage.block_manager.list_resync_quarantine()?,
+			)),
 			BlockOperation::Info { hash } => self.handle_block_info(hash).await,
+			BlockOperation::Explain { query_peers, hash } => {
+				self.handle_block_explain(hash, *query_peers).await
+			}
+			BlockOperation::DryRun { hash } => self.handle_block_dry_run(hash).await,
 			BlockOperation::RetryNow { all, blocks } => {
 				self.handle_block_retry_now(*all, blocks).await
 			}
@@ -53,6 +64,25 @@ impl AdminRpcHandler {
 		})
 	}
 
+	async fn handle_block_explain(&self, hash: &str, query_peers: bool) -> Result<AdminRpc, Error> {
+		let hash = hex::decode(hash).ok_or_bad_request("invalid hash")?;
+		let hash = Hash::try_from(&hash).ok_or_bad_request("invalid hash")?;
+		let explanation = self
+			.garage
+			.block_manager
+			.resync
+			.explain(&self.garage.block_manager, &hash, query_peers)
+			.await?;
+		Ok(AdminRpc::BlockExplain(explanation))
+	}
+
+	async fn handle_block_dry_run(&self, hash: &str) -> Result<AdminRpc, Error> {
+		let hash = hex::decode(hash).ok_or_bad_request("invalid hash")?;
+		let hash = Hash::try_from(&hash).ok_or_bad_request("invalid hash")?;
+		let plan = self.garage.block_manager.resync_dry_run(&hash).await?;
+		Ok(AdminRpc::BlockResyncPlan(plan))
+	}
+
 	async fn handle_block_retry_now(
 		&self,
 		all: bool,
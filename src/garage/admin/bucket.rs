@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Write;
 
 use garage_util::crdt::*;
@@ -384,10 +384,37 @@ impl AdminRpcHandler {
 		}
 
 		let website = if query.allow {
-			Some(WebsiteConfig {
-				index_document: query.index_document.clone(),
-				error_document: query.error_document.clone(),
-			})
+			let mut content_types = BTreeMap::new();
+			for content_type in query.content_types.iter() {
+				let (ext, value) = content_type
+					.split_once('=')
+					.filter(|(ext, value)| !ext.is_empty() && !value.is_empty())
+					.ok_or_bad_request(format!(
+						"Invalid content type {}, expected extension=type",
+						content_type
+					))?;
+				let ext = ext.trim_start_matches('.').to_lowercase();
+				content_types.insert(ext, value.to_string());
+			}
+			// Redirections are only set through the S3 API,
+			// keep those of the existing configuration
+			let mut website = bucket_state
+				.website_config
+				.get()
+				.clone()
+				.unwrap_or_default();
+			website.index_document = query.index_document.clone();
+			website.error_document = query.error_document.clone();
+			website.root_document = query.root_document.clone();
+			website.canonical_host = query.canonical_host.clone();
+			website.security_preset = query.security_preset;
+			website.cache_control = query.cache_control.clone();
+			website.spa_mode = query.spa_mode;
+			website.index_fallbacks = query.index_fallbacks.clone();
+			website.content_types = content_types;
+			website.autoindex = query.autoindex;
+			website.immutable_paths = query.immutable_paths.clone();
+			Some(website)
 		} else {
 			None
 		};
@@ -21,7 +21,8 @@ use garage_table::*;
 use garage_rpc::ring::PARTITION_BITS;
 use garage_rpc::*;
 
-use garage_block::manager::BlockResyncErrorInfo;
+use garage_block::manager::{BlockQuarantineInfo, BlockResyncErrorInfo, ResyncPlan};
+use garage_block::resync::{ResyncExplanation, ResyncStatus};
 
 use garage_model::bucket_table::*;
 use garage_model::garage::Garage;
@@ -63,6 +64,10 @@ pub enum AdminRpc {
 	WorkerVars(Vec<(Uuid, String, String)>),
 	WorkerInfo(usize, garage_util::background::WorkerInfo),
 	BlockErrorList(Vec<BlockResyncErrorInfo>),
+	BlockQuarantineList(Vec<BlockQuarantineInfo>),
+	BlockExplain(ResyncExplanation),
+	BlockResyncPlan(ResyncPlan),
+	BlockResyncStatus(ResyncStatus, bool),
 	BlockInfo {
 		hash: Hash,
 		refcount: u64,
@@ -256,6 +261,12 @@ impl AdminRpcHandler {
 			self.garage.block_manager.resync.errors_len()?
 		)
 		.unwrap();
+		writeln!(
+			&mut ret,
+			"  quarantined blocks: {}",
+			self.garage.block_manager.resync.quarantine_len()?
+		)
+		.unwrap();
 
 		if !opt.detailed {
 			writeln!(&mut ret, "\nIf values are missing above (marked as NC), consider adding the --detailed flag (this will be slow).").unwrap();
@@ -211,6 +211,18 @@ pub async fn cmd_admin(
 		AdminRpc::BlockErrorList(el) => {
 			print_block_error_list(el);
 		}
+		AdminRpc::BlockQuarantineList(ql) => {
+			print_block_quarantine_list(ql);
+		}
+		AdminRpc::BlockExplain(ex) => {
+			print_block_explanation(ex);
+		}
+		AdminRpc::BlockResyncPlan(plan) => {
+			print_block_resync_plan(plan);
+		}
+		AdminRpc::BlockResyncStatus(status, json) => {
+			print_block_resync_status(status, json);
+		}
 		AdminRpc::BlockInfo {
 			hash,
 			refcount,
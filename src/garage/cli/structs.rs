@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
+use garage_model::bucket_table::SecurityPreset;
 use garage_util::version::garage_version;
 
 #[derive(StructOpt, Debug)]
@@ -216,9 +217,56 @@ pub struct WebsiteOpt {
 	#[structopt(short = "i", long = "index-document", default_value = "index.html")]
 	pub index_document: String,
 
+	/// Index fallback: document tried for request paths ending by / when the
+	/// index document is not found (can be repeated, documents are tried in order)
+	#[structopt(long = "index-fallback")]
+	pub index_fallbacks: Vec<String>,
+
 	/// Error document: the optional document returned when an error occurs
 	#[structopt(short = "e", long = "error-document")]
 	pub error_document: Option<String>,
+
+	/// Root document: the optional document returned for requests to /,
+	/// instead of the index document
+	#[structopt(long = "root-document")]
+	pub root_document: Option<String>,
+
+	/// Canonical host: requests to the website under any other host name
+	/// are redirected to this one
+	#[structopt(long = "canonical-host")]
+	pub canonical_host: Option<String>,
+
+	/// Security preset: set of security headers added to all responses
+	/// (basic or strict), unless they are already set
+	#[structopt(long = "security-preset")]
+	pub security_preset: Option<SecurityPreset>,
+
+	/// Cache-Control header of successful responses, instead of the
+	/// default of the web server
+	#[structopt(long = "cache-control")]
+	pub cache_control: Option<String>,
+
+	/// Single-page application mode: requests for paths without a file
+	/// extension that are not found are answered with the root document
+	#[structopt(long = "spa-mode")]
+	pub spa_mode: bool,
+
+	/// Content type of the files with an extension, as `extension=type`
+	/// (can be repeated), used when an object has no content type or a
+	/// generic one such as application/octet-stream
+	#[structopt(long = "content-type")]
+	pub content_types: Vec<String>,
+
+	/// Directory listing: requests for paths ending by / for which no index
+	/// document is found are answered with a listing of the objects under them
+	#[structopt(long = "autoindex")]
+	pub autoindex: bool,
+
+	/// Immutable path: pattern of the paths of content-addressed files, where
+	/// * matches any sequence of characters (can be repeated), whose successful
+	/// responses are cached forever
+	#[structopt(long = "immutable-path")]
+	pub immutable_paths: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
@@ -438,6 +486,16 @@ pub enum RepairWhat {
 	/// Only repair (resync/rebalance) the set of stored blocks
 	#[structopt(name = "blocks", version = garage_version())]
 	Blocks,
+	/// Only reconcile the resync queue with the block reference counters
+	#[structopt(name = "reconcile-resync", version = garage_version())]
+	ReconcileResync,
+	/// Only resync the blocks that were not resynced successfully for some time
+	#[structopt(name = "stale-blocks", version = garage_version())]
+	StaleBlocks {
+		/// Minimum number of days since the last successful resync of the blocks to resync
+		#[structopt(long = "older-than-days", default_value = "30")]
+		older_than_days: u64,
+	},
 	/// Only redo the propagation of object deletions to the version table (slow)
 	#[structopt(name = "versions", version = garage_version())]
 	Versions,
@@ -556,16 +614,41 @@ pub struct WorkerListOpt {
 
 #[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
 pub enum BlockOperation {
+	/// Show the state of resync on this node
+	#[structopt(name = "status", version = garage_version())]
+	Status {
+		/// Output the status as JSON
+		#[structopt(long = "json")]
+		json: bool,
+	},
 	/// List all blocks that currently have a resync error
 	#[structopt(name = "list-errors", version = garage_version())]
 	ListErrors,
+	/// List all blocks that were quarantined after repeatedly failing to be written to disk
+	#[structopt(name = "list-quarantined", version = garage_version())]
+	ListQuarantined,
 	/// Get detailed information about a single block
 	#[structopt(name = "info", version = garage_version())]
 	Info {
 		/// Hash of the block for which to retrieve information
 		hash: String,
 	},
-	/// Retry now the resync of one or many blocks
+	/// Explain the resync status of a block on this node, and what resync would do with it
+	#[structopt(name = "explain", version = garage_version())]
+	Explain {
+		/// Also ask other nodes whether they need the block
+		#[structopt(long = "query-peers")]
+		query_peers: bool,
+		/// Hash of the block to explain
+		hash: String,
+	},
+	/// Show what resync would do with a block on this node, without doing it
+	#[structopt(name = "dry-run", version = garage_version())]
+	DryRun {
+		/// Hash of the block
+		hash: String,
+	},
+	/// Retry now the resync of one or many blocks (this also releases quarantined blocks)
 	#[structopt(name = "retry-now", version = garage_version())]
 	RetryNow {
 		/// Retry all blocks that have a resync error
@@ -8,7 +8,8 @@ use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::time::*;
 
-use garage_block::manager::BlockResyncErrorInfo;
+use garage_block::manager::{BlockQuarantineInfo, BlockResyncErrorInfo, ResyncPlan};
+use garage_block::resync::{ResyncExplanation, ResyncStatus};
 
 use garage_model::bucket_table::*;
 use garage_model::key_table::*;
@@ -371,7 +372,7 @@ pub fn print_block_error_list(el: Vec<BlockResyncErrorInfo>) {
 	let mut tf2 = timeago::Formatter::new();
 	tf2.ago("");
 
-	let mut table = vec!["Hash\tRC\tErrors\tLast error\tNext try".into()];
+	let mut table = vec!["Hash\tRC\tErrors\tLast error\tNext try\tMessage".into()];
 	for e in el {
 		let next_try = if e.next_try > now {
 			tf2.convert(Duration::from_millis(e.next_try - now))
@@ -379,17 +380,133 @@ pub fn print_block_error_list(el: Vec<BlockResyncErrorInfo>) {
 			"asap".to_string()
 		};
 		table.push(format!(
-			"{}\t{}\t{}\t{}\tin {}",
+			"{}\t{}\t{}\t{}\tin {}\t{}",
 			hex::encode(e.hash.as_slice()),
 			e.refcount,
 			e.error_count,
 			tf.convert(Duration::from_millis(now - e.last_try)),
-			next_try
+			next_try,
+			e.last_error.as_deref().unwrap_or("")
 		));
 	}
 	format_table(table);
 }
 
+pub fn print_block_quarantine_list(ql: Vec<BlockQuarantineInfo>) {
+	let now = now_msec();
+	let tf = timeago::Formatter::new();
+
+	let mut table = vec!["Hash\tRC\tWrite errors\tQuarantined".into()];
+	for q in ql {
+		table.push(format!(
+			"{}\t{}\t{}\t{}",
+			hex::encode(q.hash.as_slice()),
+			q.refcount,
+			q.write_errors,
+			tf.convert(Duration::from_millis(now.saturating_sub(q.quarantined_at))),
+		));
+	}
+	format_table(table);
+}
+
+pub fn print_block_resync_status(status: ResyncStatus, json: bool) {
+	if json {
+		println!(
+			"{}",
+			serde_json::to_string_pretty(&status).expect("ResyncStatus is serializable")
+		);
+		return;
+	}
+
+	let oldest = match status.oldest_queued_age_ms {
+		Some(age) => timeago::Formatter::new().convert(Duration::from_millis(age)),
+		None => "-".into(),
+	};
+	format_table(vec![
+		format!("Workers:\t{}", status.n_workers),
+		format!("Tranquility:\t{}", status.tranquility),
+		format!("Queue length:\t{}", status.queue_len),
+		format!("Blocks with errors:\t{}", status.errors_len),
+		format!("Oldest queued block due:\t{}", oldest),
+		format!("Paused:\t{}", status.paused),
+	]);
+}
+
+pub fn print_block_resync_plan(plan: ResyncPlan) {
+	let mut table = vec![
+		format!("Block hash:\t{}", hex::encode(plan.hash.as_slice())),
+		format!("Present on this node:\t{}", plan.exists),
+		format!("Refcount:\t{}", plan.refcount),
+		format!("Deletable:\t{}", plan.is_deletable),
+		format!("Would fetch:\t{}", plan.would_fetch),
+		format!("Would offload:\t{}", plan.would_offload),
+		format!("Would delete:\t{}", plan.would_delete),
+	];
+	if let Some(reason) = &plan.skipped_reason {
+		table.push(format!("Kept because:\t{}", reason));
+	}
+	format_table(table);
+
+	if !plan.offload_to.is_empty() {
+		println!();
+		println!("The block would be sent to:");
+		for node in plan.offload_to {
+			println!("  {:?}", node);
+		}
+	}
+}
+
+pub fn print_block_explanation(ex: ResyncExplanation) {
+	let now = now_msec();
+	let tf = timeago::Formatter::new();
+	let mut tf2 = timeago::Formatter::new();
+	tf2.ago("");
+	let when = |t: u64| {
+		if t > now {
+			format!("in {}", tf2.convert(Duration::from_millis(t - now)))
+		} else {
+			tf.convert(Duration::from_millis(now - t))
+		}
+	};
+
+	let mut table = vec![
+		format!("Block hash:\t{}", hex::encode(ex.hash.as_slice())),
+		format!("Present on this node:\t{}", ex.exists),
+		format!("Refcount:\t{}", ex.refcount),
+		format!("Needed:\t{}", ex.is_nonzero),
+		format!("Deletable:\t{}", ex.is_deletable),
+	];
+	if ex.queued_at.is_empty() {
+		table.push("Resync queue:\tnot queued".into());
+	}
+	for t in ex.queued_at.iter() {
+		table.push(format!("Resync queue:\tscheduled {}", when(*t)));
+	}
+	table.push(format!("Being resynced:\t{}", ex.busy));
+	if let Some((errors, next_try)) = ex.errors {
+		table.push(format!(
+			"Resync errors:\t{}, next try {}",
+			errors,
+			when(next_try)
+		));
+	}
+	table.push(format!("Quarantined:\t{}", ex.quarantined));
+	table.push(format!("Resync action:\t{:?}", ex.action));
+	format_table(table);
+
+	if let Some(peers) = ex.peers_needing {
+		println!();
+		if peers.is_empty() {
+			println!("No other node needs this block.");
+		} else {
+			println!("Other nodes that need this block:");
+			for p in peers {
+				println!("  {:?}", p);
+			}
+		}
+	}
+}
+
 pub fn print_block_info(hash: Hash, refcount: u64, versions: Vec<Result<Version, Uuid>>) {
 	println!("Block hash: {}", hex::encode(hash.as_slice()));
 	println!("Refcount: {}", refcount);
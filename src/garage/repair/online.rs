@@ -44,6 +44,22 @@ pub async fn launch_online_repair(
 				garage.block_manager.clone(),
 			));
 		}
+		RepairWhat::ReconcileResync => {
+			info!("Reconciling the resync queue with the block reference counters");
+			bg.spawn_worker(garage_block::repair::ReconcileResyncWorker::new(
+				garage.block_manager.clone(),
+			));
+		}
+		RepairWhat::StaleBlocks { older_than_days } => {
+			info!(
+				"Resyncing the blocks not resynced for {} days",
+				older_than_days
+			);
+			bg.spawn_worker(garage_block::repair::ResyncStaleWorker::new(
+				garage.block_manager.clone(),
+				Duration::from_secs(older_than_days * 24 * 3600),
+			));
+		}
 		RepairWhat::Scrub { cmd } => {
 			let cmd = match cmd {
 				ScrubCmd::Start => ScrubWorkerCommand::Start,
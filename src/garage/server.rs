@@ -108,13 +108,27 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 		info!("Initializing web server...");
 		servers.push((
 			"Web",
-			tokio::spawn(WebServer::run(
-				garage.clone(),
-				web_config.bind_addr,
-				web_config.root_domain.clone(),
-				wait_from(watch_cancel.clone()),
-			)),
+			tokio::spawn(
+				WebServer::new(garage.clone(), web_config.clone())
+					.run(wait_from(watch_cancel.clone())),
+			),
 		));
+
+		if let Some(redirect_bind_addr) = web_config.https_redirect_bind_addr {
+			let redirect_config = WebConfig {
+				bind_addr: redirect_bind_addr,
+				tls: None,
+				..web_config.clone()
+			};
+			servers.push((
+				"Web HTTPS redirect",
+				tokio::spawn(
+					WebServer::new(garage.clone(), redirect_config)
+						.with_https_redirect()
+						.run(wait_from(watch_cancel.clone())),
+				),
+			));
+		}
 	}
 
 	if let Some(admin_bind_addr) = &config.admin.api_bind_addr {
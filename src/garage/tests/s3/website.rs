@@ -17,6 +17,61 @@ use serde_json::json;
 const BODY: &[u8; 16] = b"<h1>bonjour</h1>";
 const BODY_ERR: &[u8; 6] = b"erreur";
 
+/// Allow website access to a bucket, with the given options of
+/// `garage bucket website`
+fn allow_website(ctx: &common::Context, bckt_name: &str, options: &[&str]) {
+	ctx.garage
+		.command()
+		.args(["bucket", "website", "--allow"])
+		.args(options)
+		.arg(bckt_name)
+		.quiet()
+		.expect_success_status("Could not allow website on bucket");
+}
+
+/// Create a bucket containing the given objects, and allow website
+/// access to it with the given options
+async fn setup_website(
+	ctx: &common::Context,
+	bckt_name: &str,
+	objects: &[(&str, &[u8])],
+	options: &[&str],
+) -> String {
+	let bucket = ctx.create_bucket(bckt_name);
+	for (key, body) in objects {
+		ctx.client
+			.put_object()
+			.bucket(&bucket)
+			.key(*key)
+			.body(ByteStream::from(body.to_vec()))
+			.send()
+			.await
+			.unwrap();
+	}
+	allow_website(ctx, bckt_name, options);
+	bucket
+}
+
+/// Start building a request for a path of the website of a bucket
+fn website_req_builder(
+	ctx: &common::Context,
+	bckt_name: &str,
+	method: &str,
+	path: &str,
+) -> http::request::Builder {
+	Request::builder()
+		.method(method)
+		.uri(format!("http://127.0.0.1:{}{}", ctx.garage.web_port, path))
+		.header("Host", format!("{}.web.garage", bckt_name))
+}
+
+/// A request without body for a path of the website of a bucket
+fn website_req(ctx: &common::Context, bckt_name: &str, method: &str, path: &str) -> Request<Body> {
+	website_req_builder(ctx, bckt_name, method, path)
+		.body(Body::empty())
+		.unwrap()
+}
+
 #[tokio::test]
 async fn test_website() {
 	const BCKT_NAME: &str = "my-website";
@@ -280,6 +335,74 @@ async fn test_website_s3_api() {
 		);
 	}
 
+	// Test ErrorDocument headers on a HEAD request returning 404
+	{
+		let req = Request::builder()
+			.method("HEAD")
+			.uri(format!(
+				"http://127.0.0.1:{}/wrong.html",
+				ctx.garage.web_port
+			))
+			.header("Host", format!("{}.web.garage", BCKT_NAME))
+			.body(Body::empty())
+			.unwrap();
+
+		let mut resp = client.request(req).await.unwrap();
+
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+		assert_eq!(
+			resp.headers().get("content-length").unwrap(),
+			&BODY_ERR.len().to_string()
+		);
+		assert!(to_bytes(resp.body_mut()).await.unwrap().is_empty());
+	}
+
+	// Test a Range request to an existing object
+	{
+		let req = Request::builder()
+			.method("GET")
+			.uri(format!("http://127.0.0.1:{}/site/", ctx.garage.web_port))
+			.header("Host", format!("{}.web.garage", BCKT_NAME))
+			.header("Range", "bytes=0-3")
+			.body(Body::empty())
+			.unwrap();
+
+		let mut resp = client.request(req).await.unwrap();
+
+		assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+		assert_eq!(
+			resp.headers().get("content-range").unwrap(),
+			&format!("bytes 0-3/{}", BODY.len())
+		);
+		assert_eq!(
+			to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+			&BODY[..4]
+		);
+	}
+
+	// Test a Range request to a missing object: the whole ErrorDocument is returned
+	{
+		let req = Request::builder()
+			.method("GET")
+			.uri(format!(
+				"http://127.0.0.1:{}/wrong.html",
+				ctx.garage.web_port
+			))
+			.header("Host", format!("{}.web.garage", BCKT_NAME))
+			.header("Range", "bytes=0-3")
+			.body(Body::empty())
+			.unwrap();
+
+		let mut resp = client.request(req).await.unwrap();
+
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+		assert!(resp.headers().get("content-range").is_none());
+		assert_eq!(
+			to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+			BODY_ERR.as_ref()
+		);
+	}
+
 	// Test CORS with an allowed preflight request
 	{
 		let req = Request::builder()
@@ -495,3 +618,768 @@ async fn test_website_check_website_enabled() {
 		})
 	);
 }
+
+#[tokio::test]
+async fn test_website_root_document() {
+	const BCKT_NAME: &str = "my-website-root";
+	let ctx = common::context();
+	setup_website(
+		&ctx,
+		BCKT_NAME,
+		&[("home.html", BODY)],
+		&["--root-document", "home.html"],
+	)
+	.await;
+
+	let client = Client::new();
+
+	let head_resp = client
+		.request(website_req(&ctx, BCKT_NAME, "HEAD", "/"))
+		.await
+		.unwrap();
+	assert_eq!(head_resp.status(), StatusCode::OK);
+
+	let mut get_resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/"))
+		.await
+		.unwrap();
+	assert_eq!(get_resp.status(), StatusCode::OK);
+
+	for header in ["etag", "content-length", "content-type", "last-modified"] {
+		assert_eq!(
+			head_resp.headers().get(header),
+			get_resp.headers().get(header),
+		);
+	}
+	assert_eq!(
+		to_bytes(get_resp.body_mut()).await.unwrap().as_ref(),
+		BODY.as_ref()
+	);
+}
+
+#[tokio::test]
+async fn test_website_options_unknown_host() {
+	let ctx = common::context();
+	let client = Client::new();
+
+	let req = website_req_builder(&ctx, "no-such-bucket", "OPTIONS", "/")
+		.header("Origin", "https://example.com")
+		.header("Access-Control-Request-Method", "GET")
+		.body(Body::empty())
+		.unwrap();
+
+	let resp = client.request(req).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+	assert!(resp.headers().get("access-control-allow-origin").is_none());
+}
+
+#[tokio::test]
+async fn test_website_options_without_website() {
+	const BCKT_NAME: &str = "my-cors-no-website";
+	let ctx = common::context();
+	let bucket = ctx.create_bucket(BCKT_NAME);
+	let client = Client::new();
+
+	let cors = CorsConfiguration::builder()
+		.cors_rules(
+			CorsRule::builder()
+				.allowed_methods("GET")
+				.allowed_origins("https://example.com")
+				.build(),
+		)
+		.build();
+	ctx.client
+		.put_bucket_cors()
+		.bucket(&bucket)
+		.cors_configuration(cors)
+		.send()
+		.await
+		.unwrap();
+
+	let req = |method: &str| {
+		website_req_builder(&ctx, BCKT_NAME, "OPTIONS", "/")
+			.header("Origin", "https://example.com")
+			.header("Access-Control-Request-Method", method)
+			.body(Body::empty())
+			.unwrap()
+	};
+
+	// The CORS rules of the bucket apply even though it is not a website
+	let resp = client.request(req("GET")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		resp.headers().get("access-control-allow-origin").unwrap(),
+		"https://example.com"
+	);
+
+	// A request that no rule allows is denied like for a missing bucket
+	let resp = client.request(req("DELETE")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+	assert!(resp.headers().get("access-control-allow-origin").is_none());
+
+	// Other requests are not served
+	let resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_website_trace_not_reflected() {
+	const BCKT_NAME: &str = "my-website-trace";
+	const MARKER: &str = "reflected-marker-3a8f";
+	let ctx = common::context();
+	setup_website(&ctx, BCKT_NAME, &[], &[]).await;
+
+	let client = Client::new();
+
+	for bckt_name in [BCKT_NAME, "unknown"] {
+		let req = website_req_builder(&ctx, bckt_name, "TRACE", &format!("/{}", MARKER))
+			.header("X-Marker", MARKER)
+			.body(Body::empty())
+			.unwrap();
+
+		let mut resp = client.request(req).await.unwrap();
+		assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+		assert_eq!(resp.headers().get("allow").unwrap(), "GET, HEAD, OPTIONS");
+		assert!(resp
+			.headers()
+			.values()
+			.all(|v| !String::from_utf8_lossy(v.as_bytes()).contains(MARKER)));
+		let body = to_bytes(resp.body_mut()).await.unwrap();
+		assert!(!String::from_utf8_lossy(&body).contains(MARKER));
+	}
+}
+
+#[tokio::test]
+async fn test_website_put_not_allowed() {
+	const BCKT_NAME: &str = "my-website-put";
+	let ctx = common::context();
+	setup_website(&ctx, BCKT_NAME, &[("index.html", BODY)], &[]).await;
+
+	let client = Client::new();
+
+	let req = website_req_builder(&ctx, BCKT_NAME, "PUT", "/index.html")
+		.body(Body::from("<h1>overwritten</h1>"))
+		.unwrap();
+
+	let resp = client.request(req).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+	assert_eq!(resp.headers().get("allow").unwrap(), "GET, HEAD, OPTIONS");
+
+	// The object was not modified
+	let mut resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/index.html"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(to_bytes(resp.body_mut()).await.unwrap().as_ref(), BODY);
+}
+
+#[tokio::test]
+async fn test_website_canonical_host() {
+	const BCKT_NAME: &str = "canonical.example.com";
+	const ALIAS_NAME: &str = "www.canonical.example.com";
+	let ctx = common::context();
+	setup_website(
+		&ctx,
+		BCKT_NAME,
+		&[("page/index.html", BODY)],
+		&["--canonical-host", BCKT_NAME],
+	)
+	.await;
+
+	ctx.garage
+		.command()
+		.args(["bucket", "alias", BCKT_NAME, ALIAS_NAME])
+		.quiet()
+		.expect_success_status("Could not alias bucket");
+
+	let client = Client::new();
+
+	let req = |host: &str| {
+		Request::builder()
+			.method("GET")
+			.uri(format!(
+				"http://127.0.0.1:{}/page/?x=1",
+				ctx.garage.web_port
+			))
+			.header("Host", host)
+			.body(Body::empty())
+			.unwrap()
+	};
+
+	// Requests to another name of the bucket are redirected
+	let resp = client.request(req(ALIAS_NAME)).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+	assert_eq!(
+		resp.headers().get("location").unwrap(),
+		format!("//{}/page/?x=1", BCKT_NAME).as_str()
+	);
+
+	// Requests to the canonical host are served
+	let mut resp = client.request(req(BCKT_NAME)).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+		BODY.as_ref()
+	);
+}
+
+#[tokio::test]
+async fn test_website_preconditions() {
+	const BCKT_NAME: &str = "my-website-preconditions";
+	let ctx = common::context();
+	let bucket = ctx.create_bucket(BCKT_NAME);
+
+	let etag = ctx
+		.client
+		.put_object()
+		.bucket(&bucket)
+		.key("index.html")
+		.body(ByteStream::from_static(BODY))
+		.send()
+		.await
+		.unwrap()
+		.e_tag
+		.unwrap();
+
+	allow_website(&ctx, BCKT_NAME, &[]);
+
+	let client = Client::new();
+
+	let req = |header: &str, value: &str| {
+		website_req_builder(&ctx, BCKT_NAME, "GET", "/")
+			.header(header, value)
+			.body(Body::empty())
+			.unwrap()
+	};
+
+	let mut resp = client.request(req("If-Match", &etag)).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+		BODY.as_ref()
+	);
+
+	let resp = client
+		.request(req("If-Match", "\"0123456789abcdef\""))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+	let resp = client
+		.request(req("If-Unmodified-Since", "Thu, 01 Jan 1970 00:00:00 GMT"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+	let resp = client
+		.request(req("If-Unmodified-Since", "Fri, 01 Jan 2100 00:00:00 GMT"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_website_cache_control() {
+	const BCKT_NAME: &str = "my-website-cache-control";
+	let ctx = common::context();
+	setup_website(
+		&ctx,
+		BCKT_NAME,
+		&[("index.html", BODY), ("err.html", BODY_ERR)],
+		&[
+			"--error-document",
+			"err.html",
+			"--cache-control",
+			"public, max-age=3600",
+		],
+	)
+	.await;
+
+	let client = Client::new();
+
+	let resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		resp.headers().get("cache-control").unwrap(),
+		"public, max-age=3600"
+	);
+
+	// The error document is not cached like a successful response
+	let mut resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/missing.html"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+	assert!(resp.headers().get("cache-control").is_none());
+	assert_eq!(
+		to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+		BODY_ERR.as_ref()
+	);
+}
+
+#[tokio::test]
+async fn test_website_s3_api_keeps_cli_settings() {
+	const BCKT_NAME: &str = "my-website-keep-settings";
+	let ctx = common::context();
+	let bucket = setup_website(
+		&ctx,
+		BCKT_NAME,
+		&[("home.html", BODY)],
+		&["--cache-control", "public, max-age=3600"],
+	)
+	.await;
+
+	// Setting the configuration through the S3 API only changes
+	// the settings that the S3 API knows about
+	let conf = WebsiteConfiguration::builder()
+		.index_document(IndexDocument::builder().suffix("home.html").build())
+		.build();
+
+	ctx.client
+		.put_bucket_website()
+		.bucket(&bucket)
+		.website_configuration(conf)
+		.send()
+		.await
+		.unwrap();
+
+	let client = Client::new();
+
+	let mut resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		resp.headers().get("cache-control").unwrap(),
+		"public, max-age=3600"
+	);
+	assert_eq!(
+		to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+		BODY.as_ref()
+	);
+}
+
+#[tokio::test]
+async fn test_website_conditional_get() {
+	const BCKT_NAME: &str = "my-website-conditional";
+	let ctx = common::context();
+	setup_website(&ctx, BCKT_NAME, &[("index.html", BODY)], &[]).await;
+
+	let client = Client::new();
+
+	let req = |method: &str, header: Option<(&str, &str)>| {
+		let mut req = website_req_builder(&ctx, BCKT_NAME, method, "/");
+		if let Some((name, value)) = header {
+			req = req.header(name, value);
+		}
+		req.body(Body::empty()).unwrap()
+	};
+
+	let head_resp = client.request(req("HEAD", None)).await.unwrap();
+	assert_eq!(head_resp.status(), StatusCode::OK);
+	let etag = head_resp.headers().get("etag").unwrap().to_str().unwrap();
+	let last_modified = head_resp
+		.headers()
+		.get("last-modified")
+		.unwrap()
+		.to_str()
+		.unwrap();
+
+	for header in [
+		("If-None-Match", etag.to_string()),
+		("If-None-Match", format!("W/{}", etag)),
+		("If-Modified-Since", last_modified.to_string()),
+	] {
+		let mut resp = client
+			.request(req("GET", Some((header.0, &header.1))))
+			.await
+			.unwrap();
+		assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+		assert_eq!(resp.headers().get("etag").unwrap(), etag);
+		assert_eq!(resp.headers().get("last-modified").unwrap(), last_modified);
+		assert!(to_bytes(resp.body_mut()).await.unwrap().is_empty());
+	}
+
+	let mut resp = client
+		.request(req("GET", Some(("If-None-Match", "\"0123456789abcdef\""))))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+		BODY.as_ref()
+	);
+}
+
+#[tokio::test]
+async fn test_website_spa_mode() {
+	const BCKT_NAME: &str = "my-website-spa";
+	let ctx = common::context();
+	setup_website(
+		&ctx,
+		BCKT_NAME,
+		&[("index.html", BODY), ("err.html", BODY_ERR)],
+		&["--error-document", "err.html", "--spa-mode"],
+	)
+	.await;
+
+	let client = Client::new();
+
+	// Client-side routes are answered with the index document
+	let mut resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/some/client/route"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+		BODY.as_ref()
+	);
+
+	// Missing assets are still errors, answered with the error document
+	let mut resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/missing.js"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+	assert_eq!(
+		to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+		BODY_ERR.as_ref()
+	);
+}
+
+#[tokio::test]
+async fn test_website_content_types() {
+	const BCKT_NAME: &str = "my-website-content-types";
+	let ctx = common::context();
+	let bucket = ctx.create_bucket(BCKT_NAME);
+
+	for (key, content_type) in [("app.wasm", None), ("notes.wasm", Some("text/plain"))] {
+		let mut put = ctx
+			.client
+			.put_object()
+			.bucket(&bucket)
+			.key(key)
+			.body(ByteStream::from(BODY.to_vec()));
+		if let Some(content_type) = content_type {
+			put = put.content_type(content_type);
+		}
+		put.send().await.unwrap();
+	}
+
+	allow_website(
+		&ctx,
+		BCKT_NAME,
+		&["--content-type", ".wasm=application/wasm"],
+	);
+
+	let client = Client::new();
+
+	let resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/app.wasm"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		resp.headers().get("content-type").unwrap(),
+		"application/wasm"
+	);
+
+	// An object uploaded with a content type keeps it
+	let resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/notes.wasm"))
+		.await
+		.unwrap();
+	assert_eq!(resp.headers().get("content-type").unwrap(), "text/plain");
+
+	// The S3 API is not affected
+	let obj = ctx
+		.client
+		.get_object()
+		.bucket(&bucket)
+		.key("app.wasm")
+		.send()
+		.await
+		.unwrap();
+	assert_ne!(obj.content_type(), Some("application/wasm"));
+}
+
+#[tokio::test]
+async fn test_website_index_fallbacks() {
+	const BCKT_NAME: &str = "my-website-index-fallbacks";
+	let ctx = common::context();
+	setup_website(
+		&ctx,
+		BCKT_NAME,
+		&[("index.html", BODY), ("legacy/index.htm", BODY)],
+		&["--index-fallback", "index.htm"],
+	)
+	.await;
+
+	let client = Client::new();
+
+	for path in ["/", "/legacy/"] {
+		let mut resp = client
+			.request(website_req(&ctx, BCKT_NAME, "GET", path))
+			.await
+			.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+		assert_eq!(
+			to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+			BODY.as_ref()
+		);
+	}
+
+	let resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/nothing/"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_website_autoindex() {
+	const BCKT_NAME: &str = "my-website-autoindex";
+	let ctx = common::context();
+
+	// Without autoindex, directories without an index are not found
+	setup_website(
+		&ctx,
+		BCKT_NAME,
+		&[
+			("files/a.txt", BODY),
+			("files/sub/b.txt", BODY),
+			("site/index.html", BODY),
+		],
+		&[],
+	)
+	.await;
+
+	let client = Client::new();
+
+	let resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/files/"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+	allow_website(&ctx, BCKT_NAME, &["--autoindex"]);
+
+	let mut resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/files/"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		resp.headers().get("content-type").unwrap(),
+		"text/html; charset=utf-8"
+	);
+	let body = to_bytes(resp.body_mut()).await.unwrap();
+	let body = String::from_utf8_lossy(&body);
+	assert!(body.contains("<a href=\"./a.txt\">a.txt</a>"));
+	assert!(body.contains("<a href=\"./sub/\">sub/</a>"));
+	assert!(!body.contains("b.txt"));
+
+	// The index document is still served when it exists
+	let mut resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/site/"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(to_bytes(resp.body_mut()).await.unwrap().as_ref(), BODY);
+
+	// Directories without any object are not found
+	let resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/nothing/"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_website_http2() {
+	const BCKT_NAME: &str = "my-website-http2";
+	let ctx = common::context();
+
+	// Large enough to be split in several blocks and streamed
+	let big: Vec<u8> = (0..5_000_000u32).map(|i| (i % 251) as u8).collect();
+	setup_website(&ctx, BCKT_NAME, &[("big.bin", &big)], &[]).await;
+
+	// HTTP/2 over plain HTTP, with prior knowledge
+	let client = Client::builder().http2_only(true).build_http();
+	let mut resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/big.bin"))
+		.await
+		.unwrap();
+	assert_eq!(resp.version(), hyper::Version::HTTP_2);
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(to_bytes(resp.body_mut()).await.unwrap().as_ref(), &big[..]);
+
+	// HTTP/1.1 clients still work
+	let client = Client::new();
+	let mut resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/big.bin"))
+		.await
+		.unwrap();
+	assert_eq!(resp.version(), hyper::Version::HTTP_11);
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(to_bytes(resp.body_mut()).await.unwrap().as_ref(), &big[..]);
+}
+
+#[tokio::test]
+async fn test_website_health_check() {
+	let ctx = common::context();
+	let client = Client::new();
+
+	// The health check doesn't depend on the Host header, which
+	// otherwise defaults to the address of the web endpoint
+	for host in [None, Some("unknown.web.garage")] {
+		let mut req = Request::builder().method("GET").uri(format!(
+			"http://127.0.0.1:{}/_garage_health",
+			ctx.garage.web_port
+		));
+		if let Some(host) = host {
+			req = req.header("Host", host);
+		}
+		let mut resp = client
+			.request(req.body(Body::empty()).unwrap())
+			.await
+			.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+		assert_eq!(to_bytes(resp.body_mut()).await.unwrap().as_ref(), b"OK\n");
+	}
+}
+
+#[tokio::test]
+async fn test_website_range_not_satisfiable() {
+	const BCKT_NAME: &str = "my-website-range";
+	let ctx = common::context();
+	setup_website(
+		&ctx,
+		BCKT_NAME,
+		&[("index.html", BODY), ("err.html", BODY_ERR)],
+		&["--error-document", "err.html"],
+	)
+	.await;
+
+	let client = Client::new();
+
+	let req = |range: &str| {
+		website_req_builder(&ctx, BCKT_NAME, "GET", "/index.html")
+			.header("Range", range)
+			.body(Body::empty())
+			.unwrap()
+	};
+
+	let resp = client.request(req("bytes=0-1")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+
+	// The error document is not served instead of the range error
+	let mut resp = client.request(req("bytes=100000-100010")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+	assert_eq!(
+		resp.headers()
+			.get("content-range")
+			.unwrap()
+			.to_str()
+			.unwrap(),
+		format!("bytes */{}", BODY.len())
+	);
+	assert_ne!(
+		to_bytes(resp.body_mut()).await.unwrap().as_ref(),
+		BODY_ERR.as_ref()
+	);
+}
+
+#[tokio::test]
+async fn test_website_immutable_paths() {
+	const BCKT_NAME: &str = "my-website-immutable";
+	let ctx = common::context();
+	setup_website(
+		&ctx,
+		BCKT_NAME,
+		&[
+			("index.html", BODY),
+			("app.4f2a.js", BODY),
+			("app.js", BODY),
+			("static.4f2a.js/index.html", BODY),
+		],
+		&["--immutable-path", "*.*.js*"],
+	)
+	.await;
+
+	let client = Client::new();
+
+	let resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/app.4f2a.js"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		resp.headers().get("cache-control").unwrap(),
+		"public, max-age=31536000, immutable"
+	);
+
+	let resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/app.js"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert!(resp.headers().get("cache-control").is_none());
+
+	// The index document of a matching path is not immutable
+	let resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/static.4f2a.js/"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert!(resp.headers().get("cache-control").is_none());
+
+	// Neither are errors
+	let resp = client
+		.request(website_req(&ctx, BCKT_NAME, "GET", "/missing.4f2a.js"))
+		.await
+		.unwrap();
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+	assert!(resp.headers().get("cache-control").is_none());
+}
+
+#[tokio::test]
+async fn test_website_json_errors() {
+	let ctx = common::context();
+	let client = Client::new();
+
+	let req = |accept: &str| {
+		website_req_builder(&ctx, "no-such-bucket", "GET", "/")
+			.header("Accept", accept)
+			.body(Body::empty())
+			.unwrap()
+	};
+
+	let resp = client.request(req("application/json")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+	assert_eq!(
+		resp.headers().get("content-type").unwrap(),
+		"application/json"
+	);
+	let body = json_body(resp).await;
+	assert_eq!(body["code"], json!(404));
+	assert!(body["message"].is_string());
+
+	let mut resp = client.request(req("text/html, */*")).await.unwrap();
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+	assert_ne!(
+		resp.headers().get("content-type").map(|v| v.as_bytes()),
+		Some(b"application/json".as_ref())
+	);
+	let body = to_bytes(resp.body_mut()).await.unwrap();
+	assert!(serde_json::from_slice::<serde_json::Value>(&body).is_err());
+}
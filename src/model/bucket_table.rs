@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use garage_table::crdt::*;
 use garage_table::*;
 use garage_util::data::*;
@@ -80,7 +82,248 @@ mod v08 {
 	impl garage_util::migrate::InitialFormat for Bucket {}
 }
 
-pub use v08::*;
+mod v09 {
+	use std::collections::BTreeMap;
+
+	use crate::permission::BucketKeyPerm;
+	use garage_util::crdt;
+	use garage_util::data::Uuid;
+	use serde::{Deserialize, Serialize};
+
+	use super::v08;
+
+	pub use v08::{BucketQuotas, CorsRule};
+
+	/// A bucket is a collection of objects
+	///
+	/// Its parameters are not directly accessible as:
+	///  - It must be possible to merge paramaters, hence the use of a LWW CRDT.
+	///  - A bucket has 2 states, Present or Deleted and parameters make sense only if present.
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct Bucket {
+		/// ID of the bucket
+		pub id: Uuid,
+		/// State, and configuration if not deleted, of the bucket
+		pub state: crdt::Deletable<BucketParams>,
+	}
+
+	/// Configuration for a bucket
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct BucketParams {
+		/// Bucket's creation date
+		pub creation_date: u64,
+		/// Map of key with access to the bucket, and what kind of access they give
+		pub authorized_keys: crdt::Map<String, BucketKeyPerm>,
+
+		/// Map of aliases that are or have been given to this bucket
+		/// in the global namespace
+		/// (not authoritative: this is just used as an indication to
+		/// map back to aliases when doing ListBuckets)
+		pub aliases: crdt::LwwMap<String, bool>,
+		/// Map of aliases that are or have been given to this bucket
+		/// in namespaces local to keys
+		/// key = (access key id, alias name)
+		pub local_aliases: crdt::LwwMap<(String, String), bool>,
+
+		/// Whether this bucket is allowed for website access
+		/// (under all of its global alias names),
+		/// and if so, the website configuration XML document
+		pub website_config: crdt::Lww<Option<WebsiteConfig>>,
+		/// CORS rules
+		pub cors_config: crdt::Lww<Option<Vec<CorsRule>>>,
+		/// Bucket quotas
+		#[serde(default)]
+		pub quotas: crdt::Lww<BucketQuotas>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct WebsiteConfig {
+		pub index_document: String,
+		pub error_document: Option<String>,
+		/// Document served for requests to the root of the website,
+		/// instead of the index document
+		pub root_document: Option<String>,
+		/// Host name under which the website should be served: requests
+		/// for other names of the bucket are redirected to it
+		pub canonical_host: Option<String>,
+		/// Set of security headers added to all responses of the website
+		pub security_preset: Option<SecurityPreset>,
+		/// Host to which all requests to the website are redirected
+		pub redirect_all: Option<RedirectAll>,
+		/// Rules redirecting some requests, evaluated in order
+		pub routing_rules: Vec<RoutingRule>,
+		/// Cache-Control header of successful responses, instead of
+		/// the default of the web server
+		pub cache_control: Option<String>,
+		/// Requests for paths without a file extension that are not found
+		/// are answered with the document served at the root of the website,
+		/// for single-page applications that do their own routing
+		pub spa_mode: bool,
+		/// Documents tried in order for requests to paths ending by /
+		/// when the index document is not found
+		pub index_fallbacks: Vec<String>,
+		/// Content types of the files of the website by extension (in lower
+		/// case, without the dot), used instead of the content type of objects
+		/// that have none or a generic one
+		pub content_types: BTreeMap<String, String>,
+		/// Requests to paths ending by / for which no index document is
+		/// found are answered with a listing of the objects under that path
+		pub autoindex: bool,
+		/// Patterns of the paths of immutable assets, whose successful
+		/// responses get the immutable Cache-Control header of the web server
+		pub immutable_paths: Vec<String>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct RedirectAll {
+		pub hostname: String,
+		/// http or https, if None the protocol of the request is kept
+		pub protocol: Option<String>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct RoutingRule {
+		/// If None, the rule applies to all requests
+		pub condition: Option<RoutingRuleCondition>,
+		pub redirect: RoutingRuleRedirect,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct RoutingRuleCondition {
+		/// If set, the rule applies to requests that would return
+		/// this error code, instead of applying before serving the file
+		pub http_error_code: Option<u16>,
+		pub key_prefix: Option<String>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct RoutingRuleRedirect {
+		/// If None, the host of the request is kept
+		pub hostname: Option<String>,
+		/// If None, the protocol of the request is kept
+		pub protocol: Option<String>,
+		/// If None, 301 is used
+		pub http_redirect_code: Option<u16>,
+		/// Replaces the matched key prefix of the condition
+		pub replace_key_prefix: Option<String>,
+		/// Replaces the whole key
+		pub replace_key: Option<String>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub enum SecurityPreset {
+		/// X-Content-Type-Options and Referrer-Policy
+		Basic,
+		/// Basic headers, plus Strict-Transport-Security and X-Frame-Options
+		Strict,
+	}
+
+	impl garage_util::migrate::Migrate for Bucket {
+		const VERSION_MARKER: &'static [u8] = b"G09bkt";
+
+		type Previous = v08::Bucket;
+
+		fn migrate(old: v08::Bucket) -> Bucket {
+			Bucket {
+				id: old.id,
+				state: match old.state {
+					crdt::Deletable::Present(p) => crdt::Deletable::Present(migrate_params(p)),
+					crdt::Deletable::Deleted => crdt::Deletable::Deleted,
+				},
+			}
+		}
+	}
+
+	fn migrate_params(old: v08::BucketParams) -> BucketParams {
+		let website_ts = old.website_config.timestamp();
+		let website_config = old.website_config.take().map(|w| WebsiteConfig {
+			index_document: w.index_document,
+			error_document: w.error_document,
+			..Default::default()
+		});
+
+		BucketParams {
+			creation_date: old.creation_date,
+			authorized_keys: old.authorized_keys,
+			aliases: old.aliases,
+			local_aliases: old.local_aliases,
+			website_config: crdt::Lww::raw(website_ts, website_config),
+			cors_config: old.cors_config,
+			quotas: old.quotas,
+		}
+	}
+}
+
+pub use v09::*;
+
+impl Default for WebsiteConfig {
+	fn default() -> Self {
+		WebsiteConfig {
+			index_document: "index.html".into(),
+			error_document: None,
+			root_document: None,
+			canonical_host: None,
+			security_preset: None,
+			redirect_all: None,
+			routing_rules: vec![],
+			cache_control: None,
+			spa_mode: false,
+			index_fallbacks: vec![],
+			content_types: BTreeMap::new(),
+			autoindex: false,
+			immutable_paths: vec![],
+		}
+	}
+}
+
+impl RoutingRule {
+	/// Whether the rule applies to a request for a key. `error_code` is the
+	/// status of the response for that key, or None if the rule is checked
+	/// before the file is served.
+	pub fn matches(&self, key: &str, error_code: Option<u16>) -> bool {
+		match &self.condition {
+			None => error_code.is_none(),
+			Some(cond) => {
+				cond.http_error_code == error_code
+					&& cond
+						.key_prefix
+						.as_ref()
+						.map(|prefix| key.starts_with(prefix.as_str()))
+						.unwrap_or(true)
+			}
+		}
+	}
+
+	/// The key to redirect to, for a request for a key matching the rule
+	pub fn redirect_key(&self, key: &str) -> String {
+		if let Some(replace_key) = &self.redirect.replace_key {
+			return replace_key.clone();
+		}
+		let prefix = self.condition.as_ref().and_then(|c| c.key_prefix.as_ref());
+		match (&self.redirect.replace_key_prefix, prefix) {
+			(Some(replace_prefix), Some(prefix)) => match key.strip_prefix(prefix.as_str()) {
+				Some(rest) => format!("{}{}", replace_prefix, rest),
+				None => key.to_string(),
+			},
+			_ => key.to_string(),
+		}
+	}
+}
+
+impl std::str::FromStr for SecurityPreset {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, String> {
+		match s {
+			"basic" => Ok(SecurityPreset::Basic),
+			"strict" => Ok(SecurityPreset::Strict),
+			_ => Err(format!(
+				"Invalid security preset: {} (possible values: basic, strict)",
+				s
+			)),
+		}
+	}
+}
 
 impl AutoCrdt for BucketQuotas {
 	const WARN_IF_DIFFERENT: bool = true;
@@ -199,3 +442,52 @@ impl TableSchema for BucketTable {
 		filter.apply(entry.is_deleted())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use garage_util::migrate::Migrate;
+
+	#[test]
+	fn test_migrate_v08_website_config() {
+		let mut params = v08::BucketParams {
+			creation_date: 1000,
+			authorized_keys: crdt::Map::new(),
+			aliases: crdt::LwwMap::new(),
+			local_aliases: crdt::LwwMap::new(),
+			website_config: crdt::Lww::raw(
+				1234,
+				Some(v08::WebsiteConfig {
+					index_document: "home.html".into(),
+					error_document: Some("404.html".into()),
+				}),
+			),
+			cors_config: crdt::Lww::new(None),
+			quotas: crdt::Lww::new(BucketQuotas::default()),
+		};
+		params.aliases.update_in_place("site".into(), true);
+		let old = v08::Bucket {
+			id: gen_uuid(),
+			state: crdt::Deletable::Present(params),
+		};
+
+		let bucket = Bucket::decode(&old.encode().unwrap()).unwrap();
+		assert_eq!(bucket.id, old.id);
+		assert_eq!(bucket.aliases().len(), 1);
+
+		let website_config = &bucket.params().unwrap().website_config;
+		assert_eq!(website_config.timestamp(), 1234);
+		assert_eq!(
+			website_config.get(),
+			&Some(WebsiteConfig {
+				index_document: "home.html".into(),
+				error_document: Some("404.html".into()),
+				..Default::default()
+			})
+		);
+
+		// Buckets are written back in the new format
+		let bucket2 = Bucket::decode(&bucket.encode().unwrap()).unwrap();
+		assert_eq!(bucket, bucket2);
+	}
+}
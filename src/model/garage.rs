@@ -205,6 +205,9 @@ impl Garage {
 			&db,
 			config.data_dir.clone(),
 			config.compression_level,
+			config.resync_schedule.clone(),
+			config.resync_max_workers,
+			config.resync_events_history,
 			data_rep_param,
 			system.clone(),
 		);
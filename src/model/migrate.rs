@@ -59,10 +59,7 @@ impl Migrate {
 		};
 
 		let website = if *old_bucket_p.website.get() {
-			Some(WebsiteConfig {
-				index_document: "index.html".into(),
-				error_document: None,
-			})
+			Some(WebsiteConfig::default())
 		} else {
 			None
 		};
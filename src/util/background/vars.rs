@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::str::FromStr;
 
@@ -6,7 +7,7 @@ use crate::migrate::Migrate;
 use crate::persister::PersisterShared;
 
 pub struct BgVars {
-	vars: HashMap<&'static str, Box<dyn BgVarTrait>>,
+	vars: HashMap<Cow<'static, str>, Box<dyn BgVarTrait>>,
 }
 
 impl BgVars {
@@ -19,7 +20,7 @@ impl BgVars {
 	pub fn register_rw<V, T, GF, SF>(
 		&mut self,
 		p: &PersisterShared<V>,
-		name: &'static str,
+		name: impl Into<Cow<'static, str>>,
 		get_fn: GF,
 		set_fn: SF,
 	) where
@@ -34,11 +35,16 @@ impl BgVars {
 		let p2 = p.clone();
 		let set_fn = move |v| set_fn(&p2, v);
 
-		self.vars.insert(name, Box::new(BgVar { get_fn, set_fn }));
+		self.vars
+			.insert(name.into(), Box::new(BgVar { get_fn, set_fn }));
 	}
 
-	pub fn register_ro<V, T, GF>(&mut self, p: &PersisterShared<V>, name: &'static str, get_fn: GF)
-	where
+	pub fn register_ro<V, T, GF>(
+		&mut self,
+		p: &PersisterShared<V>,
+		name: impl Into<Cow<'static, str>>,
+		get_fn: GF,
+	) where
 		V: Migrate + Default + Send + Sync,
 		T: FromStr + ToString + Send + Sync + 'static,
 		GF: Fn(&PersisterShared<V>) -> T + Send + Sync + 'static,
@@ -46,7 +52,9 @@ impl BgVars {
 		let p1 = p.clone();
 		let get_fn = move || get_fn(&p1);
 
-		let set_fn = move |_| Err(Error::Message(format!("Cannot set value of {}", name)));
+		let name = name.into();
+		let var_name = name.clone();
+		let set_fn = move |_| Err(Error::Message(format!("Cannot set value of {}", var_name)));
 
 		self.vars.insert(name, Box::new(BgVar { get_fn, set_fn }));
 	}
@@ -59,8 +67,11 @@ impl BgVars {
 			.get())
 	}
 
-	pub fn get_all(&self) -> Vec<(&'static str, String)> {
-		self.vars.iter().map(|(k, v)| (*k, v.get())).collect()
+	pub fn get_all(&self) -> Vec<(&str, String)> {
+		self.vars
+			.iter()
+			.map(|(k, v)| (k.as_ref(), v.get()))
+			.collect()
 	}
 
 	pub fn set(&self, var: &str, val: &str) -> Result<(), Error> {
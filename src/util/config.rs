@@ -1,8 +1,10 @@
 //! Contains type and functions related to Garage configuration file
+use std::collections::HashMap;
 use std::io::Read;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
+use ipnetwork::IpNetwork;
 use serde::{de, Deserialize};
 
 use crate::error::Error;
@@ -33,6 +35,18 @@ pub struct Config {
 	)]
 	pub compression_level: Option<i32>,
 
+	/// Daily time windows during which block resync runs with
+	/// a different number of workers and/or tranquility
+	#[serde(default)]
+	pub resync_schedule: Vec<ResyncScheduleWindow>,
+	/// Number of resync workers spawned at startup, i.e. the maximum
+	/// number of workers that can be enabled at runtime (at most 32)
+	#[serde(default = "default_resync_max_workers")]
+	pub resync_max_workers: usize,
+	/// Number of recent resync events kept in memory, 0 to keep none
+	#[serde(default = "default_resync_events_history")]
+	pub resync_events_history: usize,
+
 	/// RPC secret key: 32 bytes hex encoded
 	pub rpc_secret: Option<String>,
 	/// Optional file where RPC secret key is read from
@@ -87,6 +101,35 @@ pub struct Config {
 	pub admin: AdminConfig,
 }
 
+/// A daily time window, in UTC, during which the number of resync workers
+/// and/or the resync tranquility are overridden
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ResyncScheduleWindow {
+	/// Name of the window, shown in the status of resync workers
+	pub name: String,
+	/// Start of the window, given as "HH:MM" and stored in minutes since midnight
+	#[serde(deserialize_with = "deserialize_time_of_day")]
+	pub start: u32,
+	/// End of the window, which can be before its start to span midnight
+	#[serde(deserialize_with = "deserialize_time_of_day")]
+	pub end: u32,
+	/// Number of resync workers during the window
+	pub n_workers: Option<usize>,
+	/// Resync tranquility during the window
+	pub tranquility: Option<u32>,
+}
+
+impl ResyncScheduleWindow {
+	/// Whether the window contains the given minute of the day
+	pub fn contains(&self, minute_of_day: u32) -> bool {
+		if self.start <= self.end {
+			self.start <= minute_of_day && minute_of_day < self.end
+		} else {
+			minute_of_day >= self.start || minute_of_day < self.end
+		}
+	}
+}
+
 /// Configuration for S3 api
 #[derive(Deserialize, Debug, Clone)]
 pub struct S3ApiConfig {
@@ -111,8 +154,159 @@ pub struct K2VApiConfig {
 pub struct WebConfig {
 	/// Address and port to bind for web serving
 	pub bind_addr: SocketAddr,
-	/// Suffix to remove from domain name to find bucket
-	pub root_domain: String,
+	/// Suffixes to remove from domain name to find bucket. Can be given
+	/// as a list, or as a single string of comma-separated suffixes
+	#[serde(deserialize_with = "deserialize_root_domains")]
+	pub root_domain: Vec<String>,
+	/// How the bucket that serves a request is found
+	#[serde(default)]
+	pub bucket_routing: WebBucketRouting,
+	/// How to handle request paths that contain consecutive slashes
+	#[serde(default)]
+	pub double_slashes: DoubleSlashMode,
+	/// Add the X-Garage-Node and X-Garage-Request-Id headers to responses
+	#[serde(default)]
+	pub add_diagnostic_headers: bool,
+	/// Bucket whose CORS rules answer preflight requests to hosts that
+	/// don't resolve to a website bucket
+	#[serde(default)]
+	pub preflight_bucket: Option<String>,
+	/// Level at which requests to a given bucket are logged, for buckets
+	/// that should be logged differently than the default (info)
+	#[serde(default)]
+	pub bucket_log_levels: HashMap<String, WebLogLevel>,
+	/// Maximum number of bytes of object data held for a response: blocks
+	/// are only fetched ahead of the one being sent if they fit, and data
+	/// is handed to the HTTP layer in chunks of at most this size
+	#[serde(default)]
+	pub max_buffered_bytes: Option<usize>,
+	/// Maximum size in bytes of the objects that are served, if any
+	#[serde(default)]
+	pub max_web_object_size: Option<u64>,
+	/// Maximum number of requests to a single bucket that are processed
+	/// at the same time, if any
+	#[serde(default)]
+	pub max_concurrent_requests_per_bucket: Option<usize>,
+	/// Per-bucket values overriding max_concurrent_requests_per_bucket
+	#[serde(default)]
+	pub bucket_max_concurrent_requests: HashMap<String, usize>,
+	/// Compress text responses with gzip or brotli for clients that accept it
+	#[serde(default = "default_web_compression")]
+	pub compression: bool,
+	/// Minimum size in bytes of a response for it to be compressed
+	#[serde(default = "default_web_compression_min_size")]
+	pub compression_min_size: usize,
+	/// Level of gzip and brotli compression, from 1 (fastest) to 9 (smallest),
+	/// if None a moderate level is used (5 for brotli, 6 for gzip)
+	#[serde(default, deserialize_with = "deserialize_web_compression_level")]
+	pub compression_level: Option<u32>,
+	/// Cache-Control header of successful responses, for websites
+	/// that don't set their own
+	#[serde(default)]
+	pub cache_control: Option<String>,
+	/// Patterns of the paths of assets that never change, where *
+	/// matches any sequence of characters
+	#[serde(default)]
+	pub immutable_paths: Vec<String>,
+	/// Cache-Control header of successful responses for immutable assets
+	#[serde(default = "default_immutable_cache_control")]
+	pub immutable_cache_control: String,
+	/// Serve HTTPS with this certificate instead of plain HTTP
+	#[serde(default)]
+	pub tls: Option<TlsConfig>,
+	/// Format of the lines logged for each request
+	#[serde(default)]
+	pub access_log_format: WebAccessLogFormat,
+	/// Addresses or CIDR ranges of the reverse proxies whose X-Forwarded-*
+	/// headers are used to know the address and scheme of the client
+	#[serde(default)]
+	pub trusted_proxies: Vec<IpNetwork>,
+	/// Error document served for the websites that don't have their own
+	#[serde(default)]
+	pub default_error_document: Option<DefaultErrorDocument>,
+	/// Address and port of a plain HTTP listener that redirects all requests to HTTPS
+	#[serde(default)]
+	pub https_redirect_bind_addr: Option<SocketAddr>,
+	/// Maximum time in seconds to fetch the document of a request before
+	/// answering with a gateway timeout error, 0 for no limit
+	#[serde(default = "default_web_request_timeout_secs")]
+	pub request_timeout_secs: u64,
+	/// Accept HTTP/2 connections, negotiated with ALPN over TLS or
+	/// with prior knowledge over plain HTTP, in addition to HTTP/1
+	#[serde(default = "default_web_http2")]
+	pub http2: bool,
+	/// Path answered by the web server itself for health checks, whatever
+	/// the Host header of the request. An empty path disables health checks.
+	#[serde(default = "default_web_health_check_path")]
+	pub health_check_path: String,
+}
+
+/// Location of an error document shared by all websites
+#[derive(Deserialize, Debug, Clone)]
+pub struct DefaultErrorDocument {
+	/// Global alias of the bucket containing the document
+	pub bucket: String,
+	/// Key of the document in that bucket
+	pub key: String,
+}
+
+/// Certificate and private key of a TLS endpoint
+#[derive(Deserialize, Debug, Clone)]
+pub struct TlsConfig {
+	/// PEM file containing the certificate chain
+	pub cert_path: PathBuf,
+	/// PEM file containing the private key
+	pub key_path: PathBuf,
+}
+
+/// Format of the lines logged by the web server for each request
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebAccessLogFormat {
+	/// A line when the request is received, and another one if it fails
+	#[default]
+	Default,
+	/// A single line when the response is sent, in the combined log
+	/// format followed by the Host header and the duration in milliseconds
+	Combined,
+}
+
+/// Level at which the web server logs requests
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebLogLevel {
+	/// Requests are not logged
+	Off,
+	Error,
+	Warn,
+	#[default]
+	Info,
+	Debug,
+	Trace,
+}
+
+/// How the web server finds the bucket that serves a request
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebBucketRouting {
+	/// The bucket is designated by the Host header, e.g. `bucket.web.garage/key`
+	#[default]
+	Vhost,
+	/// The bucket is the first segment of the path, e.g. `web.garage/bucket/key`
+	Path,
+}
+
+/// How the web server handles request paths containing consecutive slashes
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DoubleSlashMode {
+	/// Use the path as is to look up the key
+	#[default]
+	Keep,
+	/// Collapse consecutive slashes before looking up the key
+	Collapse,
+	/// Redirect the client to the path with consecutive slashes collapsed
+	Redirect,
 }
 
 /// Configuration for the admin and monitoring HTTP API
@@ -258,10 +452,113 @@ fn secret_from_file(
 	Ok(())
 }
 
+fn default_resync_max_workers() -> usize {
+	4
+}
+
+fn default_resync_events_history() -> usize {
+	100
+}
+
 fn default_compression() -> Option<i32> {
 	Some(1)
 }
 
+fn default_web_compression() -> bool {
+	false
+}
+
+fn default_web_compression_min_size() -> usize {
+	1024
+}
+
+fn default_web_request_timeout_secs() -> u64 {
+	300
+}
+
+fn default_web_http2() -> bool {
+	true
+}
+
+fn default_web_health_check_path() -> String {
+	"/_garage_health".into()
+}
+
+fn default_immutable_cache_control() -> String {
+	"public, max-age=31536000, immutable".into()
+}
+
+fn deserialize_time_of_day<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+	D: de::Deserializer<'de>,
+{
+	let value = String::deserialize(deserializer)?;
+	let parsed = value
+		.split_once(':')
+		.and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)));
+	match parsed {
+		Some((h, m)) if h < 24 && m < 60 => Ok(h * 60 + m),
+		_ => Err(de::Error::custom(format!(
+			"Invalid time of day: '{}', should be HH:MM",
+			value
+		))),
+	}
+}
+
+fn deserialize_web_compression_level<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+	D: de::Deserializer<'de>,
+{
+	// Levels 1 to 9 have the same meaning for gzip and for brotli,
+	// brotli levels 10 and 11 don't exist for gzip
+	match u32::deserialize(deserializer)? {
+		level @ 1..=9 => Ok(Some(level)),
+		level => Err(de::Error::custom(format!(
+			"Invalid web compression level: {}, should be between 1 and 9",
+			level
+		))),
+	}
+}
+
+fn deserialize_root_domains<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+	D: de::Deserializer<'de>,
+{
+	struct RootDomainsVisitor;
+
+	impl<'de> serde::de::Visitor<'de> for RootDomainsVisitor {
+		type Value = Vec<String>;
+		fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+			formatter.write_str("string or list of strings")
+		}
+
+		fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+		where
+			E: de::Error,
+		{
+			Ok(value
+				.split(',')
+				.map(str::trim)
+				.filter(|d| !d.is_empty())
+				.map(String::from)
+				.collect())
+		}
+
+		fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+		where
+			A: de::SeqAccess<'de>,
+		{
+			let mut domains = vec![];
+			while let Some(domain) = seq.next_element::<String>()? {
+				domains.push(domain);
+			}
+			Ok(domains)
+		}
+	}
+
+	deserializer.deserialize_any(RootDomainsVisitor)
+}
+
 fn deserialize_compression<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
 where
 	D: de::Deserializer<'de>,
@@ -345,6 +642,137 @@ mod tests {
 		Ok(())
 	}
 
+	#[test]
+	fn test_resync_schedule() -> Result<(), Error> {
+		let path2 = mktemp::Temp::new_file()?;
+		let mut file2 = File::create(path2.as_path())?;
+		writeln!(
+			file2,
+			r#"
+			metadata_dir = "/tmp/garage/meta"
+			data_dir = "/tmp/garage/data"
+			replication_mode = "3"
+			rpc_bind_addr = "[::]:3901"
+			rpc_secret = "foo"
+
+			[[resync_schedule]]
+			name = "nighttime"
+			start = "22:30"
+			end = "06:00"
+			n_workers = 4
+			tranquility = 0
+
+			[s3_api]
+			s3_region = "garage"
+			api_bind_addr = "[::]:3900"
+			"#
+		)?;
+
+		let config = super::read_config(path2.to_path_buf())?;
+		assert_eq!(config.resync_schedule.len(), 1);
+		assert_eq!(config.resync_max_workers, 4);
+		let window = &config.resync_schedule[0];
+		assert_eq!(window.name, "nighttime");
+		assert_eq!((window.start, window.end), (22 * 60 + 30, 6 * 60));
+		assert_eq!((window.n_workers, window.tranquility), (Some(4), Some(0)));
+		assert!(window.contains(23 * 60));
+		assert!(window.contains(0));
+		assert!(!window.contains(6 * 60));
+		assert!(!window.contains(12 * 60));
+		drop(path2);
+		drop(file2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_web_root_domains() -> Result<(), Error> {
+		for (root_domain, expected) in [
+			(r#"".web.garage""#, vec![".web.garage"]),
+			(
+				r#"".web.garage, .sites.example.org""#,
+				vec![".web.garage", ".sites.example.org"],
+			),
+			(
+				r#"[".web.garage", ".sites.example.org"]"#,
+				vec![".web.garage", ".sites.example.org"],
+			),
+		] {
+			let path2 = mktemp::Temp::new_file()?;
+			let mut file2 = File::create(path2.as_path())?;
+			writeln!(
+				file2,
+				r#"
+				metadata_dir = "/tmp/garage/meta"
+				data_dir = "/tmp/garage/data"
+				replication_mode = "3"
+				rpc_bind_addr = "[::]:3901"
+				rpc_secret = "foo"
+
+				[s3_api]
+				s3_region = "garage"
+				api_bind_addr = "[::]:3900"
+
+				[s3_web]
+				bind_addr = "[::]:3902"
+				root_domain = {}
+				"#,
+				root_domain
+			)?;
+
+			let config = super::read_config(path2.to_path_buf())?;
+			assert_eq!(config.s3_web.unwrap().root_domain, expected);
+			drop(path2);
+			drop(file2);
+		}
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_web_compression_level() -> Result<(), Error> {
+		for (level, expected) in [("1", Some(1)), ("9", Some(9)), ("0", None), ("11", None)] {
+			let path2 = mktemp::Temp::new_file()?;
+			let mut file2 = File::create(path2.as_path())?;
+			writeln!(
+				file2,
+				r#"
+				metadata_dir = "/tmp/garage/meta"
+				data_dir = "/tmp/garage/data"
+				replication_mode = "3"
+				rpc_bind_addr = "[::]:3901"
+				rpc_secret = "foo"
+
+				[s3_api]
+				s3_region = "garage"
+				api_bind_addr = "[::]:3900"
+
+				[s3_web]
+				bind_addr = "[::]:3902"
+				root_domain = ".web.garage"
+				compression_level = {}
+				"#,
+				level
+			)?;
+
+			match (super::read_config(path2.to_path_buf()), expected) {
+				(Ok(config), Some(expected)) => {
+					assert_eq!(config.s3_web.unwrap().compression_level, Some(expected))
+				}
+				(Err(_), None) => (),
+				(res, _) => panic!(
+					"unexpected result for level {}: {:?}",
+					level,
+					res.map(|_| ())
+				),
+			}
+			drop(path2);
+			drop(file2);
+		}
+
+		Ok(())
+	}
+
 	#[test]
 	fn test_rpc_secret_file_works() -> Result<(), Error> {
 		let path_secret = mktemp::Temp::new_file()?;
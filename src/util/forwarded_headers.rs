@@ -19,6 +19,77 @@ pub fn handle_forwarded_for_headers(headers: &HeaderMap<HeaderValue>) -> Result<
 	Ok(client_ip.to_string())
 }
 
+/// Get the chain of addresses of the X-Forwarded-For header, from the client
+/// to the last proxy. Each proxy appends the address of its own peer, either to
+/// the existing header or as another header line.
+pub fn handle_forwarded_for_chain(headers: &HeaderMap<HeaderValue>) -> Result<Vec<IpAddr>, Error> {
+	let mut chain = vec![];
+	for forwarded_for_header in headers.get_all("x-forwarded-for") {
+		let forwarded_for = forwarded_for_header
+			.to_str()
+			.ok_or_message("Error parsing X-Forwarded-For header")?;
+		for ip_str in forwarded_for.split(',') {
+			let ip = IpAddr::from_str(ip_str.trim())
+				.ok_or_message("Invalid IP address in X-Forwarded-For header")?;
+			chain.push(ip);
+		}
+	}
+
+	if chain.is_empty() {
+		return Err(Error::Message("X-Forwarded-For header not provided".into()));
+	}
+	Ok(chain)
+}
+
+/// Get the host requested by the client from the X-Forwarded-Host header.
+/// When it was set by a chain of proxies, the first value is the one of the client.
+pub fn handle_forwarded_host_header(headers: &HeaderMap<HeaderValue>) -> Result<String, Error> {
+	let forwarded_host_header = headers
+		.get("x-forwarded-host")
+		.ok_or_message("X-Forwarded-Host header not provided")?;
+
+	let forwarded_host = forwarded_host_header
+		.to_str()
+		.ok_or_message("Error parsing X-Forwarded-Host header")?
+		.split(',')
+		.next()
+		.unwrap_or("")
+		.trim();
+
+	if forwarded_host.is_empty() {
+		return Err(Error::Message("Empty X-Forwarded-Host header".into()));
+	}
+	Ok(forwarded_host.to_string())
+}
+
+/// Get the scheme of the client's request from the X-Forwarded-Proto header.
+/// When it was set by a chain of proxies, the first value is the one of the client.
+pub fn handle_forwarded_proto_header(
+	headers: &HeaderMap<HeaderValue>,
+) -> Result<&'static str, Error> {
+	let forwarded_proto_header = headers
+		.get("x-forwarded-proto")
+		.ok_or_message("X-Forwarded-Proto header not provided")?;
+
+	let forwarded_proto = forwarded_proto_header
+		.to_str()
+		.ok_or_message("Error parsing X-Forwarded-Proto header")?
+		.split(',')
+		.next()
+		.unwrap_or("")
+		.trim();
+
+	if forwarded_proto.eq_ignore_ascii_case("https") {
+		Ok("https")
+	} else if forwarded_proto.eq_ignore_ascii_case("http") {
+		Ok("http")
+	} else {
+		Err(Error::Message(
+			"Invalid scheme in X-Forwarded-Proto header".into(),
+		))
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -60,4 +131,42 @@ mod test {
 		let result = handle_forwarded_for_headers(&test_headers);
 		assert!(result.is_err());
 	}
+
+	#[test]
+	fn test_handle_forwarded_host_header() {
+		let mut test_headers = HeaderMap::new();
+		test_headers.insert(
+			"X-Forwarded-Host",
+			"example.com, proxy.local".parse().unwrap(),
+		);
+		assert_eq!(
+			handle_forwarded_host_header(&test_headers).unwrap(),
+			"example.com"
+		);
+
+		test_headers.insert("X-Forwarded-Host", "".parse().unwrap());
+		assert!(handle_forwarded_host_header(&test_headers).is_err());
+	}
+
+	#[test]
+	fn test_handle_forwarded_proto_header() {
+		let mut test_headers = HeaderMap::new();
+		test_headers.insert("X-Forwarded-Proto", "HTTPS, http".parse().unwrap());
+		assert_eq!(
+			handle_forwarded_proto_header(&test_headers).unwrap(),
+			"https"
+		);
+
+		test_headers.insert("X-Forwarded-Proto", "http".parse().unwrap());
+		assert_eq!(
+			handle_forwarded_proto_header(&test_headers).unwrap(),
+			"http"
+		);
+
+		test_headers.insert("X-Forwarded-Proto", "javascript".parse().unwrap());
+		assert!(handle_forwarded_proto_header(&test_headers).is_err());
+
+		test_headers.remove("X-Forwarded-Proto");
+		assert!(handle_forwarded_proto_header(&test_headers).is_err());
+	}
 }
@@ -0,0 +1,363 @@
+//! Compression of the responses of the web server, for clients that accept it
+use std::io;
+
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder};
+use async_compression::Level;
+use futures::stream::TryStreamExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use hyper::{
+	header::{
+		HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, RANGE,
+		VARY,
+	},
+	Body, Method, Request, Response, StatusCode,
+};
+
+/// Content types that are worth compressing, in addition to text/*
+/// and to the types with a +json or +xml suffix
+const COMPRESSIBLE_TYPES: &[&str] = &[
+	"application/javascript",
+	"application/json",
+	"application/manifest+json",
+	"application/wasm",
+	"application/xml",
+	"image/svg+xml",
+	"image/x-icon",
+];
+
+/// Compression levels used when none is configured, which balance CPU time
+/// and compression ratio (the default of brotli is its slowest level)
+const DEFAULT_BROTLI_LEVEL: i32 = 5;
+const DEFAULT_GZIP_LEVEL: i32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+	Brotli,
+	Gzip,
+}
+
+impl Encoding {
+	fn as_str(self) -> &'static str {
+		match self {
+			Encoding::Brotli => "br",
+			Encoding::Gzip => "gzip",
+		}
+	}
+}
+
+/// Choose the encoding of a response from the Accept-Encoding header of
+/// the request. Brotli is preferred to gzip when both are accepted, and
+/// codings with q=0 are refused.
+pub(crate) fn negotiate_encoding(accept_encoding: &str) -> Option<Encoding> {
+	let mut brotli = false;
+	let mut gzip = false;
+	for item in accept_encoding.split(',') {
+		let mut params = item.split(';');
+		let coding = params.next().unwrap_or("").trim();
+		let refused = params.any(|p| {
+			p.trim()
+				.strip_prefix("q=")
+				.and_then(|q| q.trim().parse::<f32>().ok())
+				.map(|q| q <= 0.0)
+				.unwrap_or(false)
+		});
+		if refused {
+			continue;
+		}
+		if coding.eq_ignore_ascii_case("br") {
+			brotli = true;
+		} else if coding.eq_ignore_ascii_case("gzip") {
+			gzip = true;
+		}
+	}
+	match (brotli, gzip) {
+		(true, _) => Some(Encoding::Brotli),
+		(false, true) => Some(Encoding::Gzip),
+		(false, false) => None,
+	}
+}
+
+pub(crate) fn is_compressible(content_type: &str) -> bool {
+	let mime = content_type
+		.split(';')
+		.next()
+		.unwrap_or("")
+		.trim()
+		.to_ascii_lowercase();
+	mime.starts_with("text/")
+		|| mime.ends_with("+json")
+		|| mime.ends_with("+xml")
+		|| COMPRESSIBLE_TYPES.contains(&mime.as_str())
+}
+
+/// Compress the body of a successful GET response if the client accepts it
+/// and the content is worth it. Objects that are stored with a
+/// Content-Encoding and partial content are left as is. HEAD and 304
+/// responses are not compressed but get the same Vary header as the
+/// GET responses they describe. If no compression level is given,
+/// a moderate level is used.
+pub(crate) fn compress_response(
+	req: &Request<Body>,
+	mut resp: Response<Body>,
+	min_size: usize,
+	level: Option<u32>,
+) -> Response<Body> {
+	if !matches!(*req.method(), Method::GET | Method::HEAD)
+		|| req.headers().contains_key(RANGE)
+		|| resp.headers().contains_key(CONTENT_ENCODING)
+	{
+		return resp;
+	}
+	if resp.status() == StatusCode::NOT_MODIFIED {
+		// A 304 response has no Content-Type to tell whether the full
+		// response would have been compressed, assume it could have been
+		resp.headers_mut()
+			.append(VARY, HeaderValue::from_static("accept-encoding"));
+		return resp;
+	}
+	if resp.status() != StatusCode::OK {
+		return resp;
+	}
+	let compressible = resp
+		.headers()
+		.get(CONTENT_TYPE)
+		.and_then(|ct| ct.to_str().ok())
+		.map(is_compressible)
+		.unwrap_or(false);
+	let large_enough = resp
+		.headers()
+		.get(CONTENT_LENGTH)
+		.and_then(|len| len.to_str().ok())
+		.and_then(|len| len.parse::<usize>().ok())
+		.map(|len| len >= min_size)
+		.unwrap_or(false);
+	if !compressible || !large_enough {
+		return resp;
+	}
+
+	let (mut parts, body) = resp.into_parts();
+	// The response depends on Accept-Encoding even if it is not compressed,
+	// so that caches don't serve an uncompressed response to all clients
+	parts
+		.headers
+		.append(VARY, HeaderValue::from_static("accept-encoding"));
+
+	if *req.method() == Method::HEAD {
+		return Response::from_parts(parts, body);
+	}
+
+	let encoding = match req
+		.headers()
+		.get(ACCEPT_ENCODING)
+		.and_then(|ae| ae.to_str().ok())
+		.and_then(negotiate_encoding)
+	{
+		Some(encoding) => encoding,
+		None => return Response::from_parts(parts, body),
+	};
+
+	// The length of the compressed body is not known in advance,
+	// it is sent with chunked transfer encoding
+	parts.headers.remove(CONTENT_LENGTH);
+	parts.headers.insert(
+		CONTENT_ENCODING,
+		HeaderValue::from_static(encoding.as_str()),
+	);
+	// The compressed body is not byte-for-byte identical to the object,
+	// so its ETag can only be a weak validator
+	if let Some(etag) = parts.headers.get(ETAG).and_then(weak_etag) {
+		parts.headers.insert(ETAG, etag);
+	}
+
+	let reader = StreamReader::new(body.map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+	let body = match encoding {
+		Encoding::Brotli => {
+			let level = Level::Precise(level.map_or(DEFAULT_BROTLI_LEVEL, |l| l as i32));
+			Body::wrap_stream(ReaderStream::new(BrotliEncoder::with_quality(
+				reader, level,
+			)))
+		}
+		Encoding::Gzip => {
+			let level = Level::Precise(level.map_or(DEFAULT_GZIP_LEVEL, |l| l as i32));
+			Body::wrap_stream(ReaderStream::new(GzipEncoder::with_quality(reader, level)))
+		}
+	};
+	Response::from_parts(parts, body)
+}
+
+fn weak_etag(etag: &HeaderValue) -> Option<HeaderValue> {
+	let etag = etag.to_str().ok()?;
+	if etag.starts_with("W/") {
+		return None;
+	}
+	HeaderValue::from_str(&format!("W/{}", etag)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use async_compression::tokio::bufread::GzipDecoder;
+	use tokio::io::AsyncReadExt;
+
+	#[test]
+	fn negotiate_encoding_test() {
+		assert_eq!(negotiate_encoding("gzip"), Some(Encoding::Gzip));
+		assert_eq!(
+			negotiate_encoding("gzip, deflate, br"),
+			Some(Encoding::Brotli)
+		);
+		assert_eq!(
+			negotiate_encoding("br;q=0, GZIP;q=0.5"),
+			Some(Encoding::Gzip)
+		);
+		assert_eq!(negotiate_encoding("deflate"), None);
+		assert_eq!(negotiate_encoding("identity, gzip;q=0"), None);
+		assert_eq!(negotiate_encoding(""), None);
+	}
+
+	#[test]
+	fn is_compressible_test() {
+		assert!(is_compressible("text/html; charset=utf-8"));
+		assert!(is_compressible("application/javascript"));
+		assert!(is_compressible("application/ld+json"));
+		assert!(is_compressible("image/svg+xml"));
+		assert!(!is_compressible("image/png"));
+		assert!(!is_compressible("application/zip"));
+	}
+
+	#[tokio::test]
+	async fn compress_response_test() {
+		let content = "hello world ".repeat(200);
+		let req = |accept_encoding: &str| {
+			Request::builder()
+				.uri("/index.html")
+				.header(ACCEPT_ENCODING, accept_encoding)
+				.body(Body::empty())
+				.unwrap()
+		};
+		let resp = |content_type: &str| {
+			Response::builder()
+				.header(CONTENT_TYPE, content_type)
+				.header(CONTENT_LENGTH, content.len())
+				.header(ETAG, "\"0123abcd\"")
+				.body(Body::from(content.clone()))
+				.unwrap()
+		};
+
+		let compressed = compress_response(&req("gzip"), resp("text/html"), 1024, None);
+		assert_eq!(compressed.headers()[CONTENT_ENCODING], "gzip");
+		assert_eq!(compressed.headers()[VARY], "accept-encoding");
+		assert!(compressed.headers().get(CONTENT_LENGTH).is_none());
+		assert_eq!(compressed.headers()[ETAG], "W/\"0123abcd\"");
+		let bytes = hyper::body::to_bytes(compressed.into_body()).await.unwrap();
+		assert!(bytes.len() < content.len());
+		let mut decompressed = String::new();
+		GzipDecoder::new(&bytes[..])
+			.read_to_string(&mut decompressed)
+			.await
+			.unwrap();
+		assert_eq!(decompressed, content);
+
+		// Not accepted by the client
+		let plain = compress_response(&req("identity"), resp("text/html"), 1024, None);
+		assert!(plain.headers().get(CONTENT_ENCODING).is_none());
+		assert_eq!(plain.headers()[VARY], "accept-encoding");
+		assert_eq!(plain.headers()[ETAG], "\"0123abcd\"");
+
+		// Not worth compressing
+		let plain = compress_response(&req("gzip"), resp("image/png"), 1024, None);
+		assert!(plain.headers().get(CONTENT_ENCODING).is_none());
+		let plain = compress_response(&req("gzip"), resp("text/html"), 1 << 20, None);
+		assert!(plain.headers().get(CONTENT_ENCODING).is_none());
+
+		// Already compressed
+		let mut stored = resp("text/html");
+		stored
+			.headers_mut()
+			.insert(CONTENT_ENCODING, HeaderValue::from_static("br"));
+		let stored = compress_response(&req("gzip"), stored, 1024, None);
+		assert_eq!(stored.headers()[CONTENT_ENCODING], "br");
+
+		// HEAD request
+		let head = Request::builder()
+			.method(Method::HEAD)
+			.header(ACCEPT_ENCODING, "gzip")
+			.body(Body::empty())
+			.unwrap();
+		let plain = compress_response(&head, resp("text/html"), 1024, None);
+		assert!(plain.headers().get(CONTENT_ENCODING).is_none());
+		assert_eq!(plain.headers()[VARY], "accept-encoding");
+		assert_eq!(plain.headers()[CONTENT_LENGTH], content.len().to_string());
+
+		// Not modified
+		let not_modified = Response::builder()
+			.status(StatusCode::NOT_MODIFIED)
+			.body(Body::empty())
+			.unwrap();
+		let not_modified = compress_response(&req("gzip"), not_modified, 1024, None);
+		assert_eq!(not_modified.headers()[VARY], "accept-encoding");
+	}
+
+	#[tokio::test]
+	async fn compression_level_test() {
+		// Text that compresses well, but not so trivially that
+		// all compression levels give the same result
+		let words = ["garage", "bucket", "object", "block", "node", "website"];
+		let mut x = 1u32;
+		let content = (0..4000)
+			.map(|_| {
+				x = x.wrapping_mul(1103515245).wrapping_add(12345) & 0x7fffffff;
+				words[(x >> 16) as usize % words.len()]
+			})
+			.collect::<Vec<_>>()
+			.join(" ");
+
+		for accept_encoding in ["gzip", "br"] {
+			let mut sizes = vec![];
+			for level in [1, 9] {
+				let req = Request::builder()
+					.header(ACCEPT_ENCODING, accept_encoding)
+					.body(Body::empty())
+					.unwrap();
+				let resp = Response::builder()
+					.header(CONTENT_TYPE, "text/plain")
+					.header(CONTENT_LENGTH, content.len())
+					.body(Body::from(content.clone()))
+					.unwrap();
+				let resp = compress_response(&req, resp, 1024, Some(level));
+				assert_eq!(resp.headers()[CONTENT_ENCODING], accept_encoding);
+				let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+				sizes.push(bytes.len());
+			}
+			assert!(
+				sizes[1] < sizes[0],
+				"{}: level 9 ({} bytes) not smaller than level 1 ({} bytes)",
+				accept_encoding,
+				sizes[1],
+				sizes[0]
+			);
+		}
+
+		// Without a configured level, a moderate one is used
+		for (accept_encoding, level) in [("gzip", DEFAULT_GZIP_LEVEL), ("br", DEFAULT_BROTLI_LEVEL)]
+		{
+			let mut bodies = vec![];
+			for level in [None, Some(level as u32)] {
+				let req = Request::builder()
+					.header(ACCEPT_ENCODING, accept_encoding)
+					.body(Body::empty())
+					.unwrap();
+				let resp = Response::builder()
+					.header(CONTENT_TYPE, "text/plain")
+					.header(CONTENT_LENGTH, content.len())
+					.body(Body::from(content.clone()))
+					.unwrap();
+				let resp = compress_response(&req, resp, 1024, level);
+				bodies.push(hyper::body::to_bytes(resp.into_body()).await.unwrap());
+			}
+			assert_eq!(bodies[0], bodies[1], "{}", accept_encoding);
+		}
+	}
+}
@@ -1,5 +1,5 @@
 use err_derive::Error;
-use hyper::header::HeaderValue;
+use hyper::header::{HeaderValue, ALLOW, RETRY_AFTER};
 use hyper::{HeaderMap, StatusCode};
 
 use garage_api::generic_server::ApiError;
@@ -15,9 +15,25 @@ pub enum Error {
 	#[error(display = "Not found")]
 	NotFound,
 
-	/// The client sent a request without host, or with unsupported method
+	/// The client sent a request without host, or with an invalid path
 	#[error(display = "Bad request: {}", _0)]
 	BadRequest(String),
+
+	/// The client sent a request with a method other than GET, HEAD or OPTIONS
+	#[error(display = "Method not allowed")]
+	MethodNotAllowed,
+
+	/// The bucket has reached its maximum number of concurrent requests
+	#[error(display = "Too many concurrent requests for this website")]
+	TooManyRequests,
+
+	/// The object is larger than the maximum size of served objects
+	#[error(display = "Object too large to be served ({} bytes)", _0)]
+	ObjectTooLarge(u64),
+
+	/// The document could not be fetched within the request timeout
+	#[error(display = "Timeout while fetching the document")]
+	Timeout,
 }
 
 impl<T> From<T> for Error
@@ -36,13 +52,22 @@ impl Error {
 			Error::NotFound => StatusCode::NOT_FOUND,
 			Error::ApiError(e) => e.http_status_code(),
 			Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+			Error::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+			Error::TooManyRequests => StatusCode::SERVICE_UNAVAILABLE,
+			Error::ObjectTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+			Error::Timeout => StatusCode::GATEWAY_TIMEOUT,
 		}
 	}
 
 	pub fn add_headers(&self, header_map: &mut HeaderMap<HeaderValue>) {
-		#[allow(clippy::single_match)]
 		match self {
 			Error::ApiError(e) => e.add_http_headers(header_map),
+			Error::MethodNotAllowed => {
+				header_map.insert(ALLOW, HeaderValue::from_static("GET, HEAD, OPTIONS"));
+			}
+			Error::TooManyRequests => {
+				header_map.insert(RETRY_AFTER, HeaderValue::from_static("1"));
+			}
 			_ => (),
 		}
 	}
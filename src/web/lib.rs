@@ -2,8 +2,11 @@
 #[macro_use]
 extern crate tracing;
 
+mod compression;
 mod error;
 pub use error::Error;
 
+mod tls;
+
 mod web_server;
-pub use web_server::WebServer;
+pub use web_server::{ResponseHook, WebServer};
@@ -1,46 +1,108 @@
-use std::{borrow::Cow, convert::Infallible, net::SocketAddr, sync::Arc};
+use std::{
+	borrow::Cow,
+	collections::HashMap,
+	convert::Infallible,
+	fmt,
+	net::{IpAddr, SocketAddr},
+	panic::{self, AssertUnwindSafe},
+	sync::atomic::{AtomicBool, AtomicU64, Ordering},
+	sync::Arc,
+	time::{Duration, Instant},
+};
 
+use chrono::{DateTime, Utc};
 use futures::future::Future;
+use futures::stream::{self, StreamExt};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 
+use hyper::http::uri::Authority;
 use hyper::{
-	header::{HeaderValue, HOST},
-	server::conn::AddrStream,
+	header::{
+		HeaderName, HeaderValue, ACCEPT, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_RANGE,
+		CONTENT_TYPE, HOST, LOCATION, REFERER, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+		USER_AGENT, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+	},
+	server::{accept, conn::AddrStream},
 	service::{make_service_fn, service_fn},
-	Body, Method, Request, Response, Server,
+	Body, HeaderMap, Method, Request, Response, Server, StatusCode, Uri,
 };
 
 use opentelemetry::{
 	global,
-	metrics::{Counter, ValueRecorder},
+	metrics::{Counter, ValueObserver, ValueRecorder},
 	trace::{FutureExt, TraceContextExt, Tracer},
 	Context, KeyValue,
 };
 
+use crate::compression::compress_response;
 use crate::error::*;
+use crate::tls::{tls_acceptor, tls_incoming, tls_remote_addr};
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_rustls::server::TlsStream;
 
 use garage_api::helpers::{authority_to_host, host_to_bucket};
 use garage_api::s3::cors::{add_cors_headers, find_matching_cors_rule, handle_options_for_bucket};
 use garage_api::s3::error::{
 	CommonErrorDerivative, Error as ApiError, OkOrBadRequest, OkOrInternalError,
 };
-use garage_api::s3::get::{handle_get, handle_head};
+use garage_api::s3::get::{handle_get, handle_head, GET_PREFETCH_BLOCKS};
+use garage_api::s3::list::{list_directory, DirectoryListing};
 
+use garage_model::bucket_table::{
+	Bucket, CorsRule, RedirectAll, RoutingRule, SecurityPreset, WebsiteConfig,
+};
 use garage_model::garage::Garage;
+use garage_model::s3::object_table::*;
 
 use garage_table::*;
+use garage_util::config::{
+	DoubleSlashMode, WebAccessLogFormat, WebBucketRouting, WebConfig, WebLogLevel,
+};
+use garage_util::data::Uuid;
 use garage_util::error::Error as GarageError;
 use garage_util::forwarded_headers;
-use garage_util::metrics::{gen_trace_id, RecordDuration};
+use garage_util::metrics::gen_trace_id;
+
+const X_GARAGE_NODE: &str = "x-garage-node";
+const X_GARAGE_REQUEST_ID: &str = "x-garage-request-id";
+
+// Characters of a key that are percent-encoded in redirection locations
+const KEY_ENCODE_SET: &AsciiSet = &CONTROLS
+	.add(b' ')
+	.add(b'"')
+	.add(b'#')
+	.add(b'%')
+	.add(b'<')
+	.add(b'>')
+	.add(b'?')
+	.add(b'`')
+	.add(b'{')
+	.add(b'}');
+
+// Maximum number of objects and subdirectories shown in a directory listing
+const AUTOINDEX_MAX_ENTRIES: usize = 1000;
+
+// Maximum number of buckets for which the number of concurrent requests
+// is tracked at the same time. Idle buckets are forgotten when it is reached.
+const MAX_TRACKED_BUCKETS: usize = 10000;
 
 struct WebMetrics {
 	request_counter: Counter<u64>,
 	error_counter: Counter<u64>,
+	client_aborted_counter: Counter<u64>,
+	cors_request_counter: Counter<u64>,
 	request_duration: ValueRecorder<f64>,
+	active_connections: Arc<AtomicU64>,
+	_active_connections_observer: ValueObserver<u64>,
 }
 
 impl WebMetrics {
 	fn new() -> Self {
 		let meter = global::meter("garage/web");
+		let active_connections = Arc::new(AtomicU64::new(0));
+		let observed_connections = active_connections.clone();
 		Self {
 			request_counter: meter
 				.u64_counter("web.request_counter")
@@ -50,41 +112,159 @@ impl WebMetrics {
 				.u64_counter("web.error_counter")
 				.with_description("Number of requests to the web endpoint resulting in errors")
 				.init(),
+			client_aborted_counter: meter
+				.u64_counter("web.client_aborted")
+				.with_description(
+					"Number of downloads from the web endpoint interrupted by the client",
+				)
+				.init(),
+			cors_request_counter: meter
+				.u64_counter("web.cors_requests")
+				.with_description("Number of requests to the web endpoint allowed by a CORS rule")
+				.init(),
 			request_duration: meter
 				.f64_value_recorder("web.request_duration")
 				.with_description("Duration of requests to the web endpoint")
 				.init(),
+			active_connections,
+			_active_connections_observer: meter
+				.u64_value_observer("web.active_connections", move |observer| {
+					observer.observe(observed_connections.load(Ordering::Relaxed), &[])
+				})
+				.with_description("Number of open connections to the web endpoint")
+				.init(),
 		}
 	}
+
+	/// Count a new connection as open, until the returned guard is dropped
+	fn connection_guard(&self) -> ConnectionGuard {
+		self.active_connections.fetch_add(1, Ordering::Relaxed);
+		ConnectionGuard(self.active_connections.clone())
+	}
+}
+
+/// Held by the service of a connection, which is dropped
+/// when the connection is closed
+struct ConnectionGuard(Arc<AtomicU64>);
+
+impl Drop for ConnectionGuard {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::Relaxed);
+	}
 }
 
+/// A function called on every response of the web server, including
+/// error responses, right before it is sent to the client
+pub type ResponseHook = dyn Fn(&Request<Body>, &mut Response<Body>) + Send + Sync;
+
 pub struct WebServer {
 	garage: Arc<Garage>,
 	metrics: Arc<WebMetrics>,
-	root_domain: String,
+	limiter: BucketLimiter,
+	config: WebConfig,
+	response_hook: Option<Arc<ResponseHook>>,
+	single_bucket_mode: Option<Uuid>,
+	https_redirect: bool,
+	/// Set once the server has started to shut down
+	shutting_down: AtomicBool,
 }
 
 impl WebServer {
-	/// Run a web server
-	pub async fn run(
-		garage: Arc<Garage>,
-		addr: SocketAddr,
-		root_domain: String,
-		shutdown_signal: impl Future<Output = ()>,
-	) -> Result<(), GarageError> {
-		let metrics = Arc::new(WebMetrics::new());
-		let web_server = Arc::new(WebServer {
+	pub fn new(garage: Arc<Garage>, config: WebConfig) -> Self {
+		Self {
 			garage,
-			metrics,
-			root_domain,
-		});
+			metrics: Arc::new(WebMetrics::new()),
+			limiter: BucketLimiter::new(&config),
+			config,
+			response_hook: None,
+			single_bucket_mode: None,
+			https_redirect: false,
+			shutting_down: AtomicBool::new(false),
+		}
+	}
+
+	/// Serve all requests from the given bucket, whatever their Host header,
+	/// instead of finding the bucket from the host name. In this mode,
+	/// per-bucket settings of the web configuration that are keyed by bucket
+	/// name are looked up with the (abbreviated) hex id of the bucket.
+	pub fn with_single_bucket(mut self, bucket_id: Uuid) -> Self {
+		self.single_bucket_mode = Some(bucket_id);
+		self
+	}
+
+	/// Redirect all requests to the same URL with the https scheme instead of
+	/// serving them, for a plain HTTP listener next to a TLS endpoint.
+	/// The public host name is taken from the X-Forwarded-Host header of
+	/// requests coming from a trusted proxy.
+	pub fn with_https_redirect(mut self) -> Self {
+		self.https_redirect = true;
+		self
+	}
+
+	/// Install a hook that can add, remove or rewrite headers of all responses.
+	/// If the hook panics, a 500 error is returned instead.
+	pub fn with_response_hook<F>(mut self, hook: F) -> Self
+	where
+		F: Fn(&Request<Body>, &mut Response<Body>) + Send + Sync + 'static,
+	{
+		self.response_hook = Some(Arc::new(hook));
+		self
+	}
+
+	/// Run a web server
+	pub async fn run(self, shutdown_signal: impl Future<Output = ()>) -> Result<(), GarageError> {
+		let addr = self.config.bind_addr;
+		let http2 = self.config.http2;
+		let web_server = Arc::new(self);
+
+		let shutdown_signal = {
+			let web_server = web_server.clone();
+			async move {
+				shutdown_signal.await;
+				// Health checks fail from now on, so that load balancers stop
+				// sending requests while the open connections are closed
+				web_server.shutting_down.store(true, Ordering::Relaxed);
+			}
+		};
+
+		if let Some(tls) = &web_server.config.tls {
+			let acceptor = tls_acceptor(tls, http2)?;
+			let listener = TcpListener::bind(addr).await?;
+
+			let service = make_service_fn(|conn: &TlsStream<TcpStream>| {
+				let web_server = web_server.clone();
+				let connection_guard = web_server.metrics.connection_guard();
+
+				let client_addr = tls_remote_addr(conn);
+				async move {
+					Ok::<_, Error>(service_fn(move |req: Request<Body>| {
+						// The connection is counted as open as long as its service exists
+						let _ = &connection_guard;
+						let web_server = web_server.clone();
+
+						web_server.handle_request(req, client_addr)
+					}))
+				}
+			});
+
+			let incoming = accept::from_stream(tls_incoming(listener, acceptor));
+			let server = Server::builder(incoming).http1_only(!http2).serve(service);
+			let graceful = server.with_graceful_shutdown(shutdown_signal);
+			info!("Web server listening on https://{}", addr);
+
+			graceful.await?;
+			return Ok(());
+		}
 
 		let service = make_service_fn(|conn: &AddrStream| {
 			let web_server = web_server.clone();
+			let connection_guard = web_server.metrics.connection_guard();
 
 			let client_addr = conn.remote_addr();
 			async move {
 				Ok::<_, Error>(service_fn(move |req: Request<Body>| {
+					// The connection is counted as open as long as its service exists
+					let _ = &connection_guard;
 					let web_server = web_server.clone();
 
 					web_server.handle_request(req, client_addr)
@@ -92,9 +272,15 @@ impl WebServer {
 			}
 		});
 
-		let server = Server::bind(&addr).serve(service);
+		// Over plain HTTP, HTTP/2 is used if the client starts the
+		// connection with the HTTP/2 preface (prior knowledge)
+		let server = Server::bind(&addr).http1_only(!http2).serve(service);
 		let graceful = server.with_graceful_shutdown(shutdown_signal);
-		info!("Web server listening on http://{}", addr);
+		if web_server.https_redirect {
+			info!("HTTPS redirect server listening on http://{}", addr);
+		} else {
+			info!("Web server listening on http://{}", addr);
+		}
 
 		graceful.await?;
 		Ok(())
@@ -102,84 +288,163 @@ impl WebServer {
 
 	async fn handle_request(
 		self: Arc<Self>,
-		req: Request<Body>,
+		mut req: Request<Body>,
 		addr: SocketAddr,
 	) -> Result<Response<Body>, Infallible> {
-		if let Ok(forwarded_for_ip_addr) =
-			forwarded_headers::handle_forwarded_for_headers(req.headers())
-		{
-			info!(
-				"{} (via {}) {} {}",
-				forwarded_for_ip_addr,
-				addr,
-				req.method(),
-				req.uri()
+		fill_host_header(&mut req);
+		let trace_id = gen_trace_id();
+		let log_level = request_log_level(
+			&self.config.bucket_log_levels,
+			&self.config.root_domain,
+			self.config.bucket_routing,
+			&req,
+		);
+
+		let forwarded_for = forwarded_client_ip(&self.config, &req, addr).map(|ip| ip.to_string());
+		let combined_log = self.config.access_log_format == WebAccessLogFormat::Combined;
+
+		// In the combined log format, the request is logged when the response is sent
+		if !combined_log {
+			match &forwarded_for {
+				Some(forwarded_for_ip_addr) => log_at(
+					log_level,
+					format_args!(
+						"{} (via {}) {} {}",
+						forwarded_for_ip_addr,
+						addr,
+						req.method(),
+						req.uri()
+					),
+				),
+				None => log_at(
+					log_level,
+					format_args!("{} {} {}", addr, req.method(), req.uri()),
+				),
+			}
+		}
+		if self.config.add_diagnostic_headers && !combined_log {
+			log_at(
+				log_level,
+				format_args!("{} {} request id: {}", req.method(), req.uri(), trace_id),
 			);
-		} else {
-			info!("{} {} {}", addr, req.method(), req.uri());
 		}
 
 		// Lots of instrumentation
 		let tracer = opentelemetry::global::tracer("garage");
 		let span = tracer
 			.span_builder(format!("Web {} request", req.method()))
-			.with_trace_id(gen_trace_id())
+			.with_trace_id(trace_id)
 			.with_attributes(vec![
 				KeyValue::new("method", format!("{}", req.method())),
 				KeyValue::new("uri", req.uri().to_string()),
 			])
 			.start(&tracer);
 
-		let metrics_tags = &[KeyValue::new("method", req.method().to_string())];
-
 		// The actual handler
-		let res = self
-			.serve_file(&req)
-			.with_context(Context::current_with_span(span))
-			.record_duration(&self.metrics.request_duration, &metrics_tags[..])
-			.await;
+		let request_time = Utc::now();
+		let request_start = Instant::now();
+		let mut bucket_name = None;
+		let scheme = request_scheme(&self.config, &req, addr);
+		let res = if self.https_redirect {
+			https_redirect(&self.config, &req, addr)
+		} else {
+			// The timeout applies until the response starts, e.g. when blocks
+			// of the document can't be fetched from other nodes. Errors are
+			// counted below like all the others.
+			let serve = self
+				.serve_file(&req, scheme, &mut bucket_name)
+				.with_context(Context::current_with_span(span));
+			match self.config.request_timeout_secs {
+				0 => serve.await,
+				secs => tokio::time::timeout(Duration::from_secs(secs), serve)
+					.await
+					.unwrap_or(Err(Error::Timeout)),
+			}
+		};
 
-		// More instrumentation
-		self.metrics.request_counter.add(1, &metrics_tags[..]);
+		// More instrumentation. Requests are tagged with their bucket only
+		// once it is known to be a website, so that arbitrary Host headers
+		// can't create new metric series.
+		let mut metrics_tags = vec![KeyValue::new("method", req.method().to_string())];
+		if let Some(bucket_name) = bucket_name {
+			metrics_tags.push(KeyValue::new("bucket", bucket_name));
+		}
+		let duration = Instant::now().saturating_duration_since(request_start);
+		self.metrics
+			.request_duration
+			.record(duration.as_secs_f64(), &metrics_tags);
+		self.metrics.request_counter.add(1, &metrics_tags);
 
 		// Returning the result
-		match res {
+		let mut resp = match res {
 			Ok(res) => {
 				debug!("{} {} {}", req.method(), res.status(), req.uri());
-				Ok(res)
+				res
 			}
 			Err(error) => {
-				info!(
-					"{} {} {} {}",
-					req.method(),
-					error.http_status_code(),
-					req.uri(),
-					error
-				);
-				self.metrics.error_counter.add(
-					1,
-					&[
-						metrics_tags[0].clone(),
-						KeyValue::new("status_code", error.http_status_code().to_string()),
-					],
-				);
-				Ok(error_to_res(error))
+				if combined_log {
+					debug!(
+						"{} {} {} {}",
+						req.method(),
+						error.http_status_code(),
+						req.uri(),
+						error
+					);
+				} else {
+					log_at(
+						log_level,
+						format_args!(
+							"{} {} {} {}",
+							req.method(),
+							error.http_status_code(),
+							req.uri(),
+							error
+						),
+					);
+				}
+				metrics_tags.push(KeyValue::new(
+					"status_code",
+					error.http_status_code().to_string(),
+				));
+				self.metrics.error_counter.add(1, &metrics_tags);
+				let json = req
+					.headers()
+					.get(ACCEPT)
+					.and_then(|accept| accept.to_str().ok())
+					.map(prefers_json)
+					.unwrap_or(false);
+				error_to_res(error, json)
 			}
+		};
+
+		if self.config.add_diagnostic_headers {
+			add_diagnostic_headers(
+				resp.headers_mut(),
+				&format!("{:?}", self.garage.system.id),
+				&trace_id.to_string(),
+			);
 		}
-	}
 
-	async fn serve_file(self: &Arc<Self>, req: &Request<Body>) -> Result<Response<Body>, Error> {
-		// Get http authority string (eg. [::1]:3902 or garage.tld:80)
-		let authority = req
-			.headers()
-			.get(HOST)
-			.ok_or_bad_request("HOST header required")?
-			.to_str()?;
+		if let Some(hook) = &self.response_hook {
+			resp = run_response_hook(hook.as_ref(), &req, resp);
+		}
 
-		// Get bucket
-		let host = authority_to_host(authority)?;
+		if combined_log {
+			let client_ip = forwarded_for.unwrap_or_else(|| addr.ip().to_string());
+			log_at(
+				log_level,
+				format_args!(
+					"{}",
+					combined_log_line(&client_ip, &req, &resp, request_time, duration)
+				),
+			);
+		}
 
-		let bucket_name = host_to_bucket(&host, &self.root_domain).unwrap_or(&host);
+		Ok(resp)
+	}
+
+	/// Find a bucket from its global alias, failing with NotFound if it doesn't exist
+	async fn get_bucket(&self, bucket_name: &str) -> Result<(Uuid, Bucket), Error> {
 		let bucket_id = self
 			.garage
 			.bucket_alias_table
@@ -188,14 +453,195 @@ impl WebServer {
 			.and_then(|x| x.state.take())
 			.ok_or(Error::NotFound)?;
 
-		// Check bucket isn't deleted and has website access enabled
-		let bucket = self
-			.garage
+		let bucket = self.get_bucket_by_id(bucket_id).await?;
+
+		Ok((bucket_id, bucket))
+	}
+
+	/// Find a bucket from its id, failing with NotFound if it doesn't exist
+	async fn get_bucket_by_id(&self, bucket_id: Uuid) -> Result<Bucket, Error> {
+		self.garage
 			.bucket_table
 			.get(&EmptyKey, &bucket_id)
 			.await?
-			.ok_or(Error::NotFound)?;
+			.filter(|b| !b.is_deleted())
+			.ok_or(Error::NotFound)
+	}
+
+	/// Answer a CORS preflight request to an existing bucket from its CORS
+	/// rules, whether or not it has website access enabled. If it doesn't and
+	/// the request is not allowed, it is answered as if the bucket didn't
+	/// exist, so that preflight requests don't leak which buckets exist.
+	async fn handle_options(
+		&self,
+		req: &Request<Body>,
+		bucket: &Bucket,
+	) -> Result<Response<Body>, Error> {
+		match handle_options_for_bucket(req, bucket).map_err(Error::from) {
+			Ok(mut resp) => {
+				let website_config = bucket
+					.params()
+					.and_then(|p| p.website_config.get().as_ref());
+				if let Some(preset) = website_config.and_then(|w| w.security_preset) {
+					add_security_headers(resp.headers_mut(), preset);
+				}
+				Ok(resp)
+			}
+			Err(_) if !has_website_config(bucket) => self.handle_options_without_bucket(req).await,
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Answer a CORS preflight request to a host that doesn't map to a bucket,
+	/// or to a bucket whose CORS rules don't allow it: the CORS rules of the
+	/// configured preflight bucket apply if there is one, otherwise the
+	/// request is denied
+	async fn handle_options_without_bucket(
+		&self,
+		req: &Request<Body>,
+	) -> Result<Response<Body>, Error> {
+		let preflight_bucket = match &self.config.preflight_bucket {
+			Some(name) => match self.get_bucket(name).await {
+				Ok((_, bucket)) => Some(bucket),
+				Err(Error::NotFound) => {
+					warn!("Preflight bucket {} does not exist", name);
+					None
+				}
+				Err(e) => return Err(e),
+			},
+			None => None,
+		};
+
+		match preflight_bucket {
+			Some(bucket) => Ok(handle_options_for_bucket(req, &bucket)?),
+			None => Err(Error::ApiError(ApiError::forbidden(
+				"This CORS request is not allowed.",
+			))),
+		}
+	}
+
+	/// Serve an object for a GET or HEAD request
+	async fn get_or_head(
+		&self,
+		req: &Request<Body>,
+		bucket_id: Uuid,
+		key: &str,
+	) -> Result<Response<Body>, Error> {
+		// Objects larger than the configured maximum are not served: their
+		// size is known from their metadata, before any data is read
+		if let Some(max_size) = self.config.max_web_object_size {
+			let object = self
+				.garage
+				.object_table
+				.get(&bucket_id, &key.to_string())
+				.await?;
+			match object.as_ref().and_then(object_data_size) {
+				Some(size) if size > max_size => return Err(Error::ObjectTooLarge(size)),
+				_ => (),
+			}
+		}
+
+		match *req.method() {
+			Method::HEAD => handle_head(self.garage.clone(), req, bucket_id, key, None)
+				.await
+				.map_err(Error::from),
+			Method::GET => {
+				// With a limit on buffered bytes, blocks are only fetched ahead
+				// of the one being sent if they fit in the limit
+				let prefetch_blocks = match self.config.max_buffered_bytes {
+					Some(max) => max / self.garage.config.block_size.max(1),
+					None => GET_PREFETCH_BLOCKS,
+				};
+				handle_get(
+					self.garage.clone(),
+					req,
+					bucket_id,
+					key,
+					None,
+					prefetch_blocks,
+				)
+				.await
+				.map_err(Error::from)
+			}
+			_ => Err(Error::MethodNotAllowed),
+		}
+	}
+
+	/// Serve a request. `resolved_bucket` is set to the name of the bucket
+	/// as soon as it is known to exist and to be a website.
+	async fn serve_file(
+		self: &Arc<Self>,
+		req: &Request<Body>,
+		scheme: &str,
+		resolved_bucket: &mut Option<String>,
+	) -> Result<Response<Body>, Error> {
+		check_method(req.method())?;
+
+		// Health checks don't depend on the Host header
+		let health_check_path = &self.config.health_check_path;
+		if !health_check_path.is_empty() && req.uri().path() == health_check_path {
+			return Ok(health_check(self.shutting_down.load(Ordering::Relaxed)));
+		}
+
+		// Get http authority string (eg. [::1]:3902 or garage.tld:80)
+		let authority = req
+			.headers()
+			.get(HOST)
+			.ok_or_bad_request("HOST header required")?
+			.to_str()?;
+
+		// Get bucket (in single bucket mode and in path routing mode, the
+		// host is only used for redirections and error documents)
+		let host = authority_to_host(authority)?;
+
+		// In path routing mode, the bucket is the first segment of the path
+		let routed_path = match (self.config.bucket_routing, self.single_bucket_mode) {
+			(WebBucketRouting::Path, None) => {
+				Some(split_bucket_path(req.uri().path()).ok_or(Error::NotFound)?)
+			}
+			_ => None,
+		};
+		let target = match routed_path {
+			Some((bucket_name, _)) => BucketTarget::Alias(bucket_name),
+			None => bucket_target(self.single_bucket_mode, &host, &self.config.root_domain),
+		};
+
+		let (bucket_name, lookup) = match target {
+			BucketTarget::Id(bucket_id) => {
+				let lookup = self.get_bucket_by_id(bucket_id).await;
+				(
+					Cow::Owned(format!("{:?}", bucket_id)),
+					lookup.map(|bucket| (bucket_id, bucket)),
+				)
+			}
+			BucketTarget::Alias(bucket_name) => (
+				Cow::Borrowed(bucket_name),
+				self.get_bucket(bucket_name).await,
+			),
+		};
+
+		// CORS preflight requests are answered from the CORS rules of the bucket
+		if *req.method() == Method::OPTIONS {
+			return match lookup {
+				Ok((_, bucket)) => {
+					if has_website_config(&bucket) {
+						*resolved_bucket = Some(bucket_name.to_string());
+					}
+					self.handle_options(req, &bucket).await
+				}
+				Err(Error::NotFound) => self.handle_options_without_bucket(req).await,
+				Err(e) => Err(e),
+			};
+		}
+
+		let (bucket_id, bucket) = match lookup {
+			Ok((bucket_id, bucket)) if has_website_config(&bucket) => (bucket_id, bucket),
+			Ok(_) => return Err(Error::NotFound),
+			Err(e) => return Err(e),
+		};
+		*resolved_bucket = Some(bucket_name.to_string());
 
+		// Check bucket isn't deleted and has website access enabled
 		let website_config = bucket
 			.params()
 			.ok_or(Error::NotFound)?
@@ -204,51 +650,211 @@ impl WebServer {
 			.as_ref()
 			.ok_or(Error::NotFound)?;
 
+		// Limit the number of requests to this bucket that are processed at the
+		// same time. The permit is held until the response body is fully sent.
+		let permit = self.limiter.try_acquire(&bucket_name, bucket_id)?;
+
+		// Redirect to the canonical host of the website if we are not on it
+		if let Some(canonical_host) = &website_config.canonical_host {
+			if let Some(location) =
+				canonical_host_redirect(scheme, &host, canonical_host, req.uri())
+			{
+				return moved_permanently(&location);
+			}
+		}
+
+		// Redirect all requests to another host if configured to
+		if let Some(redirect_all) = &website_config.redirect_all {
+			return moved_permanently(&redirect_all_location(redirect_all, scheme, req.uri()));
+		}
+
+		// Path in the website, and prefix of the paths of the website in
+		// path routing mode, for the redirections within the website
+		let (request_path, path_prefix) = match routed_path {
+			Some((bucket_name, request_path)) => (request_path, format!("/{}", bucket_name)),
+			None => (req.uri().path(), String::new()),
+		};
+		if request_path.is_empty() {
+			let location = match req.uri().query() {
+				Some(query) => format!("{}/?{}", path_prefix, query),
+				None => format!("{}/", path_prefix),
+			};
+			return moved_permanently(&location);
+		}
+
 		// Get path
-		let path = req.uri().path().to_string();
+		let path = match self.config.double_slashes {
+			DoubleSlashMode::Keep => request_path.to_string(),
+			DoubleSlashMode::Collapse => collapse_slashes(request_path).into_owned(),
+			DoubleSlashMode::Redirect => match collapse_slashes(request_path) {
+				Cow::Borrowed(path) => path.to_string(),
+				Cow::Owned(canonical_path) => {
+					let location = match req.uri().query() {
+						Some(query) => format!("{}{}?{}", path_prefix, canonical_path, query),
+						None => format!("{}{}", path_prefix, canonical_path),
+					};
+					return moved_permanently(&location);
+				}
+			},
+		};
 		let index = &website_config.index_document;
-		let key = path_to_key(&path, index)?;
+		let serves_root_document = website_config.root_document.is_some() && path == "/";
+		let key = match &website_config.root_document {
+			// GET and HEAD of / both resolve to the same root document,
+			// so that they return consistent headers
+			Some(root_document) if serves_root_document => Cow::Borrowed(root_document.as_str()),
+			_ => path_to_key(&path, index)?,
+		};
+
+		// Routing rules match the requested key, before the index is appended
+		let request_key = path_to_key(&path, "")?;
+		if let Some(rule) = website_config
+			.routing_rules
+			.iter()
+			.find(|rule| rule.matches(&request_key, None))
+		{
+			return routing_rule_redirect(rule, scheme, &host, &path_prefix, &request_key);
+		}
 
 		debug!(
 			"Selected bucket: \"{}\" {:?}, selected key: \"{}\"",
 			bucket_name, bucket_id, key
 		);
 
-		let ret_doc = match *req.method() {
-			Method::OPTIONS => handle_options_for_bucket(req, &bucket),
-			Method::HEAD => handle_head(self.garage.clone(), req, bucket_id, &key, None).await,
-			Method::GET => handle_get(self.garage.clone(), req, bucket_id, &key, None).await,
-			_ => Err(ApiError::bad_request("HTTP method not supported")),
+		let mut ret_doc = self.get_or_head(req, bucket_id, &key).await;
+		// Key of the object that is served, whose extension is used
+		// to find its content type if it has none
+		let mut served_key = key.clone();
+
+		// Requests to directories try the fallback index documents in turn
+		if path.ends_with('/') && !serves_root_document {
+			for fallback in website_config.index_fallbacks.iter() {
+				match &ret_doc {
+					Err(error) if error.http_status_code() == StatusCode::NOT_FOUND => (),
+					_ => break,
+				}
+				let fallback_key = path_to_key(&path, fallback)?;
+				debug!("Index not found, trying \"{}\"", fallback_key);
+				ret_doc = self.get_or_head(req, bucket_id, &fallback_key).await;
+				served_key = fallback_key;
+			}
 		}
-		.map_err(Error::from);
+
+		// Requests to directories without an index document can be
+		// answered with a listing of the directory
+		let ret_doc = match ret_doc {
+			Err(error)
+				if website_config.autoindex
+					&& path.ends_with('/')
+					&& !serves_root_document
+					&& error.http_status_code() == StatusCode::NOT_FOUND =>
+			{
+				debug!("Index not found, listing \"{}\"", request_key);
+				let listing =
+					list_directory(&self.garage, bucket_id, &request_key, AUTOINDEX_MAX_ENTRIES)
+						.await?;
+				// A directory without any object under it doesn't exist,
+				// except for the root of the website
+				if listing.common_prefixes.is_empty()
+					&& listing.objects.iter().all(|obj| obj.key == request_key)
+					&& !request_key.is_empty()
+				{
+					Err(error)
+				} else {
+					served_key = request_key.clone();
+					Response::builder()
+						.header(CONTENT_TYPE, "text/html; charset=utf-8")
+						.body(Body::from(autoindex_page(&request_key, &listing)))
+						.map_err(Error::from)
+				}
+			}
+			ret_doc => ret_doc,
+		};
+
+		// In SPA mode, client-side routes are answered with the root document.
+		// If it can't be found either, the error document is used as usual.
+		let ret_doc = match ret_doc {
+			Err(error)
+				if website_config.spa_mode
+					&& error.http_status_code() == StatusCode::NOT_FOUND
+					&& is_spa_route(&request_key) =>
+			{
+				let root_key = website_config
+					.root_document
+					.as_deref()
+					.unwrap_or(&website_config.index_document);
+				debug!("SPA route \"{}\", serving \"{}\"", request_key, root_key);
+				served_key = Cow::Borrowed(root_key);
+				self.get_or_head(req, bucket_id, root_key).await
+			}
+			ret_doc => ret_doc,
+		};
 
 		match ret_doc {
 			Err(error) => {
-				// For a HEAD or OPTIONS method, and for non-4xx errors,
-				// we don't return the error document as content,
+				// Routing rules can redirect requests that return an error
+				let code = error.http_status_code().as_u16();
+				if let Some(rule) = website_config
+					.routing_rules
+					.iter()
+					.find(|rule| rule.matches(&request_key, Some(code)))
+				{
+					return routing_rule_redirect(rule, scheme, &host, &path_prefix, &request_key);
+				}
+
+				// For non-4xx errors, we don't return the error document as content,
 				// we return above and just return the error message
 				// by relying on err_to_res that is called when we return an Err.
-				if *req.method() == Method::HEAD
-					|| *req.method() == Method::OPTIONS
-					|| !error.http_status_code().is_client_error()
-				{
+				// A HEAD request gets the headers of the error document, so that
+				// they are consistent with the response to a GET request.
+				// An unsatisfiable range is not answered with the error document
+				// either, so that the Content-Range header giving the size of the
+				// object is kept, and neither is an object that is too large.
+				if !error.http_status_code().is_client_error()
+					|| matches!(
+						error.http_status_code(),
+						StatusCode::RANGE_NOT_SATISFIABLE | StatusCode::PAYLOAD_TOO_LARGE
+					) {
 					return Err(error);
 				}
 
-				// If no error document is set: just return the error directly
-				let error_document = match &website_config.error_document {
-					Some(ed) => ed.trim_start_matches('/').to_owned(),
-					None => return Err(error),
+				// If no error document is set, neither for the website nor
+				// for all websites: just return the error directly
+				let (error_doc_bucket_id, error_document) = match (
+					&website_config.error_document,
+					&self.config.default_error_document,
+				) {
+					(Some(ed), _) => (bucket_id, ed.trim_start_matches('/').to_owned()),
+					(None, Some(default)) => match self.get_bucket(&default.bucket).await {
+						Ok((default_bucket_id, _)) => (
+							default_bucket_id,
+							default.key.trim_start_matches('/').to_owned(),
+						),
+						Err(e) => {
+							warn!(
+								"Couldn't find bucket {} of the default error document: {}",
+								default.bucket, e
+							);
+							return Err(error);
+						}
+					},
+					(None, None) => return Err(error),
 				};
 
 				// We want to return the error document
-				// Create a fake HTTP request with path = the error document
+				// Create a fake HTTP request with path = the error document.
+				// The headers of the original request, such as Range or
+				// conditional headers, are not copied: the error document
+				// is always returned in full.
 				let req2 = Request::builder()
-					.uri(format!("http://{}/{}", host, &error_document))
+					.method(req.method())
+					.uri(format!("{}://{}/{}", scheme, host, &error_document))
 					.body(Body::empty())
 					.unwrap();
 
-				match handle_get(self.garage.clone(), &req2, bucket_id, &error_document, None).await
+				match self
+					.get_or_head(&req2, error_doc_bucket_id, &error_document)
+					.await
 				{
 					Ok(mut error_doc) => {
 						// The error won't be logged back in handle_request,
@@ -262,6 +868,11 @@ impl WebServer {
 						);
 
 						*error_doc.status_mut() = error.http_status_code();
+						// The error status replaces any partial content status
+						error_doc.headers_mut().remove(CONTENT_RANGE);
+						if let Some(preset) = website_config.security_preset {
+							add_security_headers(error_doc.headers_mut(), preset);
+						}
 
 						// Preserve error message in a special header
 						for error_line in error.to_string().split('\n') {
@@ -275,25 +886,92 @@ impl WebServer {
 					Err(error_doc_error) => {
 						warn!(
 							"Couldn't get error document {} for bucket {:?}: {}",
-							error_document, bucket_id, error_doc_error
+							error_document, error_doc_bucket_id, error_doc_error
 						);
 						Err(error)
 					}
 				}
 			}
 			Ok(mut resp) => {
+				if let Some(preset) = website_config.security_preset {
+					add_security_headers(resp.headers_mut(), preset);
+				}
+
+				if let Some(content_type) =
+					content_type_override(website_config, &served_key, resp.headers())
+				{
+					if let Ok(value) = HeaderValue::from_str(content_type) {
+						resp.headers_mut().insert(CONTENT_TYPE, value);
+					}
+				}
+
+				// Cache-Control set in the object's metadata takes precedence
+				let serves_requested_key = served_key == request_key
+					&& !request_key.is_empty()
+					&& !request_key.ends_with('/');
+				if let Some(cache_control) =
+					cache_control(&self.config, website_config, &path, serves_requested_key)
+				{
+					if let Ok(value) = HeaderValue::from_str(cache_control) {
+						resp.headers_mut().entry(CACHE_CONTROL).or_insert(value);
+					}
+				}
+
 				// Maybe add CORS headers
 				if let Some(rule) = find_matching_cors_rule(&bucket, req)? {
 					add_cors_headers(&mut resp, rule)
 						.ok_or_internal_error("Invalid bucket CORS configuration")?;
+					self.metrics.cors_request_counter.add(
+						1,
+						&[
+							KeyValue::new("bucket", bucket_name.to_string()),
+							KeyValue::new("origin", matched_cors_origin(rule, req).to_string()),
+						],
+					);
+				}
+				let resp = if self.config.compression {
+					compress_response(
+						req,
+						resp,
+						self.config.compression_min_size,
+						self.config.compression_level,
+					)
+				} else {
+					resp
+				};
+				let resp = match self.config.max_buffered_bytes {
+					Some(max) => resp.map(|body| bound_body(body, max)),
+					None => resp,
+				};
+				// Bodies of HEAD requests and of 304 responses are never sent,
+				// so only successful GET requests can be aborted by the client
+				let resp = if *req.method() == Method::GET && resp.status().is_success() {
+					let metrics = self.metrics.clone();
+					let tags = [KeyValue::new("bucket", bucket_name.to_string())];
+					let uri = req.uri().clone();
+					let content_length = resp
+						.headers()
+						.get(CONTENT_LENGTH)
+						.and_then(|v| v.to_str().ok()?.parse().ok());
+					resp.map(|body| {
+						watch_abort(body, content_length, move || {
+							debug!("GET {} aborted by the client", uri);
+							metrics.client_aborted_counter.add(1, &tags);
+						})
+					})
+				} else {
+					resp
+				};
+				match permit {
+					Some(permit) => Ok(resp.map(|body| hold_permit(body, permit))),
+					None => Ok(resp),
 				}
-				Ok(resp)
 			}
 		}
 	}
 }
 
-fn error_to_res(e: Error) -> Response<Body> {
+fn error_to_res(e: Error, json: bool) -> Response<Body> {
 	// If we are here, it is either that:
 	// - there was an error before trying to get the requested URL
 	//   from the bucket (e.g. bucket not found)
@@ -301,52 +979,1818 @@ fn error_to_res(e: Error) -> Response<Body> {
 	//   was a HEAD request or we couldn't get the error document)
 	// We do NOT enter this code path when returning the bucket's
 	// error document (this is handled in serve_file)
-	let body = Body::from(format!("{}\n", e));
-	let mut http_error = Response::new(body);
+	// Clients that prefer JSON get the error as a JSON object
+	let body = match json {
+		true => serde_json::json!({
+			"code": e.http_status_code().as_u16(),
+			"message": e.to_string(),
+		})
+		.to_string(),
+		false => format!("{}\n", e),
+	};
+	let mut http_error = Response::new(Body::from(body));
 	*http_error.status_mut() = e.http_status_code();
 	e.add_headers(http_error.headers_mut());
+	if json {
+		http_error
+			.headers_mut()
+			.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+	}
 	http_error
 }
 
-/// Path to key
-///
-/// Convert the provided path to the internal key
-/// When a path ends with "/", we append the index name to match traditional web server behavior
-/// which is also AWS S3 behavior.
-fn path_to_key<'a>(path: &'a str, index: &str) -> Result<Cow<'a, str>, Error> {
-	let path_utf8 = percent_encoding::percent_decode_str(path).decode_utf8()?;
+/// Whether the Accept header of a request prefers JSON to plain text, i.e.
+/// if application/json is accepted with a quality at least as high as the one
+/// of plain text. Wildcards only count for plain text.
+fn prefers_json(accept: &str) -> bool {
+	let mut json_q = 0.0f32;
+	let mut text_q = 0.0f32;
+	for item in accept.split(',') {
+		let mut params = item.split(';');
+		let media_type = params.next().unwrap_or("").trim();
+		let q = params
+			.find_map(|p| p.trim().strip_prefix("q="))
+			.map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+			.unwrap_or(1.0);
+		if media_type.eq_ignore_ascii_case("application/json") {
+			json_q = json_q.max(q);
+		} else if ["text/plain", "text/*", "*/*"]
+			.iter()
+			.any(|t| media_type.eq_ignore_ascii_case(t))
+		{
+			text_q = text_q.max(q);
+		}
+	}
+	json_q > 0.0 && json_q >= text_q
+}
 
-	if !path_utf8.starts_with('/') {
-		return Err(Error::BadRequest("Path must start with a / (slash)".into()));
+/// Size of the current version of an object, as served by GET requests.
+/// None if the object has no data, e.g. if it was deleted.
+fn object_data_size(object: &Object) -> Option<u64> {
+	let version = object.versions().iter().rev().find(|v| v.is_data())?;
+	match &version.state {
+		ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+		| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => Some(meta.size),
+		_ => None,
 	}
+}
 
-	match path_utf8.chars().last() {
-		None => unreachable!(),
-		Some('/') => {
-			let mut key = String::with_capacity(path_utf8.len() + index.len());
-			key.push_str(&path_utf8[1..]);
-			key.push_str(index);
-			Ok(key.into())
-		}
-		Some(_) => match path_utf8 {
-			Cow::Borrowed(pu8) => Ok((&pu8[1..]).into()),
-			Cow::Owned(pu8) => Ok(pu8[1..].to_string().into()),
+/// HTTP/2 requests usually give the host in the :authority pseudo-header,
+/// which is part of their URI, and not in a Host header. The Host header
+/// is then set from it, so that requests are handled the same way
+/// whatever the version of HTTP.
+fn fill_host_header(req: &mut Request<Body>) {
+	if req.headers().contains_key(HOST) {
+		return;
+	}
+	let host = match req.uri().authority() {
+		Some(authority) => match authority.port() {
+			Some(port) => format!("{}:{}", authority.host(), port),
+			None => authority.host().to_string(),
 		},
+		None => return,
+	};
+	if let Ok(value) = HeaderValue::from_str(&host) {
+		req.headers_mut().insert(HOST, value);
 	}
 }
 
-#[cfg(test)]
-mod tests {
-	use super::*;
+/// Find the level at which a request is logged, from the bucket its Host
+/// header or its path designates. This doesn't check that the bucket exists.
+fn request_log_level(
+	bucket_log_levels: &HashMap<String, WebLogLevel>,
+	root_domains: &[String],
+	bucket_routing: WebBucketRouting,
+	req: &Request<Body>,
+) -> WebLogLevel {
+	if bucket_log_levels.is_empty() {
+		return WebLogLevel::default();
+	}
+	if bucket_routing == WebBucketRouting::Path {
+		return split_bucket_path(req.uri().path())
+			.and_then(|(bucket_name, _)| bucket_log_levels.get(bucket_name).copied())
+			.unwrap_or_default();
+	}
+	let host = match req
+		.headers()
+		.get(HOST)
+		.and_then(|h| h.to_str().ok())
+		.and_then(|authority| authority_to_host(authority).ok())
+	{
+		Some(host) => host,
+		None => return WebLogLevel::default(),
+	};
+	let bucket_name = host_to_website_bucket(&host, root_domains);
+	bucket_log_levels
+		.get(bucket_name)
+		.copied()
+		.unwrap_or_default()
+}
 
-	#[test]
-	fn path_to_key_test() -> Result<(), Error> {
-		assert_eq!(path_to_key("/file%20.jpg", "index.html")?, "file .jpg");
-		assert_eq!(path_to_key("/%20t/", "index.html")?, " t/index.html");
-		assert_eq!(path_to_key("/", "index.html")?, "index.html");
-		assert_eq!(path_to_key("/hello", "index.html")?, "hello");
-		assert!(path_to_key("", "index.html").is_err());
-		assert!(path_to_key("i/am/relative", "index.html").is_err());
-		Ok(())
+/// A request in the combined log format, followed by the Host header
+/// and the duration of the request in milliseconds. The size of the
+/// response is only known if it has a Content-Length.
+fn combined_log_line(
+	client_ip: &str,
+	req: &Request<Body>,
+	resp: &Response<Body>,
+	time: DateTime<Utc>,
+	duration: std::time::Duration,
+) -> String {
+	let header = |headers: &HeaderMap, name: HeaderName| {
+		headers
+			.get(name)
+			.and_then(|v| v.to_str().ok())
+			.map(|v| v.replace('\\', "\\\\").replace('"', "\\\""))
+			.unwrap_or_else(|| "-".into())
+	};
+	format!(
+		"{} - - [{}] \"{} {} {:?}\" {} {} \"{}\" \"{}\" \"{}\" {}",
+		client_ip,
+		time.format("%d/%b/%Y:%H:%M:%S %z"),
+		req.method(),
+		req.uri(),
+		req.version(),
+		resp.status().as_u16(),
+		header(resp.headers(), CONTENT_LENGTH),
+		header(req.headers(), REFERER),
+		header(req.headers(), USER_AGENT),
+		header(req.headers(), HOST),
+		duration.as_millis()
+	)
+}
+
+fn log_at(level: WebLogLevel, args: fmt::Arguments<'_>) {
+	match level {
+		WebLogLevel::Off => (),
+		WebLogLevel::Error => error!("{}", args),
+		WebLogLevel::Warn => warn!("{}", args),
+		WebLogLevel::Info => info!("{}", args),
+		WebLogLevel::Debug => debug!("{}", args),
+		WebLogLevel::Trace => trace!("{}", args),
+	}
+}
+
+/// Apply the response hook, replacing the response by a 500 error if it panics
+fn run_response_hook(
+	hook: &ResponseHook,
+	req: &Request<Body>,
+	mut resp: Response<Body>,
+) -> Response<Body> {
+	match panic::catch_unwind(AssertUnwindSafe(|| hook(req, &mut resp))) {
+		Ok(()) => resp,
+		Err(_) => {
+			error!("Response hook panicked on {} {}", req.method(), req.uri());
+			let mut resp = Response::new(Body::from("Internal error\n"));
+			*resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+			resp
+		}
+	}
+}
+
+/// Stamp a response with the node that served it and the id of the request,
+/// which is also the id of its trace
+fn add_diagnostic_headers(headers: &mut HeaderMap, node_id: &str, request_id: &str) {
+	if let Ok(v) = HeaderValue::from_str(node_id) {
+		headers.insert(X_GARAGE_NODE, v);
+	}
+	if let Ok(v) = HeaderValue::from_str(request_id) {
+		headers.insert(X_GARAGE_REQUEST_ID, v);
+	}
+}
+
+/// Reject methods that are never allowed on the web endpoint before doing
+/// anything else, with a 405 listing the allowed methods. The response
+/// never includes any content of the request, in particular for TRACE.
+fn check_method(method: &Method) -> Result<(), Error> {
+	match *method {
+		Method::GET | Method::HEAD | Method::OPTIONS => Ok(()),
+		_ => Err(Error::MethodNotAllowed),
+	}
+}
+
+/// Limits the number of concurrent requests to each bucket
+struct BucketLimiter {
+	default_limit: Option<usize>,
+	bucket_limits: HashMap<String, usize>,
+	semaphores: std::sync::Mutex<HashMap<Uuid, (usize, Arc<Semaphore>)>>,
+}
+
+impl BucketLimiter {
+	fn new(config: &WebConfig) -> Self {
+		Self {
+			default_limit: config.max_concurrent_requests_per_bucket,
+			bucket_limits: config.bucket_max_concurrent_requests.clone(),
+			semaphores: std::sync::Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Take a permit to process a request to a bucket. Returns None if
+	/// the bucket has no limit.
+	fn try_acquire(
+		&self,
+		bucket_name: &str,
+		bucket_id: Uuid,
+	) -> Result<Option<OwnedSemaphorePermit>, Error> {
+		let limit = match self
+			.bucket_limits
+			.get(bucket_name)
+			.or(self.default_limit.as_ref())
+		{
+			Some(limit) => (*limit).min(u32::MAX as usize),
+			None => return Ok(None),
+		};
+
+		let mut semaphores = self.semaphores.lock().unwrap();
+		if semaphores.len() >= MAX_TRACKED_BUCKETS && !semaphores.contains_key(&bucket_id) {
+			// Forget buckets with no request in progress
+			semaphores.retain(|_, (limit, sem)| sem.available_permits() < *limit);
+		}
+		let semaphore = match semaphores.get(&bucket_id) {
+			Some((l, sem)) if *l == limit => sem.clone(),
+			_ => {
+				let sem = Arc::new(Semaphore::new(limit));
+				semaphores.insert(bucket_id, (limit, sem.clone()));
+				sem
+			}
+		};
+		drop(semaphores);
+
+		match semaphore.try_acquire_owned() {
+			Ok(permit) => Ok(Some(permit)),
+			Err(_) => Err(Error::TooManyRequests),
+		}
+	}
+}
+
+/// Keep a permit until the response body has been sent or dropped
+fn hold_permit(body: Body, permit: OwnedSemaphorePermit) -> Body {
+	Body::wrap_stream(body.map(move |chunk| {
+		let _ = &permit;
+		chunk
+	}))
+}
+
+/// Calls a function when dropped, unless disarmed
+struct AbortGuard(Option<Box<dyn FnOnce() + Send>>);
+
+impl Drop for AbortGuard {
+	fn drop(&mut self) {
+		if let Some(on_abort) = self.0.take() {
+			on_abort();
+		}
+	}
+}
+
+/// Call `on_abort` if the response body is dropped before it has been fully
+/// sent, which is what hyper does when the client goes away. An error of the
+/// body itself is a server-side error and not an abort. When the response
+/// has a Content-Length, hyper drops the body as soon as that many bytes
+/// have been sent, without waiting for its end, so the body is complete
+/// once `content_length` bytes have been read from it.
+fn watch_abort<F>(body: Body, content_length: Option<u64>, on_abort: F) -> Body
+where
+	F: FnOnce() + Send + 'static,
+{
+	let guard = AbortGuard(Some(Box::new(on_abort)));
+	let state = (body, guard, 0u64);
+	let chunks = stream::unfold(state, move |(mut body, mut guard, mut sent)| async move {
+		match body.next().await {
+			Some(Ok(chunk)) => {
+				sent += chunk.len() as u64;
+				if content_length.map_or(false, |len| sent >= len) {
+					guard.0 = None;
+				}
+				Some((Ok(chunk), (body, guard, sent)))
+			}
+			Some(Err(e)) => {
+				guard.0 = None;
+				Some((Err(e), (body, guard, sent)))
+			}
+			None => {
+				guard.0 = None;
+				None
+			}
+		}
+	});
+	Body::wrap_stream(chunks)
+}
+
+/// Split the chunks of a response body so that none of them is bigger than
+/// `max_chunk` bytes. The body is still pulled lazily, one chunk at a time.
+/// Splitting does not copy data, the memory held for the response is
+/// bounded by the number of blocks that `handle_get` fetches ahead.
+fn bound_body(body: Body, max_chunk: usize) -> Body {
+	let max_chunk = max_chunk.max(1);
+	let chunks = body.flat_map(move |chunk| {
+		let pieces = match chunk {
+			Ok(mut bytes) => {
+				let mut pieces = Vec::with_capacity(bytes.len() / max_chunk + 1);
+				while bytes.len() > max_chunk {
+					pieces.push(Ok(bytes.split_to(max_chunk)));
+				}
+				pieces.push(Ok(bytes));
+				pieces
+			}
+			Err(e) => vec![Err(e)],
+		};
+		stream::iter(pieces)
+	});
+	Body::wrap_stream(chunks)
+}
+
+/// How the bucket that serves a request is found
+#[derive(Debug, PartialEq, Eq)]
+enum BucketTarget<'a> {
+	/// Always the same bucket, in single bucket mode
+	Id(Uuid),
+	/// The bucket with this global alias
+	Alias(&'a str),
+}
+
+fn bucket_target<'a>(
+	single_bucket_mode: Option<Uuid>,
+	host: &'a str,
+	root_domains: &[String],
+) -> BucketTarget<'a> {
+	match single_bucket_mode {
+		Some(bucket_id) => BucketTarget::Id(bucket_id),
+		None => BucketTarget::Alias(host_to_website_bucket(host, root_domains)),
+	}
+}
+
+/// Get the name of the bucket designated by a host. If the host ends with
+/// several of the root domains, the longest one is removed. If it ends with
+/// none of them, the whole host is the bucket name.
+fn host_to_website_bucket<'a>(host: &'a str, root_domains: &[String]) -> &'a str {
+	root_domains
+		.iter()
+		.filter_map(|root| {
+			host_to_bucket(host, root).map(|bucket| (root.trim_start_matches('.').len(), bucket))
+		})
+		.max_by_key(|(root_len, _)| *root_len)
+		.map(|(_, bucket)| bucket)
+		.unwrap_or(host)
+}
+
+/// In path routing mode, split a request path into the name of the bucket,
+/// which is its first segment, and the path in the website. The path in
+/// the website is empty if the request path is only `/bucket`.
+fn split_bucket_path(path: &str) -> Option<(&str, &str)> {
+	let path = path.strip_prefix('/')?;
+	let (bucket_name, rest) = match path.find('/') {
+		Some(i) => path.split_at(i),
+		None => (path, ""),
+	};
+	if bucket_name.is_empty() {
+		return None;
+	}
+	Some((bucket_name, rest))
+}
+
+fn has_website_config(bucket: &Bucket) -> bool {
+	bucket
+		.params()
+		.map(|p| p.website_config.get().is_some())
+		.unwrap_or(false)
+}
+
+/// Whether a peer is one of the configured reverse proxies, whose
+/// X-Forwarded-* headers can be trusted.
+fn is_trusted_proxy(config: &WebConfig, ip: IpAddr) -> bool {
+	// IPv4 peers of a dual-stack listener are seen as IPv4-mapped IPv6 addresses
+	let ip = match ip {
+		IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+		IpAddr::V4(_) => ip,
+	};
+	config.trusted_proxies.iter().any(|net| net.contains(ip))
+}
+
+/// Address of the client of a request sent through trusted proxies, taken from
+/// the X-Forwarded-For header. The chain is read from the right, skipping the
+/// addresses of trusted proxies: the first other address is the one of the client,
+/// as what comes before it may have been forged. Returns None for requests that
+/// don't come from a trusted proxy, whose client is their peer.
+fn forwarded_client_ip(
+	config: &WebConfig,
+	req: &Request<Body>,
+	addr: SocketAddr,
+) -> Option<IpAddr> {
+	if !is_trusted_proxy(config, addr.ip()) {
+		return None;
+	}
+	let chain = forwarded_headers::handle_forwarded_for_chain(req.headers()).ok()?;
+	chain
+		.iter()
+		.rev()
+		.find(|ip| !is_trusted_proxy(config, **ip))
+		.or_else(|| chain.first())
+		.copied()
+}
+
+/// Scheme used by the client to send a request. Behind a reverse proxy that
+/// terminates TLS, it is taken from the X-Forwarded-Proto header, which is
+/// only trusted if the request comes from one of the configured proxies.
+fn request_scheme(config: &WebConfig, req: &Request<Body>, addr: SocketAddr) -> &'static str {
+	let own_scheme = if config.tls.is_some() {
+		"https"
+	} else {
+		"http"
+	};
+	if !is_trusted_proxy(config, addr.ip()) {
+		return own_scheme;
+	}
+	forwarded_headers::handle_forwarded_proto_header(req.headers()).unwrap_or(own_scheme)
+}
+
+/// Redirect a request to the same URL with the https scheme. The port of
+/// the request is dropped, as the HTTPS endpoint is expected on the default port.
+fn https_redirect(
+	config: &WebConfig,
+	req: &Request<Body>,
+	addr: SocketAddr,
+) -> Result<Response<Body>, Error> {
+	let forwarded_host = match is_trusted_proxy(config, addr.ip()) {
+		true => forwarded_headers::handle_forwarded_host_header(req.headers()).ok(),
+		false => None,
+	};
+	let authority = match forwarded_host {
+		Some(forwarded_host) => forwarded_host,
+		None => req
+			.headers()
+			.get(HOST)
+			.ok_or_bad_request("HOST header required")?
+			.to_str()?
+			.to_string(),
+	};
+	let authority = authority
+		.parse::<Authority>()
+		.ok_or_bad_request("Invalid host")?;
+	let path_and_query = req
+		.uri()
+		.path_and_query()
+		.map(|pq| pq.as_str())
+		.unwrap_or("/");
+	moved_permanently(&format!("https://{}{}", authority.host(), path_and_query))
+}
+
+/// If `host` is not the canonical host of the website, get the location
+/// to redirect to, keeping the scheme, path and query.
+fn canonical_host_redirect(
+	scheme: &str,
+	host: &str,
+	canonical_host: &str,
+	uri: &Uri,
+) -> Option<String> {
+	if host.eq_ignore_ascii_case(canonical_host) {
+		return None;
+	}
+	let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+	Some(format!("{}://{}{}", scheme, canonical_host, path_and_query))
+}
+
+/// Allowed origin of a CORS rule that matched a request: the origin of the
+/// request if it is listed in the rule, or `*`. Only configured values are
+/// returned, so that they can be used as metric tags.
+fn matched_cors_origin<'a>(rule: &'a CorsRule, req: &Request<Body>) -> &'a str {
+	let origin = req.headers().get("Origin").and_then(|o| o.to_str().ok());
+	rule.allow_origins
+		.iter()
+		.find(|x| Some(x.as_str()) == origin)
+		.map(String::as_str)
+		.unwrap_or("*")
+}
+
+/// Whether a key that is not found can be a route of a single-page
+/// application, i.e. its last segment has no file extension
+fn is_spa_route(key: &str) -> bool {
+	!key.rsplit('/').next().unwrap_or("").contains('.')
+}
+
+/// Response to a health check: the node serves requests unless it is
+/// shutting down
+fn health_check(shutting_down: bool) -> Response<Body> {
+	let (status, body) = match shutting_down {
+		false => (StatusCode::OK, "OK\n"),
+		true => (StatusCode::SERVICE_UNAVAILABLE, "Shutting down\n"),
+	};
+	let mut resp = Response::new(Body::from(body));
+	*resp.status_mut() = status;
+	resp.headers_mut()
+		.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+	resp
+}
+
+/// HTML page listing the objects and subdirectories of the directory `prefix`
+fn autoindex_page(prefix: &str, listing: &DirectoryListing) -> String {
+	let title = html_escape(&format!("Index of /{}", prefix));
+	let mut page = format!(
+		"<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n<ul>\n",
+		title
+	);
+	if !prefix.is_empty() {
+		page.push_str("<li><a href=\"../\">../</a></li>\n");
+	}
+	let names = listing
+		.common_prefixes
+		.iter()
+		.map(|pfx| (pfx, None))
+		.chain(listing.objects.iter().map(|obj| (&obj.key, Some(obj.size))));
+	for (key, size) in names {
+		let name = &key[prefix.len()..];
+		if name.is_empty() {
+			continue;
+		}
+		// Links are prefixed by ./ so that a name containing a colon
+		// is not taken for a URL scheme
+		let href = format!("./{}", utf8_percent_encode(name, KEY_ENCODE_SET));
+		page.push_str(&format!(
+			"<li><a href=\"{}\">{}</a>",
+			html_escape(&href),
+			html_escape(name)
+		));
+		if let Some(size) = size {
+			page.push_str(&format!(" ({} bytes)", size));
+		}
+		page.push_str("</li>\n");
+	}
+	page.push_str("</ul>\n");
+	if listing.truncated {
+		page.push_str(&format!(
+			"<p>Only the first {} entries are listed.</p>\n",
+			AUTOINDEX_MAX_ENTRIES
+		));
+	}
+	page.push_str("</body>\n</html>\n");
+	page
+}
+
+fn html_escape(s: &str) -> String {
+	let mut escaped = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&#39;"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+/// Content types that don't tell anything about the content of an object.
+/// "blob" is the content type of objects uploaded without one.
+const GENERIC_CONTENT_TYPES: [&str; 3] =
+	["blob", "application/octet-stream", "binary/octet-stream"];
+
+/// Content type of a website response for `key`, taken from the content
+/// types of the website by extension, if the object has no content type
+/// or a generic one
+fn content_type_override<'a>(
+	website_config: &'a WebsiteConfig,
+	key: &str,
+	headers: &HeaderMap,
+) -> Option<&'a str> {
+	let generic = match headers.get(CONTENT_TYPE).map(|v| v.to_str()) {
+		None => true,
+		Some(Ok(ct)) => GENERIC_CONTENT_TYPES
+			.iter()
+			.any(|g| ct.trim().eq_ignore_ascii_case(g)),
+		Some(Err(_)) => false,
+	};
+	if !generic {
+		return None;
+	}
+	let (_, ext) = key.rsplit('/').next()?.rsplit_once('.')?;
+	website_config
+		.content_types
+		.get(&ext.to_lowercase())
+		.map(String::as_str)
+}
+
+/// Cache-Control header of the successful responses for a path of a website.
+/// Immutable paths are only the ones of files served for their own key, so
+/// not index documents, the root document of SPA routes or directory listings.
+fn cache_control<'a>(
+	config: &'a WebConfig,
+	website_config: &'a WebsiteConfig,
+	path: &str,
+	serves_requested_key: bool,
+) -> Option<&'a str> {
+	if serves_requested_key
+		&& config
+			.immutable_paths
+			.iter()
+			.chain(website_config.immutable_paths.iter())
+			.any(|pattern| path_matches(pattern, path))
+	{
+		return Some(&config.immutable_cache_control);
+	}
+	website_config
+		.cache_control
+		.as_deref()
+		.or(config.cache_control.as_deref())
+}
+
+/// Whether a path matches a pattern, where * matches any sequence of characters
+fn path_matches(pattern: &str, path: &str) -> bool {
+	let mut parts = pattern.split('*');
+	let mut rest = match path.strip_prefix(parts.next().unwrap_or("")) {
+		Some(rest) => rest,
+		None => return false,
+	};
+	let parts = parts.collect::<Vec<_>>();
+	match parts.split_last() {
+		None => rest.is_empty(),
+		Some((last, middle)) => {
+			for part in middle {
+				match rest.find(part) {
+					Some(i) => rest = &rest[i + part.len()..],
+					None => return false,
+				}
+			}
+			rest.ends_with(last)
+		}
+	}
+}
+
+/// Headers added to the responses of a website with the given security preset
+fn security_preset_headers(preset: SecurityPreset) -> Vec<(HeaderName, &'static str)> {
+	match preset {
+		SecurityPreset::Basic => vec![
+			(X_CONTENT_TYPE_OPTIONS, "nosniff"),
+			(REFERRER_POLICY, "strict-origin-when-cross-origin"),
+		],
+		SecurityPreset::Strict => vec![
+			(X_CONTENT_TYPE_OPTIONS, "nosniff"),
+			(REFERRER_POLICY, "no-referrer"),
+			(
+				STRICT_TRANSPORT_SECURITY,
+				"max-age=31536000; includeSubDomains",
+			),
+			(X_FRAME_OPTIONS, "DENY"),
+		],
+	}
+}
+
+/// Add the headers of a security preset to a response. Headers that are
+/// already set, e.g. from the object's metadata, are kept as they are.
+fn add_security_headers(headers: &mut HeaderMap, preset: SecurityPreset) {
+	for (name, value) in security_preset_headers(preset) {
+		headers
+			.entry(name)
+			.or_insert_with(|| HeaderValue::from_static(value));
+	}
+}
+
+/// Location to which a request is redirected when all requests to the
+/// website are redirected to another host, keeping the path and query.
+/// Without a protocol in the configuration, the scheme of the request is kept.
+fn redirect_all_location(redirect_all: &RedirectAll, scheme: &str, uri: &Uri) -> String {
+	let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+	format!(
+		"{}://{}{}",
+		redirect_all.protocol.as_deref().unwrap_or(scheme),
+		redirect_all.hostname,
+		path_and_query
+	)
+}
+
+/// Build the response redirecting a request for a key that matches a
+/// routing rule. Without a host or protocol in the rule, the location is
+/// relative to the current host, otherwise the missing parts are those of
+/// the request. `path_prefix` is prepended to the path when redirecting to
+/// the same host, in path routing mode.
+fn routing_rule_redirect(
+	rule: &RoutingRule,
+	scheme: &str,
+	host: &str,
+	path_prefix: &str,
+	key: &str,
+) -> Result<Response<Body>, Error> {
+	let redirect = &rule.redirect;
+	let new_key = rule.redirect_key(key);
+	let path = utf8_percent_encode(&new_key, KEY_ENCODE_SET);
+	let location = match (&redirect.protocol, &redirect.hostname) {
+		(None, None) => format!("{}/{}", path_prefix, path),
+		(Some(protocol), None) => format!("{}://{}{}/{}", protocol, host, path_prefix, path),
+		(protocol, Some(hostname)) => format!(
+			"{}://{}/{}",
+			protocol.as_deref().unwrap_or(scheme),
+			hostname,
+			path
+		),
+	};
+	let status = StatusCode::from_u16(redirect.http_redirect_code.unwrap_or(301))
+		.ok_or_internal_error("Invalid redirect code in routing rule")?;
+	Ok(Response::builder()
+		.status(status)
+		.header(LOCATION, location)
+		.body(Body::empty())?)
+}
+
+/// Build a 301 response redirecting the client to the given location
+fn moved_permanently(location: &str) -> Result<Response<Body>, Error> {
+	Ok(Response::builder()
+		.status(StatusCode::MOVED_PERMANENTLY)
+		.header(LOCATION, location)
+		.body(Body::empty())?)
+}
+
+/// Collapse consecutive slashes in a request path,
+/// e.g. `//a//b` becomes `/a/b`
+fn collapse_slashes(path: &str) -> Cow<'_, str> {
+	if !path.contains("//") {
+		return Cow::Borrowed(path);
+	}
+
+	let mut ret = String::with_capacity(path.len());
+	let mut prev_slash = false;
+	for c in path.chars() {
+		if c == '/' && prev_slash {
+			continue;
+		}
+		prev_slash = c == '/';
+		ret.push(c);
+	}
+	Cow::Owned(ret)
+}
+
+/// Remove the `.` and `..` segments of an absolute path, as described in
+/// RFC 3986 section 5.2.4. `..` segments can't go above the root. Returns
+/// None if the path has no such segment.
+fn remove_dot_segments(path: &str) -> Option<String> {
+	let segments = path.split('/').skip(1);
+	if !segments.clone().any(|s| s == "." || s == "..") {
+		return None;
+	}
+
+	let mut output: Vec<&str> = vec![];
+	let mut segments = segments.peekable();
+	while let Some(segment) = segments.next() {
+		match segment {
+			"." => (),
+			".." => {
+				output.pop();
+			}
+			_ => output.push(segment),
+		}
+		// A path ending with a dot segment refers to a directory
+		if segments.peek().is_none() && (segment == "." || segment == "..") {
+			output.push("");
+		}
+	}
+	Some(format!("/{}", output.join("/")))
+}
+
+/// Path to key
+///
+/// Convert the provided path to the internal key
+/// When a path ends with "/", we append the index name to match traditional web server behavior
+/// which is also AWS S3 behavior.
+fn path_to_key<'a>(path: &'a str, index: &str) -> Result<Cow<'a, str>, Error> {
+	let path_utf8 = percent_encoding::percent_decode_str(path).decode_utf8()?;
+
+	if !path_utf8.starts_with('/') {
+		return Err(Error::BadRequest("Path must start with a / (slash)".into()));
+	}
+
+	let path_utf8 = match remove_dot_segments(&path_utf8) {
+		Some(normalized) => Cow::Owned(normalized),
+		None => path_utf8,
+	};
+
+	match path_utf8.chars().last() {
+		None => unreachable!(),
+		Some('/') => {
+			let mut key = String::with_capacity(path_utf8.len() + index.len());
+			key.push_str(&path_utf8[1..]);
+			key.push_str(index);
+			Ok(key.into())
+		}
+		Some(_) => match path_utf8 {
+			Cow::Borrowed(pu8) => Ok((&pu8[1..]).into()),
+			Cow::Owned(pu8) => Ok(pu8[1..].to_string().into()),
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn path_to_key_test() -> Result<(), Error> {
+		assert_eq!(path_to_key("/file%20.jpg", "index.html")?, "file .jpg");
+		assert_eq!(path_to_key("/%20t/", "index.html")?, " t/index.html");
+		assert_eq!(path_to_key("/", "index.html")?, "index.html");
+		assert_eq!(path_to_key("/hello", "index.html")?, "hello");
+		assert!(path_to_key("", "index.html").is_err());
+		assert!(path_to_key("i/am/relative", "index.html").is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn path_to_key_dot_segments_test() -> Result<(), Error> {
+		assert_eq!(path_to_key("/a/../b", "index.html")?, "b");
+		assert_eq!(path_to_key("/./x", "index.html")?, "x");
+		assert_eq!(
+			path_to_key("/../../etc/passwd", "index.html")?,
+			"etc/passwd"
+		);
+		assert_eq!(path_to_key("/a/b/..", "index.html")?, "a/index.html");
+		assert_eq!(path_to_key("/a/.", "index.html")?, "a/index.html");
+		assert_eq!(path_to_key("/%2E%2E/a%20b", "index.html")?, "a b");
+		assert_eq!(path_to_key("/a..b/.c", "index.html")?, "a..b/.c");
+		assert_eq!(path_to_key("/a//b/../c", "index.html")?, "a//c");
+		assert!(matches!(
+			path_to_key("/a/b", "index.html")?,
+			Cow::Borrowed("a/b")
+		));
+		Ok(())
+	}
+
+	#[test]
+	fn collapse_slashes_test() -> Result<(), Error> {
+		assert!(matches!(collapse_slashes("/a/b"), Cow::Borrowed("/a/b")));
+		assert_eq!(collapse_slashes("//a//b"), "/a/b");
+		assert_eq!(collapse_slashes("/a///b/"), "/a/b/");
+		assert_eq!(
+			path_to_key(&collapse_slashes("//a//b"), "index.html")?,
+			"a/b"
+		);
+
+		let resp = moved_permanently("/a/b?x=1")?;
+		assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+		assert_eq!(resp.headers().get(LOCATION).unwrap(), "/a/b?x=1");
+		Ok(())
+	}
+
+	#[test]
+	fn response_hook_test() {
+		let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+
+		let hook = |_: &Request<Body>, resp: &mut Response<Body>| {
+			resp.headers_mut()
+				.insert("x-hooked", HeaderValue::from_static("yes"));
+		};
+		let resp = run_response_hook(&hook, &req, Response::new(Body::empty()));
+		assert_eq!(resp.status(), StatusCode::OK);
+		assert_eq!(resp.headers().get("x-hooked").unwrap(), "yes");
+
+		let mut error_resp = Response::new(Body::empty());
+		*error_resp.status_mut() = StatusCode::NOT_FOUND;
+		let resp = run_response_hook(&hook, &req, error_resp);
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+		assert_eq!(resp.headers().get("x-hooked").unwrap(), "yes");
+
+		let panicking_hook = |_: &Request<Body>, _: &mut Response<Body>| panic!("oops");
+		let resp = run_response_hook(&panicking_hook, &req, Response::new(Body::empty()));
+		assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+		assert!(resp.headers().get("x-hooked").is_none());
+	}
+
+	#[test]
+	fn request_log_level_test() {
+		let root_domains = vec![".web.garage".to_string()];
+		let levels = HashMap::from([
+			("noisy".to_string(), WebLogLevel::Off),
+			("watched".to_string(), WebLogLevel::Debug),
+		]);
+		let req = |host: &str| {
+			Request::builder()
+				.uri("/")
+				.header(HOST, host)
+				.body(Body::empty())
+				.unwrap()
+		};
+
+		assert_eq!(
+			request_log_level(
+				&levels,
+				&root_domains,
+				WebBucketRouting::Vhost,
+				&req("noisy.web.garage")
+			),
+			WebLogLevel::Off
+		);
+		assert_eq!(
+			request_log_level(
+				&levels,
+				&root_domains,
+				WebBucketRouting::Vhost,
+				&req("watched:3902")
+			),
+			WebLogLevel::Debug
+		);
+		assert_eq!(
+			request_log_level(
+				&levels,
+				&root_domains,
+				WebBucketRouting::Vhost,
+				&req("other.web.garage")
+			),
+			WebLogLevel::Info
+		);
+		assert_eq!(
+			request_log_level(
+				&HashMap::new(),
+				&root_domains,
+				WebBucketRouting::Vhost,
+				&req("noisy.web.garage")
+			),
+			WebLogLevel::Info
+		);
+
+		let req = Request::builder()
+			.uri("/noisy/index.html")
+			.header(HOST, "web.garage")
+			.body(Body::empty())
+			.unwrap();
+		assert_eq!(
+			request_log_level(&levels, &root_domains, WebBucketRouting::Path, &req),
+			WebLogLevel::Off
+		);
+	}
+
+	#[test]
+	fn combined_log_line_test() {
+		let req = Request::builder()
+			.uri("/index.html?x=1")
+			.header(HOST, "site.web.garage")
+			.header(USER_AGENT, "curl/8.0 \"test\"")
+			.body(Body::empty())
+			.unwrap();
+		let resp = Response::builder()
+			.header(CONTENT_LENGTH, 1234)
+			.body(Body::empty())
+			.unwrap();
+		let time = "2023-06-01T12:34:56Z".parse::<DateTime<Utc>>().unwrap();
+
+		assert_eq!(
+			combined_log_line(
+				"192.0.2.1",
+				&req,
+				&resp,
+				time,
+				std::time::Duration::from_millis(42)
+			),
+			"192.0.2.1 - - [01/Jun/2023:12:34:56 +0000] \"GET /index.html?x=1 HTTP/1.1\" 200 1234 \"-\" \"curl/8.0 \\\"test\\\"\" \"site.web.garage\" 42"
+		);
+	}
+
+	#[test]
+	fn active_connections_test() {
+		let metrics = WebMetrics::new();
+		let first = metrics.connection_guard();
+		let second = metrics.connection_guard();
+		assert_eq!(metrics.active_connections.load(Ordering::Relaxed), 2);
+		drop(first);
+		assert_eq!(metrics.active_connections.load(Ordering::Relaxed), 1);
+		drop(second);
+		assert_eq!(metrics.active_connections.load(Ordering::Relaxed), 0);
+	}
+
+	#[test]
+	fn request_timeout_test() {
+		let config: WebConfig = toml::from_str(
+			r#"
+			bind_addr = "[::]:3902"
+			root_domain = ".web.garage"
+			"#,
+		)
+		.unwrap();
+		assert_eq!(config.request_timeout_secs, 300);
+
+		let resp = error_to_res(Error::Timeout, false);
+		assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+	}
+
+	#[tokio::test]
+	async fn error_to_res_json_test() {
+		let mut resp = error_to_res(Error::NotFound, true);
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+		assert_eq!(resp.headers()[CONTENT_TYPE], "application/json");
+		let body = hyper::body::to_bytes(resp.body_mut()).await.unwrap();
+		let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+		assert_eq!(
+			body,
+			serde_json::json!({"code": 404, "message": "Not found"})
+		);
+
+		let mut resp = error_to_res(Error::NotFound, false);
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+		assert!(resp.headers().get(CONTENT_TYPE).is_none());
+		let body = hyper::body::to_bytes(resp.body_mut()).await.unwrap();
+		assert_eq!(body.as_ref(), b"Not found\n");
+	}
+
+	#[test]
+	fn prefers_json_test() {
+		assert!(prefers_json("application/json"));
+		assert!(prefers_json("text/plain;q=0.5, application/json"));
+		assert!(prefers_json("application/json, */*"));
+		assert!(!prefers_json("*/*"));
+		assert!(!prefers_json("text/html, */*;q=0.8"));
+		assert!(!prefers_json("application/json;q=0.5, text/plain"));
+		assert!(!prefers_json("application/json;q=0"));
+	}
+
+	#[test]
+	fn autoindex_page_test() {
+		use garage_api::s3::list::DirectoryEntry;
+
+		let listing = DirectoryListing {
+			common_prefixes: vec!["docs/img/".into()],
+			objects: vec![
+				DirectoryEntry {
+					key: "docs/".into(),
+					size: 0,
+					last_modified: 0,
+				},
+				DirectoryEntry {
+					key: "docs/a&b <1>.txt".into(),
+					size: 12,
+					last_modified: 0,
+				},
+			],
+			truncated: false,
+		};
+		let page = autoindex_page("docs/", &listing);
+		assert!(page.contains("<title>Index of /docs/</title>"));
+		assert!(page.contains("<a href=\"../\">../</a>"));
+		assert!(page.contains("<a href=\"./img/\">img/</a>"));
+		assert!(page
+			.contains("<a href=\"./a&amp;b%20%3C1%3E.txt\">a&amp;b &lt;1&gt;.txt</a> (12 bytes)"));
+		assert_eq!(page.matches("<li>").count(), 3);
+		assert!(!page.contains("Only the first"));
+
+		let listing = DirectoryListing {
+			common_prefixes: vec![],
+			objects: vec![],
+			truncated: true,
+		};
+		let page = autoindex_page("", &listing);
+		assert!(!page.contains("../"));
+		assert!(page.contains("Only the first 1000 entries are listed."));
+	}
+
+	#[test]
+	fn object_data_size_test() {
+		let meta = ObjectVersionMeta {
+			headers: ObjectVersionHeaders {
+				content_type: "text/html".into(),
+				other: Default::default(),
+			},
+			size: 5000000000,
+			etag: "etag".into(),
+		};
+		let version = |timestamp, data| ObjectVersion {
+			uuid: garage_util::data::gen_uuid(),
+			timestamp,
+			state: ObjectVersionState::Complete(data),
+		};
+		let bucket_id = garage_util::data::gen_uuid();
+
+		let object = Object::new(
+			bucket_id,
+			"big.iso".into(),
+			vec![version(
+				1,
+				ObjectVersionData::FirstBlock(meta, Default::default()),
+			)],
+		);
+		assert_eq!(object_data_size(&object), Some(5000000000));
+
+		let deleted = Object::new(
+			bucket_id,
+			"big.iso".into(),
+			vec![version(2, ObjectVersionData::DeleteMarker)],
+		);
+		assert_eq!(object_data_size(&deleted), None);
+
+		assert_eq!(
+			Error::ObjectTooLarge(5000000000).http_status_code(),
+			StatusCode::PAYLOAD_TOO_LARGE
+		);
+	}
+
+	#[test]
+	fn fill_host_header_test() {
+		let mut req = Request::builder()
+			.version(hyper::Version::HTTP_2)
+			.uri("https://user@site.web.garage:8443/page")
+			.body(Body::empty())
+			.unwrap();
+		fill_host_header(&mut req);
+		assert_eq!(req.headers().get(HOST).unwrap(), "site.web.garage:8443");
+
+		// An existing Host header is kept
+		let mut req = Request::builder()
+			.uri("https://other.web.garage/page")
+			.header(HOST, "site.web.garage")
+			.body(Body::empty())
+			.unwrap();
+		fill_host_header(&mut req);
+		assert_eq!(req.headers().get(HOST).unwrap(), "site.web.garage");
+
+		let mut req = Request::builder().uri("/page").body(Body::empty()).unwrap();
+		fill_host_header(&mut req);
+		assert!(req.headers().get(HOST).is_none());
+	}
+
+	#[tokio::test]
+	async fn health_check_test() {
+		let mut resp = health_check(false);
+		assert_eq!(resp.status(), StatusCode::OK);
+		assert_eq!(resp.headers().get(CACHE_CONTROL).unwrap(), "no-store");
+		let body = hyper::body::to_bytes(resp.body_mut()).await.unwrap();
+		assert_eq!(body.as_ref(), b"OK\n");
+
+		let resp = health_check(true);
+		assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+	}
+
+	#[test]
+	fn check_method_test() {
+		for method in [Method::GET, Method::HEAD, Method::OPTIONS] {
+			assert!(check_method(&method).is_ok());
+		}
+		for method in [
+			Method::TRACE,
+			Method::CONNECT,
+			Method::PATCH,
+			Method::PUT,
+			Method::POST,
+			Method::DELETE,
+			Method::from_bytes(b"PROPFIND").unwrap(),
+		] {
+			let resp = error_to_res(check_method(&method).unwrap_err(), false);
+			assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+			assert_eq!(
+				resp.headers().get(hyper::header::ALLOW).unwrap(),
+				"GET, HEAD, OPTIONS"
+			);
+		}
+	}
+
+	#[tokio::test]
+	async fn bound_body_test() {
+		use std::sync::atomic::{AtomicUsize, Ordering};
+
+		let pulled = Arc::new(AtomicUsize::new(0));
+		let pulled2 = pulled.clone();
+		let inner = stream::iter(0..4).map(move |i| {
+			pulled2.fetch_add(1, Ordering::SeqCst);
+			Ok::<_, std::io::Error>(hyper::body::Bytes::from(vec![i as u8; 1000]))
+		});
+		let mut body = bound_body(Body::wrap_stream(inner), 300);
+
+		// A slow consumer reading a single chunk only causes
+		// a single chunk of the object to be pulled
+		let first = body.next().await.unwrap().unwrap();
+		assert_eq!(first.len(), 300);
+		assert_eq!(pulled.load(Ordering::SeqCst), 1);
+
+		let mut total = first.len();
+		while let Some(chunk) = body.next().await {
+			let chunk = chunk.unwrap();
+			assert!(chunk.len() <= 300);
+			total += chunk.len();
+		}
+		assert_eq!(total, 4000);
+		assert_eq!(pulled.load(Ordering::SeqCst), 4);
+	}
+
+	#[tokio::test]
+	async fn watch_abort_test() {
+		use std::sync::atomic::{AtomicUsize, Ordering};
+
+		let aborted = Arc::new(AtomicUsize::new(0));
+		let make_body = |chunks: Vec<Result<&'static [u8], std::io::Error>>| {
+			let aborted = aborted.clone();
+			let inner = stream::iter(chunks).map(|c| c.map(hyper::body::Bytes::from_static));
+			watch_abort(Body::wrap_stream(inner), None, move || {
+				aborted.fetch_add(1, Ordering::SeqCst);
+			})
+		};
+
+		// The client goes away in the middle of the download
+		let mut body = make_body(vec![Ok(b"hello"), Ok(b"world")]);
+		assert_eq!(body.next().await.unwrap().unwrap().as_ref(), b"hello");
+		drop(body);
+		assert_eq!(aborted.load(Ordering::SeqCst), 1);
+
+		// A complete download is not an abort
+		let body = make_body(vec![Ok(b"hello"), Ok(b"world")]);
+		let bytes = hyper::body::to_bytes(body).await.unwrap();
+		assert_eq!(bytes.as_ref(), b"helloworld");
+		assert_eq!(aborted.load(Ordering::SeqCst), 1);
+
+		// Neither is a download that fails on the server side
+		let mut body = make_body(vec![
+			Ok(b"hello"),
+			Err(std::io::Error::new(std::io::ErrorKind::Other, "disk error")),
+		]);
+		assert!(body.next().await.unwrap().is_ok());
+		assert!(body.next().await.unwrap().is_err());
+		drop(body);
+		assert_eq!(aborted.load(Ordering::SeqCst), 1);
+	}
+
+	#[tokio::test]
+	async fn watch_abort_content_length_test() {
+		use std::sync::atomic::AtomicUsize;
+		use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+		// Counts the bodies dropped by hyper
+		struct DropCounter(Arc<AtomicUsize>);
+		impl Drop for DropCounter {
+			fn drop(&mut self) {
+				self.0.fetch_add(1, Ordering::SeqCst);
+			}
+		}
+
+		// hyper stops reading the body of a response with a Content-Length
+		// once that many bytes are sent. The body never ends here, so that
+		// the download can only be seen as complete from its length.
+		let aborted = Arc::new(AtomicUsize::new(0));
+		let dropped = Arc::new(AtomicUsize::new(0));
+		let (aborted2, dropped2) = (aborted.clone(), dropped.clone());
+		let make_svc = make_service_fn(move |_| {
+			let (aborted, dropped) = (aborted2.clone(), dropped2.clone());
+			async move {
+				Ok::<_, Infallible>(service_fn(move |_req: Request<Body>| {
+					let aborted = aborted.clone();
+					let counter = DropCounter(dropped.clone());
+					async move {
+						let inner = stream::iter(vec![&b"hello"[..], &b"world"[..]])
+							.map(move |c| {
+								let _ = &counter;
+								Ok::<_, std::io::Error>(hyper::body::Bytes::from_static(c))
+							})
+							.chain(stream::pending());
+						let body = watch_abort(Body::wrap_stream(inner), Some(10), move || {
+							aborted.fetch_add(1, Ordering::SeqCst);
+						});
+						Ok::<_, Infallible>(
+							Response::builder()
+								.header(CONTENT_LENGTH, 10)
+								.body(body)
+								.unwrap(),
+						)
+					}
+				}))
+			}
+		});
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_svc));
+
+		let mut stream = TcpStream::connect(addr).await.unwrap();
+		stream
+			.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+			.await
+			.unwrap();
+		let mut resp = vec![];
+		stream.read_to_end(&mut resp).await.unwrap();
+		assert!(resp.ends_with(b"\r\n\r\nhelloworld"));
+
+		// Wait for hyper to drop the body
+		let start = Instant::now();
+		while dropped.load(Ordering::SeqCst) == 0 {
+			assert!(start.elapsed() < Duration::from_secs(5));
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		assert_eq!(aborted.load(Ordering::SeqCst), 0);
+	}
+
+	#[test]
+	fn canonical_host_redirect_test() {
+		let uri: Uri = "/page?x=1".parse().unwrap();
+		assert_eq!(
+			canonical_host_redirect("https", "www.site.com", "site.com", &uri).as_deref(),
+			Some("https://site.com/page?x=1")
+		);
+		assert_eq!(
+			canonical_host_redirect("http", "site.com", "site.com", &uri),
+			None
+		);
+		assert_eq!(
+			canonical_host_redirect("http", "Site.COM", "site.com", &uri),
+			None
+		);
+		assert_eq!(
+			canonical_host_redirect("http", "site.com", "www.site.com", &"/".parse().unwrap())
+				.as_deref(),
+			Some("http://www.site.com/")
+		);
+	}
+
+	#[test]
+	fn security_preset_test() {
+		let mut headers = HeaderMap::new();
+		add_security_headers(&mut headers, SecurityPreset::Strict);
+		assert_eq!(headers[X_CONTENT_TYPE_OPTIONS], "nosniff");
+		assert_eq!(headers[X_FRAME_OPTIONS], "DENY");
+		assert_eq!(headers[REFERRER_POLICY], "no-referrer");
+		assert_eq!(
+			headers[STRICT_TRANSPORT_SECURITY],
+			"max-age=31536000; includeSubDomains"
+		);
+
+		let mut headers = HeaderMap::new();
+		add_security_headers(&mut headers, SecurityPreset::Basic);
+		assert_eq!(headers[X_CONTENT_TYPE_OPTIONS], "nosniff");
+		assert!(!headers.contains_key(X_FRAME_OPTIONS));
+		assert!(!headers.contains_key(STRICT_TRANSPORT_SECURITY));
+
+		// Headers that are explicitly set win over the preset
+		let mut headers = HeaderMap::new();
+		headers.insert(X_FRAME_OPTIONS, HeaderValue::from_static("SAMEORIGIN"));
+		add_security_headers(&mut headers, SecurityPreset::Strict);
+		assert_eq!(headers[X_FRAME_OPTIONS], "SAMEORIGIN");
+		assert_eq!(headers.get_all(X_FRAME_OPTIONS).iter().count(), 1);
+		assert_eq!(headers[X_CONTENT_TYPE_OPTIONS], "nosniff");
+	}
+
+	#[test]
+	fn bucket_target_test() {
+		let root_domains = vec![".web.garage".to_string()];
+		let bucket_id = Uuid::from([3u8; 32]);
+		for host in ["site.web.garage", "example.com", "localhost", ""] {
+			assert_eq!(
+				bucket_target(Some(bucket_id), host, &root_domains),
+				BucketTarget::Id(bucket_id)
+			);
+		}
+		assert_eq!(
+			bucket_target(None, "site.web.garage", &root_domains),
+			BucketTarget::Alias("site")
+		);
+		assert_eq!(
+			bucket_target(None, "example.com", &root_domains),
+			BucketTarget::Alias("example.com")
+		);
+
+		// The port of the Host header doesn't change the bucket
+		for (authority, bucket) in [
+			("site.web.garage:8080", "site"),
+			("site.web.garage", "site"),
+			("example.com:3902", "example.com"),
+			("[::1]:3902", "[::1]"),
+		] {
+			let host = authority_to_host(authority).unwrap();
+			assert_eq!(
+				bucket_target(None, &host, &root_domains),
+				BucketTarget::Alias(bucket)
+			);
+		}
+	}
+
+	#[test]
+	fn host_to_website_bucket_test() {
+		let root_domains = vec![
+			".web.example.com".to_string(),
+			".sites.example.org".to_string(),
+			".example.org".to_string(),
+		];
+		assert_eq!(
+			host_to_website_bucket("blog.web.example.com", &root_domains),
+			"blog"
+		);
+		assert_eq!(
+			host_to_website_bucket("blog.sites.example.org", &root_domains),
+			"blog"
+		);
+		assert_eq!(
+			host_to_website_bucket("blog.example.org", &root_domains),
+			"blog"
+		);
+		assert_eq!(
+			host_to_website_bucket("example.com", &root_domains),
+			"example.com"
+		);
+		assert_eq!(host_to_website_bucket("example.com", &[]), "example.com");
+	}
+
+	#[test]
+	fn split_bucket_path_test() {
+		assert_eq!(split_bucket_path("/site/"), Some(("site", "/")));
+		assert_eq!(
+			split_bucket_path("/site/a/b.html"),
+			Some(("site", "/a/b.html"))
+		);
+		assert_eq!(split_bucket_path("/site"), Some(("site", "")));
+		assert_eq!(split_bucket_path("/"), None);
+		assert_eq!(split_bucket_path("//a"), None);
+		assert_eq!(split_bucket_path("site/a"), None);
+
+		// The bucket segment is stripped before the index is appended
+		let (_, index_path) = split_bucket_path("/site/").unwrap();
+		assert_eq!(path_to_key(index_path, "index.html").unwrap(), "index.html");
+		let (_, deep_path) = split_bucket_path("/site/a/b.html").unwrap();
+		assert_eq!(path_to_key(deep_path, "index.html").unwrap(), "a/b.html");
+		let (_, dir_path) = split_bucket_path("/site/a/").unwrap();
+		assert_eq!(path_to_key(dir_path, "index.html").unwrap(), "a/index.html");
+	}
+
+	#[test]
+	fn routing_rules_test() {
+		use garage_model::bucket_table::{RoutingRuleCondition, RoutingRuleRedirect};
+
+		let rule =
+			|code: Option<u16>, prefix: Option<&str>, redirect: RoutingRuleRedirect| RoutingRule {
+				condition: Some(RoutingRuleCondition {
+					http_error_code: code,
+					key_prefix: prefix.map(String::from),
+				}),
+				redirect,
+			};
+		let no_redirect = RoutingRuleRedirect {
+			hostname: None,
+			protocol: None,
+			http_redirect_code: None,
+			replace_key_prefix: None,
+			replace_key: None,
+		};
+
+		let docs = rule(
+			None,
+			Some("docs/"),
+			RoutingRuleRedirect {
+				replace_key_prefix: Some("documents/".into()),
+				..no_redirect.clone()
+			},
+		);
+		assert!(docs.matches("docs/a b.html", None));
+		assert!(!docs.matches("docs/a b.html", Some(404)));
+		assert!(!docs.matches("images/a.png", None));
+		let resp =
+			routing_rule_redirect(&docs, "http", "site.web.garage", "", "docs/a b.html").unwrap();
+		assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+		assert_eq!(resp.headers()[LOCATION], "/documents/a%20b.html");
+		let resp =
+			routing_rule_redirect(&docs, "http", "web.garage", "/site", "docs/a.html").unwrap();
+		assert_eq!(resp.headers()[LOCATION], "/site/documents/a.html");
+
+		let not_found = rule(
+			Some(404),
+			None,
+			RoutingRuleRedirect {
+				hostname: Some("example.com".into()),
+				protocol: Some("https".into()),
+				http_redirect_code: Some(302),
+				replace_key: Some("404.html".into()),
+				..no_redirect.clone()
+			},
+		);
+		assert!(!not_found.matches("missing", None));
+		assert!(not_found.matches("missing", Some(404)));
+		assert!(!not_found.matches("missing", Some(403)));
+		let resp =
+			routing_rule_redirect(&not_found, "http", "site.web.garage", "", "missing").unwrap();
+		assert_eq!(resp.status(), StatusCode::FOUND);
+		assert_eq!(resp.headers()[LOCATION], "https://example.com/404.html");
+		let resp = routing_rule_redirect(&not_found, "http", "web.garage", "/site", "a").unwrap();
+		assert_eq!(resp.headers()[LOCATION], "https://example.com/404.html");
+
+		let other_host = rule(
+			None,
+			None,
+			RoutingRuleRedirect {
+				protocol: Some("http".into()),
+				..no_redirect.clone()
+			},
+		);
+		let resp = routing_rule_redirect(&other_host, "https", "site.web.garage", "", "a").unwrap();
+		assert_eq!(resp.headers()[LOCATION], "http://site.web.garage/a");
+		let resp = routing_rule_redirect(&other_host, "https", "web.garage", "/site", "a").unwrap();
+		assert_eq!(resp.headers()[LOCATION], "http://web.garage/site/a");
+
+		let other_key = rule(
+			None,
+			None,
+			RoutingRuleRedirect {
+				hostname: Some("example.com".into()),
+				replace_key: Some("b".into()),
+				..no_redirect
+			},
+		);
+		let resp = routing_rule_redirect(&other_key, "https", "site.web.garage", "", "a").unwrap();
+		assert_eq!(resp.headers()[LOCATION], "https://example.com/b");
+
+		let redirect_all = RedirectAll {
+			hostname: "example.com".into(),
+			protocol: None,
+		};
+		let uri = "/a/b?x=1".parse::<Uri>().unwrap();
+		assert_eq!(
+			redirect_all_location(&redirect_all, "https", &uri),
+			"https://example.com/a/b?x=1"
+		);
+	}
+
+	#[test]
+	fn https_redirect_test() {
+		let mut config: WebConfig = toml::from_str(
+			r#"
+			bind_addr = "[::]:80"
+			root_domain = ".web.garage"
+			"#,
+		)
+		.unwrap();
+		let proxy: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+		let req = Request::builder()
+			.uri("/a/b?x=1")
+			.header(HOST, "site.web.garage:8080")
+			.header("x-forwarded-host", "www.example.com")
+			.body(Body::empty())
+			.unwrap();
+
+		let resp = https_redirect(&config, &req, proxy).unwrap();
+		assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+		assert_eq!(resp.headers()[LOCATION], "https://site.web.garage/a/b?x=1");
+
+		config.trusted_proxies = vec![proxy.ip().into()];
+		let resp = https_redirect(&config, &req, proxy).unwrap();
+		assert_eq!(resp.headers()[LOCATION], "https://www.example.com/a/b?x=1");
+
+		let ipv6 = Request::builder()
+			.uri("/")
+			.header(HOST, "[::1]:3902")
+			.body(Body::empty())
+			.unwrap();
+		let resp = https_redirect(&config, &ipv6, proxy).unwrap();
+		assert_eq!(resp.headers()[LOCATION], "https://[::1]/");
+	}
+
+	#[test]
+	fn request_scheme_test() {
+		let mut config: WebConfig = toml::from_str(
+			r#"
+			bind_addr = "[::]:3902"
+			root_domain = ".web.garage"
+			"#,
+		)
+		.unwrap();
+		let proxy: SocketAddr = "192.0.2.1:40000".parse().unwrap();
+		let client: SocketAddr = "198.51.100.7:40000".parse().unwrap();
+		let req = Request::builder()
+			.header("x-forwarded-proto", "https")
+			.body(Body::empty())
+			.unwrap();
+
+		assert_eq!(request_scheme(&config, &req, proxy), "http");
+		config.trusted_proxies = vec![proxy.ip().into()];
+		assert_eq!(request_scheme(&config, &req, proxy), "https");
+		assert_eq!(request_scheme(&config, &req, client), "http");
+		let no_header = Request::builder().body(Body::empty()).unwrap();
+		assert_eq!(request_scheme(&config, &no_header, proxy), "http");
+	}
+
+	#[test]
+	fn forwarded_client_ip_test() {
+		let mut config: WebConfig = toml::from_str(
+			r#"
+			bind_addr = "[::]:3902"
+			root_domain = ".web.garage"
+			trusted_proxies = ["10.0.0.0/8", "::1"]
+			"#,
+		)
+		.unwrap();
+		let proxy: SocketAddr = "10.1.2.3:40000".parse().unwrap();
+		let mapped_proxy: SocketAddr = "[::ffff:10.1.2.3]:40000".parse().unwrap();
+		let client: SocketAddr = "198.51.100.7:40000".parse().unwrap();
+		let req = Request::builder()
+			.header("x-forwarded-for", "203.0.113.9, 198.51.100.1, 10.0.0.5")
+			.body(Body::empty())
+			.unwrap();
+
+		// The addresses of trusted proxies are skipped, not the ones before
+		let expected: IpAddr = "198.51.100.1".parse().unwrap();
+		assert_eq!(forwarded_client_ip(&config, &req, proxy), Some(expected));
+		assert_eq!(
+			forwarded_client_ip(&config, &req, mapped_proxy),
+			Some(expected)
+		);
+		// Untrusted peers can't set their address
+		assert_eq!(forwarded_client_ip(&config, &req, client), None);
+
+		let ipv6_proxy: SocketAddr = "[::1]:40000".parse().unwrap();
+		let lines = Request::builder()
+			.header("x-forwarded-for", "203.0.113.9")
+			.header("x-forwarded-for", "10.0.0.5")
+			.body(Body::empty())
+			.unwrap();
+		let expected: IpAddr = "203.0.113.9".parse().unwrap();
+		assert_eq!(
+			forwarded_client_ip(&config, &lines, ipv6_proxy),
+			Some(expected)
+		);
+
+		let invalid = Request::builder()
+			.header("x-forwarded-for", "unknown")
+			.body(Body::empty())
+			.unwrap();
+		assert_eq!(forwarded_client_ip(&config, &invalid, proxy), None);
+
+		config.trusted_proxies = vec![];
+		assert_eq!(forwarded_client_ip(&config, &req, proxy), None);
+	}
+
+	#[test]
+	fn matched_cors_origin_test() {
+		let rule = CorsRule {
+			id: None,
+			max_age_seconds: None,
+			allow_origins: vec!["https://a.example.com".into(), "*".into()],
+			allow_methods: vec!["GET".into()],
+			allow_headers: vec![],
+			expose_headers: vec![],
+		};
+		let req = |origin: &str| {
+			Request::builder()
+				.header("Origin", origin)
+				.body(Body::empty())
+				.unwrap()
+		};
+		assert_eq!(
+			matched_cors_origin(&rule, &req("https://a.example.com")),
+			"https://a.example.com"
+		);
+		assert_eq!(
+			matched_cors_origin(&rule, &req("https://attacker.example")),
+			"*"
+		);
+	}
+
+	#[test]
+	fn is_spa_route_test() {
+		assert!(is_spa_route("some/client/route"));
+		assert!(is_spa_route("some/client/route/"));
+		assert!(is_spa_route("v1.2/route"));
+		assert!(!is_spa_route("missing.js"));
+		assert!(!is_spa_route("assets/logo.png"));
+	}
+
+	#[test]
+	fn path_matches_test() {
+		assert!(path_matches("/assets/*", "/assets/app.3f2a1c.js"));
+		assert!(path_matches("*.*.js", "/app.3f2a1c.js"));
+		assert!(path_matches("/favicon.ico", "/favicon.ico"));
+		assert!(!path_matches("/favicon.ico", "/favicon.ico.bak"));
+		assert!(!path_matches("/assets/*", "/index.html"));
+		assert!(!path_matches("*.*.js", "/app.js"));
+		assert!(path_matches("*", "/anything"));
+	}
+
+	#[test]
+	fn content_type_override_test() {
+		let mut website_config = WebsiteConfig::default();
+		website_config
+			.content_types
+			.insert("wasm".into(), "application/wasm".into());
+		let headers = |ct: &str| {
+			let mut headers = HeaderMap::new();
+			headers.insert(CONTENT_TYPE, HeaderValue::from_str(ct).unwrap());
+			headers
+		};
+
+		assert_eq!(
+			content_type_override(&website_config, "app/main.WASM", &headers("blob")),
+			Some("application/wasm")
+		);
+		assert_eq!(
+			content_type_override(
+				&website_config,
+				"main.wasm",
+				&headers("application/octet-stream")
+			),
+			Some("application/wasm")
+		);
+		assert_eq!(
+			content_type_override(&website_config, "main.wasm", &HeaderMap::new()),
+			Some("application/wasm")
+		);
+		// Specific content types of objects are kept
+		assert_eq!(
+			content_type_override(&website_config, "main.wasm", &headers("text/plain")),
+			None
+		);
+		assert_eq!(
+			content_type_override(&website_config, "main.js", &headers("blob")),
+			None
+		);
+		assert_eq!(
+			content_type_override(&website_config, "v1.0/wasm", &headers("blob")),
+			None
+		);
+	}
+
+	#[test]
+	fn cache_control_test() {
+		let mut config: WebConfig = toml::from_str(
+			r#"
+			bind_addr = "[::]:3902"
+			root_domain = ".web.garage"
+			cache_control = "public, max-age=60"
+			immutable_paths = ["/assets/*"]
+			"#,
+		)
+		.unwrap();
+		let mut website_config = WebsiteConfig::default();
+
+		assert_eq!(
+			cache_control(&config, &website_config, "/index.html", true),
+			Some("public, max-age=60")
+		);
+		assert_eq!(
+			cache_control(&config, &website_config, "/assets/app.js", true),
+			Some("public, max-age=31536000, immutable")
+		);
+		// The index document of a matching directory is not immutable
+		assert_eq!(
+			cache_control(&config, &website_config, "/assets/", false),
+			Some("public, max-age=60")
+		);
+
+		// Hashed file names set by the website
+		website_config.immutable_paths = vec!["*.*.js".into()];
+		assert_eq!(
+			cache_control(&config, &website_config, "/app.4f2a.js", true),
+			Some("public, max-age=31536000, immutable")
+		);
+		assert_eq!(
+			cache_control(&config, &website_config, "/app.js", true),
+			Some("public, max-age=60")
+		);
+
+		website_config.cache_control = Some("no-cache".into());
+		assert_eq!(
+			cache_control(&config, &website_config, "/index.html", true),
+			Some("no-cache")
+		);
+
+		config.cache_control = None;
+		website_config.cache_control = None;
+		assert_eq!(
+			cache_control(&config, &website_config, "/index.html", true),
+			None
+		);
+	}
+
+	#[test]
+	fn bucket_limiter_test() {
+		let limiter = BucketLimiter {
+			default_limit: Some(2),
+			bucket_limits: HashMap::from([("small".to_string(), 1)]),
+			semaphores: std::sync::Mutex::new(HashMap::new()),
+		};
+		let busy = Uuid::from([1u8; 32]);
+		let other = Uuid::from([2u8; 32]);
+
+		// Saturate the first bucket
+		let p1 = limiter.try_acquire("busy", busy).unwrap();
+		let p2 = limiter.try_acquire("busy", busy).unwrap();
+		assert!(p1.is_some() && p2.is_some());
+		assert!(limiter.try_acquire("busy", busy).is_err());
+
+		// Another bucket is not affected
+		let p3 = limiter.try_acquire("other", other).unwrap();
+		assert!(p3.is_some());
+
+		// A permit is released when the response is done
+		drop(p1);
+		assert!(limiter.try_acquire("busy", busy).unwrap().is_some());
+
+		// Per-bucket limits override the default one
+		let small = Uuid::from([3u8; 32]);
+		let _p4 = limiter.try_acquire("small", small).unwrap();
+		assert!(matches!(
+			limiter.try_acquire("small", small),
+			Err(Error::TooManyRequests)
+		));
+
+		let none = BucketLimiter {
+			default_limit: None,
+			bucket_limits: HashMap::new(),
+			semaphores: std::sync::Mutex::new(HashMap::new()),
+		};
+		assert!(none.try_acquire("busy", busy).unwrap().is_none());
+	}
+
+	#[test]
+	fn diagnostic_headers_test() {
+		let trace_id = gen_trace_id();
+		// this is the value that is logged and used as the trace id
+		let logged = trace_id.to_string();
+
+		let mut headers = HeaderMap::new();
+		add_diagnostic_headers(&mut headers, "0123456789abcdef", &logged);
+		assert_eq!(headers.get(X_GARAGE_NODE).unwrap(), "0123456789abcdef");
+		assert_eq!(headers.get(X_GARAGE_REQUEST_ID).unwrap(), logged.as_str());
 	}
 }